@@ -0,0 +1,8 @@
+#![no_main]
+
+/* cargo fuzz run elf ../tests/corpus/elf */
+
+use libfuzzer_sys::fuzz_target;
+use voidio::net::fuzz::walk_elf;
+
+fuzz_target!(|data: &[u8]| walk_elf(data));
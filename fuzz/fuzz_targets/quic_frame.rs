@@ -0,0 +1,8 @@
+#![no_main]
+
+/* cargo fuzz run quic_frame */
+
+use libfuzzer_sys::fuzz_target;
+use voidio::net::fuzz::walk_quic_frames;
+
+fuzz_target!(|data: &[u8]| walk_quic_frames(data));
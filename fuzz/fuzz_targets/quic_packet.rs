@@ -0,0 +1,10 @@
+#![no_main]
+
+/* cargo fuzz run quic_packet ../tests/corpus/quic */
+
+use libfuzzer_sys::fuzz_target;
+use voidio::net::fuzz::walk_quic_packets;
+
+fuzz_target!(|data: &[u8]| {
+    walk_quic_packets(data);
+});
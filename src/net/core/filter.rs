@@ -0,0 +1,411 @@
+use std::{io::{Error, ErrorKind, Result}, net::IpAddr, str::FromStr};
+
+use super::sys::SockFilter;
+
+/*
+Classic BPF socket filters for UDP sockets.
+
+A `FilterExpr` describes which datagrams a socket accepts; `SocketFilter` compiles it to `sock_filter`
+instructions that the kernel runs before queueing, so rejected traffic never reaches recvmmsg.
+Attaching cBPF needs no privileges, unlike eBPF/XDP.
+
+On a UDP socket the filter sees the datagram starting at the UDP header; the IP header is reached
+through the SKF_NET_OFF ancillary offset.
+
+Text form, as accepted by `SocketFilter::parse`:
+    dst port 4433 | dst port 4000-4100 | src port 53
+    len 20-1350                  payload length in bytes
+    payload c0ff                 payload starts with these hex bytes
+    src net 10.0.0.0/8 | src net fd00::/8
+combined with `and`, `or`, `not` and parentheses (`and` binds tighter than `or`).
+*/
+
+const UDP_HEADER_LEN: u32 = 8;
+const SKF_NET_OFF: i32 = -0x100000;
+const MAX_FILTER_INSNS: usize = 4096; // BPF_MAXINSNS
+
+const BPF_LD: u16 = 0x00;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_LEN: u16 = 0x80;
+const BPF_K: u16 = 0x00;
+const BPF_AND: u16 = 0x50;
+const BPF_RSH: u16 = 0x70;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterExpr {
+    DstPort(u16, u16), // inclusive range
+    SrcPort(u16, u16),
+    PayloadLen(u32, u32),
+    PayloadPrefix(Vec<u8>),
+    SrcNet(IpAddr, u8),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+}
+
+impl FilterExpr {
+    pub fn dst_port(port: u16) -> Self {
+        FilterExpr::DstPort(port, port)
+    }
+
+    pub fn dst_ports(first: u16, last: u16) -> Self {
+        FilterExpr::DstPort(first, last)
+    }
+
+    pub fn src_port(port: u16) -> Self {
+        FilterExpr::SrcPort(port, port)
+    }
+
+    pub fn src_ports(first: u16, last: u16) -> Self {
+        FilterExpr::SrcPort(first, last)
+    }
+
+    pub fn payload_len(min: u32, max: u32) -> Self {
+        FilterExpr::PayloadLen(min, max)
+    }
+
+    pub fn payload_prefix(prefix: &[u8]) -> Self {
+        FilterExpr::PayloadPrefix(prefix.to_vec())
+    }
+
+    pub fn src_net(addr: IpAddr, prefix_len: u8) -> Self {
+        FilterExpr::SrcNet(addr, prefix_len)
+    }
+
+    pub fn and(self, other: FilterExpr) -> Self {
+        FilterExpr::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: FilterExpr) -> Self {
+        FilterExpr::Or(Box::new(self), Box::new(other))
+    }
+}
+
+impl std::ops::Not for FilterExpr {
+    type Output = FilterExpr;
+
+    fn not(self) -> Self::Output {
+        FilterExpr::Not(Box::new(self))
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.expr()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(invalid(format!("Unexpected '{token}' in socket filter"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SocketFilter {
+    expr: FilterExpr,
+    program: Vec<SockFilter>,
+}
+
+impl SocketFilter {
+    pub fn new(expr: FilterExpr) -> Result<Self> {
+        let program = Compiler::compile(&expr)?;
+        Ok(Self { expr, program })
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        Self::new(s.parse()?)
+    }
+
+    pub fn expr(&self) -> &FilterExpr {
+        &self.expr
+    }
+
+    pub fn instructions(&self) -> &[SockFilter] {
+        &self.program
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str> {
+        let token = self.peek().ok_or_else(|| invalid("Unexpected end of socket filter".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, word: &str) -> Result<()> {
+        let token = self.next()?;
+        if token == word { Ok(()) } else { Err(invalid(format!("Expected '{word}', found '{token}'"))) }
+    }
+
+    fn expr(&mut self) -> Result<FilterExpr> {
+        let mut left = self.term()?;
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            left = left.or(self.term()?);
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<FilterExpr> {
+        let mut left = self.factor()?;
+        while self.peek() == Some("and") {
+            self.pos += 1;
+            left = left.and(self.factor()?);
+        }
+        Ok(left)
+    }
+
+    fn factor(&mut self) -> Result<FilterExpr> {
+        match self.next()? {
+            "not" => Ok(!self.factor()?),
+            "(" => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            "dst" | "src" if self.peek() == Some("port") => {
+                let is_dst = self.tokens[self.pos - 1] == "dst";
+                self.pos += 1;
+                let (first, last) = parse_range(self.next()?)?;
+                let (first, last) = (to_port(first)?, to_port(last)?);
+                Ok(if is_dst { FilterExpr::DstPort(first, last) } else { FilterExpr::SrcPort(first, last) })
+            }
+            "src" => {
+                self.expect("net")?;
+                let token = self.next()?;
+                let (addr, prefix_len) = token.split_once('/').unwrap_or((token, ""));
+                let addr: IpAddr = addr.parse().map_err(|_| invalid(format!("Invalid address '{addr}'")))?;
+                let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+                let prefix_len = if prefix_len.is_empty() { max_prefix } else {
+                    prefix_len.parse().map_err(|_| invalid(format!("Invalid prefix length '{prefix_len}'")))?
+                };
+                Ok(FilterExpr::SrcNet(addr, prefix_len))
+            }
+            "len" => {
+                let (min, max) = parse_range(self.next()?)?;
+                Ok(FilterExpr::PayloadLen(min, max))
+            }
+            "payload" => {
+                let hex = self.next()?;
+                let hex = hex.strip_prefix("0x").unwrap_or(hex);
+                if hex.is_empty() || !hex.is_ascii() || hex.len() % 2 != 0 {
+                    return Err(invalid(format!("Invalid payload prefix '{hex}'")));
+                }
+                let bytes = (0..hex.len()).step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<std::result::Result<Vec<u8>, _>>()
+                    .map_err(|_| invalid(format!("Invalid payload prefix '{hex}'")))?;
+                Ok(FilterExpr::PayloadPrefix(bytes))
+            }
+            token => Err(invalid(format!("Unknown socket filter term '{token}'"))),
+        }
+    }
+}
+
+fn parse_range(token: &str) -> Result<(u32, u32)> {
+    let (first, last) = token.split_once('-').unwrap_or((token, token));
+    let parse = |n: &str| n.parse::<u32>().map_err(|_| invalid(format!("Invalid number '{n}'")));
+    let (first, last) = (parse(first)?, parse(last)?);
+    if first > last {
+        return Err(invalid(format!("Empty range '{token}', {first} is above {last}")));
+    }
+    Ok((first, last))
+}
+
+fn to_port(n: u32) -> Result<u16> {
+    u16::try_from(n).map_err(|_| invalid(format!("Port {n} out of range")))
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+#[derive(Clone, Copy)]
+struct Label(usize);
+
+struct Insn {
+    code: u16,
+    jt: Option<Label>,
+    jf: Option<Label>,
+    k: u32,
+}
+
+/* Emits jumps as (true, false) label pairs; every label is bound after the jumps that use it, so all offsets are forward as cBPF requires. */
+struct Compiler {
+    insns: Vec<Insn>,
+    labels: Vec<usize>,
+}
+
+impl Compiler {
+    fn compile(expr: &FilterExpr) -> Result<Vec<SockFilter>> {
+        let mut c = Compiler { insns: Vec::new(), labels: Vec::new() };
+        let accept = c.label();
+        let reject = c.label();
+        c.expr(expr, accept, reject)?;
+        c.bind(accept);
+        c.emit(BPF_RET | BPF_K, u32::MAX);
+        c.bind(reject);
+        c.emit(BPF_RET | BPF_K, 0);
+
+        if c.insns.len() > MAX_FILTER_INSNS {
+            return Err(invalid("Socket filter is too large".to_string()));
+        }
+        let mut program = Vec::with_capacity(c.insns.len());
+        for (at, insn) in c.insns.iter().enumerate() {
+            let offset = |label: Option<Label>| -> Result<u8> {
+                match label {
+                    None => Ok(0),
+                    Some(label) => u8::try_from(c.labels[label.0] - at - 1)
+                        .map_err(|_| invalid("Socket filter branch is too long".to_string())),
+                }
+            };
+            program.push(SockFilter { code: insn.code, jt: offset(insn.jt)?, jf: offset(insn.jf)?, k: insn.k });
+        }
+        Ok(program)
+    }
+
+    fn label(&mut self) -> Label {
+        self.labels.push(usize::MAX);
+        Label(self.labels.len() - 1)
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label.0] = self.insns.len();
+    }
+
+    fn emit(&mut self, code: u16, k: u32) {
+        self.insns.push(Insn { code, jt: None, jf: None, k });
+    }
+
+    fn jump(&mut self, op: u16, k: u32, jt: Label, jf: Label) {
+        self.insns.push(Insn { code: BPF_JMP | op | BPF_K, jt: Some(jt), jf: Some(jf), k });
+    }
+
+    /* Jumps to `t` if first <= A <= last, otherwise to `f`; an empty range is an error rather than a term that never matches. */
+    fn range(&mut self, first: u32, last: u32, t: Label, f: Label) -> Result<()> {
+        if first > last {
+            return Err(invalid(format!("Empty range {first}-{last}, {first} is above {last}")));
+        }
+        if first == last {
+            self.jump(BPF_JEQ, first, t, f);
+            return Ok(());
+        }
+        let upper = self.label();
+        self.jump(BPF_JGE, first, upper, f);
+        self.bind(upper);
+        self.jump(BPF_JGT, last, f, t);
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &FilterExpr, t: Label, f: Label) -> Result<()> {
+        match expr {
+            FilterExpr::And(a, b) => {
+                let next = self.label();
+                self.expr(a, next, f)?;
+                self.bind(next);
+                self.expr(b, t, f)?;
+            }
+            FilterExpr::Or(a, b) => {
+                let next = self.label();
+                self.expr(a, t, next)?;
+                self.bind(next);
+                self.expr(b, t, f)?;
+            }
+            FilterExpr::Not(a) => self.expr(a, f, t)?,
+            FilterExpr::DstPort(first, last) => {
+                self.emit(BPF_LD | BPF_H | BPF_ABS, 2);
+                self.range(*first as u32, *last as u32, t, f)?;
+            }
+            FilterExpr::SrcPort(first, last) => {
+                self.emit(BPF_LD | BPF_H | BPF_ABS, 0);
+                self.range(*first as u32, *last as u32, t, f)?;
+            }
+            FilterExpr::PayloadLen(min, max) => {
+                self.emit(BPF_LD | BPF_W | BPF_LEN, 0);
+                let min = min.saturating_add(UDP_HEADER_LEN);
+                let max = max.saturating_add(UDP_HEADER_LEN);
+                self.range(min, max, t, f)?;
+            }
+            FilterExpr::PayloadPrefix(prefix) => {
+                // Out-of-bounds loads abort the filter with "drop", which would be wrong under `not`, so check the length first
+                let body = self.label();
+                self.emit(BPF_LD | BPF_W | BPF_LEN, 0);
+                self.jump(BPF_JGE, UDP_HEADER_LEN + prefix.len() as u32, if prefix.is_empty() { t } else { body }, f);
+                self.bind(body);
+                self.compare_bytes(UDP_HEADER_LEN as i32, prefix, t, f);
+            }
+            FilterExpr::SrcNet(addr, prefix_len) => {
+                let (version, src_off, bytes): (u32, i32, Vec<u8>) = match addr {
+                    IpAddr::V4(v4) => (4, 12, v4.octets().to_vec()),
+                    IpAddr::V6(v6) => (6, 8, v6.octets().to_vec()),
+                };
+                let prefix_len = *prefix_len as usize;
+                if prefix_len > bytes.len() * 8 {
+                    return Err(invalid(format!("Prefix length {prefix_len} too long for {addr}")));
+                }
+                let words = prefix_len.div_ceil(32);
+                let body = self.label();
+                self.emit(BPF_LD | BPF_B | BPF_ABS, SKF_NET_OFF as u32);
+                self.emit(BPF_ALU | BPF_RSH | BPF_K, 4);
+                self.jump(BPF_JEQ, version, if words == 0 { t } else { body }, f);
+                self.bind(body);
+                for w in 0..words {
+                    let bits = (prefix_len - w * 32).min(32);
+                    let mask = if bits == 32 { u32::MAX } else { !(u32::MAX >> bits) };
+                    let net = u32::from_be_bytes(bytes[w * 4..w * 4 + 4].try_into().unwrap()) & mask;
+                    self.emit(BPF_LD | BPF_W | BPF_ABS, (SKF_NET_OFF + src_off + w as i32 * 4) as u32);
+                    if mask != u32::MAX {
+                        self.emit(BPF_ALU | BPF_AND | BPF_K, mask);
+                    }
+                    let next = if w + 1 == words { t } else { self.label() };
+                    self.jump(BPF_JEQ, net, next, f);
+                    if w + 1 != words {
+                        self.bind(next);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn compare_bytes(&mut self, offset: i32, bytes: &[u8], t: Label, f: Label) {
+        let mut at = 0;
+        while at < bytes.len() {
+            let rest = &bytes[at..];
+            let (size, width, k) = match rest.len() {
+                4.. => (BPF_W, 4, u32::from_be_bytes(rest[..4].try_into().unwrap())),
+                2 | 3 => (BPF_H, 2, u16::from_be_bytes(rest[..2].try_into().unwrap()) as u32),
+                _ => (BPF_B, 1, rest[0] as u32),
+            };
+            self.emit(BPF_LD | size | BPF_ABS, (offset + at as i32) as u32);
+            at += width;
+            let next = if at == bytes.len() { t } else { self.label() };
+            self.jump(BPF_JEQ, k, next, f);
+            if at != bytes.len() {
+                self.bind(next);
+            }
+        }
+    }
+}
@@ -8,6 +8,7 @@ pub mod sockaddr;
 pub mod sockdomains;
 pub mod socket;
 pub mod bulk;
+pub mod filter;
 
 pub use utils::*;
 pub use sockopts::*;
@@ -15,4 +16,5 @@ pub use sockaddr::*;
 pub use sockdomains::*;
 pub use sys::*;
 pub use socket::*;
-pub use bulk::*;
\ No newline at end of file
+pub use bulk::*;
+pub use filter::*;
\ No newline at end of file
@@ -27,7 +27,7 @@ impl SocketAddrSrcV4 {
             sin_family: 2, // AF_INET
             sin_port: addr.port().to_be(),
             #[cfg(unix)]
-            sin_addr: super::sys::InAddr { s_addr: u32::from_be_bytes(addr.ip().octets()) },
+            sin_addr: super::sys::InAddr { s_addr: u32::from_ne_bytes(addr.ip().octets()) }, // already in network order
             #[cfg(windows)]
             sin_addr: super::sys::InAddr { s_addr: addr.ip().octets().try_into().unwrap() },
             sin_zero: [0; 8],
@@ -169,6 +169,15 @@ impl <'a>Socket {
         }
     }
 
+    /* Replaces any filter already attached to the socket. */
+    pub fn attach_filter(&self, filter: &SocketFilter) -> Result<()> {
+        xattach_filter(*self, filter.instructions())
+    }
+
+    pub fn detach_filter(&self) -> Result<()> {
+        xdetach_filter(*self)
+    }
+
     #[inline(always)]
     pub fn as_raw(&self) -> SocketRaw {
         self.0
@@ -90,6 +90,34 @@ pub const __MAX_BPF_PROG_TYPE: BpfProgType = BpfProgType(33);
 
 pub const BPF_OBJ_NAME_LEN: usize = 16;
 
+pub const BPF_MAP_TYPE_UNSPEC: u32 = 0;
+pub const BPF_MAP_TYPE_HASH: u32 = 1;
+pub const BPF_MAP_TYPE_ARRAY: u32 = 2;
+pub const BPF_MAP_TYPE_PROG_ARRAY: u32 = 3;
+pub const BPF_MAP_TYPE_PERF_EVENT_ARRAY: u32 = 4;
+pub const BPF_MAP_TYPE_PERCPU_HASH: u32 = 5;
+pub const BPF_MAP_TYPE_PERCPU_ARRAY: u32 = 6;
+pub const BPF_MAP_TYPE_STACK_TRACE: u32 = 7;
+pub const BPF_MAP_TYPE_CGROUP_ARRAY: u32 = 8;
+pub const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+pub const BPF_MAP_TYPE_LRU_PERCPU_HASH: u32 = 10;
+pub const BPF_MAP_TYPE_LPM_TRIE: u32 = 11;
+pub const BPF_MAP_TYPE_ARRAY_OF_MAPS: u32 = 12;
+pub const BPF_MAP_TYPE_HASH_OF_MAPS: u32 = 13;
+pub const BPF_MAP_TYPE_DEVMAP: u32 = 14;
+pub const BPF_MAP_TYPE_SOCKMAP: u32 = 15;
+pub const BPF_MAP_TYPE_CPUMAP: u32 = 16;
+pub const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+pub const BPF_MAP_TYPE_SOCKHASH: u32 = 18;
+pub const BPF_MAP_TYPE_DEVMAP_HASH: u32 = 25;
+
+pub const BPF_F_REPLACE: u32 = 1 << 2;
+pub const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+pub const BPF_ANY: u64 = 0;
+pub const BPF_NOEXIST: u64 = 1;
+pub const BPF_EXIST: u64 = 2;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct BpfAttachType(pub u32);
@@ -157,23 +185,42 @@ pub const __MAX_BPF_ATTACH_TYPE: BpfAttachType = BpfAttachType(58);
 #[derive(Copy, Clone)]
 pub union bpf_attr {
     pub link_create: BpfAttrLinkCreate,
+    pub link_update: BpfAttrLinkUpdate,
+    pub link_detach: BpfAttrLinkDetach,
     pub map_create: BpfAttrMapCreate,
     pub elem: BpfAttrElem,
     pub batch: BpfAttrBatch,
     pub prog_load: BpfAttrProgLoad,
     pub obj: BpfAttrObj,
+    pub get_id: BpfAttrGetId,
+    pub info: BpfAttrInfo,
 }
 
 #[repr(C)]
 #[derive(Copy,Clone)]
 pub struct BpfAttrLinkCreate {
     pub prog_fd: u32,
+    pub target_fd: u32, // target_ifindex for BPF_XDP
     pub attach_type: BpfAttachType,
-    pub target_fd: u32,
     pub flags: u32,
     pub __reserved: u64,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct BpfAttrLinkUpdate {
+    pub link_fd: u32,
+    pub new_prog_fd: u32,
+    pub flags: u32,
+    pub old_prog_fd: u32, // only honoured together with BPF_F_REPLACE
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct BpfAttrLinkDetach {
+    pub link_fd: u32,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct BpfAttrMapCreate {
@@ -272,6 +319,33 @@ pub struct BpfAttrObj {
     pub file_flags: c_uint,
     pub path_fd:    c_int,
 }
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct BpfAttrGetId {
+    pub id: c_uint, // start_id, prog_id, map_id, btf_id or link_id depending on the command
+    pub next_id: c_uint,
+    pub open_flags: c_uint,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct BpfAttrInfo {
+    pub bpf_fd: c_uint,
+    pub info_len: c_uint,
+    pub info: c_ulonglong,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BpfLinkInfoXdp {
+    pub type_: u32,
+    pub id: u32,
+    pub prog_id: u32,
+    pub _pad: u32, // the per-type union is 8-byte aligned
+    pub ifindex: u32,
+    pub _pad2: u32,
+}
 #[repr(C, packed)]
 pub struct BpfInsn {
     pub code: u8, // opcode
@@ -299,21 +373,147 @@ pub struct BpfMapDef {
     pub map_flags: u32,
 }
 
+/* The leading fields of the kernel's bpf_map_info; BPF_OBJ_GET_INFO_BY_FD fills as much as the buffer holds. */
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BpfMapInfo {
+    pub type_: u32,
+    pub id: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32,
+    pub name: [u8; 16],
+}
+
+/// # Safety
+/// `attr` must point to a readable `bpf_attr` of at least `size` bytes, and every pointer stored in it
+/// must be valid for what `cmd` does with it (read, or written by the kernel).
 pub unsafe fn bpf(cmd: BpfCmd, attr: *const bpf_attr, size: u32) -> i32 {
     syscall(SYS_BPF, cmd, attr, size) as i32
 }
+
+/// # Safety
+/// `fd` must be a BPF program file descriptor owned by the caller.
+#[deprecated(note = "BPF_PROG_ATTACH does not support XDP; use `XdpAttachment` instead")]
 pub unsafe fn bpf_set_link_xdp_fd(ifindex: u32, fd: i32, flags: u32) -> i32 {
     bpf(
         BPF_PROG_ATTACH,
         &bpf_attr {
             link_create: BpfAttrLinkCreate {
                 prog_fd: fd as u32,
-                attach_type: BPF_XDP,
                 target_fd: ifindex,
+                attach_type: BPF_XDP,
                 flags,
                 __reserved: 0,
             },
         },
         std::mem::size_of::<BpfAttrLinkCreate>() as u32,
     )
+}
+
+/// # Safety
+/// `prog_fd` must be a BPF program file descriptor owned by the caller; the returned link fd is the
+/// caller's to close.
+pub unsafe fn bpf_link_create(prog_fd: i32, target_fd: u32, attach_type: BpfAttachType, flags: u32) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.link_create = BpfAttrLinkCreate {
+        prog_fd: prog_fd as u32,
+        target_fd,
+        attach_type,
+        flags,
+        __reserved: 0,
+    };
+    bpf(BPF_LINK_CREATE, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `link_fd`, `new_prog_fd` and `old_prog_fd` must be BPF file descriptors owned by the caller.
+pub unsafe fn bpf_link_update(link_fd: i32, new_prog_fd: i32, old_prog_fd: Option<i32>) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.link_update = BpfAttrLinkUpdate {
+        link_fd: link_fd as u32,
+        new_prog_fd: new_prog_fd as u32,
+        flags: if old_prog_fd.is_some() { BPF_F_REPLACE } else { 0 },
+        old_prog_fd: old_prog_fd.unwrap_or(0) as u32,
+    };
+    bpf(BPF_LINK_UPDATE, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `link_fd` must be a BPF link file descriptor owned by the caller; it stays open after the detach.
+pub unsafe fn bpf_link_detach(link_fd: i32) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.link_detach = BpfAttrLinkDetach { link_fd: link_fd as u32 };
+    bpf(BPF_LINK_DETACH, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `fd` must be a BPF object file descriptor owned by the caller and `pathname` a valid NUL-terminated
+/// string that outlives the call.
+pub unsafe fn bpf_obj_pin(fd: i32, pathname: *const c_char) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.obj.pathname = pathname as u64;
+    attr.obj.bpf_fd = fd as u32;
+    bpf(BPF_OBJ_PIN, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `pathname` must be a valid NUL-terminated string that outlives the call; the returned fd is the
+/// caller's to close.
+pub unsafe fn bpf_obj_get(pathname: *const c_char, file_flags: u32) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.obj.pathname = pathname as u64;
+    attr.obj.file_flags = file_flags;
+    bpf(BPF_OBJ_GET, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `map_fd` must be a BPF map file descriptor owned by the caller; `key` and `value` must point to
+/// readable buffers of the map's key size and value size.
+pub unsafe fn bpf_map_update_elem(map_fd: i32, key: *const u8, value: *const u8, flags: u64) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.elem.map_fd = map_fd as u32;
+    attr.elem.key = key as u64;
+    attr.elem._inner.value = value as u64;
+    attr.elem.flags = flags;
+    bpf(BPF_MAP_UPDATE_ELEM, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `map_fd` must be a BPF map file descriptor owned by the caller; `key` must point to a readable
+/// buffer of the map's key size and `value` to a writable one of its value size.
+pub unsafe fn bpf_map_lookup_elem(map_fd: i32, key: *const u8, value: *mut u8) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.elem.map_fd = map_fd as u32;
+    attr.elem.key = key as u64;
+    attr.elem._inner.value = value as u64;
+    bpf(BPF_MAP_LOOKUP_ELEM, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `map_fd` must be a BPF map file descriptor owned by the caller and `key` must point to a readable
+/// buffer of the map's key size.
+pub unsafe fn bpf_map_delete_elem(map_fd: i32, key: *const u8) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.elem.map_fd = map_fd as u32;
+    attr.elem.key = key as u64;
+    bpf(BPF_MAP_DELETE_ELEM, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// `fd` must be a BPF object file descriptor owned by the caller and `info` must point to a writable
+/// buffer of `info_len` bytes laid out as the kernel's info struct for that object type.
+pub unsafe fn bpf_obj_get_info_by_fd(fd: i32, info: *mut u8, info_len: u32) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.info = BpfAttrInfo { bpf_fd: fd as u32, info_len, info: info as u64 };
+    bpf(BPF_OBJ_GET_INFO_BY_FD, &attr, std::mem::size_of::<bpf_attr>() as u32)
+}
+
+/// # Safety
+/// Needs no pointer; unsafe like the other wrappers because the returned fd is the caller's to close.
+pub unsafe fn bpf_prog_get_fd_by_id(id: u32) -> i32 {
+    let mut attr: bpf_attr = std::mem::zeroed();
+    attr.get_id.id = id;
+    bpf(BPF_PROG_GET_FD_BY_ID, &attr, std::mem::size_of::<bpf_attr>() as u32)
 }
\ No newline at end of file
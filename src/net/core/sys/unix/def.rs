@@ -21,6 +21,10 @@ pub const SO_RCVBUF: c_int = 8;
 pub const SO_SNDBUF: c_int = 7;
 pub const SO_RCVTIMEO: c_int = 20;
 pub const SO_SNDTIMEO: c_int = 21;
+pub const SO_ATTACH_FILTER: c_int = 26;
+pub const SO_DETACH_FILTER: c_int = 27;
+
+pub const MSG_WAITFORONE: c_int = 0x10000;
 
 pub const XDP_MMAP_OFFSETS: c_int = 1;
 pub const XDP_RX_RING: c_int = 2;
@@ -80,6 +84,11 @@ pub const IPPROTO_QUIC: c_int = 261;
 
 pub const TCP_NODELAY: c_int = 1;
 
+pub const ENOENT: c_int = 2;
+pub const EINVAL: c_int = 22;
+pub const EOPNOTSUPP: c_int = 95;
+pub const ENOTSUPP: c_int = 524; // kernel-internal, leaks out of some XDP drivers
+
 pub const F_GETFL: c_int = 3;
 pub const O_NONBLOCK: c_int = 0o4000;
 
@@ -187,6 +196,22 @@ pub struct XdpMmapOffsets {
     pub cr: XdpRingOffset,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SockFprog {
+    pub len: u16,
+    pub filter: *const SockFilter,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct TimeVal {
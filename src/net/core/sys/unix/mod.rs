@@ -86,7 +86,7 @@ impl ToIpv4Addr for SockAddrIn {
     fn to_socket_addr_v4(&self) -> std::net::SocketAddrV4 {
         std::net::SocketAddrV4::new(
             self.to_ipv4_addr(),
-            self.sin_port,
+            u16::from_be(self.sin_port),
         )
     }
 }
@@ -101,7 +101,7 @@ impl ToIpv6Addr for SockAddrIn6 {
     fn to_socket_addr_v6(&self) -> std::net::SocketAddrV6 {
         std::net::SocketAddrV6::new(
             self.to_ipv6_addr(),
-            self.sin6_port,
+            u16::from_be(self.sin6_port),
             self.sin6_flowinfo,
             self.sin6_scope_id,
         )
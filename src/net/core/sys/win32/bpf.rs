@@ -89,6 +89,34 @@ pub const __MAX_BPF_PROG_TYPE: BpfProgType = BpfProgType(33);
 
 pub const BPF_OBJ_NAME_LEN: usize = 16;
 
+pub const BPF_F_REPLACE: u32 = 1 << 2;
+pub const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+pub const BPF_ANY: u64 = 0;
+pub const BPF_NOEXIST: u64 = 1;
+pub const BPF_EXIST: u64 = 2;
+
+pub const BPF_MAP_TYPE_UNSPEC: u32 = 0;
+pub const BPF_MAP_TYPE_HASH: u32 = 1;
+pub const BPF_MAP_TYPE_ARRAY: u32 = 2;
+pub const BPF_MAP_TYPE_PROG_ARRAY: u32 = 3;
+pub const BPF_MAP_TYPE_PERF_EVENT_ARRAY: u32 = 4;
+pub const BPF_MAP_TYPE_PERCPU_HASH: u32 = 5;
+pub const BPF_MAP_TYPE_PERCPU_ARRAY: u32 = 6;
+pub const BPF_MAP_TYPE_STACK_TRACE: u32 = 7;
+pub const BPF_MAP_TYPE_CGROUP_ARRAY: u32 = 8;
+pub const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+pub const BPF_MAP_TYPE_LRU_PERCPU_HASH: u32 = 10;
+pub const BPF_MAP_TYPE_LPM_TRIE: u32 = 11;
+pub const BPF_MAP_TYPE_ARRAY_OF_MAPS: u32 = 12;
+pub const BPF_MAP_TYPE_HASH_OF_MAPS: u32 = 13;
+pub const BPF_MAP_TYPE_DEVMAP: u32 = 14;
+pub const BPF_MAP_TYPE_SOCKMAP: u32 = 15;
+pub const BPF_MAP_TYPE_CPUMAP: u32 = 16;
+pub const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+pub const BPF_MAP_TYPE_SOCKHASH: u32 = 18;
+pub const BPF_MAP_TYPE_DEVMAP_HASH: u32 = 25;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct BpfAttachType(pub u32);
@@ -167,8 +195,8 @@ pub union bpf_attr {
 #[derive(Copy,Clone)]
 pub struct BpfAttrLinkCreate {
     pub prog_fd: u32,
-    pub attach_type: BpfAttachType,
     pub target_fd: u32,
+    pub attach_type: BpfAttachType,
     pub flags: u32,
     pub __reserved: u64,
 }
@@ -308,8 +336,8 @@ pub unsafe fn bpf_set_link_xdp_fd(ifindex: u32, fd: i32, flags: u32) -> i32 {
         &bpf_attr {
             link_create: BpfAttrLinkCreate {
                 prog_fd: fd as u32,
-                attach_type: BPF_XDP,
                 target_fd: ifindex,
+                attach_type: BPF_XDP,
                 flags,
                 __reserved: 0,
             },
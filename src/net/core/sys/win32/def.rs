@@ -129,6 +129,16 @@ pub struct SockAddrIn6 {
     pub sin6_scope_id: u32,
 }
 
+// Linux classic BPF layout; Winsock has no socket filters, kept so filters can still be compiled
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
 #[repr(C)]
 pub struct TimeVal {
     pub tv_sec: i64,
@@ -30,6 +30,27 @@ pub fn xsetsockopt<T>(sock: Socket, level: i32, optname: i32, optval: &T, optlen
     if result == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
 }
 
+#[cfg(unix)]
+pub fn xattach_filter(sock: Socket, filter: &[sys::SockFilter]) -> Result<(), Error> {
+    let prog = sys::SockFprog { len: filter.len() as u16, filter: filter.as_ptr() };
+    xsetsockopt(sock, sys::SOL_SOCKET, sys::SO_ATTACH_FILTER, &prog, std::mem::size_of::<sys::SockFprog>() as i32)
+}
+
+#[cfg(unix)]
+pub fn xdetach_filter(sock: Socket) -> Result<(), Error> {
+    xsetsockopt(sock, sys::SOL_SOCKET, sys::SO_DETACH_FILTER, &0i32, std::mem::size_of::<i32>() as i32)
+}
+
+#[cfg(windows)]
+pub fn xattach_filter(_sock: Socket, _filter: &[sys::SockFilter]) -> Result<(), Error> {
+    Err(Error::new(std::io::ErrorKind::Unsupported, "Socket filters are not supported on Windows"))
+}
+
+#[cfg(windows)]
+pub fn xdetach_filter(_sock: Socket) -> Result<(), Error> {
+    Err(Error::new(std::io::ErrorKind::Unsupported, "Socket filters are not supported on Windows"))
+}
+
 #[inline(always)]
 pub fn xbind<A: ToSocketAddrs>(sock: Socket, addr: A) -> Result<(), Error> {
     let addr = addr.to_socket_addrs().ok();
@@ -91,11 +112,12 @@ pub fn xsend(sock: Socket, buf: &[u8], flags: i32) -> Result<usize, Error> {
 }
 
 
+/* recv(2) returns the byte count, 0 included, or -1; a 1-byte read is not an error. */
 #[inline(always)]
 pub fn xrecv(sock: Socket, buf: &mut [u8], flags: i32) -> Result<usize, Error> {
     unsafe {
         let result = sys::recv(sock.as_raw(), buf.as_mut_ptr() as *mut _, buf.len() as std::os::raw::c_int, flags);
-        if result != 1 { Ok(result as usize) } else { Err(std::io::Error::last_os_error()) }
+        if result >= 0 { Ok(result as usize) } else { Err(std::io::Error::last_os_error()) }
     }
 }
 
@@ -0,0 +1,88 @@
+use std::fs;
+use std::io::Result;
+use super::Elf;
+
+/* An ELF object that owns its bytes; `elf()` borrows a parsed view for as long as the file lives. */
+pub struct ElfFile {
+    data: Vec<u8>,
+}
+
+impl ElfFile {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::from_vec(fs::read(path)?)
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> Result<Self> {
+        Elf::load_from_bytes(&data)?;
+        Ok(Self { data })
+    }
+
+    pub fn elf(&self) -> Elf<'_> {
+        Elf::load_from_bytes(&self.data).expect("validated when the file was opened")
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(unix)]
+pub use mapped::MappedElfFile;
+
+#[cfg(unix)]
+mod mapped {
+    use std::fs::File;
+    use std::io::{Error, ErrorKind, Result};
+    use std::os::fd::AsRawFd;
+    use std::os::raw::c_void;
+    use crate::net::{mmap, munmap, MAP_FAILED, MAP_PRIVATE, PROT_READ};
+    use super::Elf;
+
+    /*
+    A read-only private mapping of an ELF object, unmapped on drop.
+    Pages are only faulted in as sections are read; the file must not be truncated while mapped.
+    */
+    pub struct MappedElfFile {
+        addr: *mut c_void,
+        len: usize,
+    }
+
+    // The mapping is read-only and owned exclusively by this value.
+    unsafe impl Send for MappedElfFile {}
+    unsafe impl Sync for MappedElfFile {}
+
+    impl MappedElfFile {
+        pub fn open(path: &str) -> Result<Self> {
+            let file = File::open(path)?;
+            let len = usize::try_from(file.metadata()?.len()).map_err(|_| Error::new(ErrorKind::InvalidData, "file too large to map"))?;
+            if len == 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "not an ELF file"));
+            }
+            let addr = unsafe { mmap(std::ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+            if addr == MAP_FAILED {
+                return Err(Error::last_os_error());
+            }
+            let mapped = Self { addr, len };
+            Elf::load_from_bytes(mapped.data())?;
+            Ok(mapped)
+        }
+
+        pub fn elf(&self) -> Elf<'_> {
+            Elf::load_from_bytes(self.data()).expect("validated when the file was mapped")
+        }
+
+        pub fn data(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for MappedElfFile {
+        fn drop(&mut self) {
+            unsafe { munmap(self.addr, self.len); }
+        }
+    }
+}
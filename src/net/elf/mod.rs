@@ -1,2 +1,4 @@
 pub mod parser;
-pub use parser::*;
\ No newline at end of file
+pub use parser::*;
+pub mod file;
+pub use file::*;
\ No newline at end of file
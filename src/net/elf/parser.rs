@@ -1,5 +1,42 @@
-use std::fs::File;
-use std::io::Read;
+use std::io::{Error, ErrorKind, Result};
+
+/*
+ELF reader for 32/64-bit, little/big-endian objects.
+
+Every structure is decoded field by field with the file's byte order and widened to the Elf64 layout,
+and every offset is bounds checked, so malformed input yields an error instead of a panic.
+*/
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOTE: u32 = 7;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHT_DYNSYM: u32 = 11;
+
+pub const PT_NULL: u32 = 0;
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+pub const PT_NOTE: u32 = 4;
+
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_XINDEX: u16 = 0xffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfEndian {
+    Little,
+    Big,
+}
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -35,6 +72,19 @@ pub struct Elf64SectionHeader {
     pub sh_entsize:   u64,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Elf64ProgramHeader {
+    pub p_type:   u32,
+    pub p_flags:  u32,
+    pub p_offset: u64,
+    pub p_vaddr:  u64,
+    pub p_paddr:  u64,
+    pub p_filesz: u64,
+    pub p_memsz:  u64,
+    pub p_align:  u64,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Elf64Rel {
@@ -62,105 +112,514 @@ pub struct Elf64Sym {
     pub st_size:  u64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolBinding {
+    Local,
+    Global,
+    Weak,
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    NoType,
+    Object,
+    Func,
+    Section,
+    File,
+    Common,
+    Tls,
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Section<'a> {
+    pub index: usize,
+    pub name: &'a str,
+    pub header: Elf64SectionHeader,
+    pub data: &'a [u8], // empty for SHT_NOBITS
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol<'a> {
+    pub index: usize,
+    pub name: &'a str,
+    pub value: u64,
+    pub size: u64,
+    pub binding: SymbolBinding,
+    pub kind: SymbolType,
+    pub other: u8,
+    pub shndx: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub offset: u64,
+    pub symbol: u32,
+    pub kind: u32,
+    pub addend: Option<i64>, // only for SHT_RELA
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Note<'a> {
+    pub name: &'a [u8], // without the trailing NUL
+    pub kind: u32,
+    pub desc: &'a [u8],
+}
+
 pub struct Elf<'a> {
     pub data: &'a [u8],
+    pub class: ElfClass,
+    pub endian: ElfEndian,
     pub header: Elf64Header,
     pub section_headers: Vec<Elf64SectionHeader>,
+    pub program_headers: Vec<Elf64ProgramHeader>,
 }
 
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    class: ElfClass,
+    endian: ElfEndian,
+}
 
-impl<'a> Elf<'a> {
-    pub fn load_from_file(path: &str) -> std::io::Result<Self> {
-        let mut f = File::open(path)?;
-        let mut buf = Vec::new();
-        f.read_to_end(&mut buf)?;
-        let data = buf.leak(); // leaks for 'static lifetime; ok for short-lived tool
-        Elf::load_from_bytes(data)
+impl<'a> Reader<'a> {
+    fn bytes(&self, off: usize, len: usize) -> Result<&'a [u8]> {
+        off.checked_add(len)
+            .and_then(|end| self.data.get(off..end))
+            .ok_or_else(|| invalid("ELF offset out of bounds"))
     }
 
-    pub fn load_from_bytes(data: &'a [u8]) -> std::io::Result<Self> {
-        if data.len() < std::mem::size_of::<Elf64Header>() {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "file too small"));
+    fn u8(&self, off: usize) -> Result<u8> {
+        Ok(self.bytes(off, 1)?[0])
+    }
+
+    fn u16(&self, off: usize) -> Result<u16> {
+        let b: [u8; 2] = self.bytes(off, 2)?.try_into().unwrap();
+        Ok(match self.endian { ElfEndian::Little => u16::from_le_bytes(b), ElfEndian::Big => u16::from_be_bytes(b) })
+    }
+
+    fn u32(&self, off: usize) -> Result<u32> {
+        let b: [u8; 4] = self.bytes(off, 4)?.try_into().unwrap();
+        Ok(match self.endian { ElfEndian::Little => u32::from_le_bytes(b), ElfEndian::Big => u32::from_be_bytes(b) })
+    }
+
+    fn u64(&self, off: usize) -> Result<u64> {
+        let b: [u8; 8] = self.bytes(off, 8)?.try_into().unwrap();
+        Ok(match self.endian { ElfEndian::Little => u64::from_le_bytes(b), ElfEndian::Big => u64::from_be_bytes(b) })
+    }
+
+    /* Address/offset sized field: 4 bytes in ELF32, 8 in ELF64. */
+    fn word(&self, off: usize) -> Result<u64> {
+        match self.class {
+            ElfClass::Elf32 => self.u32(off).map(u64::from),
+            ElfClass::Elf64 => self.u64(off),
         }
+    }
+
+    fn is64(&self) -> bool {
+        self.class == ElfClass::Elf64
+    }
 
-        let header: Elf64Header = unsafe { std::ptr::read_unaligned(data.as_ptr() as *const Elf64Header) };
+    fn header(&self) -> Result<Elf64Header> {
+        let mut e_ident = [0u8; 16];
+        e_ident.copy_from_slice(self.bytes(0, 16)?);
+        let (w, o) = if self.is64() { (8, 0) } else { (4, 12) }; // ELF32 has three 4-byte words instead of 8
+        Ok(Elf64Header {
+            e_ident,
+            e_type: self.u16(16)?,
+            e_machine: self.u16(18)?,
+            e_version: self.u32(20)?,
+            e_entry: self.word(24)?,
+            e_phoff: self.word(24 + w)?,
+            e_shoff: self.word(24 + 2 * w)?,
+            e_flags: self.u32(48 - o)?,
+            e_ehsize: self.u16(52 - o)?,
+            e_phentsize: self.u16(54 - o)?,
+            e_phnum: self.u16(56 - o)?,
+            e_shentsize: self.u16(58 - o)?,
+            e_shnum: self.u16(60 - o)?,
+            e_shstrndx: self.u16(62 - o)?,
+        })
+    }
 
-        if &header.e_ident[0..4] != b"\x7FELF" {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not an ELF file"));
+    fn section_header(&self, off: usize) -> Result<Elf64SectionHeader> {
+        self.bytes(off, if self.is64() { 64 } else { 40 })?; // after this `off + n` cannot overflow
+        if self.is64() {
+            Ok(Elf64SectionHeader {
+                sh_name: self.u32(off)?,
+                sh_type: self.u32(off + 4)?,
+                sh_flags: self.u64(off + 8)?,
+                sh_addr: self.u64(off + 16)?,
+                sh_offset: self.u64(off + 24)?,
+                sh_size: self.u64(off + 32)?,
+                sh_link: self.u32(off + 40)?,
+                sh_info: self.u32(off + 44)?,
+                sh_addralign: self.u64(off + 48)?,
+                sh_entsize: self.u64(off + 56)?,
+            })
+        } else {
+            Ok(Elf64SectionHeader {
+                sh_name: self.u32(off)?,
+                sh_type: self.u32(off + 4)?,
+                sh_flags: self.u32(off + 8)? as u64,
+                sh_addr: self.u32(off + 12)? as u64,
+                sh_offset: self.u32(off + 16)? as u64,
+                sh_size: self.u32(off + 20)? as u64,
+                sh_link: self.u32(off + 24)?,
+                sh_info: self.u32(off + 28)?,
+                sh_addralign: self.u32(off + 32)? as u64,
+                sh_entsize: self.u32(off + 36)? as u64,
+            })
         }
-        if header.e_ident[4] != 2 {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not 64-bit ELF"));
+    }
+
+    fn program_header(&self, off: usize) -> Result<Elf64ProgramHeader> {
+        self.bytes(off, if self.is64() { 56 } else { 32 })?;
+        if self.is64() {
+            Ok(Elf64ProgramHeader {
+                p_type: self.u32(off)?,
+                p_flags: self.u32(off + 4)?,
+                p_offset: self.u64(off + 8)?,
+                p_vaddr: self.u64(off + 16)?,
+                p_paddr: self.u64(off + 24)?,
+                p_filesz: self.u64(off + 32)?,
+                p_memsz: self.u64(off + 40)?,
+                p_align: self.u64(off + 48)?,
+            })
+        } else {
+            Ok(Elf64ProgramHeader {
+                p_type: self.u32(off)?,
+                p_offset: self.u32(off + 4)? as u64,
+                p_vaddr: self.u32(off + 8)? as u64,
+                p_paddr: self.u32(off + 12)? as u64,
+                p_filesz: self.u32(off + 16)? as u64,
+                p_memsz: self.u32(off + 20)? as u64,
+                p_flags: self.u32(off + 24)?,
+                p_align: self.u32(off + 28)? as u64,
+            })
         }
+    }
 
-        let shoff = header.e_shoff as usize;
-        let shent = header.e_shentsize as usize;
-        let shnum = header.e_shnum as usize;
-
-        let end = shoff.checked_add(shent * shnum).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::InvalidData, "overflow in section header math")
-        })?;
-        if end > data.len() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "section header table out of bounds",
-            ));
+    fn sym(&self, off: usize) -> Result<Elf64Sym> {
+        self.bytes(off, if self.is64() { 24 } else { 16 })?;
+        if self.is64() {
+            Ok(Elf64Sym {
+                st_name: self.u32(off)?,
+                st_info: self.u8(off + 4)?,
+                st_other: self.u8(off + 5)?,
+                st_shndx: self.u16(off + 6)?,
+                st_value: self.u64(off + 8)?,
+                st_size: self.u64(off + 16)?,
+            })
+        } else {
+            Ok(Elf64Sym {
+                st_name: self.u32(off)?,
+                st_value: self.u32(off + 4)? as u64,
+                st_size: self.u32(off + 8)? as u64,
+                st_info: self.u8(off + 12)?,
+                st_other: self.u8(off + 13)?,
+                st_shndx: self.u16(off + 14)?,
+            })
+        }
+    }
+
+    fn relocation(&self, off: usize, rela: bool) -> Result<Relocation> {
+        self.bytes(off, self.word_size() * if rela { 3 } else { 2 })?;
+        let info = self.word(off + self.word_size())?;
+        let (symbol, kind) = if self.is64() {
+            ((info >> 32) as u32, info as u32)
+        } else {
+            ((info >> 8) as u32, (info & 0xff) as u32)
+        };
+        let addend = if !rela { None } else if self.is64() {
+            Some(self.u64(off + 16)? as i64)
+        } else {
+            Some(self.u32(off + 8)? as i32 as i64)
+        };
+        Ok(Relocation { offset: self.word(off)?, symbol, kind, addend })
+    }
+
+    fn word_size(&self) -> usize {
+        if self.is64() { 8 } else { 4 }
+    }
+}
+
+impl<'a> Elf<'a> {
+    pub fn load_from_bytes(data: &'a [u8]) -> std::io::Result<Self> {
+        if data.len() < 16 || &data[0..4] != b"\x7FELF" {
+            return Err(invalid("not an ELF file"));
+        }
+        let class = match data[4] {
+            1 => ElfClass::Elf32,
+            2 => ElfClass::Elf64,
+            _ => return Err(invalid("unknown ELF class")),
+        };
+        let endian = match data[5] {
+            1 => ElfEndian::Little,
+            2 => ElfEndian::Big,
+            _ => return Err(invalid("unknown ELF data encoding")),
+        };
+        let reader = Reader { data, class, endian };
+        let header = reader.header()?;
+
+        let (shdr_size, phdr_size) = match class {
+            ElfClass::Elf32 => (40, 32),
+            ElfClass::Elf64 => (64, 56),
+        };
+
+        let mut section_headers = Vec::new();
+        if header.e_shoff != 0 {
+            let shoff = to_usize(header.e_shoff)?;
+            let shent = header.e_shentsize as usize;
+            if shent < shdr_size {
+                return Err(invalid("section header entry size too small"));
+            }
+            // With extended numbering the real count lives in the first section header
+            let shnum = match header.e_shnum {
+                0 => to_usize(reader.section_header(shoff)?.sh_size)?,
+                n => n as usize,
+            };
+            table_bounds(data, shoff, shent, shnum, "section header table out of bounds")?;
+            section_headers.reserve(shnum);
+            for i in 0..shnum {
+                section_headers.push(reader.section_header(shoff + i * shent)?);
+            }
+            for sh in section_headers.iter() {
+                if sh.sh_type != SHT_NOBITS && sh.sh_type != SHT_NULL {
+                    range(data, sh.sh_offset, sh.sh_size).ok_or_else(|| invalid("section data out of bounds"))?;
+                }
+            }
         }
 
-        let mut section_headers = Vec::with_capacity(shnum);
-        for i in 0..shnum {
-            let off = shoff + i * shent;
-            let sh: Elf64SectionHeader =
-                unsafe { std::ptr::read_unaligned(data[off..].as_ptr() as *const Elf64SectionHeader) };
-            section_headers.push(sh);
+        let mut program_headers = Vec::new();
+        if header.e_phoff != 0 && header.e_phnum != 0 {
+            let phoff = to_usize(header.e_phoff)?;
+            let phent = header.e_phentsize as usize;
+            if phent < phdr_size {
+                return Err(invalid("program header entry size too small"));
+            }
+            let phnum = header.e_phnum as usize;
+            table_bounds(data, phoff, phent, phnum, "program header table out of bounds")?;
+            program_headers.reserve(phnum);
+            for i in 0..phnum {
+                program_headers.push(reader.program_header(phoff + i * phent)?);
+            }
         }
 
-        Ok(Elf { data, header, section_headers })
+        Ok(Elf { data, class, endian, header, section_headers, program_headers })
+    }
+
+    fn reader(&self) -> Reader<'a> {
+        Reader { data: self.data, class: self.class, endian: self.endian }
+    }
+
+    fn shstrndx(&self) -> usize {
+        match self.header.e_shstrndx {
+            SHN_XINDEX => self.section_headers.first().map(|sh| sh.sh_link as usize).unwrap_or(0),
+            index => index as usize,
+        }
     }
 
     pub fn get_strtab_header(&self) -> Option<&Elf64SectionHeader> {
-        let strtab_index = self.header.e_shstrndx as usize;
-        if strtab_index < self.section_headers.len() {
-            return Some(&self.section_headers[strtab_index]);
+        self.section_headers.get(self.shstrndx())
+    }
+
+    pub fn get_strtab_data(&self) -> Option<&'a [u8]> {
+        self.section_data(self.get_strtab_header()?)
+    }
+
+    pub fn get_section_header_by_name(&self, name: &str) -> Option<&Elf64SectionHeader> {
+        let index = self.section_by_name(name)?.index;
+        self.section_headers.get(index)
+    }
+
+    pub fn get_section_data_by_name(&self, name: &str) -> Option<&'a [u8]> {
+        self.section_by_name(name).map(|section| section.data)
+    }
+
+    /* Bytes of a section in the file; `None` for headers not belonging to this file. */
+    pub fn section_data(&self, header: &Elf64SectionHeader) -> Option<&'a [u8]> {
+        if header.sh_type == SHT_NOBITS {
+            return Some(&[]);
         }
-        None
+        range(self.data, header.sh_offset, header.sh_size).map(|r| &self.data[r])
+    }
+
+    pub fn section(&self, index: usize) -> Option<Section<'a>> {
+        let header = *self.section_headers.get(index)?;
+        let names = self.get_strtab_data().unwrap_or(&[]);
+        Some(Section {
+            index,
+            name: c_str(names, header.sh_name as usize),
+            header,
+            data: self.section_data(&header).unwrap_or(&[]),
+        })
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = Section<'a>> + '_ {
+        (0..self.section_headers.len()).filter_map(move |i| self.section(i))
+    }
+
+    pub fn section_by_name(&self, name: &str) -> Option<Section<'a>> {
+        self.sections().find(|section| section.name == name)
     }
 
-    pub fn get_strtab_data(&self) -> Option<&[u8]> {
-        let strtab = &self.get_strtab_header()?;
-        let start = strtab.sh_offset as usize;
-        let end = start + strtab.sh_size as usize;
-        if end <= self.data.len() {
-            return Some(&self.data[start..end]);
+    /* The static symbol table, falling back to the dynamic one. */
+    pub fn symbol_table(&self) -> Option<Section<'a>> {
+        self.sections().find(|s| s.header.sh_type == SHT_SYMTAB)
+            .or_else(|| self.sections().find(|s| s.header.sh_type == SHT_DYNSYM))
+    }
+
+    /* Symbols of a SHT_SYMTAB/SHT_DYNSYM section, names resolved through its sh_link string table. */
+    pub fn symbols(&self, table: &Section<'a>) -> Result<impl Iterator<Item = Symbol<'a>> + '_> {
+        let symtab = self.symtab(table)?;
+        Ok((0..symtab.count).filter_map(move |index| symtab.get(index)))
+    }
+
+    pub fn symbol(&self, table: &Section<'a>, index: usize) -> Option<Symbol<'a>> {
+        self.symtab(table).ok()?.get(index)
+    }
+
+    fn symtab(&self, table: &Section<'a>) -> Result<SymbolTable<'a>> {
+        if table.header.sh_type != SHT_SYMTAB && table.header.sh_type != SHT_DYNSYM {
+            return Err(invalid("not a symbol table section"));
         }
-        None
+        let entsize = entry_size(&table.header, if self.class == ElfClass::Elf64 { 24 } else { 16 })?;
+        Ok(SymbolTable {
+            reader: Reader { data: table.data, ..self.reader() },
+            names: self.section(table.header.sh_link as usize).map(|s| s.data).unwrap_or(&[]),
+            entsize,
+            count: table.data.len() / entsize,
+        })
     }
 
-    pub fn get_section_header_by_name(&self, name: &str) -> Option<&Elf64SectionHeader> {
-        let strtab = self.get_strtab_header()?;
-        let strtab_data = &self.data[strtab.sh_offset as usize..strtab.sh_offset as usize + strtab.sh_size as usize];
-        let name_b = name.as_bytes();
-        for section in self.section_headers.iter() {
-            let name_offset = section.sh_name as usize;
-            if name_offset < strtab_data.len() {
-                let section_name = &strtab_data[name_offset..];
-                if let Some(end) = section_name.iter().position(|&c| c == 0) {
-                    if &section_name[..end] == name_b {
-                        return Some(section);
-                    }
-                }
+    /* Entries of a SHT_REL or SHT_RELA section. */
+    pub fn relocations(&self, section: &Section<'a>) -> Result<impl Iterator<Item = Relocation> + '_> {
+        let rela = match section.header.sh_type {
+            SHT_REL => false,
+            SHT_RELA => true,
+            _ => return Err(invalid("not a relocation section")),
+        };
+        let word = self.reader().word_size();
+        let entsize = entry_size(&section.header, if rela { 3 * word } else { 2 * word })?;
+        let reader = Reader { data: section.data, ..self.reader() };
+        let count = section.data.len() / entsize;
+        Ok((0..count).filter_map(move |i| reader.relocation(i * entsize, rela).ok()))
+    }
+
+    pub fn program_data(&self, header: &Elf64ProgramHeader) -> Option<&'a [u8]> {
+        range(self.data, header.p_offset, header.p_filesz).map(|r| &self.data[r])
+    }
+
+    /* Notes of a SHT_NOTE section or PT_NOTE segment body. Stops at the first malformed entry. */
+    pub fn notes(&self, data: &'a [u8], align: u64) -> impl Iterator<Item = Note<'a>> {
+        let reader = Reader { data, ..self.reader() };
+        let align = if align == 8 { 8 } else { 4 };
+        let mut off = 0usize;
+        std::iter::from_fn(move || {
+            if off >= data.len() {
+                return None;
             }
+            let namesz = reader.u32(off).ok()? as usize;
+            let descsz = reader.u32(off + 4).ok()? as usize;
+            let kind = reader.u32(off + 8).ok()?;
+            let name_off = off + 12;
+            let desc_off = name_off.checked_add(align_up(namesz, align)?)?;
+            let name = reader.bytes(name_off, namesz).ok()?;
+            let desc = reader.bytes(desc_off, descsz).ok()?;
+            off = desc_off.checked_add(align_up(descsz, align)?)?;
+            let name = name.strip_suffix(&[0]).unwrap_or(name);
+            Some(Note { name, kind, desc })
+        })
+    }
+}
+
+struct SymbolTable<'a> {
+    reader: Reader<'a>,
+    names: &'a [u8],
+    entsize: usize,
+    count: usize,
+}
+
+impl<'a> SymbolTable<'a> {
+    fn get(&self, index: usize) -> Option<Symbol<'a>> {
+        if index >= self.count {
+            return None;
         }
-        None
+        let sym = self.reader.sym(index * self.entsize).ok()?;
+        Some(Symbol {
+            index,
+            name: c_str(self.names, sym.st_name as usize),
+            value: sym.st_value,
+            size: sym.st_size,
+            binding: SymbolBinding::from_info(sym.st_info),
+            kind: SymbolType::from_info(sym.st_info),
+            other: sym.st_other,
+            shndx: sym.st_shndx,
+        })
     }
+}
 
-    pub fn get_section_data_by_name(&self, name: &str) -> Option<&[u8]> {
-        if let Some(section) = self.get_section_header_by_name(name) {
-            let start = section.sh_offset as usize;
-            let end = start + section.sh_size as usize;
-            if end <= self.data.len() {
-                return Some(&self.data[start..end]);
-            }
+impl SymbolBinding {
+    pub fn from_info(info: u8) -> Self {
+        match info >> 4 {
+            0 => SymbolBinding::Local,
+            1 => SymbolBinding::Global,
+            2 => SymbolBinding::Weak,
+            other => SymbolBinding::Other(other),
+        }
+    }
+}
+
+impl SymbolType {
+    pub fn from_info(info: u8) -> Self {
+        match info & 0xf {
+            0 => SymbolType::NoType,
+            1 => SymbolType::Object,
+            2 => SymbolType::Func,
+            3 => SymbolType::Section,
+            4 => SymbolType::File,
+            5 => SymbolType::Common,
+            6 => SymbolType::Tls,
+            other => SymbolType::Other(other),
         }
-        None
     }
-}
\ No newline at end of file
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn to_usize(value: u64) -> Result<usize> {
+    usize::try_from(value).map_err(|_| invalid("ELF offset does not fit in memory"))
+}
+
+fn range(data: &[u8], offset: u64, size: u64) -> Option<std::ops::Range<usize>> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(size).ok()?)?;
+    (end <= data.len()).then_some(start..end)
+}
+
+fn table_bounds(data: &[u8], offset: usize, entsize: usize, count: usize, msg: &str) -> Result<()> {
+    let end = entsize.checked_mul(count).and_then(|len| offset.checked_add(len)).ok_or_else(|| invalid(msg))?;
+    if end > data.len() { Err(invalid(msg)) } else { Ok(()) }
+}
+
+fn entry_size(header: &Elf64SectionHeader, min: usize) -> Result<usize> {
+    match header.sh_entsize {
+        0 => Ok(min),
+        n if (n as usize) < min => Err(invalid("section entry size too small")),
+        n => to_usize(n),
+    }
+}
+
+fn align_up(value: usize, align: usize) -> Option<usize> {
+    value.checked_add(align - 1).map(|v| v & !(align - 1))
+}
+
+fn c_str(table: &[u8], offset: usize) -> &str {
+    let bytes = table.get(offset..).unwrap_or(&[]);
+    let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or("")
+}
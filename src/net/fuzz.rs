@@ -0,0 +1,65 @@
+use crate::net::xdp::BpfObject;
+use crate::net::{open_initial_packet, Elf, QuicFrame, QuicFrames, QuicLongHeader, QuicPacketHeader, QuicPackets, PT_NOTE, SHT_DYNSYM, SHT_NOTE, SHT_REL, SHT_RELA, SHT_SYMTAB};
+
+/*
+Parser walks shared by the fuzz targets (fuzz/fuzz_targets) and the regression corpus test, so both
+check the same code. Every input is acceptable; a panic or a failed assertion is the bug.
+*/
+
+/* Parses `data` as an ELF object and as an XDP object, and walks every section, symbol, relocation and note. */
+pub fn walk_elf(data: &[u8]) {
+    let _ = BpfObject::parse(data, ".maps");
+    let Ok(elf) = Elf::load_from_bytes(data) else { return };
+    let symtab = elf.symbol_table();
+    for section in elf.sections() {
+        match section.header.sh_type {
+            SHT_SYMTAB | SHT_DYNSYM => if let Ok(symbols) = elf.symbols(&section) { symbols.for_each(drop) },
+            SHT_REL | SHT_RELA => if let Ok(relocations) = elf.relocations(&section) {
+                for rel in relocations {
+                    if let Some(symtab) = &symtab { let _ = elf.symbol(symtab, rel.symbol as usize); }
+                }
+            },
+            SHT_NOTE => elf.notes(section.data, section.header.sh_addralign).for_each(drop),
+            _ => {}
+        }
+    }
+    for header in &elf.program_headers {
+        if let Some(data) = elf.program_data(header) {
+            if header.p_type == PT_NOTE { elf.notes(data, header.p_align).for_each(drop); }
+        }
+    }
+}
+
+/* Walks the coalesced packets of a datagram, then opens its Initial packets like the server's datagram loop; returns how many opened. */
+pub fn walk_quic_packets(data: &[u8]) -> usize {
+    let mut packets = QuicPackets::new(data, 8);
+    let mut walked = 0;
+    for packet in packets.by_ref() {
+        walked += packet.len();
+        let _ = packet.sample();
+        if let QuicPacketHeader::VersionNegotiation(vn) = packet.header { vn.versions().for_each(drop); }
+    }
+    assert_eq!(walked + packets.remaining().len(), data.len());
+
+    let mut datagram = data.to_vec();
+    let (mut off, mut opened) = (0, 0);
+    while off < datagram.len() {
+        let _ = QuicLongHeader::parse(&datagram[off..]);
+        let Some(initial) = open_initial_packet(&mut datagram[off..]) else { break };
+        assert!(initial.len > 0 && off + initial.len <= data.len());
+        assert!(initial.payload.end <= initial.len && initial.token.end <= initial.payload.start);
+        off += initial.len;
+        opened += 1;
+    }
+    opened
+}
+
+/* Decodes the frames of a packet payload up to the first error; each must encode back to itself. */
+pub fn walk_quic_frames(data: &[u8]) {
+    for frame in QuicFrames::new(data) {
+        let Ok(frame) = frame else { break };
+        let mut out = Vec::new();
+        frame.encode(&mut out).unwrap();
+        assert_eq!(QuicFrame::decode(&out).unwrap().0, frame);
+    }
+}
@@ -6,4 +6,6 @@ pub mod elf;
 pub use elf::*;
 pub mod xdp;
 pub mod quic;
-pub use quic::*;
\ No newline at end of file
+pub use quic::*;
+#[doc(hidden)]
+pub mod fuzz; // for the fuzz targets and the corpus test, not part of the API
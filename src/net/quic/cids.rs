@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use crate::net::connection::ConnectionId;
+
+/*
+Connection IDs of both ends after the handshake (RFC 9000 §5.1). Each end issues CIDs of its own with
+NEW_CONNECTION_ID frames and the other may send to any of them; either end retires the ones it is done with.
+*/
+
+/* How many of the peer's CIDs we keep at once, advertised as active_connection_id_limit. */
+pub const ACTIVE_CONNECTION_ID_LIMIT: u64 = 4;
+
+pub(crate) struct IssuedCid {
+    pub(crate) cid: ConnectionId,
+    pub(crate) reset_token: [u8; 16],
+}
+
+/* The CIDs we issued; sequence number 0 is the one of the handshake. */
+pub(crate) struct LocalCids {
+    issued: BTreeMap<u64, IssuedCid>,
+    next_sequence: u64,
+    limit: u64, // the peer's active_connection_id_limit
+    pending: Vec<u64>, // sequence numbers of NEW_CONNECTION_ID frames to send
+    retired: Vec<ConnectionId>, // retired by the peer, no longer routed to this connection
+}
+
+impl LocalCids {
+    pub(crate) fn new(initial: ConnectionId) -> Self {
+        let issued = BTreeMap::from([(0, IssuedCid { cid: initial, reset_token: [0; 16] })]);
+        Self { issued, next_sequence: 1, limit: 1, pending: Vec::new(), retired: Vec::new() }
+    }
+
+    /* Takes the peer's limit once its transport parameters are known; we never go beyond our own. */
+    pub(crate) fn set_limit(&mut self, limit: u64) {
+        self.limit = limit.min(ACTIVE_CONNECTION_ID_LIMIT);
+    }
+
+    /* How many more CIDs the peer would take. */
+    pub(crate) fn wanted(&self) -> usize {
+        (self.limit as usize).saturating_sub(self.issued.len())
+    }
+
+    pub(crate) fn issue(&mut self, cid: ConnectionId, reset_token: [u8; 16]) {
+        self.issued.insert(self.next_sequence, IssuedCid { cid, reset_token });
+        self.pending.push(self.next_sequence);
+        self.next_sequence += 1;
+    }
+
+    pub(crate) fn contains(&self, cid: &ConnectionId) -> bool {
+        self.issued.values().any(|issued| issued.cid == *cid)
+    }
+
+    /*
+    The peer is done with the CID of `sequence`. Retiring one we never issued, or the one the frame
+    itself came in on, is a PROTOCOL_VIOLATION (RFC 9000 §19.16).
+    */
+    pub(crate) fn on_retire(&mut self, sequence: u64, packet_dcid: &ConnectionId) -> Result<()> {
+        if sequence >= self.next_sequence {
+            return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: RETIRE_CONNECTION_ID for a CID never issued"));
+        }
+        if self.issued.get(&sequence).is_some_and(|issued| issued.cid == *packet_dcid) {
+            return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: RETIRE_CONNECTION_ID for the CID it was sent to"));
+        }
+        if let Some(issued) = self.issued.remove(&sequence) {
+            self.retired.push(issued.cid);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn take_retired(&mut self) -> Vec<ConnectionId> {
+        std::mem::take(&mut self.retired)
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /* The next CID to announce in a NEW_CONNECTION_ID frame, skipping those retired meanwhile. */
+    pub(crate) fn pop_pending(&mut self) -> Option<(u64, &IssuedCid)> {
+        while let Some(sequence) = self.pending.pop() {
+            if self.issued.contains_key(&sequence) {
+                return Some((sequence, &self.issued[&sequence]));
+            }
+        }
+        None
+    }
+
+    pub(crate) fn on_lost(&mut self, sequence: u64) {
+        if self.issued.contains_key(&sequence) {
+            self.pending.push(sequence);
+        }
+    }
+}
+
+/* The CIDs the peer issued to us; `current` is the one packets go to. */
+pub(crate) struct RemoteCids {
+    active: BTreeMap<u64, (ConnectionId, [u8; 16])>, // besides the handshake CID, which is never announced
+    current: u64,
+    retire_prior_to: u64,
+    pending_retire: Vec<u64>,
+}
+
+impl RemoteCids {
+    pub(crate) fn new() -> Self {
+        Self { active: BTreeMap::new(), current: 0, retire_prior_to: 0, pending_retire: Vec::new() }
+    }
+
+    /*
+    Takes a CID from a NEW_CONNECTION_ID frame and retires what it asks to (RFC 9000 §5.1.2, §19.15).
+    Returns the CID to send to from now on when the current one got retired.
+    */
+    pub(crate) fn on_new_connection_id(&mut self, sequence: u64, retire_prior_to: u64, cid: ConnectionId, reset_token: [u8; 16]) -> Result<Option<ConnectionId>> {
+        if sequence < self.retire_prior_to {
+            self.pending_retire.push(sequence); // retired before it arrived
+            return Ok(None);
+        }
+        match self.active.get(&sequence) {
+            Some(known) if *known != (cid, reset_token) => {
+                return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: NEW_CONNECTION_ID reuses a sequence number"));
+            }
+            Some(_) => return Ok(None),
+            None => {}
+        }
+        self.active.insert(sequence, (cid, reset_token));
+
+        if retire_prior_to > self.retire_prior_to {
+            self.retire_prior_to = retire_prior_to;
+            let retired: Vec<u64> = self.active.range(..retire_prior_to).map(|(&sequence, _)| sequence).collect();
+            for sequence in retired {
+                self.active.remove(&sequence);
+                self.pending_retire.push(sequence);
+            }
+            if self.current < retire_prior_to && !self.pending_retire.contains(&self.current) {
+                self.pending_retire.push(self.current);
+            }
+        }
+        /* the handshake CID is never announced but counts while in use */
+        let in_use = self.current >= self.retire_prior_to && !self.active.contains_key(&self.current);
+        if self.active.len() + in_use as usize > ACTIVE_CONNECTION_ID_LIMIT as usize {
+            return Err(Error::new(ErrorKind::InvalidData, "CONNECTION_ID_LIMIT_ERROR: more CIDs than active_connection_id_limit"));
+        }
+        if self.current >= self.retire_prior_to { return Ok(None); }
+        let (&next, &(cid, _)) = self.active.iter().next().expect("the new CID is active");
+        self.current = next;
+        Ok(Some(cid))
+    }
+
+    /*
+    Moves to a CID of the peer's that was never used and retires the current one, so packets on a new path
+    cannot be linked to those on the old one (RFC 9000 §9.5). None when the peer left us no spare CID.
+    */
+    pub(crate) fn rotate(&mut self) -> Option<ConnectionId> {
+        let (&next, &(cid, _)) = self.active.iter().find(|(&sequence, _)| sequence != self.current)?;
+        self.active.remove(&self.current);
+        self.pending_retire.push(self.current);
+        self.current = next;
+        Some(cid)
+    }
+
+    /* The stateless reset tokens that came with the CIDs still in use. */
+    pub(crate) fn reset_tokens(&self) -> impl Iterator<Item = &[u8; 16]> {
+        self.active.values().map(|(_, token)| token)
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending_retire.is_empty()
+    }
+
+    pub(crate) fn pop_retire(&mut self) -> Option<u64> {
+        self.pending_retire.pop()
+    }
+
+    pub(crate) fn on_retire_lost(&mut self, sequence: u64) {
+        self.pending_retire.push(sequence);
+    }
+}
@@ -1,18 +1,50 @@
+use std::io::ErrorKind;
 use std::net::{SocketAddr, ToSocketAddrs};
-use rand::RngCore;
-use ring::hkdf::{Salt, HKDF_SHA256};
-use rustls::pki_types::ServerName;
-use crate::net::{AfInet, AfInet6, IpProtoUdp, SockDgram, Socket, INITIAL_SALT};
-use crate::net::connection::QuicConnection;
-use crate::net::quic::packets::{build_initial_packet};
-use super::{QuicStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rustls::client::{ClientSessionMemoryCache, Resumption};
+use rustls::{ClientConfig, RootCertStore};
+use rustls::pki_types::{CertificateDer, ServerName};
+use crate::net::{
+    generate_connection_id, verify_retry_integrity, AfInet, AfInet6, CongestionAlgorithm, IpProtoUdp, QuicPacket, QuicPacketHeader, QuicPacketType, SoRecvTimeout,
+    SockDgram, Socket, SUPPORTED_VERSIONS,
+};
+use crate::net::connection::{ConnectionId, QuicConnection, QuicConnectionType, DEFAULT_IDLE_TIMEOUT};
+use crate::net::quic::space::SpaceId;
+use super::{QuicDatagram, QuicStream};
+
+/* How long `connect` waits for the server to confirm the handshake. */
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/* Longest wait for a datagram before timers are checked again. */
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+/* Servers whose session tickets are kept. */
+const MAX_SESSIONS: usize = 32;
+
+pub(crate) type ConnectionHandler = Box<dyn FnMut(&mut QuicConnection) + Send>;
 
 pub struct QuicClient {
     server_name: ServerName<'static>,
     addr: SocketAddr,
     socket: Socket,
+    roots: RootCertStore,
+    alpn_protocols: Vec<Vec<u8>>,
+    congestion: CongestionAlgorithm,
+    token: Option<Vec<u8>>, // from the server's NEW_TOKEN, used once by the next connect
+    versions: Vec<u32>, // in order of preference, the first is tried first
+    idle_timeout: Duration,
+    early_data: bool,
+    tls_config: Option<Arc<ClientConfig>>, // built by the first connect, its session tickets resume the next ones
     connection: Option<QuicConnection>,
-    onopen_handler: Option<Box<dyn FnMut(& mut QuicConnection) + Send>>,
+    onopen_handler: Option<ConnectionHandler>,
+    onearlydata_handler: Option<ConnectionHandler>,
+}
+
+fn udp_socket(addr: &SocketAddr) -> Socket {
+    if addr.is_ipv4() {
+        Socket::new(AfInet, SockDgram, IpProtoUdp).unwrap()
+    } else {
+        Socket::new(AfInet6, SockDgram, IpProtoUdp).unwrap()
+    }
 }
 
 impl QuicClient {
@@ -23,31 +55,180 @@ impl QuicClient {
             .unwrap()
             .next()
             .unwrap();
-        let socket = if addr.is_ipv4() {
-            Socket::new(AfInet, SockDgram, IpProtoUdp).unwrap()
-        } else {
-            Socket::new(AfInet6, SockDgram, IpProtoUdp).unwrap()
-        };
         Self {
             server_name,
             addr,
-            socket,
+            socket: udp_socket(&addr),
+            roots: RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            alpn_protocols: Vec::new(),
+            congestion: CongestionAlgorithm::default(),
+            token: None,
+            versions: SUPPORTED_VERSIONS.to_vec(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            early_data: false,
+            tls_config: None,
             connection: None,
             onopen_handler: None,
+            onearlydata_handler: None,
+        }
+    }
+
+    /* Connects to `addr` instead of what the host name resolved to; the name is still what the certificate must match. */
+    pub fn set_address(&mut self, addr: SocketAddr) -> &mut Self {
+        if addr.is_ipv4() != self.addr.is_ipv4() {
+            let _ = self.socket.close();
+            self.socket = udp_socket(&addr);
+        }
+        if addr != self.addr {
+            self.token = None;
+        }
+        self.addr = addr;
+        self
+    }
+
+    /* Verifies servers against the platform's trust store instead of the bundled webpki roots. */
+    pub fn use_native_roots(&mut self) -> std::io::Result<&mut Self> {
+        let native = rustls_native_certs::load_native_certs();
+        if native.certs.is_empty() {
+            if let Some(e) = native.errors.into_iter().next() {
+                return Err(std::io::Error::other(e));
+            }
         }
+        self.roots = RootCertStore::empty();
+        self.roots.add_parsable_certificates(native.certs);
+        self.tls_config = None;
+        Ok(self)
+    }
+
+    /* Trusts one more certificate authority, e.g. a private one. */
+    pub fn add_root_certificate(&mut self, cert: CertificateDer<'static>) -> std::io::Result<&mut Self> {
+        self.roots.add(cert).map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+        self.tls_config = None;
+        Ok(self)
+    }
+
+    /* Application protocols offered through ALPN, in order of preference. */
+    pub fn set_alpn_protocols(&mut self, protocols: &[&[u8]]) -> &mut Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+        self.tls_config = None;
+        self
+    }
+
+    /* Congestion controller the connection uses; NewReno unless set. */
+    pub fn set_congestion_control(&mut self, algorithm: CongestionAlgorithm) -> &mut Self {
+        self.congestion = algorithm;
+        self
     }
+
+    /* QUIC versions to connect with, in order of preference; the server may answer with a Version Negotiation packet. */
+    pub fn set_versions(&mut self, versions: &[u32]) -> std::io::Result<&mut Self> {
+        if versions.is_empty() || versions.iter().any(|v| !SUPPORTED_VERSIONS.contains(v)) {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "unsupported QUIC version"));
+        }
+        self.versions = versions.to_vec();
+        Ok(self)
+    }
+
+    /* The max_idle_timeout the connection advertises; the smaller of both ends' applies, zero means none (RFC 9000 §10.1). */
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /*
+    Whether a connection resuming an earlier session sends 0-RTT data: what `on_early_data` writes goes out
+    with the first flight. Off unless set.
+    */
+    pub fn set_early_data(&mut self, enabled: bool) -> &mut Self {
+        self.early_data = enabled;
+        self.tls_config = None;
+        self
+    }
+
+    /*
+    Runs the handshake to completion, blocking until the server confirms it, then calls `on_open`.
+    A token the server handed out on an earlier connection spares this one a Retry, and a session ticket
+    from one resumes the TLS session, with 0-RTT data if enabled.
+    The first version is tried first; when the server answers with a Version Negotiation packet the
+    connection starts over once in the first version both speak (RFC 9000 §6.2).
+    */
     pub fn connect(&mut self) -> Result<(), String> {
-        let mut scid = vec![0u8; 8];
-        let mut dcid = vec![0u8; 20];
-        rand::rng().fill_bytes(&mut scid);
-        rand::rng().fill_bytes(&mut dcid);
-        let salt = Salt::new(HKDF_SHA256, &INITIAL_SALT);
-        //let client_hello = build_quic_client_hello(self.server_name.clone(), &dcid, &[b"h3"]);
-        //let packet = build_initial_packet(&salt, &dcid, &scid, client_hello.as_ref());
-        //self.socket.send_to(packet.as_ref(), &self.addr, 0).map_err(|e| e.to_string())?;
+        let config = self.tls_config()?;
+        let token = self.token.take().unwrap_or_default();
+
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let mut buf = vec![0u8; 65535];
+        let mut version = self.versions[0];
+        let mut negotiated = false;
+        let mut conn = 'attempt: loop {
+            let scid = ConnectionId::from_slice(&generate_connection_id(8));
+            let dcid = ConnectionId::from_slice(&generate_connection_id(20));
+            let mut conn = QuicConnection::new(scid, dcid, &self.addr, QuicConnectionType::Client);
+            conn.set_congestion_control(&self.congestion);
+            conn.token = token.clone();
+            conn.version = version;
+            conn.original_version = version;
+            conn.versions = self.versions.clone();
+            conn.version_negotiated = negotiated;
+            conn.idle_timeout = self.idle_timeout;
+            conn.connect_tls(config.clone(), self.server_name.clone()).map_err(|e| e.to_string())?;
+            if conn.zero_rtt_keys.is_some() {
+                if let Some(handler) = self.onearlydata_handler.as_mut() {
+                    handler(&mut conn);
+                }
+            }
+
+            loop {
+                self.flush(&mut conn)?;
+                if let Some(reason) = conn.close_reason() {
+                    return Err(reason.to_string());
+                }
+                if conn.handshake_confirmed { break 'attempt conn; }
+                if Instant::now() >= deadline {
+                    return Err("QUIC handshake timed out".to_string());
+                }
+                self.receive(&mut conn, &mut buf, RECV_TIMEOUT)?;
+                if let Some(offered) = conn.offered_versions.take() {
+                    /* a second Version Negotiation packet would mean a downgrade attempt (RFC 9368 §4) */
+                    if negotiated {
+                        return Err("QUIC version negotiation failed".to_string());
+                    }
+                    version = *self.versions.iter().find(|v| offered.contains(v)).ok_or("no QUIC version in common with the server")?;
+                    negotiated = true;
+                    continue 'attempt;
+                }
+            }
+        };
+
+        self.keep_token(&mut conn);
+        if let Some(handler) = self.onopen_handler.as_mut() {
+            handler(&mut conn);
+        }
+        self.connection = Some(conn);
         Ok(())
     }
-    
+
+    /*
+    TLS only resumes a session with the config whose certificate verifier checked it, so one config serves
+    every connection until the trust or ALPN settings change, dropping its tickets.
+    */
+    fn tls_config(&mut self) -> Result<Arc<ClientConfig>, String> {
+        if let Some(config) = &self.tls_config {
+            return Ok(config.clone());
+        }
+        let mut config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(self.roots.clone())
+            .with_no_client_auth();
+        config.alpn_protocols = self.alpn_protocols.clone();
+        config.resumption = Resumption::store(Arc::new(ClientSessionMemoryCache::new(MAX_SESSIONS)));
+        config.enable_early_data = self.early_data;
+        let config = Arc::new(config);
+        self.tls_config = Some(config.clone());
+        Ok(config)
+    }
+
     pub fn on_open<F>(&mut self, h: F)
     where
         F: FnMut(& mut QuicConnection) + Send + 'static,
@@ -55,6 +236,18 @@ impl QuicClient {
         self.onopen_handler = Some(Box::new(h));
     }
 
+    /*
+    Called during `connect` when the connection can send 0-RTT data, before anything went out. Streams opened
+    and written here travel with the first flight; `is_early_data_accepted` tells afterwards whether the
+    server took them, if not they were sent again once the handshake completed.
+    */
+    pub fn on_early_data<F>(&mut self, h: F)
+    where
+        F: FnMut(& mut QuicConnection) + Send + 'static,
+    {
+        self.onearlydata_handler = Some(Box::new(h));
+    }
+
     pub fn open_bistream(&'_ mut self) -> Result<QuicStream<'_>, String> {
         if let Some(connection) = &mut self.connection {
             connection.open_bistream()
@@ -62,4 +255,199 @@ impl QuicClient {
             Err("Connection not established".to_string())
         }
     }
-}
\ No newline at end of file
+
+    pub fn open_unistream(&'_ mut self) -> Result<QuicStream<'_>, String> {
+        if let Some(connection) = &mut self.connection {
+            connection.open_unistream()
+        } else {
+            Err("Connection not established".to_string())
+        }
+    }
+
+    pub fn send_datagram(&mut self, data: &[u8]) -> Result<(), String> {
+        if let Some(connection) = &mut self.connection {
+            connection.send_datagram(data)
+        } else {
+            Err("Connection not established".to_string())
+        }
+    }
+
+    pub fn on_datagram<F>(&mut self, h: F) -> Result<(), String>
+    where
+        F: FnMut(&mut QuicDatagram) + Send + Sync + 'static,
+    {
+        let connection = self.connection.as_mut().ok_or("Connection not established")?;
+        connection.on_datagram(h);
+        Ok(())
+    }
+
+    pub fn connection(&mut self) -> Option<&mut QuicConnection> {
+        self.connection.as_mut()
+    }
+
+    /*
+    Moves the connection to a new local port, as a NAT rebinding would (RFC 9000 §9.2); the server follows
+    once the next packet reaches it. Fails when the server asked for disable_active_migration.
+    */
+    pub fn migrate(&mut self) -> Result<(), String> {
+        let conn = self.connection.as_mut().ok_or("Connection not established")?;
+        if conn.peer_params.as_ref().is_some_and(|params| params.disable_active_migration) {
+            return Err("the server does not allow migration".to_string());
+        }
+        let _ = self.socket.close();
+        self.socket = udp_socket(&self.addr);
+        conn.migrate();
+        Ok(())
+    }
+
+    /* Closes the connection with an application error code and reason, and sends CONNECTION_CLOSE right away. */
+    pub fn close(&mut self, code: u64, reason: &str) -> Result<(), String> {
+        let mut conn = self.connection.take().ok_or("Connection not established")?;
+        conn.close(code, reason);
+        let result = self.flush(&mut conn);
+        self.connection = Some(conn);
+        result
+    }
+
+    /*
+    Sends what the connection has queued and processes what the server sends for `duration`;
+    stream and datagram handlers run from here. Fails once the connection ended, with the reason why.
+    */
+    pub fn poll(&mut self, duration: Duration) -> Result<(), String> {
+        let mut conn = self.connection.take().ok_or("Connection not established")?;
+        let deadline = Instant::now() + duration;
+        let mut buf = vec![0u8; 65535];
+        let result = loop {
+            if let Err(e) = self.flush(&mut conn) { break Err(e); }
+            if let Some(reason) = conn.close_reason() { break Err(reason.to_string()); }
+            let now = Instant::now();
+            if now >= deadline { break Ok(()); }
+            if let Err(e) = self.receive(&mut conn, &mut buf, (deadline - now).min(RECV_TIMEOUT)) { break Err(e); }
+        };
+        self.keep_token(&mut conn);
+        self.connection = Some(conn);
+        result
+    }
+
+    /* Keeps the latest NEW_TOKEN token for the next connection to this server. */
+    fn keep_token(&mut self, conn: &mut QuicConnection) {
+        if let Some(token) = conn.new_token.take() {
+            self.token = Some(token);
+        }
+    }
+
+    /* Runs due timers and sends every datagram the connection has ready. */
+    fn flush(&self, conn: &mut QuicConnection) -> Result<(), String> {
+        issue_cids(conn);
+        let now = Instant::now();
+        if conn.poll_timeout().is_some_and(|at| at <= now) {
+            conn.handle_timeout(now);
+        }
+        while let Some(datagram) = conn.poll_transmit(now) {
+            self.socket.send_to(&datagram, &self.addr, 0).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /* Waits up to `max_wait` for a datagram from the server, less when a timer is due earlier. */
+    fn receive(&self, conn: &mut QuicConnection, buf: &mut [u8], max_wait: Duration) -> Result<(), String> {
+        let now = Instant::now();
+        let wait = conn.poll_timeout().map_or(max_wait, |at| at.saturating_duration_since(now).min(max_wait)).max(Duration::from_millis(1));
+        self.socket.set_socket_option(SoRecvTimeout, wait).map_err(|e| e.to_string())?;
+        let mut len = 0;
+        match self.socket.popmsg(buf, &mut len, 0) {
+            Ok(from) if from == self.addr => {
+                exec_datagram(conn, &mut buf[..len], &from, Instant::now());
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/* Gives the server as many CIDs of ours as it takes, for it to use on new paths (RFC 9000 §5.1.1). */
+fn issue_cids(conn: &mut QuicConnection) {
+    for _ in 0..conn.local_cids.wanted() {
+        conn.local_cids.issue(ConnectionId::from_slice(&generate_connection_id(conn.id.len)), rand::random());
+    }
+}
+
+/*
+Follows a Retry if it is the first one, comes before anything else from the server, carries a token
+and a valid integrity tag, and points to a CID other than the one the client used (RFC 9000 §17.2.5.2).
+Anything else is dropped.
+*/
+fn exec_retry(conn: &mut QuicConnection, parsed: &QuicPacket) {
+    let QuicPacketHeader::Retry(retry) = parsed.header else { return; };
+    let fresh = conn.retry_scid.is_none() && conn.spaces[SpaceId::Initial as usize].largest_received().is_none();
+    if fresh
+        && retry.dcid == conn.id.as_bytes()
+        && retry.scid != conn.dcid.as_bytes()
+        && !retry.token.is_empty()
+        && verify_retry_integrity(conn.original_dcid.as_bytes(), parsed.bytes)
+    {
+        conn.accept_retry(&ConnectionId::from_slice(retry.scid), retry.token);
+    }
+}
+
+/*
+Takes note of the versions a Version Negotiation packet offers if it answers our first flight: it must come
+before anything else from the server, echo our CIDs and not list the version we used (RFC 9000 §6.2).
+Anything else is dropped.
+*/
+fn exec_version_negotiation(conn: &mut QuicConnection, parsed: &QuicPacket) {
+    let QuicPacketHeader::VersionNegotiation(vn) = parsed.header else { return; };
+    let versions: Vec<u32> = vn.supported.chunks_exact(4).map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]])).collect();
+    let fresh = conn.retry_scid.is_none() && conn.spaces[SpaceId::Initial as usize].largest_received().is_none();
+    if fresh && vn.dcid == conn.id.as_bytes() && vn.scid == conn.dcid.as_bytes() && !versions.contains(&conn.version) {
+        conn.offered_versions = Some(versions);
+    }
+}
+
+/*
+Processes the packets of a datagram from the server. One that is not for us may be a stateless reset,
+which looks like a short header packet with a random CID (RFC 9000 §10.3.1).
+*/
+fn exec_datagram(conn: &mut QuicConnection, datagram: &mut [u8], source: &SocketAddr, now: Instant) {
+    let mut i = 0;
+    while i < datagram.len() {
+        let Some(parsed) = QuicPacket::parse(&datagram[i..], conn.id.len) else {
+            if conn.is_stateless_reset(&datagram[i..]) {
+                conn.on_stateless_reset(now);
+            }
+            break;
+        };
+        let (packet_type, len) = (parsed.header.packet_type(), parsed.len());
+        if packet_type == QuicPacketType::VersionNegotiation {
+            exec_version_negotiation(conn, &parsed);
+            break;
+        }
+        if packet_type == QuicPacketType::Retry {
+            exec_retry(conn, &parsed);
+            break;
+        }
+        let space = match packet_type {
+            QuicPacketType::Initial => Some(SpaceId::Initial),
+            QuicPacketType::Handshake => Some(SpaceId::Handshake),
+            QuicPacketType::OneRtt => Some(SpaceId::Data),
+            _ => None,
+        };
+        let header = parsed.header.protected().filter(|h| conn.local_cids.contains(&ConnectionId::from_slice(h.dcid)));
+        if header.is_none() && conn.is_stateless_reset(&datagram[i..]) {
+            conn.on_stateless_reset(now);
+            break;
+        }
+        if let (Some(space), Some(header)) = (space, header) {
+            let (scid, pn_offset) = (ConnectionId::from_slice(header.scid), header.pn_offset);
+            let first_initial = space == SpaceId::Initial && conn.spaces[space as usize].largest_received().is_none();
+            let handled = conn.handle_packet(space, packet_type, &mut datagram[i..i + len], pn_offset, source, now);
+            /* from the server's first Initial on, packets go to the CID it chose (RFC 9000 §7.2) */
+            if handled.is_some() && first_initial {
+                conn.dcid = scid;
+            }
+        }
+        i += len;
+    }
+}
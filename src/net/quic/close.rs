@@ -0,0 +1,310 @@
+use std::io::{Error, ErrorKind};
+use std::time::Instant;
+
+use crate::net::{QuicFrame, QuicPacketType};
+use crate::net::connection::{QuicConnection, QuicConnectionState};
+use crate::net::quic::packets::{write_long_header, write_short_header, TAG_LEN};
+use crate::net::quic::space::SpaceId;
+use crate::net::quic::transmit::MAX_DATAGRAM_SIZE;
+
+/*
+Ending a connection (RFC 9000 §10). An endpoint closing it sends CONNECTION_CLOSE and stays in the closing
+state for three PTOs, answering whatever still comes in with the same frame; one that got CONNECTION_CLOSE
+drains for as long without sending anything. An idle timeout ends a connection silently, and a stateless
+reset lets an endpoint that lost its state tell the peer so.
+*/
+
+/* Transport error codes (RFC 9000 §20.1, RFC 9368 §10.2). */
+pub const NO_ERROR: u64 = 0x00;
+pub const INTERNAL_ERROR: u64 = 0x01;
+pub const CONNECTION_REFUSED: u64 = 0x02;
+pub const FLOW_CONTROL_ERROR: u64 = 0x03;
+pub const STREAM_LIMIT_ERROR: u64 = 0x04;
+pub const STREAM_STATE_ERROR: u64 = 0x05;
+pub const FINAL_SIZE_ERROR: u64 = 0x06;
+pub const FRAME_ENCODING_ERROR: u64 = 0x07;
+pub const TRANSPORT_PARAMETER_ERROR: u64 = 0x08;
+pub const CONNECTION_ID_LIMIT_ERROR: u64 = 0x09;
+pub const PROTOCOL_VIOLATION: u64 = 0x0a;
+pub const INVALID_TOKEN: u64 = 0x0b;
+pub const APPLICATION_ERROR: u64 = 0x0c;
+pub const CRYPTO_BUFFER_EXCEEDED: u64 = 0x0d;
+pub const KEY_UPDATE_ERROR: u64 = 0x0e;
+pub const AEAD_LIMIT_REACHED: u64 = 0x0f;
+pub const NO_VIABLE_PATH: u64 = 0x10;
+pub const VERSION_NEGOTIATION_ERROR: u64 = 0x11;
+/* CRYPTO_ERROR plus the TLS alert */
+pub const CRYPTO_ERROR: u64 = 0x100;
+
+/* The prefixes of our error messages, which name the code the connection is closed with. */
+const TRANSPORT_ERRORS: [(&str, u64); 18] = [
+    ("NO_ERROR", NO_ERROR),
+    ("INTERNAL_ERROR", INTERNAL_ERROR),
+    ("CONNECTION_REFUSED", CONNECTION_REFUSED),
+    ("FLOW_CONTROL_ERROR", FLOW_CONTROL_ERROR),
+    ("STREAM_LIMIT_ERROR", STREAM_LIMIT_ERROR),
+    ("STREAM_STATE_ERROR", STREAM_STATE_ERROR),
+    ("FINAL_SIZE_ERROR", FINAL_SIZE_ERROR),
+    ("FRAME_ENCODING_ERROR", FRAME_ENCODING_ERROR),
+    ("TRANSPORT_PARAMETER_ERROR", TRANSPORT_PARAMETER_ERROR),
+    ("CONNECTION_ID_LIMIT_ERROR", CONNECTION_ID_LIMIT_ERROR),
+    ("PROTOCOL_VIOLATION", PROTOCOL_VIOLATION),
+    ("INVALID_TOKEN", INVALID_TOKEN),
+    ("APPLICATION_ERROR", APPLICATION_ERROR),
+    ("CRYPTO_BUFFER_EXCEEDED", CRYPTO_BUFFER_EXCEEDED),
+    ("KEY_UPDATE_ERROR", KEY_UPDATE_ERROR),
+    ("AEAD_LIMIT_REACHED", AEAD_LIMIT_REACHED),
+    ("NO_VIABLE_PATH", NO_VIABLE_PATH),
+    ("VERSION_NEGOTIATION_ERROR", VERSION_NEGOTIATION_ERROR),
+];
+
+/* The TLS alert a CRYPTO_ERROR stands for when TLS did not name one: internal_error. */
+const INTERNAL_ERROR_ALERT: u64 = 80;
+/* Longest reason phrase sent, so CONNECTION_CLOSE always fits a packet. */
+const MAX_REASON_LEN: usize = 256;
+
+/* A stateless reset is at least 5 unpredictable bytes followed by the token (RFC 9000 §10.3). */
+pub(crate) const MIN_STATELESS_RESET_LEN: usize = 5 + 16;
+/* About the size of a short 1-RTT packet, so resets do not stand out. */
+const STATELESS_RESET_LEN: usize = 42;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuicCloseReason {
+    /* CONNECTION_CLOSE of type 0x1c; `remote` when the peer sent it */
+    Transport { code: u64, frame_type: u64, reason: String, remote: bool },
+    /* CONNECTION_CLOSE of type 0x1d, from QuicConnection::close */
+    Application { code: u64, reason: String, remote: bool },
+    IdleTimeout,
+    StatelessReset,
+}
+
+impl QuicCloseReason {
+    /* Whether the peer ended the connection. */
+    pub fn is_remote(&self) -> bool {
+        match self {
+            QuicCloseReason::Transport { remote, .. } | QuicCloseReason::Application { remote, .. } => *remote,
+            QuicCloseReason::IdleTimeout => false,
+            QuicCloseReason::StatelessReset => true,
+        }
+    }
+}
+
+impl std::fmt::Display for QuicCloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let by = |remote: &bool| if *remote { "closed by the peer" } else { "closed" };
+        match self {
+            QuicCloseReason::Transport { code, reason, remote, .. } => {
+                let name = TRANSPORT_ERRORS.iter().find(|(_, c)| c == code).map_or(if *code >= CRYPTO_ERROR { "CRYPTO_ERROR" } else { "transport error" }, |(name, _)| name);
+                write!(f, "{} with {} ({:#x}): {}", by(remote), name, code, reason)
+            }
+            QuicCloseReason::Application { code, reason, remote } => write!(f, "{} with application error {:#x}: {}", by(remote), code, reason),
+            QuicCloseReason::IdleTimeout => write!(f, "idle timeout"),
+            QuicCloseReason::StatelessReset => write!(f, "stateless reset by the peer"),
+        }
+    }
+}
+
+/* The transport error code and reason phrase an error message stands for; unnamed errors are PROTOCOL_VIOLATION. */
+fn transport_error(e: &Error, alert: Option<u8>) -> (u64, String) {
+    let message = e.to_string();
+    if let Some((name, reason)) = message.split_once(": ") {
+        if name == "CRYPTO_ERROR" {
+            return (CRYPTO_ERROR + alert.map_or(INTERNAL_ERROR_ALERT, u64::from), reason.to_string());
+        }
+        if let Some((_, code)) = TRANSPORT_ERRORS.iter().find(|(n, _)| *n == name) {
+            return (*code, reason.to_string());
+        }
+    }
+    if let Some((_, code)) = TRANSPORT_ERRORS.iter().find(|(n, _)| *n == message) {
+        return (*code, String::new());
+    }
+    let code = if e.kind() == ErrorKind::InvalidData { PROTOCOL_VIOLATION } else { INTERNAL_ERROR };
+    (code, message)
+}
+
+/* At most MAX_REASON_LEN bytes of `reason`, cut between characters. */
+fn truncate_reason(reason: &str) -> &[u8] {
+    let end = reason.char_indices().map(|(i, c)| i + c.len_utf8()).take_while(|&end| end <= MAX_REASON_LEN).last().unwrap_or(0);
+    &reason.as_bytes()[..end]
+}
+
+/* Compares without returning early, so timing tells nothing about a token (RFC 9000 §10.3.1). */
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/*
+A stateless reset for a datagram of `received` bytes: it looks like a short header packet, is smaller than what
+triggered it so two endpoints cannot loop, and ends with `token` (RFC 9000 §10.3). None when the datagram is
+too small to answer.
+*/
+pub(crate) fn write_stateless_reset(token: &[u8; 16], received: usize) -> Option<Vec<u8>> {
+    if received <= MIN_STATELESS_RESET_LEN { return None; }
+    let len = STATELESS_RESET_LEN.min(received - 1);
+    let mut out: Vec<u8> = (0..len - 16).map(|_| rand::random()).collect();
+    out[0] = 0b0100_0000 | (out[0] & 0b0011_1111);
+    out.extend_from_slice(token);
+    Some(out)
+}
+
+impl QuicConnection {
+    /*
+    Closes the connection with an application error code and reason (RFC 9000 §10.2). CONNECTION_CLOSE goes
+    out with the next datagram, after which nothing else is sent; `on_close` fires right away.
+    */
+    pub fn close(&mut self, code: u64, reason: &str) {
+        let reason = QuicCloseReason::Application { code, reason: reason.to_string(), remote: false };
+        self.leave_open(reason, QuicConnectionState::Closing, Instant::now());
+    }
+
+    pub fn state(&self) -> QuicConnectionState {
+        self.state
+    }
+
+    /* Why the connection ended; None while it is open. */
+    pub fn close_reason(&self) -> Option<&QuicCloseReason> {
+        self.close_reason.as_ref()
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.state == QuicConnectionState::Open
+    }
+
+    /* Closes the connection with the transport error an error that ended it stands for. */
+    pub(crate) fn close_on_error(&mut self, e: &Error, now: Instant) {
+        let alert = self.tls.as_ref().and_then(|tls| tls.alert()).map(u8::from);
+        let (code, reason) = transport_error(e, alert);
+        self.leave_open(QuicCloseReason::Transport { code, frame_type: 0, reason, remote: false }, QuicConnectionState::Closing, now);
+    }
+
+    /* The peer closed the connection: drain without sending anything, not even ACKs (RFC 9000 §10.2.2). */
+    pub(crate) fn on_peer_close(&mut self, error_code: u64, frame_type: Option<u64>, reason: &[u8], now: Instant) {
+        let reason = String::from_utf8_lossy(reason).into_owned();
+        let reason = match frame_type {
+            Some(frame_type) => QuicCloseReason::Transport { code: error_code, frame_type, reason, remote: true },
+            None => QuicCloseReason::Application { code: error_code, reason, remote: true },
+        };
+        self.leave_open(reason, QuicConnectionState::Draining, now);
+    }
+
+    /*
+    Whether `datagram` ends with a stateless reset token of the peer's, from its transport parameters or a
+    NEW_CONNECTION_ID frame (RFC 9000 §10.3.1). Only checked for datagrams that cannot be processed otherwise.
+    */
+    pub(crate) fn is_stateless_reset(&self, datagram: &[u8]) -> bool {
+        if datagram.len() < MIN_STATELESS_RESET_LEN || datagram[0] & 0x80 != 0 { return false; }
+        self.is_reset_token(&datagram[datagram.len() - 16..])
+    }
+
+    pub(crate) fn is_reset_token(&self, tail: &[u8]) -> bool {
+        let handshake_token = self.peer_params.as_ref().and_then(|params| params.stateless_reset_token.as_ref());
+        self.remote_cids.reset_tokens().chain(handshake_token).any(|token| constant_time_eq(token, tail))
+    }
+
+    /* The peer lost the connection's state; nothing more is sent to it (RFC 9000 §10.3.1). */
+    pub(crate) fn on_stateless_reset(&mut self, now: Instant) {
+        self.leave_open(QuicCloseReason::StatelessReset, QuicConnectionState::Draining, now);
+    }
+
+    /*
+    Ends the connection once: closing or draining last three PTOs (RFC 9000 §10.2), an idle timeout closes it
+    right away (§10.1). `on_close` fires on the way out.
+    */
+    fn leave_open(&mut self, reason: QuicCloseReason, state: QuicConnectionState, now: Instant) {
+        if !self.is_open() { return; }
+        self.state = state;
+        self.close_reason = Some(reason);
+        self.close_pending = state == QuicConnectionState::Closing;
+        self.close_deadline = Some(now + 3 * self.recovery.pto());
+        if let Some(mut handler) = self.onclose_handler.take() {
+            handler(self);
+        }
+    }
+
+    /* The idle timeout in force: the smaller of both ends' max_idle_timeout, zero meaning none (RFC 9000 §10.1). */
+    fn idle_timeout(&self) -> Option<std::time::Duration> {
+        let peer = self.peer_params.as_ref().map(|params| params.max_idle_timeout).unwrap_or_default();
+        let timeout = [self.idle_timeout, peer].into_iter().filter(|t| !t.is_zero()).min()?;
+        /* never shorter than three PTOs, so a few lost packets do not end the connection */
+        Some(timeout.max(3 * self.recovery.pto()))
+    }
+
+    /* When the connection times out without anything from the peer; for a closing connection, when it is done. */
+    pub(crate) fn close_timeout(&self) -> Option<Instant> {
+        match self.state {
+            QuicConnectionState::Open => self.idle_timeout().map(|timeout| self.last_activity + timeout),
+            QuicConnectionState::Closing | QuicConnectionState::Draining => self.close_deadline,
+            QuicConnectionState::Closed => None,
+        }
+    }
+
+    /* Runs out the idle, closing or draining timer; returns whether it did. */
+    pub(crate) fn handle_close_timeout(&mut self, now: Instant) -> bool {
+        if self.close_timeout().is_none_or(|at| at > now) { return false; }
+        if self.is_open() {
+            self.leave_open(QuicCloseReason::IdleTimeout, QuicConnectionState::Closed, now);
+        }
+        self.state = QuicConnectionState::Closed;
+        true
+    }
+
+    /* A packet came in after we closed; CONNECTION_CLOSE is sent again for the 1st, 2nd, 4th, 8th... (RFC 9000 §10.2.1). */
+    pub(crate) fn on_packet_while_closing(&mut self) {
+        if self.state != QuicConnectionState::Closing { return; }
+        self.closing_packets += 1;
+        if self.closing_packets.is_power_of_two() {
+            self.close_pending = true;
+        }
+    }
+
+    /*
+    The datagram a closing connection sends: CONNECTION_CLOSE at every level there are keys for, as the peer
+    may not have the later ones yet (RFC 9000 §10.2.3). An application close becomes APPLICATION_ERROR without
+    a reason outside 1-RTT packets.
+    */
+    pub(crate) fn poll_close(&mut self) -> Option<Vec<u8>> {
+        if self.state != QuicConnectionState::Closing || !self.close_pending { return None; }
+        self.close_pending = false;
+        let size = match self.address_validated {
+            true => MAX_DATAGRAM_SIZE,
+            false => (3 * self.bytes_received).saturating_sub(self.bytes_sent).min(MAX_DATAGRAM_SIZE),
+        };
+        let reason = self.close_reason.clone()?;
+        let mut out = Vec::with_capacity(MAX_DATAGRAM_SIZE);
+        for space in SpaceId::ALL {
+            if self.spaces[space as usize].keys.is_none() { continue; }
+            let frame = match &reason {
+                QuicCloseReason::Transport { code, frame_type, reason, .. } => {
+                    QuicFrame::ConnectionClose { error_code: *code, frame_type: Some(*frame_type), reason: truncate_reason(reason) }
+                }
+                QuicCloseReason::Application { code, reason, .. } if space == SpaceId::Data => {
+                    QuicFrame::ConnectionClose { error_code: *code, frame_type: None, reason: truncate_reason(reason) }
+                }
+                QuicCloseReason::Application { .. } => QuicFrame::ConnectionClose { error_code: APPLICATION_ERROR, frame_type: Some(0), reason: b"" },
+                QuicCloseReason::IdleTimeout | QuicCloseReason::StatelessReset => return None,
+            };
+            let start = out.len();
+            let state = &mut self.spaces[space as usize];
+            let pn = state.next_pn;
+            state.next_pn += 1;
+            let pn_offset = match space {
+                SpaceId::Initial => write_long_header(&mut out, QuicPacketType::Initial, self.version, self.dcid.as_bytes(), self.id.as_bytes(), &self.token, pn),
+                SpaceId::Handshake => write_long_header(&mut out, QuicPacketType::Handshake, self.version, self.dcid.as_bytes(), self.id.as_bytes(), &[], pn),
+                SpaceId::Data => write_short_header(&mut out, self.dcid.as_bytes(), pn),
+            };
+            frame.encode(&mut out).ok()?;
+            /* header protection samples 16 bytes from 4 bytes past the packet number */
+            out.resize(out.len().max(pn_offset + 4 + 4), 0);
+            if out.len() + TAG_LEN > size {
+                out.truncate(start);
+                break;
+            }
+            state.keys.as_mut()?.seal(&mut out, start, pn_offset, pn).ok()?;
+        }
+        if out.is_empty() { return None; }
+        if !self.address_validated {
+            self.bytes_sent += out.len();
+        }
+        Some(out)
+    }
+}
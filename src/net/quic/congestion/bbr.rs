@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::net::{RttEstimator, SentPacket};
+use super::{CongestionController, INITIAL_WINDOW, MAX_DATAGRAM, MINIMUM_WINDOW};
+
+const STARTUP_GAIN: f64 = 2.77;
+const PROBE_BW_GAINS: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+const CWND_GAIN: f64 = 2.0;
+/* rounds the bandwidth filter remembers */
+const BW_FILTER_ROUNDS: u64 = 10;
+/* startup ends after this many rounds without 25% bandwidth growth */
+const FULL_BW_ROUNDS: u32 = 3;
+const MIN_RTT_EXPIRY: Duration = Duration::from_secs(10);
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+const PROBE_RTT_WINDOW: usize = 4 * MAX_DATAGRAM;
+/* a round losing more than this fraction of its data caps the window */
+const LOSS_THRESHOLD: f64 = 0.02;
+const INFLIGHT_HI_BETA: f64 = 0.7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BbrState {
+    Startup,
+    Drain,
+    ProbeBw,
+    ProbeRtt,
+}
+
+/*
+A BBRv2-style model based controller: it estimates the bottleneck bandwidth and the minimum RTT,
+paces at the estimated bandwidth, keeps about two bandwidth-delay products in flight and
+caps that with `inflight_hi` once rounds start losing more than 2% of their data.
+*/
+pub struct Bbr {
+    state: BbrState,
+    window: usize,
+    pacing_gain: f64,
+    bw_samples: VecDeque<(u64, u64)>, // (round, bytes per second)
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Option<Instant>,
+    delivered: usize,
+    round: u64,
+    round_start: Option<(Instant, usize)>, // when the current round began and what was delivered by then
+    round_lost: usize,
+    full_bw: u64,
+    full_bw_rounds: u32,
+    filled_pipe: bool,
+    cycle_index: usize,
+    cycle_stamp: Option<Instant>,
+    probe_rtt_done: Option<Instant>,
+    inflight_hi: usize,
+}
+
+impl Default for Bbr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bbr {
+    pub fn new() -> Self {
+        Self {
+            state: BbrState::Startup,
+            window: INITIAL_WINDOW,
+            pacing_gain: STARTUP_GAIN,
+            bw_samples: VecDeque::new(),
+            min_rtt: None,
+            min_rtt_stamp: None,
+            delivered: 0,
+            round: 0,
+            round_start: None,
+            round_lost: 0,
+            full_bw: 0,
+            full_bw_rounds: 0,
+            filled_pipe: false,
+            cycle_index: 0,
+            cycle_stamp: None,
+            probe_rtt_done: None,
+            inflight_hi: usize::MAX,
+        }
+    }
+
+    pub fn state(&self) -> BbrState {
+        self.state
+    }
+
+    /* The bottleneck bandwidth estimate in bytes per second. */
+    pub fn bandwidth(&self) -> u64 {
+        self.bw_samples.iter().map(|&(_, bw)| bw).max().unwrap_or(0)
+    }
+
+    fn bdp(&self) -> Option<usize> {
+        let min_rtt = self.min_rtt?;
+        match self.bandwidth() {
+            0 => None,
+            bw => Some((bw as u128 * min_rtt.as_micros() / 1_000_000) as usize),
+        }
+    }
+
+    fn update_min_rtt(&mut self, now: Instant, rtt: &RttEstimator) {
+        let latest = rtt.latest();
+        let expired = self.min_rtt_stamp.is_some_and(|stamp| now.saturating_duration_since(stamp) > MIN_RTT_EXPIRY);
+        if self.min_rtt.is_none_or(|min| latest <= min) || (expired && self.state == BbrState::ProbeRtt) {
+            self.min_rtt = Some(latest);
+            self.min_rtt_stamp = Some(now);
+        } else if expired && self.state != BbrState::ProbeRtt {
+            self.state = BbrState::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.probe_rtt_done = None;
+        }
+    }
+
+    /* Called once per round trip with the delivery rate measured over it. */
+    fn on_round_end(&mut self, delivered: usize, lost: usize, elapsed: Duration) {
+        self.round += 1;
+        if !elapsed.is_zero() {
+            let bw = (delivered as u128 * 1_000_000 / elapsed.as_micros().max(1)) as u64;
+            self.bw_samples.push_back((self.round, bw));
+        }
+        while self.bw_samples.front().is_some_and(|&(round, _)| round + BW_FILTER_ROUNDS <= self.round) {
+            self.bw_samples.pop_front();
+        }
+
+        let lossy = lost as f64 > LOSS_THRESHOLD * (delivered + lost) as f64;
+        if lossy {
+            self.inflight_hi = ((self.window as f64 * INFLIGHT_HI_BETA) as usize).max(MINIMUM_WINDOW);
+        } else if self.state == BbrState::ProbeBw && self.cycle_index == 0 && self.inflight_hi != usize::MAX {
+            /* probing up found room again */
+            self.inflight_hi += self.inflight_hi / 4;
+        }
+
+        if self.state == BbrState::Startup {
+            let bw = self.bandwidth();
+            if bw as f64 >= self.full_bw as f64 * 1.25 {
+                self.full_bw = bw;
+                self.full_bw_rounds = 0;
+            } else {
+                self.full_bw_rounds += 1;
+            }
+            if self.full_bw_rounds >= FULL_BW_ROUNDS || lossy {
+                self.filled_pipe = true;
+                self.state = BbrState::Drain;
+                self.pacing_gain = 1.0 / STARTUP_GAIN;
+            }
+        }
+    }
+
+    fn enter_probe_bw(&mut self, now: Instant) {
+        self.state = BbrState::ProbeBw;
+        /* start anywhere but the draining phase */
+        self.cycle_index = [0, 2, 3, 4, 5, 6, 7][(self.round % 7) as usize];
+        self.cycle_stamp = Some(now);
+        self.pacing_gain = PROBE_BW_GAINS[self.cycle_index];
+    }
+
+    fn update_state(&mut self, now: Instant, bytes_in_flight: usize) {
+        let bdp = self.bdp().unwrap_or(INITIAL_WINDOW);
+        match self.state {
+            BbrState::Drain if bytes_in_flight <= bdp => self.enter_probe_bw(now),
+            BbrState::ProbeBw => {
+                let min_rtt = self.min_rtt.unwrap_or_default();
+                let elapsed = self.cycle_stamp.is_some_and(|stamp| now.saturating_duration_since(stamp) > min_rtt);
+                /* the draining phase ends early once the queue it built up is gone */
+                let drained = self.cycle_index == 1 && bytes_in_flight <= bdp;
+                if elapsed || drained {
+                    self.cycle_index = (self.cycle_index + 1) % PROBE_BW_GAINS.len();
+                    self.cycle_stamp = Some(now);
+                    self.pacing_gain = PROBE_BW_GAINS[self.cycle_index];
+                }
+            }
+            BbrState::ProbeRtt => {
+                if self.probe_rtt_done.is_none() && bytes_in_flight <= PROBE_RTT_WINDOW {
+                    self.probe_rtt_done = Some(now + PROBE_RTT_DURATION);
+                }
+                if self.probe_rtt_done.is_some_and(|done| now >= done) {
+                    self.min_rtt_stamp = Some(now);
+                    if self.filled_pipe {
+                        self.enter_probe_bw(now);
+                    } else {
+                        self.state = BbrState::Startup;
+                        self.pacing_gain = STARTUP_GAIN;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_window(&mut self, acked: usize) {
+        let target = self.bdp().map_or(INITIAL_WINDOW, |bdp| ((bdp as f64 * CWND_GAIN) as usize).max(INITIAL_WINDOW / 2));
+        if self.filled_pipe {
+            self.window = (self.window + acked).min(target);
+        } else if self.window < target || self.delivered < INITIAL_WINDOW {
+            self.window += acked;
+        }
+        self.window = self.window.min(self.inflight_hi).max(PROBE_RTT_WINDOW);
+        if self.state == BbrState::ProbeRtt {
+            self.window = self.window.min(PROBE_RTT_WINDOW);
+        }
+    }
+}
+
+impl CongestionController for Bbr {
+    fn on_packets_acked(&mut self, now: Instant, acked: &[SentPacket], rtt: &RttEstimator, bytes_in_flight: usize) {
+        let bytes: usize = acked.iter().filter(|p| p.in_flight).map(|p| p.size).sum();
+        if bytes == 0 { return; }
+        self.delivered += bytes;
+        self.update_min_rtt(now, rtt);
+
+        /* a round trip ends when a packet sent after it began is acknowledged */
+        let (round_start, round_delivered) = *self.round_start.get_or_insert((now, self.delivered - bytes));
+        if acked.iter().any(|p| p.time_sent >= round_start) {
+            let lost = std::mem::take(&mut self.round_lost);
+            self.on_round_end(self.delivered - round_delivered, lost, now.saturating_duration_since(round_start));
+            self.round_start = Some((now, self.delivered));
+        }
+        self.update_state(now, bytes_in_flight);
+        self.update_window(bytes);
+    }
+
+    fn on_packets_lost(&mut self, _now: Instant, lost: &[SentPacket], persistent_congestion: bool, _bytes_in_flight: usize) {
+        self.round_lost += lost.iter().filter(|p| p.in_flight).map(|p| p.size).sum::<usize>();
+        if persistent_congestion {
+            self.window = PROBE_RTT_WINDOW;
+            self.bw_samples.clear();
+        }
+    }
+
+    fn window(&self) -> usize {
+        self.window
+    }
+
+    fn pacing_rate(&self, rtt: &RttEstimator) -> u64 {
+        match self.bandwidth() {
+            0 => {
+                let rtt = rtt.smoothed().max(Duration::from_millis(1));
+                (INITIAL_WINDOW as f64 * STARTUP_GAIN / rtt.as_secs_f64()) as u64
+            }
+            bw => (bw as f64 * self.pacing_gain) as u64,
+        }
+    }
+}
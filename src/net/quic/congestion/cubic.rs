@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use crate::net::{RttEstimator, SentPacket};
+use super::{CongestionController, RecoveryPeriod, INITIAL_WINDOW, MAX_DATAGRAM, MINIMUM_WINDOW};
+
+/* RFC 9438 constants; windows are computed in datagrams. */
+const C: f64 = 0.4;
+const BETA: f64 = 0.7;
+
+/* CUBIC (RFC 9438): the window grows along a cubic curve centred on the size it had at the last congestion event. */
+pub struct Cubic {
+    window: usize,
+    ssthresh: usize,
+    recovery: RecoveryPeriod,
+    w_max: f64,                  // window before the last reduction
+    k: f64,                      // seconds until the curve is back at w_max
+    epoch_start: Option<Instant>, // start of the current congestion avoidance stage
+    w_est: f64,                  // what Reno would have reached since the epoch started
+}
+
+impl Default for Cubic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cubic {
+    pub fn new() -> Self {
+        Self {
+            window: INITIAL_WINDOW,
+            ssthresh: usize::MAX,
+            recovery: RecoveryPeriod::default(),
+            w_max: 0.0,
+            k: 0.0,
+            epoch_start: None,
+            w_est: 0.0,
+        }
+    }
+
+    pub fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    /* W_cubic(t) in datagrams, RFC 9438 §4.2 */
+    fn w_cubic(&self, t: Duration) -> f64 {
+        C * (t.as_secs_f64() - self.k).powi(3) + self.w_max
+    }
+}
+
+impl CongestionController for Cubic {
+    fn on_packets_acked(&mut self, now: Instant, acked: &[SentPacket], rtt: &RttEstimator, _bytes_in_flight: usize) {
+        let bytes: usize = acked.iter().filter(|p| p.in_flight && !self.recovery.contains(p.time_sent)).map(|p| p.size).sum();
+        if bytes == 0 { return; }
+        if self.window < self.ssthresh {
+            self.window += bytes;
+            return;
+        }
+
+        let mss = MAX_DATAGRAM as f64;
+        let cwnd = self.window as f64 / mss;
+        let epoch_start = *self.epoch_start.get_or_insert_with(|| {
+            self.k = if cwnd < self.w_max { ((self.w_max - cwnd) / C).cbrt() } else { 0.0 };
+            self.w_est = cwnd;
+            now
+        });
+        let t = now.saturating_duration_since(epoch_start);
+        let acked = bytes as f64 / mss;
+
+        /* Reno-friendly region (§4.3) */
+        let alpha = 3.0 * (1.0 - BETA) / (1.0 + BETA);
+        self.w_est += alpha * acked / cwnd;
+        if self.w_cubic(t) < self.w_est {
+            self.window = self.window.max((self.w_est * mss) as usize);
+            return;
+        }
+
+        /* concave and convex regions (§4.4, §4.5): aim for where the curve will be one RTT from now */
+        let target = self.w_cubic(t + rtt.smoothed()).clamp(cwnd, 1.5 * cwnd);
+        self.window += ((target - cwnd) * acked / cwnd * mss) as usize;
+    }
+
+    fn on_packets_lost(&mut self, now: Instant, lost: &[SentPacket], persistent_congestion: bool, _bytes_in_flight: usize) {
+        let Some(latest) = lost.iter().filter(|p| p.in_flight).map(|p| p.time_sent).max() else { return; };
+        if self.recovery.enter(now, latest) {
+            let cwnd = self.window as f64 / MAX_DATAGRAM as f64;
+            /* fast convergence (§4.7): release bandwidth to newer flows */
+            self.w_max = if cwnd < self.w_max { cwnd * (1.0 + BETA) / 2.0 } else { cwnd };
+            self.ssthresh = ((self.window as f64 * BETA) as usize).max(MINIMUM_WINDOW);
+            self.window = self.ssthresh;
+            self.epoch_start = None;
+        }
+        if persistent_congestion {
+            self.window = MINIMUM_WINDOW;
+            self.epoch_start = None;
+            self.recovery.reset();
+        }
+    }
+
+    fn window(&self) -> usize {
+        self.window
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::net::{RttEstimator, SentPacket};
+
+mod newreno;
+pub use newreno::*;
+mod cubic;
+pub use cubic::*;
+mod bbr;
+pub use bbr::*;
+mod pacer;
+pub use pacer::*;
+
+/*
+Congestion control (RFC 9002 §7). Controllers only see what loss detection reports;
+the connection asks them how many bytes may be in flight and how fast to pace them.
+*/
+
+/* Datagram size the windows are counted in. */
+pub const MAX_DATAGRAM: usize = 1200;
+/* RFC 9002 §7.2 */
+pub const INITIAL_WINDOW: usize = 10 * MAX_DATAGRAM;
+pub const MINIMUM_WINDOW: usize = 2 * MAX_DATAGRAM;
+
+pub trait CongestionController: Send + Sync {
+    fn on_packet_sent(&mut self, _now: Instant, _bytes: usize, _bytes_in_flight: usize) {}
+
+    /* `acked` are the in-flight packets an ACK newly acknowledged. */
+    fn on_packets_acked(&mut self, now: Instant, acked: &[SentPacket], rtt: &RttEstimator, bytes_in_flight: usize);
+
+    fn on_packets_lost(&mut self, now: Instant, lost: &[SentPacket], persistent_congestion: bool, bytes_in_flight: usize);
+
+    /* Bytes that may be in flight. */
+    fn window(&self) -> usize;
+
+    /* Bytes per second to pace at; by default the window spread over 1/1.25 of the smoothed RTT (RFC 9002 §7.7). */
+    fn pacing_rate(&self, rtt: &RttEstimator) -> u64 {
+        let rtt = rtt.smoothed().max(Duration::from_millis(1));
+        (self.window() as u128 * 5 * 1_000_000 / (4 * rtt.as_micros())) as u64
+    }
+}
+
+/* Picks the controller each new connection gets. */
+#[derive(Clone, Default)]
+pub enum CongestionAlgorithm {
+    #[default]
+    NewReno,
+    Cubic,
+    Bbr,
+    Custom(Arc<dyn Fn() -> Box<dyn CongestionController> + Send + Sync>),
+}
+
+impl CongestionAlgorithm {
+    pub fn build(&self) -> Box<dyn CongestionController> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new()),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new()),
+            CongestionAlgorithm::Bbr => Box::new(Bbr::new()),
+            CongestionAlgorithm::Custom(build) => build(),
+        }
+    }
+}
+
+/* A congestion event only reduces the window once per round trip: not for packets sent before recovery began (RFC 9002 §7.3.2). */
+#[derive(Default)]
+pub(crate) struct RecoveryPeriod {
+    start: Option<Instant>,
+}
+
+impl RecoveryPeriod {
+    pub(crate) fn contains(&self, time_sent: Instant) -> bool {
+        self.start.is_some_and(|start| time_sent <= start)
+    }
+
+    /* Starts a recovery period for a loss of a packet sent at `time_sent`; false when one already covers it. */
+    pub(crate) fn enter(&mut self, now: Instant, time_sent: Instant) -> bool {
+        if self.contains(time_sent) { return false; }
+        self.start = Some(now);
+        true
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.start = None;
+    }
+}
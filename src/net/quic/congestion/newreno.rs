@@ -0,0 +1,61 @@
+use std::time::Instant;
+
+use crate::net::{RttEstimator, SentPacket};
+use super::{CongestionController, RecoveryPeriod, INITIAL_WINDOW, MAX_DATAGRAM, MINIMUM_WINDOW};
+
+/* The RFC 9002 §7 / Appendix B controller: slow start, congestion avoidance and halving on loss. */
+pub struct NewReno {
+    window: usize,
+    ssthresh: usize,
+    recovery: RecoveryPeriod,
+    acked_in_avoidance: usize, // bytes acknowledged towards the next one-datagram increase
+}
+
+impl Default for NewReno {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NewReno {
+    pub fn new() -> Self {
+        Self { window: INITIAL_WINDOW, ssthresh: usize::MAX, recovery: RecoveryPeriod::default(), acked_in_avoidance: 0 }
+    }
+
+    pub fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+}
+
+impl CongestionController for NewReno {
+    fn on_packets_acked(&mut self, _now: Instant, acked: &[SentPacket], _rtt: &RttEstimator, _bytes_in_flight: usize) {
+        for packet in acked.iter().filter(|p| p.in_flight && !self.recovery.contains(p.time_sent)) {
+            if self.window < self.ssthresh {
+                self.window += packet.size;
+            } else {
+                self.acked_in_avoidance += packet.size;
+                if self.acked_in_avoidance >= self.window {
+                    self.acked_in_avoidance -= self.window;
+                    self.window += MAX_DATAGRAM;
+                }
+            }
+        }
+    }
+
+    fn on_packets_lost(&mut self, now: Instant, lost: &[SentPacket], persistent_congestion: bool, _bytes_in_flight: usize) {
+        let Some(latest) = lost.iter().filter(|p| p.in_flight).map(|p| p.time_sent).max() else { return; };
+        if self.recovery.enter(now, latest) {
+            self.ssthresh = (self.window / 2).max(MINIMUM_WINDOW);
+            self.window = self.ssthresh;
+            self.acked_in_avoidance = 0;
+        }
+        if persistent_congestion {
+            self.window = MINIMUM_WINDOW;
+            self.recovery.reset();
+        }
+    }
+
+    fn window(&self) -> usize {
+        self.window
+    }
+}
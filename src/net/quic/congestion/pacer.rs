@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+use super::MAX_DATAGRAM;
+
+/*
+Token bucket pacing (RFC 9002 §7.7): a burst of up to `capacity` bytes may leave at once,
+after that packets are spread out at the controller's pacing rate.
+*/
+pub struct Pacer {
+    capacity: u64,
+    tokens: u64,
+    last: Option<Instant>,
+}
+
+/* Bursts stay within what a sender can put on the wire back to back without building queues. */
+pub const DEFAULT_BURST: u64 = 10 * MAX_DATAGRAM as u64;
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new(DEFAULT_BURST)
+    }
+}
+
+impl Pacer {
+    pub fn new(capacity: u64) -> Self {
+        Self { capacity, tokens: capacity, last: None }
+    }
+
+    fn refill(&mut self, now: Instant, rate: u64) {
+        if let Some(last) = self.last {
+            let earned = now.saturating_duration_since(last).as_nanos() * rate as u128 / 1_000_000_000;
+            self.tokens = (self.tokens as u128 + earned).min(self.capacity as u128) as u64;
+        }
+        self.last = Some(now);
+    }
+
+    /* When `bytes` may be sent at `rate` bytes per second; None means right away. */
+    pub fn delay(&mut self, now: Instant, bytes: usize, rate: u64) -> Option<Instant> {
+        self.refill(now, rate);
+        let bytes = bytes as u64;
+        if self.tokens >= bytes || rate == 0 { return None; }
+        let wait = (bytes - self.tokens) as u128 * 1_000_000_000 / rate as u128;
+        Some(now + Duration::from_nanos(wait.max(1) as u64))
+    }
+
+    pub fn on_sent(&mut self, bytes: usize) {
+        self.tokens = self.tokens.saturating_sub(bytes as u64);
+    }
+}
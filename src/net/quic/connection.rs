@@ -1,14 +1,34 @@
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use super::{QuicDatagram, QuicMessage, QuicStream};
+use std::time::{Duration, Instant};
+use super::{QuicCloseReason, QuicDatagram, QuicMessage, QuicStream};
+use super::{CongestionAlgorithm, CongestionController, LossDetection, Pacer, PacketProtector, TransportParameters, QUIC_VERSION_1, SUPPORTED_VERSIONS};
+use super::cids::{LocalCids, RemoteCids};
+use super::path::{OffPathResponse, PathValidation};
+use super::space::{PacketSpace, SpaceId};
+use super::datagram::DatagramQueue;
+use super::streams::Streams;
+use super::h3::H3Connection;
 
+/* Where a connection is in its life (RFC 9000 §10): closing after it sent CONNECTION_CLOSE, draining after it got one. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QuicConnectionState {
     Open,
     Closing,
+    Draining,
     Closed,
 }
 
+pub(crate) type StreamHandler = Box<dyn FnMut(&mut QuicStream) + Send + Sync + 'static>;
+pub(crate) type MessageHandler = Box<dyn FnMut(QuicMessage) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+pub(crate) type DatagramHandler = Box<dyn FnMut(&mut QuicDatagram) + Send + Sync + 'static>;
+pub(crate) type ConnectionCloseHandler = Box<dyn FnMut(&QuicConnection) + Send + Sync + 'static>;
+pub(crate) type MigrateHandler = Box<dyn FnMut(&SocketAddr, &SocketAddr) + Send + Sync + 'static>;
+
+/* The max_idle_timeout we advertise unless set otherwise. */
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub enum QuicConnectionType {
     Client,
     Server,
@@ -69,48 +89,171 @@ impl PartialEq for ConnectionId {
 pub struct QuicConnection {
     pub(crate) id: ConnectionId,
     pub(crate) dcid: ConnectionId,
-    //pub(crate) state: QuicConnectionState,
-    pub(crate) last_packet_number: u32, // Packet Number
+    pub(crate) local_cids: LocalCids, // ours, `id` among them
+    pub(crate) remote_cids: RemoteCids, // the peer's, `dcid` among them
+    pub(crate) original_dcid: ConnectionId, // the DCID of the client's first Initial
+    pub(crate) retry_scid: Option<ConnectionId>, // the SCID of the Retry the handshake went through
+    pub(crate) version: u32, // the QUIC version packets are sent in
+    pub(crate) original_version: u32, // the version of the client's first flight
+    pub(crate) versions: Vec<u32>, // the versions we support, in order of preference
+    pub(crate) version_negotiated: bool, // a client that started over after a Version Negotiation packet
+    pub(crate) offered_versions: Option<Vec<u32>>, // what a Version Negotiation packet offered the client
+    pub(crate) original_initial: Option<PacketProtector>, // a server's keys for Initials the client still sends in original_version
+    pub(crate) zero_rtt_keys: Option<PacketProtector>, // a client's to send 0-RTT packets with, a server's to read them
+    pub(crate) early_data_accepted: bool, // the server took the client's 0-RTT data
+    pub(crate) token: Vec<u8>, // what a client puts in its Initial packets, from a Retry or NEW_TOKEN
+    pub(crate) new_token: Option<Vec<u8>>, // a NEW_TOKEN token the server has yet to send, or the client got
+    pub(crate) address_validated: bool, // until then a server sends at most 3 times what it received
+    pub(crate) bytes_received: usize,
+    pub(crate) bytes_sent: usize,
+    pub(crate) state: QuicConnectionState,
+    pub(crate) close_reason: Option<QuicCloseReason>,
+    pub(crate) close_pending: bool, // CONNECTION_CLOSE goes out with the next datagram
+    pub(crate) close_deadline: Option<Instant>, // the end of the closing or draining state
+    pub(crate) closing_packets: u32, // received while closing
+    pub(crate) idle_timeout: Duration, // our max_idle_timeout
+    pub(crate) last_activity: Instant, // what the idle timeout counts from
+    pub(crate) restart_idle_on_send: bool, // the next ack-eliciting packet restarts the idle timer
     pub(crate) address: SocketAddr,
-    pub(crate) onstream_handler: Option<Box<dyn FnMut(&mut QuicStream) + Send + Sync + 'static>>,
-    onmessage_handler: Option<
-        Box<dyn FnMut(QuicMessage) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>,
-    >,
-    pub(crate) ondatagram_handler: Option<Box<dyn FnMut(&QuicDatagram) + Send + Sync + 'static>>,
-    pub(crate) onclose_handler: Option<Box<dyn FnMut(&QuicConnection) + Send + Sync + 'static>>,
-    pub(crate) last_bistream_id: u32,
-    pub(crate) last_unistream_id: u32,
+    pub(crate) path: Option<PathValidation>, // while the peer's new address is being validated
+    pub(crate) path_responses: Vec<[u8; 8]>, // PATH_RESPONSE frames to send to `address`
+    pub(crate) off_path_responses: Vec<OffPathResponse>,
+    pub(crate) ping_pending: bool,
+    pub(crate) onstream_handler: Option<StreamHandler>,
+    onmessage_handler: Option<MessageHandler>,
+    pub(crate) ondatagram_handler: Option<DatagramHandler>,
+    pub(crate) onclose_handler: Option<ConnectionCloseHandler>,
+    pub(crate) onmigrate_handler: Option<MigrateHandler>,
+    pub(crate) streams: Streams,
+    pub(crate) datagrams: DatagramQueue,
+    pub(crate) h3: Option<Box<H3Connection>>, // once HTTP/3 is served on the connection
+    pub(crate) tls: Option<rustls::quic::Connection>,
+    pub(crate) peer_params: Option<TransportParameters>, // once the handshake is complete
+    pub(crate) spaces: [PacketSpace; 3],
+    pub(crate) write_level: SpaceId, // encryption level TLS currently writes at
+    pub(crate) handshake_complete: bool,
+    pub(crate) handshake_done_pending: bool,
+    pub(crate) handshake_confirmed: bool, // HANDSHAKE_DONE sent (server) or received (client)
+    pub(crate) recovery: LossDetection,
+    pub(crate) congestion: Box<dyn CongestionController>,
+    pub(crate) congestion_algorithm: CongestionAlgorithm, // what a new path starts with
+    pub(crate) pacer: Pacer,
+    pub(crate) pacing_until: Option<Instant>, // when the pacer lets the next packet go
 }
 
 impl QuicConnection {
-    pub fn new(scid: ConnectionId, dcid: ConnectionId, last_packet_number: u32, address: &SocketAddr, type_: QuicConnectionType) -> Self {
+    pub fn new(scid: ConnectionId, dcid: ConnectionId, address: &SocketAddr, type_: QuicConnectionType) -> Self {
         match type_ {
             QuicConnectionType::Client => {
                 Self {
                     id: scid,
                     dcid,
-                    last_packet_number,
+                    local_cids: LocalCids::new(scid),
+                    remote_cids: RemoteCids::new(),
+                    original_dcid: dcid,
+                    retry_scid: None,
+                    version: QUIC_VERSION_1,
+                    original_version: QUIC_VERSION_1,
+                    versions: SUPPORTED_VERSIONS.to_vec(),
+                    version_negotiated: false,
+                    offered_versions: None,
+                    original_initial: None,
+                    zero_rtt_keys: None,
+                    early_data_accepted: false,
+                    token: Vec::new(),
+                    new_token: None,
+                    address_validated: true,
+                    bytes_received: 0,
+                    bytes_sent: 0,
+                    state: QuicConnectionState::Open,
+                    close_reason: None,
+                    close_pending: false,
+                    close_deadline: None,
+                    closing_packets: 0,
+                    idle_timeout: DEFAULT_IDLE_TIMEOUT,
+                    last_activity: Instant::now(),
+                    restart_idle_on_send: false,
                     address: *address,
+                    path: None,
+                    path_responses: Vec::new(),
+                    off_path_responses: Vec::new(),
+                    ping_pending: false,
                     onstream_handler: None,
                     onmessage_handler: None,
                     ondatagram_handler: None,
                     onclose_handler: None,
-                    last_bistream_id: 0, // Client-Initiated, Bidirectional: starts at 0
-                    last_unistream_id: 2, // Client-Initiated, Unidirectional: starts at 2
+                    onmigrate_handler: None,
+                    streams: Streams::new(true),
+                    datagrams: DatagramQueue::new(),
+                    h3: None,
+                    tls: None,
+                    peer_params: None,
+                    spaces: SpaceId::ALL.map(PacketSpace::new),
+                    write_level: SpaceId::Initial,
+                    handshake_complete: false,
+                    handshake_done_pending: false,
+                    handshake_confirmed: false,
+                    recovery: LossDetection::new(true),
+                    congestion: CongestionAlgorithm::default().build(),
+                    congestion_algorithm: CongestionAlgorithm::default(),
+                    pacer: Pacer::default(),
+                    pacing_until: None,
                 }
             }
             QuicConnectionType::Server => {
                 Self {
                     id: scid,
                     dcid,
-                    last_packet_number,
+                    local_cids: LocalCids::new(scid),
+                    remote_cids: RemoteCids::new(),
+                    original_dcid: dcid,
+                    retry_scid: None,
+                    version: QUIC_VERSION_1,
+                    original_version: QUIC_VERSION_1,
+                    versions: SUPPORTED_VERSIONS.to_vec(),
+                    version_negotiated: false,
+                    offered_versions: None,
+                    original_initial: None,
+                    zero_rtt_keys: None,
+                    early_data_accepted: false,
+                    token: Vec::new(),
+                    new_token: None,
+                    address_validated: false,
+                    bytes_received: 0,
+                    bytes_sent: 0,
+                    state: QuicConnectionState::Open,
+                    close_reason: None,
+                    close_pending: false,
+                    close_deadline: None,
+                    closing_packets: 0,
+                    idle_timeout: DEFAULT_IDLE_TIMEOUT,
+                    last_activity: Instant::now(),
+                    restart_idle_on_send: false,
                     address: *address,
+                    path: None,
+                    path_responses: Vec::new(),
+                    off_path_responses: Vec::new(),
+                    ping_pending: false,
                     onstream_handler: None,
                     onmessage_handler: None,
                     ondatagram_handler: None,
                     onclose_handler: None,
-                    last_bistream_id: 1, // Server-Initiated, Bidirectional: starts at 1
-                    last_unistream_id: 3, // Server-Initiated, Unidirectional: starts at 3
+                    onmigrate_handler: None,
+                    streams: Streams::new(false),
+                    datagrams: DatagramQueue::new(),
+                    h3: None,
+                    tls: None,
+                    peer_params: None,
+                    spaces: SpaceId::ALL.map(PacketSpace::new),
+                    write_level: SpaceId::Initial,
+                    handshake_complete: false,
+                    handshake_done_pending: false,
+                    handshake_confirmed: false,
+                    recovery: LossDetection::new(false),
+                    congestion: CongestionAlgorithm::default().build(),
+                    congestion_algorithm: CongestionAlgorithm::default(),
+                    pacer: Pacer::default(),
+                    pacing_until: None,
                 }
             }
         }
@@ -118,12 +261,21 @@ impl QuicConnection {
 
     pub fn initiate_message_channel(&mut self) {
     }
-    pub fn open_bistream(&mut self) -> Result<QuicStream, String>{
-        self.last_bistream_id += 4;
-        Ok(QuicStream::new(
-            self.last_bistream_id,
-            self,
-        ))
+    /* Opens a stream; fails while the peer's stream limit is reached (it is told with STREAMS_BLOCKED). */
+    pub fn open_bistream(&mut self) -> Result<QuicStream<'_>, String> {
+        let id = self.streams.open(false).map_err(|e| e.to_string())?;
+        Ok(QuicStream::new(id, self))
+    }
+
+    pub fn open_unistream(&mut self) -> Result<QuicStream<'_>, String> {
+        let id = self.streams.open(true).map_err(|e| e.to_string())?;
+        Ok(QuicStream::new(id, self))
+    }
+
+    /* A handle on a stream that is still open. */
+    pub fn stream(&mut self, id: u64) -> Option<QuicStream<'_>> {
+        self.streams.get(id)?;
+        Some(QuicStream::new(id, self))
     }
 
     pub fn on_stream<F>(&mut self, h: F)
@@ -149,17 +301,73 @@ impl QuicConnection {
     }
     pub fn on_datagram<F>(&mut self, h: F)
     where
-        F: FnMut(&QuicDatagram) + Send + Sync + 'static,
+        F: FnMut(&mut QuicDatagram) + Send + Sync + 'static,
     {
         self.ondatagram_handler = Some(Box::new(h));
     }
 
+    /*
+    Queues an unreliable datagram (RFC 9221). Fails until the handshake showed the peer takes datagrams,
+    or when `data` is larger than `max_datagram_size`.
+    */
+    pub fn send_datagram(&mut self, data: &[u8]) -> Result<(), String> {
+        self.datagrams.push(data)
+    }
+
+    /* The largest datagram the peer accepts, None if it takes none (or the handshake is not done yet). */
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.datagrams.max_size()
+    }
+
+    /* How many datagrams may wait for the congestion window; beyond that the oldest are dropped. */
+    pub fn set_datagram_queue_capacity(&mut self, capacity: usize) {
+        self.datagrams.set_capacity(capacity);
+    }
+
+    /* Datagrams dropped from a full queue so far. */
+    pub fn dropped_datagrams(&self) -> u64 {
+        self.datagrams.dropped
+    }
+
+    /* Called once when the connection ends, whichever end closed it; `close_reason` tells why. */
     pub fn on_close<F>(&mut self, h: F)
     where
     F: FnMut(&QuicConnection) + Send + Sync + 'static {
         self.onclose_handler = Some(Box::new(h));
     }
 
+    /*
+    Called once the peer proved it owns the address it moved to (RFC 9000 §9), with the address it left
+    and the new one.
+    */
+    pub fn on_migrate<F>(&mut self, h: F)
+    where
+        F: FnMut(&SocketAddr, &SocketAddr) + Send + Sync + 'static,
+    {
+        self.onmigrate_handler = Some(Box::new(h));
+    }
+
+    /* Replaces the congestion controller; meant to be called before any data is sent. */
+    pub fn set_congestion_control(&mut self, algorithm: &CongestionAlgorithm) {
+        self.congestion = algorithm.build();
+        self.congestion_algorithm = algorithm.clone();
+    }
+
+    /* What the peer sent in its transport parameters; None until the handshake is complete. */
+    pub fn peer_transport_parameters(&self) -> Option<&TransportParameters> {
+        self.peer_params.as_ref()
+    }
+
+    /* The QUIC version the connection runs. */
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /* Bytes the congestion controller currently lets into flight. */
+    pub fn congestion_window(&self) -> usize {
+        self.congestion.window()
+    }
+
     #[inline(always)]
     pub fn address(&self) -> &SocketAddr {
         &self.address
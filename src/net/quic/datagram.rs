@@ -1,14 +1,24 @@
+use std::collections::VecDeque;
 use std::net::SocketAddr;
 
 use super::connection::QuicConnection;
+use super::frame::varint_len;
 
+/* What we advertise in max_datagram_frame_size: any DATAGRAM frame that fits a UDP payload (RFC 9221 §3). */
+pub const MAX_DATAGRAM_FRAME_SIZE: u64 = 65535;
+/* Datagrams waiting for the congestion window before the oldest ones are dropped. */
+pub const DEFAULT_DATAGRAM_QUEUE: usize = 64;
+/* Largest payload that fits a 1200-byte datagram with a short header carrying a 20-byte CID. */
+const MAX_DATAGRAM_PAYLOAD: usize = 1200 - 16 - (1 + 20 + 4) - 1 - 2;
+
+/* A datagram the peer sent, with the connection it came in on so a handler can answer. */
 pub struct QuicDatagram<'a> {
-    pub(crate) src: &'a QuicConnection,
+    pub(crate) src: &'a mut QuicConnection,
     pub(crate) data: Vec<u8>,
 }
 
 impl<'a> QuicDatagram<'a> {
-    pub fn new(src: &'a QuicConnection, data: Vec<u8>) -> Self {
+    pub fn new(src: &'a mut QuicConnection, data: Vec<u8>) -> Self {
         Self {
             src,
             data,
@@ -18,6 +28,9 @@ impl<'a> QuicDatagram<'a> {
     pub fn src(&self) -> SocketAddr {
         self.src.address
     }
+    pub fn src_connection(&mut self) -> &mut QuicConnection {
+        self.src
+    }
     pub fn data(&self) -> &[u8] {
         &self.data
     }
@@ -27,10 +40,79 @@ impl<'a> QuicDatagram<'a> {
     pub fn str(&self) -> String {
         String::from_utf8_lossy(&self.data).to_string()
     }
+    /* Queues a datagram back to the peer on the same connection. */
+    pub fn reply(&mut self, data: &[u8]) -> Result<(), String> {
+        self.src.send_datagram(data)
+    }
 }
 
 impl<'a> std::fmt::Display for QuicDatagram<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "QuicDatagram(src: {}, data: {:?})", self.src.address, self.data)
     }
-}
\ No newline at end of file
+}
+
+/*
+Outgoing datagrams (RFC 9221). They wait here only while the congestion window is full; once `capacity`
+are waiting the oldest is dropped, since stale unreliable data is worth less than fresh.
+*/
+pub(crate) struct DatagramQueue {
+    queue: VecDeque<Vec<u8>>,
+    capacity: usize,
+    peer_max: Option<u64>, // the peer's max_datagram_frame_size, None if it takes no datagrams
+    pub(crate) dropped: u64,
+}
+
+impl DatagramQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity: DEFAULT_DATAGRAM_QUEUE,
+            peer_max: None,
+            dropped: 0,
+        }
+    }
+
+    pub(crate) fn set_peer_max(&mut self, max: Option<u64>) {
+        self.peer_max = max.filter(|&max| max > 0);
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.queue.len() > self.capacity {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /* The largest payload the peer accepts and a packet can carry; None until the peer said it takes datagrams. */
+    pub(crate) fn max_size(&self) -> Option<usize> {
+        let max = self.peer_max?;
+        let payload = (max as usize).saturating_sub(1 + varint_len(max));
+        Some(payload.min(MAX_DATAGRAM_PAYLOAD))
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) -> Result<(), String> {
+        let max = self.max_size().ok_or("the peer does not accept datagrams")?;
+        if data.len() > max {
+            return Err(format!("datagram of {} bytes exceeds the maximum of {}", data.len(), max));
+        }
+        if self.queue.len() == self.capacity {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(data.to_vec());
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /* The next datagram if its frame fits in `room` bytes. */
+    pub(crate) fn pop(&mut self, room: usize) -> Option<Vec<u8>> {
+        let len = self.queue.front()?.len();
+        if 1 + varint_len(len as u64) + len > room { return None; }
+        self.queue.pop_front()
+    }
+}
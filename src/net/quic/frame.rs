@@ -0,0 +1,431 @@
+use std::io::{Error, ErrorKind, Result};
+use crate::net::{encode_varint_into, varint, QuicPacketType};
+
+/*
+QUIC frames (RFC 9000 §19) and DATAGRAM (RFC 9221).
+
+Decoding borrows from the decrypted payload. Malformed frames are InvalidData errors, which a
+connection turns into FRAME_ENCODING_ERROR; encoding refuses values a varint cannot carry.
+*/
+
+pub const VARINT_MAX: u64 = (1 << 62) - 1;
+const MAX_STREAMS: u64 = 1 << 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EcnCounts {
+    pub ect0: u64,
+    pub ect1: u64,
+    pub ce: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AckFrame {
+    pub largest: u64,
+    pub delay: u64, // encoded, still scaled by the peer's ack_delay_exponent
+    pub first_range: u64,
+    pub ranges: Vec<(u64, u64)>, // (gap, ack range length) pairs, descending
+    pub ecn: Option<EcnCounts>,
+}
+
+impl AckFrame {
+    /* Acknowledged packet numbers as inclusive (smallest, largest) pairs, from the largest down. */
+    pub fn acked(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let first = self.largest.checked_sub(self.first_range).map(|smallest| (smallest, self.largest));
+        let rest = self.ranges.iter().scan(first.map(|(smallest, _)| smallest), |smallest, &(gap, len)| {
+            let largest = smallest.and_then(|s| s.checked_sub(gap)).and_then(|s| s.checked_sub(2))?;
+            *smallest = Some(largest.checked_sub(len)?);
+            Some((smallest.unwrap(), largest))
+        });
+        first.into_iter().chain(rest)
+    }
+
+    /* Every range must stay at or above packet number 0. */
+    fn validate(&self) -> bool {
+        self.acked().count() == self.ranges.len() + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuicFrame<'a> {
+    Padding(usize), // a run of PADDING bytes
+    Ping,
+    Ack(AckFrame),
+    ResetStream { stream_id: u64, error_code: u64, final_size: u64 },
+    StopSending { stream_id: u64, error_code: u64 },
+    Crypto { offset: u64, data: &'a [u8] },
+    NewToken(&'a [u8]),
+    Stream { stream_id: u64, offset: u64, data: &'a [u8], fin: bool },
+    MaxData(u64),
+    MaxStreamData { stream_id: u64, max: u64 },
+    MaxStreams { bidi: bool, max: u64 },
+    DataBlocked(u64),
+    StreamDataBlocked { stream_id: u64, limit: u64 },
+    StreamsBlocked { bidi: bool, limit: u64 },
+    NewConnectionId { sequence: u64, retire_prior_to: u64, cid: &'a [u8], reset_token: [u8; 16] },
+    RetireConnectionId(u64),
+    PathChallenge([u8; 8]),
+    PathResponse([u8; 8]),
+    ConnectionClose { error_code: u64, frame_type: Option<u64>, reason: &'a [u8] }, // frame_type is None for application closes
+    HandshakeDone,
+    Datagram(&'a [u8]),
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let (v, n) = varint(&self.buf[self.off..]).ok_or_else(|| invalid("truncated varint"))?;
+        self.off += n;
+        Ok(v as u64)
+    }
+
+    fn bytes(&mut self, len: u64) -> Result<&'a [u8]> {
+        let end = usize::try_from(len).ok().and_then(|len| self.off.checked_add(len)).filter(|&end| end <= self.buf.len());
+        let end = end.ok_or_else(|| invalid("frame runs past the end of the packet"))?;
+        let bytes = &self.buf[self.off..end];
+        self.off = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.bytes(N as u64)?.try_into().unwrap())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.buf[self.off..];
+        self.off = self.buf.len();
+        rest
+    }
+}
+
+impl<'a> QuicFrame<'a> {
+    /* Decodes the frame at the start of `buf`, returning it and its encoded length. */
+    pub fn decode(buf: &'a [u8]) -> Result<(Self, usize)> {
+        let mut r = Reader { buf, off: 0 };
+        let frame_type = r.varint()?;
+        if r.off != varint_len(frame_type) { return Err(invalid("frame type not minimally encoded")); }
+        let frame = match frame_type {
+            0x00 => {
+                let run = buf.iter().take_while(|&&b| b == 0).count();
+                r.off = run;
+                QuicFrame::Padding(run)
+            }
+            0x01 => QuicFrame::Ping,
+            0x02 | 0x03 => {
+                let largest = r.varint()?;
+                let delay = r.varint()?;
+                let count = r.varint()?;
+                let first_range = r.varint()?;
+                let mut ranges = Vec::with_capacity(count.min(64) as usize);
+                for _ in 0..count {
+                    ranges.push((r.varint()?, r.varint()?));
+                }
+                let ecn = if frame_type == 0x03 {
+                    Some(EcnCounts { ect0: r.varint()?, ect1: r.varint()?, ce: r.varint()? })
+                } else {
+                    None
+                };
+                let ack = AckFrame { largest, delay, first_range, ranges, ecn };
+                if !ack.validate() { return Err(invalid("ACK range below packet number 0")); }
+                QuicFrame::Ack(ack)
+            }
+            0x04 => QuicFrame::ResetStream { stream_id: r.varint()?, error_code: r.varint()?, final_size: r.varint()? },
+            0x05 => QuicFrame::StopSending { stream_id: r.varint()?, error_code: r.varint()? },
+            0x06 => {
+                let offset = r.varint()?;
+                let len = r.varint()?;
+                if offset + len > VARINT_MAX { return Err(invalid("CRYPTO data beyond 2^62-1")); }
+                QuicFrame::Crypto { offset, data: r.bytes(len)? }
+            }
+            0x07 => {
+                let len = r.varint()?;
+                if len == 0 { return Err(invalid("empty NEW_TOKEN")); }
+                QuicFrame::NewToken(r.bytes(len)?)
+            }
+            0x08..=0x0f => {
+                let stream_id = r.varint()?;
+                let offset = if frame_type & 0x04 != 0 { r.varint()? } else { 0 };
+                let data = if frame_type & 0x02 != 0 { let len = r.varint()?; r.bytes(len)? } else { r.rest() };
+                if offset + data.len() as u64 > VARINT_MAX { return Err(invalid("STREAM data beyond 2^62-1")); }
+                QuicFrame::Stream { stream_id, offset, data, fin: frame_type & 0x01 != 0 }
+            }
+            0x10 => QuicFrame::MaxData(r.varint()?),
+            0x11 => QuicFrame::MaxStreamData { stream_id: r.varint()?, max: r.varint()? },
+            0x12 | 0x13 => {
+                let max = r.varint()?;
+                if max > MAX_STREAMS { return Err(invalid("MAX_STREAMS above 2^60")); }
+                QuicFrame::MaxStreams { bidi: frame_type == 0x12, max }
+            }
+            0x14 => QuicFrame::DataBlocked(r.varint()?),
+            0x15 => QuicFrame::StreamDataBlocked { stream_id: r.varint()?, limit: r.varint()? },
+            0x16 | 0x17 => {
+                let limit = r.varint()?;
+                if limit > MAX_STREAMS { return Err(invalid("STREAMS_BLOCKED above 2^60")); }
+                QuicFrame::StreamsBlocked { bidi: frame_type == 0x16, limit }
+            }
+            0x18 => {
+                let sequence = r.varint()?;
+                let retire_prior_to = r.varint()?;
+                if retire_prior_to > sequence { return Err(invalid("Retire Prior To above Sequence Number")); }
+                let [len] = r.array()?;
+                if !(1..=20).contains(&len) { return Err(invalid("connection ID length outside 1..=20")); }
+                let cid = r.bytes(len as u64)?;
+                QuicFrame::NewConnectionId { sequence, retire_prior_to, cid, reset_token: r.array()? }
+            }
+            0x19 => QuicFrame::RetireConnectionId(r.varint()?),
+            0x1a => QuicFrame::PathChallenge(r.array()?),
+            0x1b => QuicFrame::PathResponse(r.array()?),
+            0x1c | 0x1d => {
+                let error_code = r.varint()?;
+                let frame_type = if frame_type == 0x1c { Some(r.varint()?) } else { None };
+                let len = r.varint()?;
+                QuicFrame::ConnectionClose { error_code, frame_type, reason: r.bytes(len)? }
+            }
+            0x1e => QuicFrame::HandshakeDone,
+            0x30 => QuicFrame::Datagram(r.rest()),
+            0x31 => {
+                let len = r.varint()?;
+                QuicFrame::Datagram(r.bytes(len)?)
+            }
+            _ => return Err(invalid("unknown frame type")),
+        };
+        Ok((frame, r.off))
+    }
+
+    /*
+    Appends the frame to `out`. Length fields are always written, so STREAM and DATAGRAM frames
+    need not be last in the packet.
+    */
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        let start = out.len();
+        let result = self.encode_unchecked(out);
+        if result.is_err() { out.truncate(start); }
+        result
+    }
+
+    fn encode_unchecked(&self, out: &mut Vec<u8>) -> Result<()> {
+        let w = |v: u64, out: &mut Vec<u8>| -> Result<()> {
+            if v > VARINT_MAX { return Err(unencodable("value exceeds 2^62-1")); }
+            encode_varint_into(v, out);
+            Ok(())
+        };
+        match self {
+            QuicFrame::Padding(len) => out.resize(out.len() + len, 0),
+            QuicFrame::Ping => out.push(0x01),
+            QuicFrame::Ack(ack) => {
+                if !ack.validate() { return Err(unencodable("ACK range below packet number 0")); }
+                out.push(if ack.ecn.is_some() { 0x03 } else { 0x02 });
+                w(ack.largest, out)?;
+                w(ack.delay, out)?;
+                w(ack.ranges.len() as u64, out)?;
+                w(ack.first_range, out)?;
+                for &(gap, len) in &ack.ranges {
+                    w(gap, out)?;
+                    w(len, out)?;
+                }
+                if let Some(ecn) = ack.ecn {
+                    w(ecn.ect0, out)?;
+                    w(ecn.ect1, out)?;
+                    w(ecn.ce, out)?;
+                }
+            }
+            QuicFrame::ResetStream { stream_id, error_code, final_size } => {
+                out.push(0x04);
+                w(*stream_id, out)?;
+                w(*error_code, out)?;
+                w(*final_size, out)?;
+            }
+            QuicFrame::StopSending { stream_id, error_code } => {
+                out.push(0x05);
+                w(*stream_id, out)?;
+                w(*error_code, out)?;
+            }
+            QuicFrame::Crypto { offset, data } => {
+                out.push(0x06);
+                w(*offset, out)?;
+                w(data.len() as u64, out)?;
+                if offset + data.len() as u64 > VARINT_MAX { return Err(unencodable("data beyond 2^62-1")); }
+                out.extend_from_slice(data);
+            }
+            QuicFrame::NewToken(token) => {
+                if token.is_empty() { return Err(unencodable("empty NEW_TOKEN")); }
+                out.push(0x07);
+                w(token.len() as u64, out)?;
+                out.extend_from_slice(token);
+            }
+            QuicFrame::Stream { stream_id, offset, data, fin } => {
+                out.push(0x08 | 0x02 | if *offset != 0 { 0x04 } else { 0 } | *fin as u8);
+                w(*stream_id, out)?;
+                if *offset != 0 { w(*offset, out)?; }
+                w(data.len() as u64, out)?;
+                if offset + data.len() as u64 > VARINT_MAX { return Err(unencodable("data beyond 2^62-1")); }
+                out.extend_from_slice(data);
+            }
+            QuicFrame::MaxData(max) => {
+                out.push(0x10);
+                w(*max, out)?;
+            }
+            QuicFrame::MaxStreamData { stream_id, max } => {
+                out.push(0x11);
+                w(*stream_id, out)?;
+                w(*max, out)?;
+            }
+            QuicFrame::MaxStreams { bidi, max } => {
+                if *max > MAX_STREAMS { return Err(unencodable("MAX_STREAMS above 2^60")); }
+                out.push(if *bidi { 0x12 } else { 0x13 });
+                w(*max, out)?;
+            }
+            QuicFrame::DataBlocked(limit) => {
+                out.push(0x14);
+                w(*limit, out)?;
+            }
+            QuicFrame::StreamDataBlocked { stream_id, limit } => {
+                out.push(0x15);
+                w(*stream_id, out)?;
+                w(*limit, out)?;
+            }
+            QuicFrame::StreamsBlocked { bidi, limit } => {
+                if *limit > MAX_STREAMS { return Err(unencodable("STREAMS_BLOCKED above 2^60")); }
+                out.push(if *bidi { 0x16 } else { 0x17 });
+                w(*limit, out)?;
+            }
+            QuicFrame::NewConnectionId { sequence, retire_prior_to, cid, reset_token } => {
+                if retire_prior_to > sequence || !(1..=20).contains(&cid.len()) {
+                    return Err(unencodable("invalid NEW_CONNECTION_ID"));
+                }
+                out.push(0x18);
+                w(*sequence, out)?;
+                w(*retire_prior_to, out)?;
+                out.push(cid.len() as u8);
+                out.extend_from_slice(cid);
+                out.extend_from_slice(reset_token);
+            }
+            QuicFrame::RetireConnectionId(sequence) => {
+                out.push(0x19);
+                w(*sequence, out)?;
+            }
+            QuicFrame::PathChallenge(data) => {
+                out.push(0x1a);
+                out.extend_from_slice(data);
+            }
+            QuicFrame::PathResponse(data) => {
+                out.push(0x1b);
+                out.extend_from_slice(data);
+            }
+            QuicFrame::ConnectionClose { error_code, frame_type, reason } => {
+                out.push(if frame_type.is_some() { 0x1c } else { 0x1d });
+                w(*error_code, out)?;
+                if let Some(frame_type) = frame_type { w(*frame_type, out)?; }
+                w(reason.len() as u64, out)?;
+                out.extend_from_slice(reason);
+            }
+            QuicFrame::HandshakeDone => out.push(0x1e),
+            QuicFrame::Datagram(data) => {
+                out.push(0x31);
+                w(data.len() as u64, out)?;
+                out.extend_from_slice(data);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn frame_type(&self) -> u64 {
+        match self {
+            QuicFrame::Padding(_) => 0x00,
+            QuicFrame::Ping => 0x01,
+            QuicFrame::Ack(ack) => if ack.ecn.is_some() { 0x03 } else { 0x02 },
+            QuicFrame::ResetStream { .. } => 0x04,
+            QuicFrame::StopSending { .. } => 0x05,
+            QuicFrame::Crypto { .. } => 0x06,
+            QuicFrame::NewToken(_) => 0x07,
+            QuicFrame::Stream { offset, fin, .. } => 0x0a | if *offset != 0 { 0x04 } else { 0 } | *fin as u64,
+            QuicFrame::MaxData(_) => 0x10,
+            QuicFrame::MaxStreamData { .. } => 0x11,
+            QuicFrame::MaxStreams { bidi, .. } => if *bidi { 0x12 } else { 0x13 },
+            QuicFrame::DataBlocked(_) => 0x14,
+            QuicFrame::StreamDataBlocked { .. } => 0x15,
+            QuicFrame::StreamsBlocked { bidi, .. } => if *bidi { 0x16 } else { 0x17 },
+            QuicFrame::NewConnectionId { .. } => 0x18,
+            QuicFrame::RetireConnectionId(_) => 0x19,
+            QuicFrame::PathChallenge(_) => 0x1a,
+            QuicFrame::PathResponse(_) => 0x1b,
+            QuicFrame::ConnectionClose { frame_type, .. } => if frame_type.is_some() { 0x1c } else { 0x1d },
+            QuicFrame::HandshakeDone => 0x1e,
+            QuicFrame::Datagram(_) => 0x31,
+        }
+    }
+
+    /* RFC 9000 §13.2.1: everything but ACK, PADDING and CONNECTION_CLOSE makes the peer acknowledge. */
+    pub fn is_ack_eliciting(&self) -> bool {
+        !matches!(self, QuicFrame::Padding(_) | QuicFrame::Ack(_) | QuicFrame::ConnectionClose { .. })
+    }
+
+    /* RFC 9000 §9.1: a packet of nothing but these does not move the connection to the address it came from. */
+    pub fn is_probing(&self) -> bool {
+        matches!(self, QuicFrame::PathChallenge(_) | QuicFrame::PathResponse(_) | QuicFrame::NewConnectionId { .. } | QuicFrame::Padding(_))
+    }
+
+    /* RFC 9000 §12.4 Table 3 (and RFC 9221 §4 for DATAGRAM). */
+    pub fn is_allowed_in(&self, packet_type: QuicPacketType) -> bool {
+        use QuicPacketType::*;
+        match self {
+            QuicFrame::Padding(_) | QuicFrame::Ping | QuicFrame::ConnectionClose { frame_type: Some(_), .. } => {
+                !matches!(packet_type, Retry | VersionNegotiation)
+            }
+            QuicFrame::Ack(_) | QuicFrame::Crypto { .. } => matches!(packet_type, Initial | Handshake | OneRtt),
+            QuicFrame::NewToken(_) | QuicFrame::HandshakeDone => packet_type == OneRtt,
+            QuicFrame::PathResponse(_) => packet_type == OneRtt,
+            _ => matches!(packet_type, ZeroRtt | OneRtt),
+        }
+    }
+}
+
+/* Iterates the frames of a decrypted payload; stops after the first malformed frame. */
+pub struct QuicFrames<'a> {
+    payload: &'a [u8],
+    failed: bool,
+}
+
+impl<'a> QuicFrames<'a> {
+    pub fn new(payload: &'a [u8]) -> Self {
+        Self { payload, failed: false }
+    }
+}
+
+impl<'a> Iterator for QuicFrames<'a> {
+    type Item = Result<QuicFrame<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.payload.is_empty() || self.failed { return None; }
+        match QuicFrame::decode(self.payload) {
+            Ok((frame, len)) => {
+                self.payload = &self.payload[len..];
+                Some(Ok(frame))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+pub(crate) fn varint_len(v: u64) -> usize {
+    match v {
+        0..=63 => 1,
+        64..=16383 => 2,
+        16384..=1073741823 => 4,
+        _ => 8,
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn unencodable(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg.to_string())
+}
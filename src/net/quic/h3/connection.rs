@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+
+use crate::net::{encode_varint_into, is_unidirectional};
+use crate::net::connection::QuicConnection;
+use crate::net::quic::streams::is_server_initiated;
+use super::frame::read_varint;
+use super::{
+    H3Error, H3Frame, H3Request, H3RequestHandler, H3Response, H3Result, H3Settings, QpackDecoder, QpackEncoder,
+    H3_CLOSED_CRITICAL_STREAM, H3_FRAME_ERROR, H3_FRAME_UNEXPECTED, H3_ID_ERROR, H3_MESSAGE_ERROR, H3_MISSING_SETTINGS,
+    H3_REQUEST_CANCELLED, H3_REQUEST_INCOMPLETE, H3_REQUEST_REJECTED, H3_STREAM_CREATION_ERROR, QPACK_BLOCKED_STREAMS,
+    QPACK_MAX_TABLE_CAPACITY, STREAM_CONTROL, STREAM_PUSH, STREAM_QPACK_DECODER, STREAM_QPACK_ENCODER,
+};
+
+/* Response bodies go out in DATA frames of at most this size. */
+const MAX_DATA_FRAME: usize = 16 * 1024;
+
+/* Where a request stream is: the frames it may carry next depend on it (RFC 9114 §4.1). */
+enum Phase {
+    Headers,
+    /* a field section waiting for QPACK inserts; with the request when it holds the trailers */
+    Blocked { section: Vec<u8>, request: Option<H3Request> },
+    Body(H3Request),
+    Trailers(H3Request),
+    /* answered, rejected or cancelled */
+    Done,
+}
+
+struct RequestStream {
+    buf: Vec<u8>, // the start of a frame not complete yet
+    fin: bool,
+    early_data: bool,
+    phase: Phase,
+}
+
+/* The HTTP/3 server side of a connection. */
+pub(crate) struct H3Connection {
+    handler: H3RequestHandler,
+    control: u64, // our streams; the encoder stream stays empty, we never insert
+    decoder: u64,
+    peer_control: Option<u64>,
+    peer_encoder: Option<u64>,
+    peer_decoder: Option<u64>,
+    peer_settings: Option<H3Settings>,
+    control_buf: Vec<u8>,                  // the start of a control frame not complete yet
+    untyped: HashMap<u64, Vec<u8>>,        // unidirectional streams of the peer whose type did not arrive yet
+    requests: HashMap<u64, RequestStream>,
+    qpack_decoder: QpackDecoder,
+    qpack_encoder: QpackEncoder,
+    next_request: u64,                     // the lowest request stream ID not seen yet
+    goaway_sent: Option<u64>,
+    goaway_received: Option<u64>,
+}
+
+impl H3Connection {
+    /* Opens our control stream with SETTINGS and the QPACK encoder and decoder streams (RFC 9114 §6.2). */
+    fn open(conn: &mut QuicConnection, handler: H3RequestHandler) -> H3Result<Self> {
+        let settings = H3Settings {
+            qpack_max_table_capacity: QPACK_MAX_TABLE_CAPACITY,
+            qpack_blocked_streams: QPACK_BLOCKED_STREAMS,
+            max_field_section_size: None,
+        };
+        let mut preface = Vec::new();
+        H3Frame::Settings(settings).encode(&mut preface);
+        let control = open_stream(conn, STREAM_CONTROL, &preface)?;
+        open_stream(conn, STREAM_QPACK_ENCODER, &[])?;
+        Ok(Self {
+            handler,
+            control,
+            decoder: open_stream(conn, STREAM_QPACK_DECODER, &[])?,
+            peer_control: None,
+            peer_encoder: None,
+            peer_decoder: None,
+            peer_settings: None,
+            control_buf: Vec::new(),
+            untyped: HashMap::new(),
+            requests: HashMap::new(),
+            qpack_decoder: QpackDecoder::new(QPACK_MAX_TABLE_CAPACITY as usize, QPACK_BLOCKED_STREAMS as usize),
+            qpack_encoder: QpackEncoder::new(),
+            next_request: 0,
+            goaway_sent: None,
+            goaway_received: None,
+        })
+    }
+
+    /* Takes what arrived on stream `id`; errors are connection errors. */
+    fn on_stream(&mut self, conn: &mut QuicConnection, id: u64) -> H3Result<()> {
+        if is_server_initiated(id) { return Ok(()); }
+        /* forget streams the connection is done with */
+        self.requests.retain(|id, _| conn.streams.get(*id).is_some());
+        self.untyped.retain(|id, _| conn.streams.get(*id).is_some());
+        let data = conn.streams.read_all(id);
+        let reset = conn.streams.is_reset(id);
+        let closed = reset || conn.streams.is_finished(id);
+        if is_unidirectional(id) {
+            self.on_unidirectional(conn, id, &data, closed)?;
+        } else {
+            self.on_request_stream(conn, id, data, closed, reset)?;
+        }
+        let instructions = self.qpack_decoder.take_instructions();
+        if !instructions.is_empty() {
+            write_critical(conn, self.decoder, &instructions)?;
+        }
+        Ok(())
+    }
+
+    fn on_unidirectional(&mut self, conn: &mut QuicConnection, id: u64, data: &[u8], closed: bool) -> H3Result<()> {
+        let critical = [self.peer_control, self.peer_encoder, self.peer_decoder].contains(&Some(id));
+        if critical && closed {
+            return Err(H3Error::new(H3_CLOSED_CRITICAL_STREAM, "the peer closed a control or QPACK stream"));
+        }
+        if Some(id) == self.peer_control {
+            return self.on_control(data);
+        }
+        if Some(id) == self.peer_encoder {
+            self.qpack_decoder.on_encoder_stream(data)?;
+            return self.unblock(conn);
+        }
+        if Some(id) == self.peer_decoder {
+            return self.qpack_encoder.on_decoder_stream(data);
+        }
+        let mut buf = self.untyped.remove(&id).unwrap_or_default();
+        buf.extend_from_slice(data);
+        let Some((stream_type, n)) = read_varint(&buf) else {
+            if !closed {
+                self.untyped.insert(id, buf);
+            }
+            return Ok(());
+        };
+        let slot = match stream_type {
+            STREAM_CONTROL => &mut self.peer_control,
+            STREAM_QPACK_ENCODER => &mut self.peer_encoder,
+            STREAM_QPACK_DECODER => &mut self.peer_decoder,
+            STREAM_PUSH => return Err(H3Error::new(H3_STREAM_CREATION_ERROR, "push stream opened by a client")),
+            _ => {
+                /* reserved and unknown stream types are not read (RFC 9114 §6.2) */
+                let _ = conn.streams.stop_sending(id, H3_STREAM_CREATION_ERROR);
+                return Ok(());
+            }
+        };
+        if slot.is_some() {
+            return Err(H3Error::new(H3_STREAM_CREATION_ERROR, format!("second stream of type {:#x}", stream_type)));
+        }
+        *slot = Some(id);
+        self.on_unidirectional(conn, id, &buf[n..], closed)
+    }
+
+    fn on_control(&mut self, data: &[u8]) -> H3Result<()> {
+        self.control_buf.extend_from_slice(data);
+        let mut off = 0;
+        while let Some((frame, n)) = H3Frame::decode(&self.control_buf[off..])? {
+            off += n;
+            match frame {
+                H3Frame::Settings(settings) if self.peer_settings.is_none() => self.peer_settings = Some(settings),
+                _ if self.peer_settings.is_none() => return Err(H3Error::new(H3_MISSING_SETTINGS, "the control stream does not start with SETTINGS")),
+                H3Frame::Goaway(id) => {
+                    if self.goaway_received.is_some_and(|last| id > last) {
+                        return Err(H3Error::new(H3_ID_ERROR, "GOAWAY with a larger ID than before"));
+                    }
+                    self.goaway_received = Some(id);
+                }
+                /* we do not push, push IDs need no bookkeeping */
+                H3Frame::MaxPushId(_) | H3Frame::CancelPush(_) | H3Frame::Unknown(_) => {}
+                frame => return Err(H3Error::new(H3_FRAME_UNEXPECTED, format!("frame type {:#x} on the control stream", frame.frame_type()))),
+            }
+        }
+        self.control_buf.drain(..off);
+        Ok(())
+    }
+
+    fn on_request_stream(&mut self, conn: &mut QuicConnection, id: u64, data: Vec<u8>, closed: bool, reset: bool) -> H3Result<()> {
+        let mut stream = match self.requests.remove(&id) {
+            Some(stream) => stream,
+            None if data.is_empty() && !closed => return Ok(()),
+            None => {
+                self.next_request = self.next_request.max(id + 4);
+                let early_data = conn.streams.get(id).is_some_and(|s| s.early_data);
+                let mut stream = RequestStream { buf: Vec::new(), fin: false, early_data, phase: Phase::Headers };
+                /* after GOAWAY the client takes requests with IDs above it elsewhere (RFC 9114 §5.2) */
+                if self.goaway_sent.is_some_and(|last| id >= last) {
+                    self.reject(conn, id, H3_REQUEST_REJECTED);
+                    stream.phase = Phase::Done;
+                }
+                stream
+            }
+        };
+        if reset {
+            if !matches!(stream.phase, Phase::Done) {
+                self.qpack_decoder.cancel(id);
+                let _ = conn.streams.reset(id, H3_REQUEST_CANCELLED);
+                stream.phase = Phase::Done;
+            }
+        } else if !matches!(stream.phase, Phase::Done) {
+            stream.buf.extend_from_slice(&data);
+            stream.fin |= closed;
+            self.advance(conn, id, &mut stream)?;
+        }
+        self.requests.insert(id, stream);
+        Ok(())
+    }
+
+    /* Takes the frames of a request stream as far as they go, and answers the request once it is complete. */
+    fn advance(&mut self, conn: &mut QuicConnection, id: u64, stream: &mut RequestStream) -> H3Result<()> {
+        if matches!(stream.phase, Phase::Blocked { .. }) {
+            let Phase::Blocked { section, request } = std::mem::replace(&mut stream.phase, Phase::Done) else { unreachable!() };
+            stream.phase = self.field_section(conn, id, &section, request, stream.early_data)?;
+        }
+        let mut off = 0;
+        while matches!(stream.phase, Phase::Headers | Phase::Body(_) | Phase::Trailers(_)) {
+            let Some((frame, n)) = H3Frame::decode(&stream.buf[off..])? else { break; };
+            off += n;
+            stream.phase = match (std::mem::replace(&mut stream.phase, Phase::Done), frame) {
+                (phase, H3Frame::Unknown(_)) => phase,
+                (Phase::Headers, H3Frame::Headers(section)) => self.field_section(conn, id, section, None, stream.early_data)?,
+                (Phase::Body(mut request), H3Frame::Data(data)) => {
+                    request.body.extend_from_slice(data);
+                    Phase::Body(request)
+                }
+                (Phase::Body(request), H3Frame::Headers(section)) => self.field_section(conn, id, section, Some(request), stream.early_data)?,
+                (_, frame) => return Err(H3Error::new(H3_FRAME_UNEXPECTED, format!("frame type {:#x} on a request stream", frame.frame_type()))),
+            };
+        }
+        stream.buf.drain(..off);
+        if !stream.fin || matches!(stream.phase, Phase::Blocked { .. } | Phase::Done) {
+            return Ok(());
+        }
+        if !stream.buf.is_empty() {
+            return Err(H3Error::new(H3_FRAME_ERROR, "request stream ends inside a frame"));
+        }
+        match std::mem::replace(&mut stream.phase, Phase::Done) {
+            Phase::Body(request) | Phase::Trailers(request) => self.respond(conn, request),
+            _ => {
+                self.reject(conn, id, H3_REQUEST_INCOMPLETE);
+                Ok(())
+            }
+        }
+    }
+
+    /*
+    Decodes the header section of a request, or its trailers when `request` is given; trailers are decoded
+    for QPACK's sake and dropped. A malformed request is rejected with H3_MESSAGE_ERROR.
+    */
+    fn field_section(&mut self, conn: &mut QuicConnection, id: u64, section: &[u8], request: Option<H3Request>, early_data: bool) -> H3Result<Phase> {
+        let Some(fields) = self.qpack_decoder.decode(id, section)? else {
+            return Ok(Phase::Blocked { section: section.to_vec(), request });
+        };
+        if let Some(request) = request {
+            return Ok(Phase::Trailers(request));
+        }
+        match H3Request::from_fields(id, fields, early_data) {
+            Ok(request) => Ok(Phase::Body(request)),
+            Err(_) => {
+                self.reject(conn, id, H3_MESSAGE_ERROR);
+                Ok(Phase::Done)
+            }
+        }
+    }
+
+    /* Retries the request streams waiting for QPACK inserts. */
+    fn unblock(&mut self, conn: &mut QuicConnection) -> H3Result<()> {
+        let waiting: Vec<u64> = self.requests.iter().filter(|(_, s)| matches!(s.phase, Phase::Blocked { .. })).map(|(id, _)| *id).collect();
+        for id in waiting {
+            let mut stream = self.requests.remove(&id).unwrap();
+            let result = self.advance(conn, id, &mut stream);
+            self.requests.insert(id, stream);
+            result?;
+        }
+        Ok(())
+    }
+
+    fn respond(&mut self, conn: &mut QuicConnection, request: H3Request) -> H3Result<()> {
+        let id = request.stream_id;
+        let declared = request.header("content-length").map(|len| len.parse::<usize>());
+        if declared.is_some_and(|len| len != Ok(request.body.len())) {
+            self.reject(conn, id, H3_MESSAGE_ERROR);
+            return Ok(());
+        }
+        let response = (self.handler)(&request);
+        let mut out = Vec::new();
+        H3Frame::Headers(&self.encode_response(&response)).encode(&mut out);
+        if request.method != "HEAD" {
+            for chunk in response.body.chunks(MAX_DATA_FRAME) {
+                H3Frame::Data(chunk).encode(&mut out);
+            }
+        }
+        /* a client that sent STOP_SENDING no longer wants the response */
+        if conn.streams.write(id, &out).is_ok() {
+            let _ = conn.streams.finish(id);
+        }
+        if response.goaway {
+            self.send_goaway(conn)?;
+        }
+        Ok(())
+    }
+
+    fn encode_response(&self, response: &H3Response) -> Vec<u8> {
+        let status = response.status.to_string();
+        let length = response.body.len().to_string();
+        let mut fields: Vec<(&[u8], &[u8])> = vec![(b":status", status.as_bytes())];
+        fields.extend(response.headers.iter().map(|(n, v)| (n.as_bytes(), v.as_bytes())));
+        let bodiless = response.status < 200 || response.status == 204 || response.status == 304;
+        if !bodiless && response.headers.iter().all(|(n, _)| n != "content-length") {
+            fields.push((b"content-length", length.as_bytes()));
+        }
+        self.qpack_encoder.encode(&fields)
+    }
+
+    /* Refuses requests the client has not sent yet: it should take them to a new connection. */
+    fn send_goaway(&mut self, conn: &mut QuicConnection) -> H3Result<()> {
+        if self.goaway_sent.is_some() { return Ok(()); }
+        self.goaway_sent = Some(self.next_request);
+        let mut out = Vec::new();
+        H3Frame::Goaway(self.next_request).encode(&mut out);
+        write_critical(conn, self.control, &out)
+    }
+
+    /* Ends a request stream with `code` in both directions. */
+    fn reject(&mut self, conn: &mut QuicConnection, id: u64, code: u64) {
+        self.qpack_decoder.cancel(id);
+        let _ = conn.streams.stop_sending(id, code);
+        let _ = conn.streams.reset(id, code);
+    }
+}
+
+fn open_stream(conn: &mut QuicConnection, stream_type: u64, data: &[u8]) -> H3Result<u64> {
+    let id = conn.streams.open(true).map_err(|_| H3Error::new(H3_STREAM_CREATION_ERROR, "the peer allows too few unidirectional streams"))?;
+    let mut out = Vec::new();
+    encode_varint_into(stream_type, &mut out);
+    out.extend_from_slice(data);
+    write_critical(conn, id, &out)?;
+    Ok(id)
+}
+
+/* Our control and QPACK streams must stay open for as long as the connection. */
+fn write_critical(conn: &mut QuicConnection, id: u64, data: &[u8]) -> H3Result<()> {
+    conn.streams.write(id, data).map_err(|_| H3Error::new(H3_CLOSED_CRITICAL_STREAM, "the peer stopped a control or QPACK stream"))
+}
+
+impl QuicConnection {
+    /*
+    Serves HTTP/3 on the connection: every request is answered with `handler`, requests that arrived before
+    right away. Fails, closing the connection, when the client does not let us open our control and QPACK streams.
+    */
+    pub fn serve_h3(&mut self, handler: H3RequestHandler) -> H3Result<()> {
+        match H3Connection::open(self, handler) {
+            Ok(h3) => self.h3 = Some(Box::new(h3)),
+            Err(e) => {
+                self.close(e.code, &e.reason);
+                return Err(e);
+            }
+        }
+        for id in self.streams.peer_stream_ids() {
+            self.dispatch_stream(id);
+        }
+        Ok(())
+    }
+
+    /* Whether HTTP/3 is served on the connection. */
+    pub fn is_h3(&self) -> bool {
+        self.h3.is_some()
+    }
+
+    /* Hands what arrived on a stream to HTTP/3; an error closes the connection with its HTTP/3 error code. */
+    pub(crate) fn dispatch_h3(&mut self, id: u64) {
+        let Some(mut h3) = self.h3.take() else { return; };
+        if let Err(e) = h3.on_stream(self, id) {
+            self.close(e.code, &e.reason);
+        }
+        self.h3 = Some(h3);
+    }
+}
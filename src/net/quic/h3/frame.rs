@@ -0,0 +1,172 @@
+use crate::net::{encode_varint_into, varint};
+use super::{H3Error, H3Result, H3_EXCESSIVE_LOAD, H3_FRAME_ERROR, H3_FRAME_UNEXPECTED, H3_SETTINGS_ERROR};
+
+/*
+HTTP/3 frames (RFC 9114 §7) and the stream types of unidirectional streams (§6.2).
+
+A frame is decoded once all of it arrived; payloads borrow from the stream's buffer. Frame types HTTP/3
+reserves or does not know are skipped, those only HTTP/2 had are an error.
+*/
+
+pub const FRAME_DATA: u64 = 0x00;
+pub const FRAME_HEADERS: u64 = 0x01;
+pub const FRAME_CANCEL_PUSH: u64 = 0x03;
+pub const FRAME_SETTINGS: u64 = 0x04;
+pub const FRAME_PUSH_PROMISE: u64 = 0x05;
+pub const FRAME_GOAWAY: u64 = 0x07;
+pub const FRAME_MAX_PUSH_ID: u64 = 0x0d;
+
+pub const STREAM_CONTROL: u64 = 0x00;
+pub const STREAM_PUSH: u64 = 0x01;
+pub const STREAM_QPACK_ENCODER: u64 = 0x02;
+pub const STREAM_QPACK_DECODER: u64 = 0x03;
+
+pub const SETTINGS_QPACK_MAX_TABLE_CAPACITY: u64 = 0x01;
+pub const SETTINGS_MAX_FIELD_SECTION_SIZE: u64 = 0x06;
+pub const SETTINGS_QPACK_BLOCKED_STREAMS: u64 = 0x07;
+
+/* Frames other than DATA are held in full; a larger one is refused rather than buffered. */
+pub const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum H3Frame<'a> {
+    Data(&'a [u8]),
+    Headers(&'a [u8]), // an encoded field section
+    CancelPush(u64),
+    Settings(H3Settings),
+    PushPromise { push_id: u64, fields: &'a [u8] },
+    Goaway(u64), // a stream ID from a server, a push ID from a client
+    MaxPushId(u64),
+    Unknown(u64),
+}
+
+/* What an endpoint announces in SETTINGS (RFC 9114 §7.2.4.1, RFC 9204 §5). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct H3Settings {
+    pub qpack_max_table_capacity: u64,
+    pub qpack_blocked_streams: u64,
+    pub max_field_section_size: Option<u64>, // unlimited when absent
+}
+
+fn frame_error(msg: &str) -> H3Error {
+    H3Error::new(H3_FRAME_ERROR, msg)
+}
+
+/* A varint at the start of `buf`; None when it is not all there yet. */
+pub(crate) fn read_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    varint(buf).map(|(v, n)| (v as u64, n))
+}
+
+/* The varint that makes up all of `payload`. */
+fn single_varint(payload: &[u8]) -> H3Result<u64> {
+    match read_varint(payload) {
+        Some((v, n)) if n == payload.len() => Ok(v),
+        _ => Err(frame_error("malformed frame payload")),
+    }
+}
+
+impl H3Settings {
+    fn decode(mut payload: &[u8]) -> H3Result<Self> {
+        let mut settings = Self::default();
+        let mut seen = Vec::new();
+        while !payload.is_empty() {
+            let (id, n) = read_varint(payload).ok_or_else(|| frame_error("truncated SETTINGS"))?;
+            let (value, m) = read_varint(&payload[n..]).ok_or_else(|| frame_error("truncated SETTINGS"))?;
+            payload = &payload[n + m..];
+            if seen.contains(&id) {
+                return Err(H3Error::new(H3_SETTINGS_ERROR, "setting sent twice"));
+            }
+            seen.push(id);
+            match id {
+                SETTINGS_QPACK_MAX_TABLE_CAPACITY => settings.qpack_max_table_capacity = value,
+                SETTINGS_QPACK_BLOCKED_STREAMS => settings.qpack_blocked_streams = value,
+                SETTINGS_MAX_FIELD_SECTION_SIZE => settings.max_field_section_size = Some(value),
+                /* the HTTP/2 settings HTTP/3 has no use for (RFC 9114 §7.2.4.1) */
+                0x00 | 0x02..=0x05 => return Err(H3Error::new(H3_SETTINGS_ERROR, format!("reserved setting {:#x}", id))),
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut write = |id: u64, value: u64| {
+            encode_varint_into(id, out);
+            encode_varint_into(value, out);
+        };
+        if self.qpack_max_table_capacity != 0 {
+            write(SETTINGS_QPACK_MAX_TABLE_CAPACITY, self.qpack_max_table_capacity);
+        }
+        if self.qpack_blocked_streams != 0 {
+            write(SETTINGS_QPACK_BLOCKED_STREAMS, self.qpack_blocked_streams);
+        }
+        if let Some(size) = self.max_field_section_size {
+            write(SETTINGS_MAX_FIELD_SECTION_SIZE, size);
+        }
+    }
+}
+
+impl<'a> H3Frame<'a> {
+    /*
+    Decodes the frame at the start of `buf`, returning it and its encoded length; Ok(None) until all of it
+    arrived. DATA frames are decoded whole as well.
+    */
+    pub fn decode(buf: &'a [u8]) -> H3Result<Option<(Self, usize)>> {
+        let Some((frame_type, n)) = read_varint(buf) else { return Ok(None); };
+        let Some((len, m)) = read_varint(&buf[n..]) else { return Ok(None); };
+        if frame_type != FRAME_DATA && len > MAX_FRAME_SIZE {
+            return Err(H3Error::new(H3_EXCESSIVE_LOAD, format!("frame of type {:#x} is {} bytes", frame_type, len)));
+        }
+        let start = n + m;
+        let Some(end) = usize::try_from(len).ok().and_then(|len| start.checked_add(len)).filter(|&end| end <= buf.len()) else { return Ok(None); };
+        let payload = &buf[start..end];
+        let frame = match frame_type {
+            FRAME_DATA => H3Frame::Data(payload),
+            FRAME_HEADERS => H3Frame::Headers(payload),
+            FRAME_CANCEL_PUSH => H3Frame::CancelPush(single_varint(payload)?),
+            FRAME_SETTINGS => H3Frame::Settings(H3Settings::decode(payload)?),
+            FRAME_PUSH_PROMISE => {
+                let (push_id, k) = read_varint(payload).ok_or_else(|| frame_error("truncated PUSH_PROMISE"))?;
+                H3Frame::PushPromise { push_id, fields: &payload[k..] }
+            }
+            FRAME_GOAWAY => H3Frame::Goaway(single_varint(payload)?),
+            FRAME_MAX_PUSH_ID => H3Frame::MaxPushId(single_varint(payload)?),
+            /* frame types of HTTP/2 that have no HTTP/3 counterpart (RFC 9114 §7.2.8) */
+            0x02 | 0x06 | 0x08 | 0x09 => {
+                return Err(H3Error::new(H3_FRAME_UNEXPECTED, format!("HTTP/2 frame type {:#x}", frame_type)));
+            }
+            _ => H3Frame::Unknown(frame_type),
+        };
+        Ok(Some((frame, end)))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut payload = Vec::new();
+        match self {
+            H3Frame::Data(data) | H3Frame::Headers(data) => payload.extend_from_slice(data),
+            H3Frame::CancelPush(id) | H3Frame::Goaway(id) | H3Frame::MaxPushId(id) => encode_varint_into(*id, &mut payload),
+            H3Frame::Settings(settings) => settings.encode(&mut payload),
+            H3Frame::PushPromise { push_id, fields } => {
+                encode_varint_into(*push_id, &mut payload);
+                payload.extend_from_slice(fields);
+            }
+            H3Frame::Unknown(_) => {}
+        }
+        encode_varint_into(self.frame_type(), out);
+        encode_varint_into(payload.len() as u64, out);
+        out.extend_from_slice(&payload);
+    }
+
+    pub fn frame_type(&self) -> u64 {
+        match self {
+            H3Frame::Data(_) => FRAME_DATA,
+            H3Frame::Headers(_) => FRAME_HEADERS,
+            H3Frame::CancelPush(_) => FRAME_CANCEL_PUSH,
+            H3Frame::Settings(_) => FRAME_SETTINGS,
+            H3Frame::PushPromise { .. } => FRAME_PUSH_PROMISE,
+            H3Frame::Goaway(_) => FRAME_GOAWAY,
+            H3Frame::MaxPushId(_) => FRAME_MAX_PUSH_ID,
+            H3Frame::Unknown(frame_type) => *frame_type,
+        }
+    }
+}
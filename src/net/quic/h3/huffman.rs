@@ -0,0 +1,124 @@
+use super::{H3Error, H3Result, QPACK_DECOMPRESSION_FAILED};
+
+/*
+The Huffman code of HPACK, which QPACK string literals use too (RFC 7541 Appendix B).
+
+The code is canonical: codes of one length are consecutive in symbol order and each length starts right
+after the previous one ends, so the bit length of every symbol is all the table needs.
+*/
+
+/* Code lengths of the 256 octets and of EOS (256). */
+const LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+const EOS: u16 = 256;
+const MAX_LEN: usize = 30;
+
+/* The canonical code: symbols by length, the first code of each length and where its symbols start. */
+struct Code {
+    codes: [u32; 257],
+    symbols: [u16; 257],
+    first: [u32; MAX_LEN + 1],
+    start: [usize; MAX_LEN + 1],
+    count: [usize; MAX_LEN + 1],
+}
+
+const fn canonical() -> Code {
+    let mut code = Code { codes: [0; 257], symbols: [0; 257], first: [0; MAX_LEN + 1], start: [0; MAX_LEN + 1], count: [0; MAX_LEN + 1] };
+    let mut next = 0u32;
+    let mut n = 0;
+    let mut len = 1;
+    while len <= MAX_LEN {
+        next <<= 1;
+        code.first[len] = next;
+        code.start[len] = n;
+        let mut symbol = 0;
+        while symbol < 257 {
+            if LENGTHS[symbol] as usize == len {
+                code.codes[symbol] = next;
+                code.symbols[n] = symbol as u16;
+                next += 1;
+                n += 1;
+            }
+            symbol += 1;
+        }
+        code.count[len] = n - code.start[len];
+        len += 1;
+    }
+    code
+}
+
+const CODE: Code = canonical();
+
+fn invalid(msg: &str) -> H3Error {
+    H3Error::new(QPACK_DECOMPRESSION_FAILED, msg)
+}
+
+/* Bytes `data` takes Huffman-coded. */
+pub(crate) fn encoded_len(data: &[u8]) -> usize {
+    data.iter().map(|&b| LENGTHS[b as usize] as usize).sum::<usize>().div_ceil(8)
+}
+
+/* Codes `data`, padding the last octet with the most significant bits of EOS, all ones. */
+pub(crate) fn encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut bits = 0u64;
+    let mut pending = 0;
+    for &b in data {
+        let len = LENGTHS[b as usize] as u32;
+        bits = bits << len | CODE.codes[b as usize] as u64;
+        pending += len;
+        while pending >= 8 {
+            pending -= 8;
+            out.push((bits >> pending) as u8);
+        }
+    }
+    if pending > 0 {
+        out.push((bits << (8 - pending)) as u8 | 0xff >> pending);
+    }
+}
+
+/* Decodes a string; padding longer than 7 bits or other than ones, and EOS, are errors (RFC 7541 §5.2). */
+pub(crate) fn decode(data: &[u8]) -> H3Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut len = 0;
+    for &byte in data {
+        for shift in (0..8).rev() {
+            code = code << 1 | (byte >> shift & 1) as u32;
+            len += 1;
+            let index = code.wrapping_sub(CODE.first[len]) as usize;
+            if index < CODE.count[len] {
+                let symbol = CODE.symbols[CODE.start[len] + index];
+                if symbol == EOS {
+                    return Err(invalid("EOS in a Huffman-coded string"));
+                }
+                out.push(symbol as u8);
+                code = 0;
+                len = 0;
+            } else if len == MAX_LEN {
+                return Err(invalid("invalid Huffman code"));
+            }
+        }
+    }
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(invalid("invalid Huffman padding"));
+    }
+    Ok(out)
+}
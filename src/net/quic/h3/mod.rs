@@ -0,0 +1,93 @@
+/*
+HTTP/3 (RFC 9114) on our QUIC connections, with QPACK (RFC 9204).
+
+A server connection serving HTTP/3 opens its control stream and both QPACK streams, reads the client's, and
+answers every request stream with the handler once the request is complete: its header section and the
+whole body. Connection errors close the connection with the HTTP/3 error code; a malformed request only
+resets its stream.
+*/
+
+mod huffman;
+mod frame;
+pub use frame::*;
+mod qpack;
+pub use qpack::*;
+mod request;
+pub use request::*;
+mod connection;
+pub(crate) use connection::H3Connection;
+
+/* The ALPN token of HTTP/3. */
+pub const H3_ALPN: &[u8] = b"h3";
+
+/* HTTP/3 error codes (RFC 9114 §8.1) and those of QPACK (RFC 9204 §6). */
+pub const H3_NO_ERROR: u64 = 0x100;
+pub const H3_GENERAL_PROTOCOL_ERROR: u64 = 0x101;
+pub const H3_INTERNAL_ERROR: u64 = 0x102;
+pub const H3_STREAM_CREATION_ERROR: u64 = 0x103;
+pub const H3_CLOSED_CRITICAL_STREAM: u64 = 0x104;
+pub const H3_FRAME_UNEXPECTED: u64 = 0x105;
+pub const H3_FRAME_ERROR: u64 = 0x106;
+pub const H3_EXCESSIVE_LOAD: u64 = 0x107;
+pub const H3_ID_ERROR: u64 = 0x108;
+pub const H3_SETTINGS_ERROR: u64 = 0x109;
+pub const H3_MISSING_SETTINGS: u64 = 0x10a;
+pub const H3_REQUEST_REJECTED: u64 = 0x10b;
+pub const H3_REQUEST_CANCELLED: u64 = 0x10c;
+pub const H3_REQUEST_INCOMPLETE: u64 = 0x10d;
+pub const H3_MESSAGE_ERROR: u64 = 0x10e;
+pub const H3_CONNECT_ERROR: u64 = 0x10f;
+pub const H3_VERSION_FALLBACK: u64 = 0x110;
+pub const QPACK_DECOMPRESSION_FAILED: u64 = 0x200;
+pub const QPACK_ENCODER_STREAM_ERROR: u64 = 0x201;
+pub const QPACK_DECODER_STREAM_ERROR: u64 = 0x202;
+
+/* The names of the codes, for messages. */
+const H3_ERRORS: [(&str, u64); 20] = [
+    ("H3_NO_ERROR", H3_NO_ERROR),
+    ("H3_GENERAL_PROTOCOL_ERROR", H3_GENERAL_PROTOCOL_ERROR),
+    ("H3_INTERNAL_ERROR", H3_INTERNAL_ERROR),
+    ("H3_STREAM_CREATION_ERROR", H3_STREAM_CREATION_ERROR),
+    ("H3_CLOSED_CRITICAL_STREAM", H3_CLOSED_CRITICAL_STREAM),
+    ("H3_FRAME_UNEXPECTED", H3_FRAME_UNEXPECTED),
+    ("H3_FRAME_ERROR", H3_FRAME_ERROR),
+    ("H3_EXCESSIVE_LOAD", H3_EXCESSIVE_LOAD),
+    ("H3_ID_ERROR", H3_ID_ERROR),
+    ("H3_SETTINGS_ERROR", H3_SETTINGS_ERROR),
+    ("H3_MISSING_SETTINGS", H3_MISSING_SETTINGS),
+    ("H3_REQUEST_REJECTED", H3_REQUEST_REJECTED),
+    ("H3_REQUEST_CANCELLED", H3_REQUEST_CANCELLED),
+    ("H3_REQUEST_INCOMPLETE", H3_REQUEST_INCOMPLETE),
+    ("H3_MESSAGE_ERROR", H3_MESSAGE_ERROR),
+    ("H3_CONNECT_ERROR", H3_CONNECT_ERROR),
+    ("H3_VERSION_FALLBACK", H3_VERSION_FALLBACK),
+    ("QPACK_DECOMPRESSION_FAILED", QPACK_DECOMPRESSION_FAILED),
+    ("QPACK_ENCODER_STREAM_ERROR", QPACK_ENCODER_STREAM_ERROR),
+    ("QPACK_DECODER_STREAM_ERROR", QPACK_DECODER_STREAM_ERROR),
+];
+
+/* A connection error: the HTTP/3 or QPACK code the connection is closed with, and why. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct H3Error {
+    pub code: u64,
+    pub reason: String,
+}
+
+pub type H3Result<T> = std::result::Result<T, H3Error>;
+
+impl H3Error {
+    pub fn new(code: u64, reason: impl Into<String>) -> Self {
+        Self { code, reason: reason.into() }
+    }
+}
+
+impl std::fmt::Display for H3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match H3_ERRORS.iter().find(|(_, code)| *code == self.code) {
+            Some((name, _)) => write!(f, "{}: {}", name, self.reason),
+            None => write!(f, "{:#x}: {}", self.code, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for H3Error {}
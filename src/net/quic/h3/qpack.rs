@@ -0,0 +1,558 @@
+use std::collections::{HashSet, VecDeque};
+
+use super::huffman;
+use super::{H3Error, H3Result, QPACK_DECODER_STREAM_ERROR, QPACK_DECOMPRESSION_FAILED, QPACK_ENCODER_STREAM_ERROR};
+
+/*
+QPACK field compression (RFC 9204).
+
+The decoder keeps the dynamic table the peer's encoder stream fills and may hold a field section back until
+the inserts it refers to arrived, for at most `max_blocked` streams; what it has to tell the encoder queues up
+as decoder stream instructions. Our encoder only refers to the static table and otherwise writes literals,
+so it never blocks a stream of the peer and has no inserts to track acknowledgements of.
+*/
+
+/* The dynamic table capacity and blocked streams we allow the peer's encoder (SETTINGS_QPACK_*). */
+pub const QPACK_MAX_TABLE_CAPACITY: u64 = 4096;
+pub const QPACK_BLOCKED_STREAMS: u64 = 16;
+
+/* Every entry costs its name and value plus 32 bytes (RFC 9204 §3.2.1). */
+const ENTRY_OVERHEAD: usize = 32;
+
+/* A field line: name and value, as they were on the wire. */
+pub type QpackField = (Vec<u8>, Vec<u8>);
+
+/* The static table (RFC 9204 Appendix A). */
+pub const STATIC_TABLE: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    ("strict-transport-security", "max-age=31536000; includesubdomains"),
+    ("strict-transport-security", "max-age=31536000; includesubdomains; preload"),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    ("content-security-policy", "script-src 'none'; object-src 'none'; base-uri 'none'"),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
+
+/* An integer with an N-bit prefix (RFC 9204 §4.1.1, RFC 7541 §5.1); `flags` fills the bits above the prefix. */
+pub(crate) fn encode_int(out: &mut Vec<u8>, flags: u8, prefix: u32, mut value: u64) {
+    let max = (1u64 << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/* A string literal whose length has an N-bit prefix, Huffman-coded when that is shorter (RFC 9204 §4.1.2). */
+pub(crate) fn encode_string(out: &mut Vec<u8>, flags: u8, prefix: u32, value: &[u8]) {
+    let huffman_len = huffman::encoded_len(value);
+    if huffman_len < value.len() {
+        encode_int(out, flags | 1 << prefix, prefix, huffman_len as u64);
+        huffman::encode(value, out);
+    } else {
+        encode_int(out, flags, prefix, value.len() as u64);
+        out.extend_from_slice(value);
+    }
+}
+
+/* Reads integers and strings; None when the input ends first, errors carry `code`. */
+struct Reader<'a> {
+    buf: &'a [u8],
+    off: usize,
+    code: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], code: u64) -> Self {
+        Self { buf, off: 0, code }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.off).copied()
+    }
+
+    fn int(&mut self, prefix: u32) -> H3Result<Option<u64>> {
+        let Some(first) = self.peek() else { return Ok(None); };
+        let max = (1u64 << prefix) - 1;
+        let mut value = first as u64 & max;
+        let mut off = self.off + 1;
+        if value == max {
+            let mut shift = 0;
+            loop {
+                let Some(&b) = self.buf.get(off) else { return Ok(None); };
+                off += 1;
+                if shift > 56 {
+                    return Err(H3Error::new(self.code, "integer too large"));
+                }
+                value += ((b & 0x7f) as u64) << shift;
+                shift += 7;
+                if b & 0x80 == 0 { break; }
+            }
+        }
+        self.off = off;
+        Ok(Some(value))
+    }
+
+    /* A string of at most `limit` bytes on the wire. */
+    fn string(&mut self, prefix: u32, limit: usize) -> H3Result<Option<Vec<u8>>> {
+        let Some(first) = self.peek() else { return Ok(None); };
+        let start = self.off;
+        let Some(len) = self.int(prefix)? else { return Ok(None); };
+        if len > limit as u64 {
+            return Err(H3Error::new(self.code, "string literal too long"));
+        }
+        let end = self.off + len as usize;
+        if end > self.buf.len() {
+            self.off = start;
+            return Ok(None);
+        }
+        let data = &self.buf[self.off..end];
+        self.off = end;
+        if first & 1 << prefix == 0 {
+            return Ok(Some(data.to_vec()));
+        }
+        huffman::decode(data).map(Some).map_err(|e| H3Error::new(self.code, e.reason))
+    }
+
+    fn done(&self) -> bool {
+        self.off == self.buf.len()
+    }
+}
+
+/* The dynamic table (RFC 9204 §3.2): entries by absolute index, the oldest evicted first. */
+#[derive(Debug, Default)]
+pub struct DynamicTable {
+    entries: VecDeque<QpackField>, // oldest first
+    size: usize,
+    capacity: usize,
+    max_capacity: usize, // what we advertised
+    inserted: u64,       // the Insert Count, entries ever added
+}
+
+impl DynamicTable {
+    pub fn new(max_capacity: usize) -> Self {
+        Self { max_capacity, ..Self::default() }
+    }
+
+    pub fn insert_count(&self) -> u64 {
+        self.inserted
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /* Bytes the entries take, overhead included. */
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) -> H3Result<()> {
+        if capacity > self.max_capacity {
+            return Err(H3Error::new(QPACK_ENCODER_STREAM_ERROR, "dynamic table capacity above the maximum"));
+        }
+        self.capacity = capacity;
+        self.evict(capacity);
+        Ok(())
+    }
+
+    pub fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) -> H3Result<()> {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        if size > self.capacity {
+            return Err(H3Error::new(QPACK_ENCODER_STREAM_ERROR, "entry larger than the dynamic table"));
+        }
+        self.evict(self.capacity - size);
+        self.entries.push_back((name, value));
+        self.size += size;
+        self.inserted += 1;
+        Ok(())
+    }
+
+    /* The entry with absolute index `index`, None if it was evicted or not inserted yet. */
+    pub fn get(&self, index: u64) -> Option<&QpackField> {
+        let first = self.inserted - self.entries.len() as u64;
+        self.entries.get(index.checked_sub(first)? as usize)
+    }
+
+    fn evict(&mut self, to: usize) {
+        while self.size > to {
+            let Some((name, value)) = self.entries.pop_front() else { break; };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/* Decodes the peer's field sections, with the dynamic table its encoder stream maintains. */
+#[derive(Debug)]
+pub struct QpackDecoder {
+    table: DynamicTable,
+    max_blocked: usize,
+    blocked: HashSet<u64>, // streams waiting for inserts
+    known_received: u64,   // inserts the encoder knows we have
+    pending: Vec<u8>,      // the start of an encoder stream instruction
+    instructions: Vec<u8>, // decoder stream data to send
+}
+
+impl QpackDecoder {
+    pub fn new(max_table_capacity: usize, max_blocked: usize) -> Self {
+        Self {
+            table: DynamicTable::new(max_table_capacity),
+            max_blocked,
+            blocked: HashSet::new(),
+            known_received: 0,
+            pending: Vec::new(),
+            instructions: Vec::new(),
+        }
+    }
+
+    pub fn table(&self) -> &DynamicTable {
+        &self.table
+    }
+
+    /* Applies encoder stream instructions (RFC 9204 §4.3); one may be split across calls. */
+    pub fn on_encoder_stream(&mut self, data: &[u8]) -> H3Result<()> {
+        self.pending.extend_from_slice(data);
+        let pending = std::mem::take(&mut self.pending);
+        let mut r = Reader::new(&pending, QPACK_ENCODER_STREAM_ERROR);
+        let limit = self.table.max_capacity;
+        loop {
+            let start = r.off;
+            let Some(first) = r.peek() else { break; };
+            let applied = if first & 0x80 != 0 {
+                self.insert_with_name_reference(&mut r, first & 0x40 != 0, limit)?
+            } else if first & 0x40 != 0 {
+                self.insert_with_literal_name(&mut r, limit)?
+            } else if first & 0x20 != 0 {
+                match r.int(5)? {
+                    Some(capacity) => self.table.set_capacity(usize::try_from(capacity).unwrap_or(usize::MAX)).map(|_| true)?,
+                    None => false,
+                }
+            } else {
+                match r.int(5)? {
+                    Some(relative) => {
+                        let (name, value) = self.relative_entry(relative)?;
+                        self.table.insert(name, value).map(|_| true)?
+                    }
+                    None => false,
+                }
+            };
+            if !applied {
+                r.off = start;
+                break;
+            }
+        }
+        self.pending = pending[r.off..].to_vec();
+        if self.table.inserted > self.known_received {
+            encode_int(&mut self.instructions, 0x00, 6, self.table.inserted - self.known_received);
+            self.known_received = self.table.inserted;
+        }
+        Ok(())
+    }
+
+    fn insert_with_name_reference(&mut self, r: &mut Reader, is_static: bool, limit: usize) -> H3Result<bool> {
+        let Some(index) = r.int(6)? else { return Ok(false); };
+        let Some(value) = r.string(7, limit)? else { return Ok(false); };
+        let name = if is_static {
+            static_entry(index, QPACK_ENCODER_STREAM_ERROR)?.0.as_bytes().to_vec()
+        } else {
+            self.relative_entry(index)?.0
+        };
+        self.table.insert(name, value)?;
+        Ok(true)
+    }
+
+    fn insert_with_literal_name(&mut self, r: &mut Reader, limit: usize) -> H3Result<bool> {
+        let Some(name) = r.string(5, limit)? else { return Ok(false); };
+        let Some(value) = r.string(7, limit)? else { return Ok(false); };
+        self.table.insert(name, value)?;
+        Ok(true)
+    }
+
+    /* Encoder instructions count relative indices back from the latest insert. */
+    fn relative_entry(&self, relative: u64) -> H3Result<QpackField> {
+        self.table
+            .inserted
+            .checked_sub(relative + 1)
+            .and_then(|index| self.table.get(index))
+            .cloned()
+            .ok_or_else(|| H3Error::new(QPACK_ENCODER_STREAM_ERROR, "reference to a missing dynamic table entry"))
+    }
+
+    /*
+    Decodes the field section of a HEADERS frame on `stream_id`. Ok(None) when it refers to inserts that have
+    not arrived yet: the stream is blocked and the section should be passed again once they have.
+    */
+    pub fn decode(&mut self, stream_id: u64, section: &[u8]) -> H3Result<Option<Vec<QpackField>>> {
+        let mut r = Reader::new(section, QPACK_DECOMPRESSION_FAILED);
+        let truncated = || H3Error::new(QPACK_DECOMPRESSION_FAILED, "truncated field section");
+        let encoded = r.int(8)?.ok_or_else(truncated)?;
+        let required = self.required_insert_count(encoded)?;
+        let negative = r.peek().ok_or_else(truncated)? & 0x80 != 0;
+        let delta = r.int(7)?.ok_or_else(truncated)?;
+        let base = match negative {
+            false => required.checked_add(delta),
+            true => required.checked_sub(delta + 1),
+        };
+        let base = base.ok_or_else(|| H3Error::new(QPACK_DECOMPRESSION_FAILED, "invalid base"))?;
+        if required > self.table.inserted {
+            if !self.blocked.contains(&stream_id) && self.blocked.len() >= self.max_blocked {
+                return Err(H3Error::new(QPACK_DECOMPRESSION_FAILED, "too many blocked streams"));
+            }
+            self.blocked.insert(stream_id);
+            return Ok(None);
+        }
+        self.blocked.remove(&stream_id);
+
+        let mut fields = Vec::new();
+        let mut largest = None; // the largest absolute index referred to
+        let mut dynamic = |index: Option<u64>| -> H3Result<QpackField> {
+            let index = index.filter(|&i| i < required).ok_or_else(|| H3Error::new(QPACK_DECOMPRESSION_FAILED, "invalid dynamic table reference"))?;
+            largest = largest.max(Some(index));
+            self.table.get(index).cloned().ok_or_else(|| H3Error::new(QPACK_DECOMPRESSION_FAILED, "reference to an evicted entry"))
+        };
+        while !r.done() {
+            let first = r.peek().unwrap();
+            let field = if first & 0x80 != 0 {
+                let index = r.int(6)?.ok_or_else(truncated)?;
+                match first & 0x40 != 0 {
+                    true => static_field(index)?,
+                    false => dynamic(base.checked_sub(index + 1))?,
+                }
+            } else if first & 0x40 != 0 {
+                let index = r.int(4)?.ok_or_else(truncated)?;
+                let name = match first & 0x10 != 0 {
+                    true => static_field(index)?.0,
+                    false => dynamic(base.checked_sub(index + 1))?.0,
+                };
+                (name, r.string(7, section.len())?.ok_or_else(truncated)?)
+            } else if first & 0x20 != 0 {
+                let name = r.string(3, section.len())?.ok_or_else(truncated)?;
+                (name, r.string(7, section.len())?.ok_or_else(truncated)?)
+            } else if first & 0x10 != 0 {
+                let index = r.int(4)?.ok_or_else(truncated)?;
+                dynamic(base.checked_add(index))?
+            } else {
+                let index = r.int(3)?.ok_or_else(truncated)?;
+                let name = dynamic(base.checked_add(index))?.0;
+                (name, r.string(7, section.len())?.ok_or_else(truncated)?)
+            };
+            fields.push(field);
+        }
+        if required > 0 {
+            /* the encoder must not claim more inserts than the section needs (RFC 9204 §4.5.1.1) */
+            if largest != Some(required - 1) {
+                return Err(H3Error::new(QPACK_DECOMPRESSION_FAILED, "Required Insert Count too large"));
+            }
+            encode_int(&mut self.instructions, 0x80, 7, stream_id);
+            self.known_received = self.known_received.max(required);
+        }
+        Ok(Some(fields))
+    }
+
+    /* Reconstructs the Required Insert Count from its encoding (RFC 9204 §4.5.1.1). */
+    fn required_insert_count(&self, encoded: u64) -> H3Result<u64> {
+        if encoded == 0 { return Ok(0); }
+        let max_entries = (self.table.max_capacity / ENTRY_OVERHEAD) as u64;
+        let full_range = 2 * max_entries;
+        if encoded > full_range {
+            return Err(H3Error::new(QPACK_DECOMPRESSION_FAILED, "invalid Required Insert Count"));
+        }
+        let max_value = self.table.inserted + max_entries;
+        let max_wrapped = max_value / full_range * full_range;
+        let mut required = max_wrapped + encoded - 1;
+        if required > max_value {
+            if required <= full_range {
+                return Err(H3Error::new(QPACK_DECOMPRESSION_FAILED, "invalid Required Insert Count"));
+            }
+            required -= full_range;
+        }
+        if required == 0 {
+            return Err(H3Error::new(QPACK_DECOMPRESSION_FAILED, "invalid Required Insert Count"));
+        }
+        Ok(required)
+    }
+
+    /*
+    Forgets a stream that was reset or abandoned before its field sections were decoded; the encoder is told
+    with a Stream Cancellation when the dynamic table may be in use.
+    */
+    pub fn cancel(&mut self, stream_id: u64) {
+        self.blocked.remove(&stream_id);
+        if self.table.max_capacity > 0 {
+            encode_int(&mut self.instructions, 0x40, 6, stream_id);
+        }
+    }
+
+    /* Whether `stream_id` waits for inserts. */
+    pub fn is_blocked(&self, stream_id: u64) -> bool {
+        self.blocked.contains(&stream_id)
+    }
+
+    /* Decoder stream instructions queued since the last call. */
+    pub fn take_instructions(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.instructions)
+    }
+}
+
+/* Encodes our field sections with the static table and literals only. */
+#[derive(Debug, Default)]
+pub struct QpackEncoder {
+    pending: Vec<u8>, // the start of a decoder stream instruction
+}
+
+impl QpackEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* A field section: Required Insert Count and Base are zero, every line refers to the static table or is literal. */
+    pub fn encode(&self, fields: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut out = vec![0x00, 0x00];
+        for &(name, value) in fields {
+            let exact = STATIC_TABLE.iter().position(|&(n, v)| n.as_bytes() == name && v.as_bytes() == value);
+            if let Some(index) = exact {
+                encode_int(&mut out, 0xc0, 6, index as u64);
+                continue;
+            }
+            match STATIC_TABLE.iter().position(|&(n, _)| n.as_bytes() == name) {
+                Some(index) => encode_int(&mut out, 0x50, 4, index as u64),
+                None => encode_string(&mut out, 0x20, 3, name),
+            }
+            encode_string(&mut out, 0x00, 7, value);
+        }
+        out
+    }
+
+    /*
+    Checks decoder stream instructions (RFC 9204 §4.4). With no inserts and no section referring to the
+    dynamic table, acknowledgements and increments are errors; cancellations need nothing.
+    */
+    pub fn on_decoder_stream(&mut self, data: &[u8]) -> H3Result<()> {
+        self.pending.extend_from_slice(data);
+        let pending = std::mem::take(&mut self.pending);
+        let mut r = Reader::new(&pending, QPACK_DECODER_STREAM_ERROR);
+        while let Some(first) = r.peek() {
+            let start = r.off;
+            let prefix = if first & 0x80 != 0 { 7 } else { 6 };
+            if r.int(prefix)?.is_none() {
+                r.off = start;
+                break;
+            }
+            if first & 0x80 != 0 {
+                return Err(H3Error::new(QPACK_DECODER_STREAM_ERROR, "Section Acknowledgment for a section without dynamic references"));
+            }
+            if first & 0x40 == 0 {
+                return Err(H3Error::new(QPACK_DECODER_STREAM_ERROR, "Insert Count Increment beyond what was inserted"));
+            }
+        }
+        self.pending = pending[r.off..].to_vec();
+        Ok(())
+    }
+}
+
+fn static_entry(index: u64, code: u64) -> H3Result<(&'static str, &'static str)> {
+    STATIC_TABLE.get(index as usize).copied().ok_or_else(|| H3Error::new(code, "invalid static table index"))
+}
+
+fn static_field(index: u64) -> H3Result<QpackField> {
+    let (name, value) = static_entry(index, QPACK_DECOMPRESSION_FAILED)?;
+    Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+}
@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use super::QpackField;
+
+/* Answers a request; it runs on the worker thread of the connection, once the whole request arrived. */
+pub type H3RequestHandler = Arc<dyn Fn(&H3Request) -> H3Response + Send + Sync + 'static>;
+
+/* Fields a request must not carry, they only make sense per hop in HTTP/1.1 (RFC 9114 §4.2). */
+const CONNECTION_SPECIFIC: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/* A request as the client sent it: the pseudo-header fields taken apart, the other fields in order and the body. */
+#[derive(Debug, Clone, Default)]
+pub struct H3Request {
+    pub(crate) stream_id: u64,
+    pub(crate) method: String,
+    pub(crate) scheme: String,
+    pub(crate) authority: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    pub(crate) early_data: bool,
+}
+
+impl H3Request {
+    /* Checks a request header section (RFC 9114 §4.3.1); the error says what makes it malformed. */
+    pub(crate) fn from_fields(stream_id: u64, fields: Vec<QpackField>, early_data: bool) -> Result<Self, String> {
+        let mut request = Self { stream_id, early_data, ..Self::default() };
+        let mut seen = Vec::new();
+        for (name, value) in fields {
+            let (Ok(name), Ok(value)) = (String::from_utf8(name), String::from_utf8(value)) else {
+                return Err("field is not UTF-8".to_string());
+            };
+            if name.is_empty() || name.bytes().any(|b| b.is_ascii_uppercase()) {
+                return Err(format!("invalid field name {:?}", name));
+            }
+            if let Some(pseudo) = name.strip_prefix(':') {
+                if !request.headers.is_empty() {
+                    return Err(format!("{} after regular fields", name));
+                }
+                if seen.contains(&name) {
+                    return Err(format!("{} twice", name));
+                }
+                match pseudo {
+                    "method" => request.method = value,
+                    "scheme" => request.scheme = value,
+                    "authority" => request.authority = value,
+                    "path" => request.path = value,
+                    _ => return Err(format!("unknown pseudo-header {}", name)),
+                }
+                seen.push(name);
+                continue;
+            }
+            if CONNECTION_SPECIFIC.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+                return Err(format!("connection-specific field {}", name));
+            }
+            request.headers.push((name, value));
+        }
+        let has = |name: &str| seen.iter().any(|s| s == name);
+        if request.method.is_empty() {
+            return Err(":method missing".to_string());
+        }
+        if request.method == "CONNECT" {
+            if !has(":authority") || has(":scheme") || has(":path") {
+                return Err("CONNECT needs :authority and neither :scheme nor :path".to_string());
+            }
+        } else if request.scheme.is_empty() || request.path.is_empty() {
+            return Err(":scheme or :path missing".to_string());
+        }
+        Ok(request)
+    }
+
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /* The :authority pseudo-header, or Host when the client sent that instead. */
+    pub fn authority(&self) -> &str {
+        match self.authority.is_empty() {
+            true => self.header("host").unwrap_or(""),
+            false => &self.authority,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /* The regular fields, names in lower case, in the order they came. */
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /* The first value of the field `name` (lower case). */
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /* The body, all the DATA frames of the request together. */
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /*
+    The request came in 0-RTT data, which an attacker may have replayed (RFC 9001 §9.2); only requests that
+    are safe to repeat should be acted upon, others can be answered with 425 Too Early (RFC 8470).
+    */
+    pub fn is_early_data(&self) -> bool {
+        self.early_data
+    }
+}
+
+/* What a handler answers with: a final status, fields and a body. */
+#[derive(Debug, Clone)]
+pub struct H3Response {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    pub(crate) goaway: bool,
+}
+
+impl H3Response {
+    pub fn new(status: u16) -> Self {
+        Self { status, headers: Vec::new(), body: Vec::new(), goaway: false }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /* Adds a field; names go out in lower case, as HTTP/3 requires. */
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        self.headers.push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    /* The body; content-length is added from it unless set. */
+    pub fn set_body(&mut self, body: &[u8]) -> &mut Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    /*
+    Sends GOAWAY along with this response: requests the client already sent are still answered, later ones
+    are refused with H3_REQUEST_REJECTED and the client takes them to a new connection.
+    */
+    pub fn set_goaway(&mut self) -> &mut Self {
+        self.goaway = true;
+        self
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
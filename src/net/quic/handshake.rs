@@ -0,0 +1,327 @@
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::quic::KeyChange;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, HandshakeKind, ServerConfig, Side};
+use crate::net::{EncryptionLevel, PacketProtector, StreamLimits, TransportParameters, VersionInformation, ACTIVE_CONNECTION_ID_LIMIT, MAX_DATAGRAM_FRAME_SIZE};
+use crate::net::quic::packets::tls_version;
+use crate::net::connection::{ConnectionId, QuicConnection};
+use crate::net::quic::space::SpaceId;
+
+/*
+Drives the TLS 1.3 handshake of a connection through rustls' QUIC interface (RFC 9001 §4).
+CRYPTO frame data goes in per encryption level; handshake bytes and new keys come out per level.
+*/
+
+/* The quic_transport_parameters TLS extension (RFC 9001 §8.2). */
+const QUIC_TRANSPORT_PARAMETERS: u16 = 0x39;
+
+/*
+What we tell the peer: an idle timeout, our stream and datagram limits, the CIDs of RFC 9000 §7.3,
+a server's stateless reset token for its handshake CID (§10.3) and our versions (RFC 9368 §3).
+*/
+fn local_transport_parameters(original_dcid: Option<&ConnectionId>, retry_scid: Option<&ConnectionId>, scid: &ConnectionId, versions: VersionInformation, idle_timeout: Duration, reset_token: Option<[u8; 16]>) -> TransportParameters {
+    let limits = StreamLimits::local();
+    TransportParameters {
+        original_destination_connection_id: original_dcid.copied(),
+        max_idle_timeout: idle_timeout,
+        stateless_reset_token: reset_token,
+        initial_max_data: limits.max_data,
+        initial_max_stream_data_bidi_local: limits.max_stream_data_bidi_local,
+        initial_max_stream_data_bidi_remote: limits.max_stream_data_bidi_remote,
+        initial_max_stream_data_uni: limits.max_stream_data_uni,
+        initial_max_streams_bidi: limits.max_streams_bidi,
+        initial_max_streams_uni: limits.max_streams_uni,
+        active_connection_id_limit: ACTIVE_CONNECTION_ID_LIMIT,
+        initial_source_connection_id: Some(*scid),
+        retry_source_connection_id: retry_scid.copied(),
+        max_datagram_frame_size: Some(MAX_DATAGRAM_FRAME_SIZE),
+        version_information: Some(versions),
+        ..TransportParameters::default()
+    }
+}
+
+impl QuicConnection {
+    /*
+    Starts the server side of the handshake for a client whose Initial was sent to `dcid`.
+    After a Retry that is the Retry's SCID and `original_dcid` is where the client's very first Initial went.
+    When `version` differs from the client's `original_version`, the server switched to a compatible version
+    (RFC 9368 §2.3) and still reads the client's Initials in the original one until it follows.
+    `reset_token` is the stateless reset token of our CID.
+    */
+    pub(crate) fn accept_tls(&mut self, config: Arc<ServerConfig>, dcid: &ConnectionId, original_dcid: &ConnectionId, retry_scid: Option<&ConnectionId>, reset_token: [u8; 16]) -> Result<()> {
+        self.original_dcid = *original_dcid;
+        self.retry_scid = retry_scid.copied();
+        let versions = VersionInformation { chosen_version: self.version, available_versions: self.versions.clone() };
+        let params = local_transport_parameters(Some(original_dcid), retry_scid, &self.id, versions, self.idle_timeout, Some(reset_token)).encode();
+        let tls = rustls::quic::ServerConnection::new(config, tls_version(self.version), params).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.tls = Some(rustls::quic::Connection::Server(tls));
+        self.spaces[SpaceId::Initial as usize].keys = Some(PacketProtector::initial_for_version(self.version, dcid.as_bytes(), Side::Server));
+        if self.version != self.original_version {
+            self.original_initial = Some(PacketProtector::initial_for_version(self.original_version, dcid.as_bytes(), Side::Server));
+        }
+        Ok(())
+    }
+
+    /*
+    Starts the client side of the handshake in `self.version`, queueing the ClientHello in the Initial space.
+    TLS derives the Handshake and 1-RTT keys for that version from the start, so the first flight is only
+    compatible with the version it is sent in and that is the one version it offers.
+    When TLS resumes a session with early data, 0-RTT keys are ready right away and the limits the server
+    gave last time apply to what is sent with them (RFC 9000 §7.4.1).
+    */
+    pub(crate) fn connect_tls(&mut self, config: Arc<ClientConfig>, server_name: ServerName<'static>) -> Result<()> {
+        let versions = VersionInformation { chosen_version: self.version, available_versions: vec![self.version] };
+        let params = local_transport_parameters(None, None, &self.id, versions, self.idle_timeout, None).encode();
+        let tls = rustls::quic::ClientConnection::new(config, tls_version(self.version), server_name, params).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let remembered = tls.quic_transport_parameters().and_then(|params| TransportParameters::decode(params, true).ok());
+        if let (Some(keys), Some(params)) = (tls.zero_rtt_keys(), remembered) {
+            self.zero_rtt_keys = Some(PacketProtector::zero_rtt(keys, Side::Client));
+            self.use_peer_limits(&params);
+        }
+        self.tls = Some(rustls::quic::Connection::Client(tls));
+        self.spaces[SpaceId::Initial as usize].keys = Some(PacketProtector::initial_for_version(self.version, self.dcid.as_bytes(), Side::Client));
+        self.write_crypto();
+        Ok(())
+    }
+
+    /*
+    Follows a Retry (RFC 9000 §17.2.5.2): later Initials go to the Retry's SCID with new keys and carry its token.
+    The Initial packets sent so far are forgotten and their CRYPTO data is sent again (RFC 9002 §6.3),
+    and so is what went out in 0-RTT packets, which the server dropped as well.
+    */
+    pub(crate) fn accept_retry(&mut self, scid: &ConnectionId, token: &[u8]) {
+        self.retry_scid = Some(*scid);
+        self.dcid = *scid;
+        self.token = token.to_vec();
+        self.spaces[SpaceId::Initial as usize].keys = Some(PacketProtector::initial_for_version(self.version, scid.as_bytes(), Side::Client));
+        let spaces: &[SpaceId] = if self.zero_rtt_keys.is_some() { &[SpaceId::Initial, SpaceId::Data] } else { &[SpaceId::Initial] };
+        for &space in spaces {
+            for packet in self.recovery.reset_space(space) {
+                for frame in packet.frames {
+                    self.resend(space, frame);
+                }
+            }
+        }
+    }
+
+    /* Feeds a CRYPTO frame to TLS and queues whatever handshake data it produces in response. */
+    pub(crate) fn read_crypto(&mut self, space: SpaceId, offset: u64, data: &[u8]) -> Result<()> {
+        let Some(tls) = self.tls.as_mut() else { return Ok(()); };
+        let ready = self.spaces[space as usize]
+            .on_crypto(offset, data)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "CRYPTO_BUFFER_EXCEEDED: CRYPTO data exceeds the buffer limit"))?;
+        if !ready.is_empty() {
+            tls.read_hs(&ready).map_err(|e| match tls.alert() {
+                Some(alert) => Error::new(ErrorKind::InvalidData, format!("CRYPTO_ERROR: TLS alert {:?}: {}", alert, e)),
+                None => Error::new(ErrorKind::InvalidData, format!("CRYPTO_ERROR: {}", e)),
+            })?;
+        }
+        self.write_crypto();
+        if !self.is_client() && !self.early_data_accepted {
+            self.accept_early_data()?;
+        }
+        if !self.handshake_complete && !self.tls.as_ref().is_some_and(|tls| tls.is_handshaking()) {
+            if self.peer_params.is_none() {
+                let params = self.tls.as_ref().and_then(|tls| tls.quic_transport_parameters()).unwrap_or_default();
+                self.apply_peer_parameters(TransportParameters::decode(params, self.is_client())?)?;
+            }
+            self.handshake_complete = true;
+            self.handshake_done_pending = matches!(self.tls, Some(rustls::quic::Connection::Server(_)));
+            if self.is_client() {
+                self.on_early_data_outcome();
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    A server that takes the client's 0-RTT data reads it with these keys and answers it before the handshake
+    is over, under the transport parameters of the ClientHello; TLS only accepts early data for a session
+    resumed with a ticket it had not seen before.
+    */
+    fn accept_early_data(&mut self) -> Result<()> {
+        let Some(keys) = self.tls.as_ref().and_then(|tls| tls.zero_rtt_keys()) else { return Ok(()); };
+        let params = self.tls.as_ref().and_then(|tls| tls.quic_transport_parameters()).unwrap_or_default();
+        self.apply_peer_parameters(TransportParameters::decode(params, false)?)?;
+        self.zero_rtt_keys = Some(PacketProtector::zero_rtt(keys, Side::Server));
+        self.early_data_accepted = true;
+        Ok(())
+    }
+
+    /*
+    The client learns at the end of the handshake whether the server took its 0-RTT data. If not, what the
+    0-RTT packets carried goes out again in 1-RTT packets (RFC 9001 §4.6.2); both share the application data
+    packet number space, which holds nothing else yet.
+    */
+    fn on_early_data_outcome(&mut self) {
+        let Some(rustls::quic::Connection::Client(tls)) = self.tls.as_ref() else { return; };
+        self.early_data_accepted = tls.is_early_data_accepted();
+        if self.early_data_accepted || self.recovery.unacked(SpaceId::Data).next().is_none() { return; }
+        for packet in self.recovery.reset_space(SpaceId::Data) {
+            for frame in packet.frames {
+                self.resend(SpaceId::Data, frame);
+            }
+        }
+    }
+
+    /*
+    Checks the peer's connection IDs against what was seen on the wire (RFC 9000 §7.3) and puts its
+    limits into force: stream and flow control credit, datagram size and ACK delay.
+    */
+    fn apply_peer_parameters(&mut self, params: TransportParameters) -> Result<()> {
+        let mismatch = |what: &str| Error::new(ErrorKind::InvalidData, format!("TRANSPORT_PARAMETER_ERROR: {} does not match", what));
+        if params.initial_source_connection_id != Some(self.dcid) {
+            return Err(mismatch("initial_source_connection_id"));
+        }
+        if self.is_client() && params.original_destination_connection_id != Some(self.original_dcid) {
+            return Err(mismatch("original_destination_connection_id"));
+        }
+        if self.is_client() && params.retry_source_connection_id != self.retry_scid {
+            return Err(mismatch("retry_source_connection_id"));
+        }
+        self.check_version_information(params.version_information.as_ref())?;
+        self.local_cids.set_limit(params.active_connection_id_limit);
+        self.use_peer_limits(&params);
+        self.recovery.set_peer_ack_delay(params.max_ack_delay, params.ack_delay_exponent);
+        self.peer_params = Some(params);
+        Ok(())
+    }
+
+    /* The stream, flow control and datagram limits the peer set. */
+    fn use_peer_limits(&mut self, params: &TransportParameters) {
+        self.streams.set_peer_limits(StreamLimits {
+            max_data: params.initial_max_data,
+            max_stream_data_bidi_local: params.initial_max_stream_data_bidi_local,
+            max_stream_data_bidi_remote: params.initial_max_stream_data_bidi_remote,
+            max_stream_data_uni: params.initial_max_stream_data_uni,
+            max_streams_bidi: params.initial_max_streams_bidi,
+            max_streams_uni: params.initial_max_streams_uni,
+        });
+        self.datagrams.set_peer_max(params.max_datagram_frame_size);
+    }
+
+    /*
+    Downgrade prevention (RFC 9368 §4). A server checks that the client chose the version its first flight used.
+    A client checks that the server chose the version in use and, after a Version Negotiation packet,
+    that the server's versions would have led it to the same one.
+    */
+    fn check_version_information(&self, info: Option<&VersionInformation>) -> Result<()> {
+        let error = |msg: &str| Error::new(ErrorKind::InvalidData, format!("VERSION_NEGOTIATION_ERROR: {}", msg));
+        if !self.is_client() {
+            return match info {
+                Some(info) if info.chosen_version != self.original_version => Err(error("chosen version differs from the first flight")),
+                _ => Ok(()),
+            };
+        }
+        match info {
+            Some(info) if info.chosen_version != self.version => Err(error("the server chose another version")),
+            Some(info) if self.version_negotiated && self.versions.iter().find(|v| info.available_versions.contains(v)) != Some(&self.version) => {
+                Err(error("the Version Negotiation packet did not list the server's versions"))
+            }
+            None if self.version_negotiated => Err(error("version_information missing after Version Negotiation")),
+            _ => Ok(()),
+        }
+    }
+
+    /* Collects outgoing handshake messages, switching the level they are sent at whenever TLS hands out new keys. */
+    fn write_crypto(&mut self) {
+        let Some(tls) = self.tls.as_mut() else { return; };
+        loop {
+            let mut buf = Vec::new();
+            let change = tls.write_hs(&mut buf);
+            self.spaces[self.write_level as usize].crypto_send.extend_from_slice(&buf);
+            match change {
+                Some(KeyChange::Handshake { keys }) => {
+                    self.spaces[SpaceId::Handshake as usize].keys = Some(PacketProtector::new(EncryptionLevel::Handshake, keys));
+                    self.write_level = SpaceId::Handshake;
+                }
+                Some(KeyChange::OneRtt { keys, next }) => {
+                    self.spaces[SpaceId::Data as usize].keys = Some(PacketProtector::one_rtt(keys, next));
+                    self.write_level = SpaceId::Data;
+                    /* a client sends no more 0-RTT packets once it has 1-RTT keys (RFC 9001 §4.9.3) */
+                    if matches!(tls, rustls::quic::Connection::Client(_)) {
+                        self.zero_rtt_keys = None;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /* Starts a 1-RTT key update; not allowed before the handshake is confirmed (RFC 9001 §6.1). */
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        if !self.handshake_confirmed {
+            return Err(Error::new(ErrorKind::WouldBlock, "the handshake is not confirmed yet"));
+        }
+        self.spaces[SpaceId::Data as usize]
+            .keys
+            .as_mut()
+            .ok_or_else(|| Error::new(ErrorKind::NotConnected, "no 1-RTT keys"))?
+            .initiate_key_update()
+    }
+
+    pub fn is_handshaking(&self) -> bool {
+        !self.handshake_complete
+    }
+
+    /* Whether the application can use the connection: the handshake is complete or the server took 0-RTT data. */
+    pub(crate) fn is_established(&self) -> bool {
+        self.handshake_complete || self.early_data_accepted
+    }
+
+    /* Whether TLS resumed an earlier session instead of running a full handshake. */
+    pub fn is_resumed(&self) -> bool {
+        self.tls.as_ref().and_then(|tls| tls.handshake_kind()) == Some(HandshakeKind::Resumed)
+    }
+
+    /*
+    Whether the server took the client's 0-RTT data. A client knows once the handshake is complete,
+    a server as soon as it read the ClientHello.
+    */
+    pub fn is_early_data_accepted(&self) -> bool {
+        self.early_data_accepted
+    }
+
+    /* The version_information a client sent with the ClientHello carried in `crypto`, the start of its Initial CRYPTO stream. */
+    pub(crate) fn client_hello_versions(crypto: &[u8]) -> Option<VersionInformation> {
+        let params = client_hello_transport_parameters(crypto)?;
+        TransportParameters::decode(params, false).ok()?.version_information
+    }
+
+    /* The ALPN protocol TLS agreed on, once known. */
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.tls.as_ref()?.alpn_protocol()
+    }
+}
+
+/* Finds the QUIC transport parameters extension in a complete ClientHello (RFC 8446 §4.1.2). */
+fn client_hello_transport_parameters(hello: &[u8]) -> Option<&[u8]> {
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = buf.split_at_checked(n)?;
+        *buf = rest;
+        Some(head)
+    }
+    fn take_vec<'a>(buf: &mut &'a [u8], len_bytes: usize) -> Option<&'a [u8]> {
+        let len = take(buf, len_bytes)?.iter().fold(0usize, |len, &b| len << 8 | b as usize);
+        take(buf, len)
+    }
+    let mut buf = hello;
+    if take(&mut buf, 1)? != [1] { return None; }
+    let mut body = take_vec(&mut buf, 3)?;
+    take(&mut body, 2 + 32)?; // legacy_version, random
+    take_vec(&mut body, 1)?; // legacy_session_id
+    take_vec(&mut body, 2)?; // cipher_suites
+    take_vec(&mut body, 1)?; // legacy_compression_methods
+    let mut extensions = take_vec(&mut body, 2)?;
+    while !extensions.is_empty() {
+        let kind = u16::from_be_bytes(take(&mut extensions, 2)?.try_into().ok()?);
+        let data = take_vec(&mut extensions, 2)?;
+        if kind == QUIC_TRANSPORT_PARAMETERS {
+            return Some(data);
+        }
+    }
+    None
+}
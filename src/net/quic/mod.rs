@@ -7,13 +7,37 @@ pub use client::*;
 pub mod connection;
 mod stream;
 pub use stream::*;
+mod streams;
+pub use streams::{is_unidirectional, StreamLimits, DEFAULT_MAX_DATA, DEFAULT_MAX_STREAMS, DEFAULT_MAX_STREAM_DATA};
 mod message;
 pub use message::*;
 mod datagram;
 pub use datagram::*;
-mod crypto;
-pub use crypto::*;
+pub mod h3;
+mod cids;
+pub use cids::ACTIVE_CONNECTION_ID_LIMIT;
+mod path;
+mod close;
+pub use close::*;
+mod transport_parameters;
+pub use transport_parameters::*;
+mod frame;
+pub use frame::*;
+mod space;
+pub use space::SpaceId;
+mod recovery;
+pub use recovery::*;
+mod congestion;
+pub use congestion::*;
+mod handshake;
+mod transmit;
+mod recv;
 mod spec;
 mod packets;
+pub use packets::{
+    open_initial_packet, EncryptionLevel, PacketProtector, QuicInitialPacket, QuicPacket, QuicPacketHeader, QuicPacketType, QuicPackets,
+    QuicProtectedHeader, QuicRetryHeader, QuicVersionNegotiation, verify_retry_integrity, write_retry_packet, write_version_negotiation,
+    QUIC_VERSION_1, QUIC_VERSION_2, QUIC_VERSION_NEGOTIATION, SUPPORTED_VERSIONS,
+};
 
 pub use spec::*;
\ No newline at end of file
@@ -0,0 +1,224 @@
+use crate::net::{varint, QuicLongHeader};
+
+/*
+Zero-copy QUIC v1 and v2 packet headers (RFC 9000 §17, RFC 9369 §3.2).
+
+Header protection is not removed here: `first_byte` still carries the protected reserved and
+packet number length bits, and `pn_offset` is where the (protected) packet number starts.
+*/
+
+pub const QUIC_VERSION_NEGOTIATION: u32 = 0;
+pub const QUIC_VERSION_1: u32 = 1;
+pub const QUIC_VERSION_2: u32 = 0x6b3343cf;
+/* Every version spoken here, in order of preference. */
+pub const SUPPORTED_VERSIONS: [u32; 2] = [QUIC_VERSION_1, QUIC_VERSION_2];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuicPacketType {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    Retry,
+    VersionNegotiation,
+    OneRtt,
+}
+
+/* The two Long Packet Type bits of `packet_type` in `version`; QUIC v2 assigns them differently (RFC 9369 §3.2). */
+pub(crate) fn long_packet_type_bits(version: u32, packet_type: QuicPacketType) -> u8 {
+    let v1 = match packet_type {
+        QuicPacketType::Initial => 0b00,
+        QuicPacketType::ZeroRtt => 0b01,
+        QuicPacketType::Handshake => 0b10,
+        _ => 0b11,
+    };
+    if version == QUIC_VERSION_2 { (v1 + 1) & 0b11 } else { v1 }
+}
+
+fn long_packet_type(version: u32, bits: u8) -> QuicPacketType {
+    let v1 = if version == QUIC_VERSION_2 { bits.wrapping_sub(1) & 0b11 } else { bits };
+    match v1 {
+        0b00 => QuicPacketType::Initial,
+        0b01 => QuicPacketType::ZeroRtt,
+        0b10 => QuicPacketType::Handshake,
+        _ => QuicPacketType::Retry,
+    }
+}
+
+/* Initial, 0-RTT, Handshake and 1-RTT packets. */
+#[derive(Debug, Clone, Copy)]
+pub struct QuicProtectedHeader<'a> {
+    pub first_byte: u8,
+    pub version: u32, // 0 for short headers, which carry no version
+    pub dcid: &'a [u8],
+    pub scid: &'a [u8], // empty for short headers
+    pub token: &'a [u8], // Initial only
+    pub pn_offset: usize,
+    pub length: usize, // packet number and payload; for short headers the rest of the datagram
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuicRetryHeader<'a> {
+    pub version: u32,
+    pub dcid: &'a [u8],
+    pub scid: &'a [u8],
+    pub token: &'a [u8],
+    pub integrity_tag: &'a [u8; 16],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuicVersionNegotiation<'a> {
+    pub dcid: &'a [u8],
+    pub scid: &'a [u8],
+    pub supported: &'a [u8], // big-endian u32 versions
+}
+
+impl QuicVersionNegotiation<'_> {
+    pub fn versions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.supported.chunks_exact(4).map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum QuicPacketHeader<'a> {
+    Initial(QuicProtectedHeader<'a>),
+    ZeroRtt(QuicProtectedHeader<'a>),
+    Handshake(QuicProtectedHeader<'a>),
+    Retry(QuicRetryHeader<'a>),
+    VersionNegotiation(QuicVersionNegotiation<'a>),
+    OneRtt(QuicProtectedHeader<'a>),
+}
+
+impl<'a> QuicPacketHeader<'a> {
+    pub fn packet_type(&self) -> QuicPacketType {
+        match self {
+            Self::Initial(_) => QuicPacketType::Initial,
+            Self::ZeroRtt(_) => QuicPacketType::ZeroRtt,
+            Self::Handshake(_) => QuicPacketType::Handshake,
+            Self::Retry(_) => QuicPacketType::Retry,
+            Self::VersionNegotiation(_) => QuicPacketType::VersionNegotiation,
+            Self::OneRtt(_) => QuicPacketType::OneRtt,
+        }
+    }
+
+    pub fn dcid(&self) -> &'a [u8] {
+        match self {
+            Self::Initial(h) | Self::ZeroRtt(h) | Self::Handshake(h) | Self::OneRtt(h) => h.dcid,
+            Self::Retry(h) => h.dcid,
+            Self::VersionNegotiation(h) => h.dcid,
+        }
+    }
+
+    pub fn protected(&self) -> Option<&QuicProtectedHeader<'a>> {
+        match self {
+            Self::Initial(h) | Self::ZeroRtt(h) | Self::Handshake(h) | Self::OneRtt(h) => Some(h),
+            _ => None,
+        }
+    }
+}
+
+/* One packet of a datagram: its parsed header and exactly the bytes it spans. */
+#[derive(Debug, Clone, Copy)]
+pub struct QuicPacket<'a> {
+    pub header: QuicPacketHeader<'a>,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> QuicPacket<'a> {
+    /*
+    Parses the first packet of `datagram`. Short headers carry no DCID length,
+    so the receiver supplies the length of the connection IDs it issued.
+    */
+    pub fn parse(datagram: &'a [u8], short_dcid_len: usize) -> Option<Self> {
+        let first = *datagram.first()?;
+        if first & 0b10000000 == 0 {
+            if first & 0b01000000 == 0 || short_dcid_len > 20 { return None; }
+            let pn_offset = 1 + short_dcid_len;
+            let dcid = datagram.get(1..pn_offset)?;
+            let header = QuicProtectedHeader {
+                first_byte: first, version: 0, dcid, scid: &[], token: &[], pn_offset, length: datagram.len() - pn_offset,
+            };
+            return Some(Self { header: QuicPacketHeader::OneRtt(header), bytes: datagram });
+        }
+
+        let long = QuicLongHeader::parse(datagram)?;
+        let rest = &datagram[long.header_size()..];
+        if long.version == QUIC_VERSION_NEGOTIATION {
+            if !rest.len().is_multiple_of(4) { return None; }
+            let header = QuicVersionNegotiation { dcid: long.dcid, scid: long.scid, supported: rest };
+            return Some(Self { header: QuicPacketHeader::VersionNegotiation(header), bytes: datagram });
+        }
+        if !SUPPORTED_VERSIONS.contains(&long.version) || first & 0b01000000 == 0 { return None; }
+
+        let packet_type = long_packet_type(long.version, (first & 0b00110000) >> 4);
+        if packet_type == QuicPacketType::Retry {
+            let (token, integrity_tag) = rest.split_at_checked(rest.len().checked_sub(16)?)?;
+            let header = QuicRetryHeader {
+                version: long.version, dcid: long.dcid, scid: long.scid, token, integrity_tag: integrity_tag.try_into().ok()?,
+            };
+            return Some(Self { header: QuicPacketHeader::Retry(header), bytes: datagram });
+        }
+
+        let mut off = long.header_size();
+        let mut token: &[u8] = &[];
+        if packet_type == QuicPacketType::Initial {
+            let (token_len, tl) = varint(&datagram[off..])?;
+            off += tl;
+            token = datagram.get(off..off.checked_add(token_len)?)?;
+            off += token_len;
+        }
+        let (length, ll) = varint(&datagram[off..])?;
+        let pn_offset = off + ll;
+        let end = pn_offset.checked_add(length).filter(|&end| end <= datagram.len())?;
+
+        let header = QuicProtectedHeader {
+            first_byte: first, version: long.version, dcid: long.dcid, scid: long.scid, token, pn_offset, length,
+        };
+        let header = match packet_type {
+            QuicPacketType::Initial => QuicPacketHeader::Initial(header),
+            QuicPacketType::ZeroRtt => QuicPacketHeader::ZeroRtt(header),
+            _ => QuicPacketHeader::Handshake(header),
+        };
+        Some(Self { header, bytes: &datagram[..end] })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /* The header protection sample (RFC 9001 §5.4.2), taken as if the packet number were 4 bytes long. */
+    pub fn sample(&self) -> Option<&'a [u8; 16]> {
+        let pn_offset = self.header.protected()?.pn_offset;
+        self.bytes.get(pn_offset + 4..pn_offset + 20)?.try_into().ok()
+    }
+}
+
+/* Splits a datagram into its coalesced packets (RFC 9000 §12.2), stopping at the first one that does not parse. */
+pub struct QuicPackets<'a> {
+    datagram: &'a [u8],
+    short_dcid_len: usize,
+}
+
+impl<'a> QuicPackets<'a> {
+    pub fn new(datagram: &'a [u8], short_dcid_len: usize) -> Self {
+        Self { datagram, short_dcid_len }
+    }
+
+    /* Bytes left after the packets yielded so far; non-empty once iteration ends means trailing garbage. */
+    pub fn remaining(&self) -> &'a [u8] {
+        self.datagram
+    }
+}
+
+impl<'a> Iterator for QuicPackets<'a> {
+    type Item = QuicPacket<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let packet = QuicPacket::parse(self.datagram, self.short_dcid_len)?;
+        self.datagram = &self.datagram[packet.len()..];
+        Some(packet)
+    }
+}
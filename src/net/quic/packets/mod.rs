@@ -1,2 +1,12 @@
 mod initial;
-pub use initial::*;
\ No newline at end of file
+pub use initial::*;
+mod header;
+pub use header::*;
+mod retry;
+pub use retry::{verify_retry_integrity, write_retry_packet};
+mod negotiation;
+pub use negotiation::write_version_negotiation;
+
+mod protected;
+pub use protected::{EncryptionLevel, PacketProtector};
+pub(crate) use protected::{tls_version, write_long_header, write_short_header, TAG_LEN};
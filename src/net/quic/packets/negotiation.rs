@@ -0,0 +1,22 @@
+use super::QUIC_VERSION_NEGOTIATION;
+
+/*
+A Version Negotiation packet (RFC 9000 §6.1, §17.2.1) answering a packet from `dcid` sent to `scid`,
+listing `versions` and one reserved version of the form 0x?a?a?a?a so clients keep ignoring unknown ones
+(RFC 9000 §15).
+*/
+pub fn write_version_negotiation(dcid: &[u8], scid: &[u8], versions: &[u32]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(7 + dcid.len() + scid.len() + 4 * (versions.len() + 1));
+    out.push(0x80 | rand::random::<u8>()); // all but the form bit are unused
+    out.extend_from_slice(&QUIC_VERSION_NEGOTIATION.to_be_bytes());
+    out.push(dcid.len() as u8);
+    out.extend_from_slice(dcid);
+    out.push(scid.len() as u8);
+    out.extend_from_slice(scid);
+    for version in versions {
+        out.extend_from_slice(&version.to_be_bytes());
+    }
+    let reserved = rand::random::<u32>() & 0xf0f0f0f0 | 0x0a0a0a0a;
+    out.extend_from_slice(&reserved.to_be_bytes());
+    out
+}
@@ -0,0 +1,244 @@
+use std::io::{Error, ErrorKind, Result};
+
+use rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256;
+use rustls::quic::{DirectionalKeys, Keys, PacketKey, PacketKeySet, Secrets, Version};
+use rustls::Side;
+use crate::net::{encode_varint_into, QuicPacketType, QUIC_VERSION_1, QUIC_VERSION_2};
+use super::header::long_packet_type_bits;
+use crate::net::quic::space::decode_packet_number;
+
+/*
+Packet protection with the keys rustls derives for each encryption level (RFC 9001 §5).
+Whichever AEAD TLS negotiated (AES-128-GCM, AES-256-GCM or ChaCha20-Poly1305) comes with its matching header protection.
+
+Outgoing packets always use a 4-byte packet number and a 2-byte Length field,
+which keeps the header layout fixed before the payload is known.
+*/
+
+pub(crate) const PN_LEN: usize = 4;
+pub(crate) const TAG_LEN: usize = 16;
+const KEY_PHASE_BIT: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionLevel {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    OneRtt,
+}
+
+/* 1-RTT key update state (RFC 9001 §6). Header protection keys never change, only packet keys do. */
+struct KeyUpdate {
+    secrets: Secrets,
+    next: PacketKeySet,                 // keys of the following phase, derived ahead so a forged packet cannot advance the secrets
+    prev_remote: Option<Box<dyn PacketKey>>, // for packets reordered across the last update
+    key_phase: bool,
+    phase_start: Option<u64>,           // first packet number the peer sent in the current phase
+    confirmed: bool,                    // the peer sent a packet in the current phase
+}
+
+pub struct PacketProtector {
+    level: EncryptionLevel,
+    local: Option<DirectionalKeys>,
+    remote: Option<DirectionalKeys>,
+    update: Option<KeyUpdate>,
+    sealed: u64, // packets sealed with the current local packet key
+    failed: u64, // packets that failed authentication, over all keys of this level
+}
+
+/* The rustls flavour of `version`, which picks the initial salt and the key derivation labels. */
+pub(crate) fn tls_version(version: u32) -> Version {
+    if version == QUIC_VERSION_2 { Version::V2 } else { Version::V1 }
+}
+
+fn auth_failed() -> Error {
+    Error::new(ErrorKind::InvalidData, "packet failed authentication")
+}
+
+impl PacketProtector {
+    /* Initial keys are always AES-128-GCM, whatever suite TLS negotiates later (RFC 9001 §5.2). */
+    pub fn initial(client_dcid: &[u8], side: Side) -> Self {
+        Self::initial_for_version(QUIC_VERSION_1, client_dcid, side)
+    }
+
+    /* Initial keys of `version`; QUIC v2 has its own salt and HKDF labels (RFC 9369 §3.3). */
+    pub fn initial_for_version(version: u32, client_dcid: &[u8], side: Side) -> Self {
+        let suite = TLS13_AES_128_GCM_SHA256.tls13().and_then(|s| s.quic_suite()).expect("TLS_AES_128_GCM_SHA256 supports QUIC");
+        Self::new(EncryptionLevel::Initial, suite.keys(client_dcid, side, tls_version(version)))
+    }
+
+    /* Protection for the Initial or Handshake level. */
+    pub fn new(level: EncryptionLevel, keys: Keys) -> Self {
+        Self { level, local: Some(keys.local), remote: Some(keys.remote), update: None, sealed: 0, failed: 0 }
+    }
+
+    /* 0-RTT only goes from client to server; `side` says which direction these keys serve. */
+    pub fn zero_rtt(keys: DirectionalKeys, side: Side) -> Self {
+        let (local, remote) = match side {
+            Side::Client => (Some(keys), None),
+            Side::Server => (None, Some(keys)),
+        };
+        Self { level: EncryptionLevel::ZeroRtt, local, remote, update: None, sealed: 0, failed: 0 }
+    }
+
+    /* 1-RTT protection; `secrets` are the ones rustls hands out with the keys and drive key updates. */
+    pub fn one_rtt(keys: Keys, mut secrets: Secrets) -> Self {
+        let next = secrets.next_packet_keys();
+        let update = KeyUpdate { secrets, next, prev_remote: None, key_phase: false, phase_start: None, confirmed: false };
+        Self { update: Some(update), ..Self::new(EncryptionLevel::OneRtt, keys) }
+    }
+
+    pub fn level(&self) -> EncryptionLevel {
+        self.level
+    }
+
+    pub fn key_phase(&self) -> bool {
+        self.update.as_ref().is_some_and(|u| u.key_phase)
+    }
+
+    /*
+    Switches to the next 1-RTT packet keys and flips the key phase bit of what is sent from now on.
+    Only one update may be in flight: the peer must have sent a packet in the current phase first.
+    */
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        let Some(update) = self.update.as_mut() else {
+            return Err(Error::new(ErrorKind::Unsupported, "key updates only exist for 1-RTT packets"));
+        };
+        if !update.confirmed {
+            return Err(Error::new(ErrorKind::WouldBlock, "the previous key update is not confirmed yet"));
+        }
+        self.rotate(None);
+        Ok(())
+    }
+
+    /* Moves both directions to the next phase; `first_pn` is the peer's packet that started it, if any. */
+    fn rotate(&mut self, first_pn: Option<u64>) {
+        let (Some(update), Some(local), Some(remote)) = (self.update.as_mut(), self.local.as_mut(), self.remote.as_mut()) else { return; };
+        let next = std::mem::replace(&mut update.next, update.secrets.next_packet_keys());
+        local.packet = next.local;
+        update.prev_remote = Some(std::mem::replace(&mut remote.packet, next.remote));
+        update.key_phase = !update.key_phase;
+        update.phase_start = first_pn;
+        update.confirmed = first_pn.is_some();
+        self.sealed = 0;
+    }
+
+    /*
+    Removes header protection and decrypts `packet` in place. `pn_offset` comes from the parsed header
+    and `largest` is the largest packet number received so far in the packet's space.
+    Returns the full packet number and the plaintext payload. Packets that do not authenticate are an
+    InvalidData error and should be dropped; once too many did, ConnectionAborted means AEAD_LIMIT_REACHED.
+    */
+    pub fn open<'p>(&mut self, packet: &'p mut [u8], pn_offset: usize, largest: Option<u64>) -> Result<(u64, &'p [u8])> {
+        let remote = self.remote.as_ref().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no keys to open packets at this level"))?;
+        let sample = packet.get(pn_offset + 4..pn_offset + 4 + remote.header.sample_len()).ok_or_else(auth_failed)?.to_vec();
+        let (header, rest) = packet.split_at_mut(pn_offset);
+        remote.header.decrypt_in_place(&sample, &mut header[0], &mut rest[..PN_LEN]).map_err(|_| auth_failed())?;
+
+        let first = packet[0];
+        let long = first & 0x80 != 0;
+        let reserved = if long { 0b00001100 } else { 0b00011000 };
+        let pn_len = (first & 0b11) as usize + 1;
+        let truncated = packet[pn_offset..pn_offset + pn_len].iter().fold(0u64, |pn, &b| (pn << 8) | b as u64);
+        let pn = decode_packet_number(largest, truncated, pn_len);
+
+        /* pick the packet key from the key phase bit (RFC 9001 §6.3) */
+        let key_phase = !long && first & KEY_PHASE_BIT != 0;
+        let (key, next_phase): (&dyn PacketKey, bool) = match self.update.as_ref() {
+            Some(update) if key_phase != update.key_phase => match update.prev_remote.as_ref() {
+                Some(prev) if update.phase_start.is_none_or(|start| pn < start) => (prev.as_ref(), false),
+                _ => (update.next.remote.as_ref(), true),
+            },
+            _ => (remote.packet.as_ref(), false),
+        };
+        let integrity_limit = key.integrity_limit();
+
+        let (header, payload) = packet.split_at_mut(pn_offset + pn_len);
+        let plaintext = match key.decrypt_in_place(pn, header, payload) {
+            Ok(plaintext) => plaintext,
+            Err(_) => {
+                self.failed += 1;
+                if self.failed >= integrity_limit {
+                    return Err(Error::new(ErrorKind::ConnectionAborted, "AEAD_LIMIT_REACHED"));
+                }
+                return Err(auth_failed());
+            }
+        };
+        /* reserved bits are only checked once the packet is authenticated (RFC 9000 §17.2) */
+        if first & reserved != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "reserved header bits are set"));
+        }
+
+        if next_phase {
+            self.rotate(Some(pn));
+        } else if let Some(update) = self.update.as_mut().filter(|u| key_phase == u.key_phase) {
+            update.confirmed = true;
+            update.phase_start = Some(update.phase_start.map_or(pn, |start| start.min(pn)));
+        }
+        Ok((pn, plaintext))
+    }
+
+    /*
+    Encrypts the packet that starts at `start` and whose plaintext frames follow the packet number,
+    then applies header protection. Long headers get their Length field filled in first, short headers
+    the current key phase. A key update is started on its own before the confidentiality limit is hit.
+    */
+    pub fn seal(&mut self, out: &mut Vec<u8>, start: usize, pn_offset: usize, pn: u64) -> Result<()> {
+        if self.local.as_ref().is_some_and(|local| self.sealed >= local.packet.confidentiality_limit()) {
+            self.initiate_key_update()?;
+        }
+        let local = self.local.as_ref().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no keys to seal packets at this level"))?;
+        let payload_start = pn_offset + PN_LEN;
+        if out[start] & 0x80 != 0 {
+            let length = out.len() - pn_offset + TAG_LEN;
+            if length > 0x3fff {
+                return Err(Error::new(ErrorKind::InvalidInput, "packet too long for a 2-byte Length field"));
+            }
+            out[pn_offset - 2] = 0x40 | (length >> 8) as u8;
+            out[pn_offset - 1] = length as u8;
+        } else if self.key_phase() {
+            out[start] |= KEY_PHASE_BIT;
+        } else {
+            out[start] &= !KEY_PHASE_BIT;
+        }
+        let (header, payload) = out.split_at_mut(payload_start);
+        let tag = local.packet.encrypt_in_place(pn, &header[start..], payload).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        out.extend_from_slice(tag.as_ref());
+        self.sealed += 1;
+
+        let sample = out[pn_offset + 4..].get(..local.header.sample_len()).ok_or_else(|| Error::new(ErrorKind::InvalidInput, "packet too short to sample"))?.to_vec();
+        let (header, rest) = out.split_at_mut(pn_offset);
+        local.header.encrypt_in_place(&sample, &mut header[start], &mut rest[..PN_LEN]).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    }
+}
+
+/*
+Appends a long header for an Initial, 0-RTT or Handshake packet of `version` with a Length placeholder;
+`token` only goes into Initial packets.
+Returns the packet number offset that `seal_packet` needs.
+*/
+pub(crate) fn write_long_header(out: &mut Vec<u8>, packet_type: QuicPacketType, version: u32, dcid: &[u8], scid: &[u8], token: &[u8], pn: u64) -> usize {
+    out.push(0xc0 | long_packet_type_bits(version, packet_type) << 4 | (PN_LEN as u8 - 1));
+    out.extend_from_slice(&version.to_be_bytes());
+    out.push(dcid.len() as u8);
+    out.extend_from_slice(dcid);
+    out.push(scid.len() as u8);
+    out.extend_from_slice(scid);
+    if packet_type == QuicPacketType::Initial {
+        encode_varint_into(token.len() as u64, out);
+        out.extend_from_slice(token);
+    }
+    out.extend_from_slice(&[0x40, 0]); // Length, patched by seal_packet
+    let pn_offset = out.len();
+    out.extend_from_slice(&(pn as u32).to_be_bytes());
+    pn_offset
+}
+
+/* Appends a short header with key phase 0. Returns the packet number offset. */
+pub(crate) fn write_short_header(out: &mut Vec<u8>, dcid: &[u8], pn: u64) -> usize {
+    out.push(0x40 | (PN_LEN as u8 - 1));
+    out.extend_from_slice(dcid);
+    let pn_offset = out.len();
+    out.extend_from_slice(&(pn as u32).to_be_bytes());
+    pn_offset
+}
@@ -0,0 +1,58 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_128_GCM};
+use crate::net::QuicPacketType;
+use super::header::long_packet_type_bits;
+use super::QUIC_VERSION_2;
+
+/*
+Retry packets (RFC 9000 §17.2.5) and their integrity tag (RFC 9001 §5.8).
+
+The tag is AES-128-GCM with a fixed key and nonce over an empty plaintext; the associated data is
+the Retry pseudo-packet, i.e. the Retry packet without its tag prefixed by the client's original DCID.
+QUIC v2 uses a key and nonce of its own (RFC 9369 §3.3.3).
+*/
+
+const RETRY_KEY_V1: [u8; 16] = [0xbe, 0x0c, 0x69, 0x0b, 0x9f, 0x66, 0x57, 0x5a, 0x1d, 0x76, 0x6b, 0x54, 0xe3, 0x68, 0xc8, 0x4e];
+const RETRY_NONCE_V1: [u8; 12] = [0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb];
+const RETRY_KEY_V2: [u8; 16] = [0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce, 0xad, 0x7c, 0xcc, 0x92];
+const RETRY_NONCE_V2: [u8; 12] = [0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a];
+const TAG_LEN: usize = 16;
+
+fn retry_key(version: u32) -> (LessSafeKey, Nonce) {
+    let (key, nonce) = if version == QUIC_VERSION_2 { (RETRY_KEY_V2, RETRY_NONCE_V2) } else { (RETRY_KEY_V1, RETRY_NONCE_V1) };
+    (LessSafeKey::new(UnboundKey::new(&AES_128_GCM, &key).expect("valid AES-128 key")), Nonce::assume_unique_for_key(nonce))
+}
+
+fn pseudo_packet(original_dcid: &[u8], packet: &[u8]) -> Vec<u8> {
+    let mut pseudo = Vec::with_capacity(1 + original_dcid.len() + packet.len());
+    pseudo.push(original_dcid.len() as u8);
+    pseudo.extend_from_slice(original_dcid);
+    pseudo.extend_from_slice(packet);
+    pseudo
+}
+
+/* A Retry packet of `version` sent to `dcid` (the client's SCID), asking it to come back to `scid` with `token`. */
+pub fn write_retry_packet(version: u32, dcid: &[u8], scid: &[u8], original_dcid: &[u8], token: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(7 + dcid.len() + scid.len() + token.len() + TAG_LEN);
+    out.push(0xc0 | long_packet_type_bits(version, QuicPacketType::Retry) << 4 | (rand::random::<u8>() & 0x0f)); // the low bits are unused
+    out.extend_from_slice(&version.to_be_bytes());
+    out.push(dcid.len() as u8);
+    out.extend_from_slice(dcid);
+    out.push(scid.len() as u8);
+    out.extend_from_slice(scid);
+    out.extend_from_slice(token);
+    let (key, nonce) = retry_key(version);
+    let tag = key
+        .seal_in_place_separate_tag(nonce, Aad::from(pseudo_packet(original_dcid, &out)), &mut [])
+        .expect("an empty plaintext always seals");
+    out.extend_from_slice(tag.as_ref());
+    out
+}
+
+/* Whether the integrity tag of a whole Retry packet is right for a client whose first Initial went to `original_dcid`. */
+pub fn verify_retry_integrity(original_dcid: &[u8], packet: &[u8]) -> bool {
+    let Some(split) = packet.len().checked_sub(TAG_LEN).filter(|&split| split >= 5) else { return false; };
+    let (packet, tag) = packet.split_at(split);
+    let mut tag = tag.to_vec();
+    let (key, nonce) = retry_key(u32::from_be_bytes([packet[1], packet[2], packet[3], packet[4]]));
+    key.open_in_place(nonce, Aad::from(pseudo_packet(original_dcid, packet)), &mut tag).is_ok()
+}
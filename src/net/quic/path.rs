@@ -0,0 +1,134 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::net::{Pacer, RttEstimator};
+use crate::net::connection::QuicConnection;
+use crate::net::quic::space::SpaceId;
+use crate::net::quic::transmit::MAX_DATAGRAM_SIZE;
+
+/*
+Path validation (RFC 9000 §8.2) for a peer that moved to a new address (§9). Packets go to the new
+address right away, within the anti-amplification limit, while PATH_CHALLENGE frames ask the peer to
+prove it receives them there; without an answer in time the connection falls back to `previous`.
+*/
+
+pub(crate) struct PathValidation {
+    pub(crate) previous: SocketAddr, // the last validated address
+    pub(crate) deadline: Instant,
+    pub(crate) pending: bool, // a PATH_CHALLENGE waits to be sent
+    challenges: Vec<[u8; 8]>, // sent so far, any of them may be answered
+}
+
+impl PathValidation {
+    pub(crate) fn new(previous: SocketAddr, deadline: Instant) -> Self {
+        Self { previous, deadline, pending: true, challenges: Vec::new() }
+    }
+
+    /* Data for the next PATH_CHALLENGE; each one sent carries data of its own (RFC 9000 §8.2.1). */
+    pub(crate) fn next_challenge(&mut self) -> [u8; 8] {
+        let data = rand::random();
+        self.challenges.push(data);
+        self.pending = false;
+        data
+    }
+
+    pub(crate) fn is_answered_by(&self, data: &[u8; 8]) -> bool {
+        self.challenges.contains(data)
+    }
+}
+
+/* A PATH_RESPONSE owed to an address other than the one the connection sends to (RFC 9000 §8.2.2). */
+pub(crate) struct OffPathResponse {
+    pub(crate) address: SocketAddr,
+    pub(crate) data: [u8; 8],
+    pub(crate) padded: bool, // the challenge came in a datagram large enough to answer with 1200 bytes
+}
+
+impl QuicConnection {
+    /*
+    Follows the peer to a new address once its highest-numbered non-probing packet comes from there
+    (RFC 9000 §9.3), and answers PATH_CHALLENGE frames on the path they came in on (§8.2.2).
+    Only a server follows, and only once the handshake is confirmed (§9).
+    */
+    pub(crate) fn on_path_packet(&mut self, source: &SocketAddr, pn: u64, probing: bool, len: usize, challenges: Vec<[u8; 8]>, now: Instant) {
+        let highest = self.spaces[SpaceId::Data as usize].largest_received() == Some(pn);
+        if *source != self.address && !probing && highest && self.handshake_confirmed && !self.is_client() {
+            self.follow_peer(*source, len, now);
+        }
+        for data in challenges {
+            if *source == self.address {
+                self.path_responses.push(data);
+            } else {
+                self.off_path_responses.push(OffPathResponse { address: *source, data, padded: 3 * len >= MAX_DATAGRAM_SIZE });
+            }
+        }
+    }
+
+    /*
+    Sends to `address` from now on, with a CID the peer has not used yet when it gave us one (RFC 9000 §9.5)
+    and only three times what came from there until the peer proves it owns it (§9.3.1).
+    */
+    fn follow_peer(&mut self, address: SocketAddr, len: usize, now: Instant) {
+        let previous = std::mem::replace(&mut self.address, address);
+        if let Some(dcid) = self.remote_cids.rotate() {
+            self.dcid = dcid;
+        }
+        /* a new port alone is most likely a NAT rebinding on the same path (§9.4) */
+        if address.ip() != previous.ip() {
+            self.reset_path();
+        }
+        let validated = self.path.take().map_or(previous, |path| path.previous);
+        if address == validated {
+            self.address_validated = true;
+            return;
+        }
+        self.address_validated = false;
+        self.bytes_received = len;
+        self.bytes_sent = 0;
+        let timeout = 3 * self.recovery.rtt().pto_base().max(RttEstimator::new().pto_base());
+        self.path = Some(PathValidation::new(validated, now + timeout));
+    }
+
+    /* A PATH_RESPONSE validates the path of the challenge it answers, whichever path it came in on (RFC 9000 §8.2.3). */
+    pub(crate) fn on_path_response(&mut self, data: &[u8; 8]) {
+        if !self.path.as_ref().is_some_and(|path| path.is_answered_by(data)) { return; }
+        let Some(path) = self.path.take() else { return; };
+        self.address_validated = true;
+        if let Some(mut handler) = self.onmigrate_handler.take() {
+            handler(&path.previous, &self.address);
+            if self.onmigrate_handler.is_none() {
+                self.onmigrate_handler = Some(handler);
+            }
+        }
+    }
+
+    /* The peer did not prove it owns its new address in time: back to the last validated one (RFC 9000 §9.3.2). */
+    pub(crate) fn abandon_path(&mut self) {
+        let Some(path) = self.path.take() else { return; };
+        if path.previous.ip() != self.address.ip() {
+            self.reset_path();
+        }
+        self.address = path.previous;
+        self.address_validated = true;
+    }
+
+    /*
+    Moves a client to a new local address (RFC 9000 §9.2): the next packet goes out with a fresh CID when
+    the server gave us one, and carries a PING so the server follows.
+    */
+    pub(crate) fn migrate(&mut self) {
+        if let Some(dcid) = self.remote_cids.rotate() {
+            self.dcid = dcid;
+        }
+        self.reset_path();
+        self.ping_pending = true;
+    }
+
+    /* A new path starts with a fresh congestion controller and RTT estimate (RFC 9000 §9.4). */
+    fn reset_path(&mut self) {
+        self.congestion = self.congestion_algorithm.build();
+        self.recovery.reset_rtt();
+        self.pacer = Pacer::default();
+        self.pacing_until = None;
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+use crate::net::AckFrame;
+use crate::net::quic::space::SpaceId;
+
+/*
+Loss detection and acknowledgements (RFC 9002 §5, §6 and Appendix A).
+Nothing here reads the clock: every call takes `now`, so a simulated clock drives it just as well as a real one.
+*/
+
+const PACKET_THRESHOLD: u64 = 3;
+const GRANULARITY: Duration = Duration::from_millis(1);
+const INITIAL_RTT: Duration = Duration::from_millis(333);
+const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
+/* ACK ranges kept per space; older ones are forgotten and simply not acknowledged again. */
+const MAX_ACK_RANGES: usize = 32;
+/* Defaults for the ack_delay_exponent and max_ack_delay transport parameters (RFC 9000 §18.2). */
+pub const DEFAULT_ACK_DELAY_EXPONENT: u8 = 3;
+pub const DEFAULT_MAX_ACK_DELAY: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RttEstimator {
+    latest: Duration,
+    smoothed: Duration,
+    rttvar: Duration,
+    min: Duration,
+    has_sample: bool,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        Self { latest: Duration::ZERO, smoothed: INITIAL_RTT, rttvar: INITIAL_RTT / 2, min: Duration::ZERO, has_sample: false }
+    }
+
+    /* Takes an RTT sample (RFC 9002 §5.3); `ack_delay` must already be capped by the caller. */
+    pub fn update(&mut self, latest: Duration, ack_delay: Duration) {
+        self.latest = latest;
+        if !self.has_sample {
+            self.has_sample = true;
+            self.min = latest;
+            self.smoothed = latest;
+            self.rttvar = latest / 2;
+            return;
+        }
+        self.min = self.min.min(latest);
+        /* the peer's delay is only subtracted when that cannot go below min_rtt */
+        let adjusted = if latest >= self.min + ack_delay { latest - ack_delay } else { latest };
+        self.rttvar = (self.rttvar * 3 + self.smoothed.abs_diff(adjusted)) / 4;
+        self.smoothed = (self.smoothed * 7 + adjusted) / 8;
+    }
+
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
+    pub fn smoothed(&self) -> Duration {
+        self.smoothed
+    }
+
+    pub fn rttvar(&self) -> Duration {
+        self.rttvar
+    }
+
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    /* The probe timeout before backoff and max_ack_delay (RFC 9002 §6.2.1). */
+    pub fn pto_base(&self) -> Duration {
+        self.smoothed + (self.rttvar * 4).max(GRANULARITY)
+    }
+
+    /* How long after a later packet was acknowledged an earlier one counts as lost (RFC 9002 §6.1.2). */
+    fn loss_delay(&self) -> Duration {
+        (self.smoothed.max(self.latest) * 9 / 8).max(GRANULARITY)
+    }
+}
+
+/* What a packet carried that must be sent again if it is lost. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SentFrame {
+    Crypto { offset: u64, data: Vec<u8> },
+    HandshakeDone,
+    NewToken(Vec<u8>),
+    NewConnectionId(u64), // sequence number
+    RetireConnectionId(u64),
+    PathChallenge, // a new one is sent if the path is still being validated
+    Stream { id: u64, offset: u64, data: Vec<u8>, fin: bool },
+    ResetStream { id: u64, error_code: u64, final_size: u64 },
+    StopSending { id: u64, error_code: u64 },
+    MaxData,              // the current value is sent again
+    MaxStreamData(u64),   // stream ID
+    MaxStreams { bidi: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct SentPacket {
+    pub pn: u64,
+    pub time_sent: Instant,
+    pub size: usize,
+    pub ack_eliciting: bool,
+    pub in_flight: bool, // ack-eliciting or padded; counts towards bytes in flight
+    pub frames: Vec<SentFrame>,
+}
+
+#[derive(Debug, Default)]
+pub struct AckOutcome {
+    pub acked: Vec<SentPacket>,
+    pub lost: Vec<SentPacket>,
+    pub persistent_congestion: bool,
+}
+
+#[derive(Debug)]
+pub enum RecoveryTimeout {
+    /* the time threshold declared these packets lost */
+    Lost { space: SpaceId, packets: Vec<SentPacket>, persistent_congestion: bool },
+    /* the probe timeout fired: one or two ack-eliciting packets should go out in `space` */
+    Probe { space: SpaceId },
+}
+
+#[derive(Default)]
+struct SentSpace {
+    sent: BTreeMap<u64, SentPacket>,
+    largest_sent: Option<u64>,
+    largest_acked: Option<u64>,
+    loss_time: Option<Instant>,
+    last_ack_eliciting: Option<Instant>,
+    ack_eliciting_in_flight: usize,
+    discarded: bool,
+}
+
+/* Sender-side recovery state of a connection (RFC 9002 Appendix A). */
+pub struct LossDetection {
+    rtt: RttEstimator,
+    spaces: [SentSpace; 3],
+    pto_count: u32,
+    bytes_in_flight: usize,
+    max_ack_delay: Duration, // the peer's
+    ack_delay_exponent: u8,  // the peer's
+    first_rtt_sample: Option<Instant>,
+    handshake_confirmed: bool,
+    peer_validated_address: bool, // a client is unsure until its Handshake packets get acknowledged (RFC 9002 §6.2.2.1)
+    last_activity: Option<Instant>, // when the probe timer was last armed with nothing in flight
+}
+
+impl LossDetection {
+    pub fn new(is_client: bool) -> Self {
+        Self {
+            rtt: RttEstimator::new(),
+            spaces: Default::default(),
+            pto_count: 0,
+            bytes_in_flight: 0,
+            max_ack_delay: DEFAULT_MAX_ACK_DELAY,
+            ack_delay_exponent: DEFAULT_ACK_DELAY_EXPONENT,
+            first_rtt_sample: None,
+            handshake_confirmed: false,
+            peer_validated_address: !is_client,
+            last_activity: None,
+        }
+    }
+
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    pub fn bytes_in_flight(&self) -> usize {
+        self.bytes_in_flight
+    }
+
+    /* The probe timeout without backoff, which the closing and idle timers count in (RFC 9000 §10). */
+    pub fn pto(&self) -> Duration {
+        self.rtt.pto_base() + self.max_ack_delay
+    }
+
+    pub fn pto_count(&self) -> u32 {
+        self.pto_count
+    }
+
+    /* The peer's max_ack_delay and ack_delay_exponent transport parameters. */
+    pub fn set_peer_ack_delay(&mut self, max_ack_delay: Duration, ack_delay_exponent: u8) {
+        self.max_ack_delay = max_ack_delay;
+        self.ack_delay_exponent = ack_delay_exponent;
+    }
+
+    pub fn set_handshake_confirmed(&mut self) {
+        self.handshake_confirmed = true;
+        self.peer_validated_address = true;
+    }
+
+    /* Packets of `space` still waiting for an acknowledgement, oldest first. */
+    pub fn unacked(&self, space: SpaceId) -> impl Iterator<Item = &SentPacket> {
+        self.spaces[space as usize].sent.values()
+    }
+
+    pub fn on_packet_sent(&mut self, space: SpaceId, packet: SentPacket) {
+        let state = &mut self.spaces[space as usize];
+        if packet.in_flight {
+            if packet.ack_eliciting {
+                state.last_ack_eliciting = Some(packet.time_sent);
+                state.ack_eliciting_in_flight += 1;
+            }
+            self.bytes_in_flight += packet.size;
+        }
+        self.last_activity = Some(packet.time_sent);
+        state.largest_sent = Some(state.largest_sent.map_or(packet.pn, |pn| pn.max(packet.pn)));
+        state.sent.insert(packet.pn, packet);
+    }
+
+    fn remove(&mut self, space: SpaceId, pn: u64) -> Option<SentPacket> {
+        let state = &mut self.spaces[space as usize];
+        let packet = state.sent.remove(&pn)?;
+        if packet.in_flight {
+            if packet.ack_eliciting {
+                state.ack_eliciting_in_flight -= 1;
+            }
+            self.bytes_in_flight -= packet.size;
+        }
+        Some(packet)
+    }
+
+    /* Processes an ACK frame (RFC 9002 §A.7). Acknowledging a packet that was never sent is an error. */
+    pub fn on_ack_received(&mut self, space: SpaceId, ack: &AckFrame, now: Instant) -> Result<AckOutcome> {
+        let state = &mut self.spaces[space as usize];
+        if state.largest_sent.is_none_or(|largest| ack.largest > largest) {
+            return Err(Error::new(ErrorKind::InvalidData, "ACK of a packet that was never sent"));
+        }
+        state.largest_acked = Some(state.largest_acked.map_or(ack.largest, |pn| pn.max(ack.largest)));
+
+        let newly_acked: Vec<u64> = ack.acked().flat_map(|(lo, hi)| state.sent.range(lo..=hi).map(|(&pn, _)| pn).collect::<Vec<_>>()).collect();
+        if newly_acked.is_empty() {
+            return Ok(AckOutcome::default());
+        }
+        let mut outcome = AckOutcome::default();
+        for pn in newly_acked {
+            outcome.acked.extend(self.remove(space, pn));
+        }
+
+        /* an RTT sample needs the largest acknowledged packet to be new and something ack-eliciting acknowledged */
+        let largest = outcome.acked.iter().max_by_key(|p| p.pn).unwrap();
+        if largest.pn == ack.largest && outcome.acked.iter().any(|p| p.ack_eliciting) {
+            let latest = now.saturating_duration_since(largest.time_sent);
+            let mut ack_delay = match space {
+                SpaceId::Initial => Duration::ZERO,
+                _ => Duration::from_micros(ack.delay.checked_shl(self.ack_delay_exponent as u32).unwrap_or(u64::MAX)),
+            };
+            if self.handshake_confirmed {
+                ack_delay = ack_delay.min(self.max_ack_delay);
+            }
+            self.rtt.update(latest, ack_delay);
+            self.first_rtt_sample.get_or_insert(now);
+        }
+
+        if space == SpaceId::Handshake {
+            self.peer_validated_address = true;
+        }
+        outcome.lost = self.detect_lost(space, now);
+        outcome.persistent_congestion = self.in_persistent_congestion(&outcome.lost);
+        if self.peer_validated_address {
+            self.pto_count = 0;
+        }
+        Ok(outcome)
+    }
+
+    /* Packet and time threshold loss detection (RFC 9002 §6.1, §A.10). */
+    fn detect_lost(&mut self, space: SpaceId, now: Instant) -> Vec<SentPacket> {
+        let loss_delay = self.rtt.loss_delay();
+        let lost_send_time = now.checked_sub(loss_delay);
+        let state = &mut self.spaces[space as usize];
+        state.loss_time = None;
+        let Some(largest_acked) = state.largest_acked else { return Vec::new(); };
+
+        let mut lost = Vec::new();
+        for (&pn, packet) in state.sent.range(..largest_acked) {
+            if lost_send_time.is_some_and(|t| packet.time_sent <= t) || largest_acked >= pn + PACKET_THRESHOLD {
+                lost.push(pn);
+            } else {
+                let at = packet.time_sent + loss_delay;
+                state.loss_time = Some(state.loss_time.map_or(at, |t| t.min(at)));
+            }
+        }
+        lost.into_iter().filter_map(|pn| self.remove(space, pn)).collect()
+    }
+
+    /*
+    Persistent congestion (RFC 9002 §7.6): consecutive ack-eliciting packets, sent after the first RTT sample,
+    all lost over longer than three probe timeouts.
+    */
+    fn in_persistent_congestion(&self, lost: &[SentPacket]) -> bool {
+        let Some(first_sample) = self.first_rtt_sample else { return false; };
+        let period = (self.rtt.pto_base() + self.max_ack_delay) * PERSISTENT_CONGESTION_THRESHOLD;
+        let mut run: Option<(&SentPacket, &SentPacket)> = None;
+        for packet in lost.iter().filter(|p| p.ack_eliciting && p.time_sent > first_sample) {
+            run = match run {
+                Some((start, end)) if packet.pn == end.pn + 1 => Some((start, packet)),
+                _ => Some((packet, packet)),
+            };
+            if let Some((start, end)) = run {
+                if end.time_sent.duration_since(start.time_sent) > period {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /* When the probe timeout fires and for which space (RFC 9002 §A.8). */
+    fn pto_time_and_space(&self) -> Option<(Instant, SpaceId)> {
+        let backoff = 1u32 << self.pto_count.min(16);
+        let duration = self.rtt.pto_base() * backoff;
+        if self.spaces.iter().all(|s| s.ack_eliciting_in_flight == 0) {
+            /* a client keeps probing until it knows the server can send again (anti-deadlock) */
+            let space = if self.spaces[SpaceId::Initial as usize].discarded { SpaceId::Handshake } else { SpaceId::Initial };
+            return Some((self.last_activity? + duration, space));
+        }
+        let mut earliest: Option<(Instant, SpaceId)> = None;
+        for space in SpaceId::ALL {
+            let state = &self.spaces[space as usize];
+            if state.ack_eliciting_in_flight == 0 { continue; }
+            let mut timeout = duration;
+            if space == SpaceId::Data {
+                /* application data is not probed for before the handshake is confirmed */
+                if !self.handshake_confirmed { break; }
+                timeout += self.max_ack_delay * backoff;
+            }
+            let at = state.last_ack_eliciting? + timeout;
+            if earliest.is_none_or(|(t, _)| at < t) {
+                earliest = Some((at, space));
+            }
+        }
+        earliest
+    }
+
+    /* The loss detection timer: the earliest time threshold loss, otherwise the probe timeout. */
+    pub fn timeout(&self) -> Option<Instant> {
+        if let Some(loss_time) = self.spaces.iter().filter_map(|s| s.loss_time).min() {
+            return Some(loss_time);
+        }
+        if self.spaces.iter().all(|s| s.ack_eliciting_in_flight == 0) && self.peer_validated_address {
+            return None;
+        }
+        self.pto_time_and_space().map(|(at, _)| at)
+    }
+
+    /* Runs the loss detection timer if it is due (RFC 9002 §A.9). */
+    pub fn on_timeout(&mut self, now: Instant) -> Option<RecoveryTimeout> {
+        let loss = SpaceId::ALL.into_iter().filter_map(|space| Some((self.spaces[space as usize].loss_time?, space))).min_by_key(|&(at, _)| at);
+        if let Some((at, space)) = loss {
+            if at > now { return None; }
+            let packets = self.detect_lost(space, now);
+            let persistent_congestion = self.in_persistent_congestion(&packets);
+            return Some(RecoveryTimeout::Lost { space, packets, persistent_congestion });
+        }
+        if self.timeout().is_none_or(|at| at > now) { return None; }
+        let (_, space) = self.pto_time_and_space()?;
+        self.pto_count += 1;
+        self.last_activity = Some(now);
+        Some(RecoveryTimeout::Probe { space })
+    }
+
+    /* Takes back every packet in flight in a space that stays in use, as after a Retry (RFC 9002 §6.3). */
+    pub fn reset_space(&mut self, space: SpaceId) -> Vec<SentPacket> {
+        let pns: Vec<u64> = self.spaces[space as usize].sent.keys().copied().collect();
+        let packets = pns.into_iter().filter_map(|pn| self.remove(space, pn)).collect();
+        let state = &mut self.spaces[space as usize];
+        state.loss_time = None;
+        state.last_ack_eliciting = None;
+        self.pto_count = 0;
+        packets
+    }
+
+    /* Forgets the RTT samples of a path the connection left; the new one starts from kInitialRtt (RFC 9000 §9.4). */
+    pub fn reset_rtt(&mut self) {
+        self.rtt = RttEstimator::new();
+        self.first_rtt_sample = None;
+        self.pto_count = 0;
+    }
+
+    /* Forgets a packet number space whose keys were discarded (RFC 9002 §6.4). */
+    pub fn discard_space(&mut self, space: SpaceId) {
+        let pns: Vec<u64> = self.spaces[space as usize].sent.keys().copied().collect();
+        for pn in pns {
+            self.remove(space, pn);
+        }
+        let state = &mut self.spaces[space as usize];
+        state.loss_time = None;
+        state.last_ack_eliciting = None;
+        state.discarded = true;
+        self.pto_count = 0;
+    }
+}
+
+/* Receiver side: which packets to acknowledge and when (RFC 9000 §13.2). */
+pub struct AckTracker {
+    ranges: Vec<(u64, u64)>, // inclusive packet number ranges, ascending
+    largest_time: Option<Instant>, // when the largest packet number arrived, for the ACK Delay field
+    unacked_ack_eliciting: u32,
+    immediate: bool,
+    deadline: Option<Instant>,
+    max_ack_delay: Duration,
+}
+
+impl AckTracker {
+    /* `max_ack_delay` is zero for the Initial and Handshake spaces, which are acknowledged right away. */
+    pub fn new(max_ack_delay: Duration) -> Self {
+        Self { ranges: Vec::new(), largest_time: None, unacked_ack_eliciting: 0, immediate: false, deadline: None, max_ack_delay }
+    }
+
+    pub fn largest(&self) -> Option<u64> {
+        self.ranges.last().map(|&(_, largest)| largest)
+    }
+
+    pub fn is_duplicate(&self, pn: u64) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= pn && pn <= hi)
+    }
+
+    pub fn on_packet_received(&mut self, pn: u64, ack_eliciting: bool, now: Instant) {
+        /* reordering or a gap gets acknowledged at once so the sender learns about it quickly (RFC 9000 §13.2.1) */
+        let out_of_order = self.largest().is_some_and(|largest| pn < largest || pn > largest + 1);
+        if self.largest().is_none_or(|largest| pn > largest) {
+            self.largest_time = Some(now);
+        }
+
+        let at = self.ranges.partition_point(|&(_, hi)| hi < pn);
+        self.ranges.insert(at, (pn, pn));
+        /* merge with the neighbours the new packet number touches */
+        if at + 1 < self.ranges.len() && self.ranges[at + 1].0 == pn + 1 {
+            self.ranges[at].1 = self.ranges.remove(at + 1).1;
+        }
+        if at > 0 && self.ranges[at - 1].1 + 1 == pn {
+            self.ranges[at - 1].1 = self.ranges.remove(at).1;
+        }
+        if self.ranges.len() > MAX_ACK_RANGES {
+            self.ranges.remove(0);
+        }
+
+        if ack_eliciting {
+            self.unacked_ack_eliciting += 1;
+            if self.max_ack_delay.is_zero() || self.unacked_ack_eliciting >= 2 || out_of_order {
+                self.immediate = true;
+            } else {
+                self.deadline.get_or_insert(now + self.max_ack_delay);
+            }
+        }
+    }
+
+    /* Something ack-eliciting arrived since the last ACK; it may ride along with other frames. */
+    pub fn has_pending(&self) -> bool {
+        self.unacked_ack_eliciting > 0
+    }
+
+    /* An ACK must go out now, with or without anything else to send. */
+    pub fn wants_ack(&self, now: Instant) -> bool {
+        self.immediate || self.deadline.is_some_and(|deadline| deadline <= now)
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /* Builds an ACK frame of everything received, its delay scaled by our ack_delay_exponent. */
+    pub fn ack_frame(&mut self, now: Instant, ack_delay_exponent: u8) -> Option<AckFrame> {
+        let mut iter = self.ranges.iter().rev();
+        let &(smallest, largest) = iter.next()?;
+        let mut ranges = Vec::new();
+        let mut prev = smallest;
+        for &(lo, hi) in iter {
+            ranges.push((prev - hi - 2, hi - lo));
+            prev = lo;
+        }
+        let delay = self.largest_time.map_or(0, |t| now.saturating_duration_since(t).as_micros() as u64 >> ack_delay_exponent);
+        self.unacked_ack_eliciting = 0;
+        self.immediate = false;
+        self.deadline = None;
+        Some(AckFrame { largest, delay, first_range: largest - smallest, ranges, ecn: None })
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new(self.max_ack_delay);
+    }
+}
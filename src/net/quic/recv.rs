@@ -0,0 +1,273 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::net::{AckFrame, MIN_STATELESS_RESET_LEN, QuicDatagram, QuicFrame, QuicFrames, QuicPacketType, QuicStream, SentFrame, SentPacket, MAX_DATAGRAM_FRAME_SIZE};
+use crate::net::connection::{ConnectionId, QuicConnection};
+use crate::net::quic::frame::varint_len;
+use crate::net::quic::space::SpaceId;
+
+impl QuicConnection {
+    pub(crate) fn is_client(&self) -> bool {
+        matches!(self.tls, Some(rustls::quic::Connection::Client(_)))
+    }
+
+    /*
+    Decrypts a packet of `space` that came from `source` in place, hands its frames to the connection and
+    records it for acknowledgement. Returns the packet number, None for packets that do not authenticate or were seen before.
+    An error in the packet closes the connection with the matching transport error (RFC 9000 §10.2).
+    */
+    pub(crate) fn handle_packet(&mut self, space: SpaceId, packet_type: QuicPacketType, packet: &mut [u8], pn_offset: usize, source: &SocketAddr, now: Instant) -> Option<u64> {
+        if !self.is_open() {
+            if !self.address_validated {
+                self.bytes_received += packet.len();
+            }
+            self.on_packet_while_closing();
+            return None;
+        }
+        match self.process_packet(space, packet_type, packet, pn_offset, source, now) {
+            Ok(Some(pn)) => {
+                self.last_activity = now;
+                self.restart_idle_on_send = true;
+                Some(pn)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.close_on_error(&e, now);
+                None
+            }
+        }
+    }
+
+    fn process_packet(&mut self, space: SpaceId, packet_type: QuicPacketType, packet: &mut [u8], pn_offset: usize, source: &SocketAddr, now: Instant) -> Result<Option<u64>> {
+        let len = packet.len();
+        if !self.address_validated {
+            self.bytes_received += len;
+        }
+        let packet_dcid = match packet_type {
+            QuicPacketType::OneRtt => ConnectionId::from_slice(&packet[1..1 + self.id.len]),
+            _ => ConnectionId::from_slice(&packet[6..6 + packet[5] as usize]),
+        };
+        /* decryption works in place, the end of a packet that fails it may still be a stateless reset token */
+        let tail: Option<[u8; 16]> = (packet_type == QuicPacketType::OneRtt && len >= MIN_STATELESS_RESET_LEN).then(|| packet[len - 16..].try_into().unwrap());
+        let state = &mut self.spaces[space as usize];
+        let largest = state.largest_received();
+        /* after a compatible version change the client's Initials may still come in its first version (RFC 9368 §2.3) */
+        let version = (packet[0] & 0x80 != 0).then(|| u32::from_be_bytes([packet[1], packet[2], packet[3], packet[4]]));
+        let keys = match version {
+            Some(version) if version != self.version && version == self.original_version && space == SpaceId::Initial => {
+                self.original_initial.as_mut().filter(|_| state.keys.is_some())
+            }
+            Some(version) if version != self.version => None,
+            _ if packet_type == QuicPacketType::ZeroRtt => self.zero_rtt_keys.as_mut(),
+            _ => state.keys.as_mut(),
+        };
+        let Some(keys) = keys else { return Ok(None); };
+        let opened = match keys.open(packet, pn_offset, largest) {
+            Ok(opened) => Some(opened),
+            Err(e) if e.kind() == ErrorKind::InvalidData => None, // dropped, not fatal (RFC 9001 §5.3)
+            Err(e) => return Err(e),
+        };
+        let Some((pn, payload)) = opened else {
+            if tail.is_some_and(|tail| self.is_reset_token(&tail)) {
+                self.on_stateless_reset(now);
+            }
+            return Ok(None);
+        };
+        if state.is_duplicate(pn) { return Ok(None); }
+
+        let mut ack_eliciting = false;
+        let mut probing = true;
+        let mut challenges = Vec::new();
+        let early = packet_type == QuicPacketType::ZeroRtt;
+        for frame in QuicFrames::new(payload) {
+            let frame = frame.map_err(|e| Error::new(ErrorKind::InvalidData, format!("FRAME_ENCODING_ERROR: {}", e)))?;
+            if !frame.is_allowed_in(packet_type) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("PROTOCOL_VIOLATION: frame type {:#x} not allowed in {:?} packets", frame.frame_type(), packet_type)));
+            }
+            ack_eliciting |= frame.is_ack_eliciting();
+            probing &= frame.is_probing();
+            match frame {
+                QuicFrame::Ack(ack) => self.on_ack(space, &ack, now)?,
+                QuicFrame::Crypto { offset, data } => self.read_crypto(space, offset, data)?,
+                QuicFrame::HandshakeDone if !self.is_client() => {
+                    return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: HANDSHAKE_DONE sent by a client"));
+                }
+                QuicFrame::HandshakeDone => {
+                    /* the handshake is confirmed and Handshake keys go (RFC 9001 §4.9.2) */
+                    self.handshake_confirmed = true;
+                    self.recovery.set_handshake_confirmed();
+                    self.discard_space(SpaceId::Handshake);
+                }
+                QuicFrame::Stream { stream_id, offset, data, fin } => {
+                    self.accept_stream(stream_id, early)?;
+                    self.streams.on_stream_frame(stream_id, offset, data, fin)?;
+                    self.dispatch_stream(stream_id);
+                }
+                QuicFrame::ResetStream { stream_id, error_code, final_size } => {
+                    self.accept_stream(stream_id, early)?;
+                    self.streams.on_reset_stream(stream_id, error_code, final_size)?;
+                    self.dispatch_stream(stream_id);
+                }
+                QuicFrame::StopSending { stream_id, error_code } => {
+                    self.accept_stream(stream_id, early)?;
+                    self.streams.on_stop_sending(stream_id, error_code)?;
+                }
+                QuicFrame::MaxStreamData { stream_id, max } => {
+                    self.accept_stream(stream_id, early)?;
+                    self.streams.on_max_stream_data(stream_id, max)?;
+                }
+                QuicFrame::NewToken(_) if !self.is_client() => {
+                    return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: NEW_TOKEN sent by a client"));
+                }
+                QuicFrame::NewToken(token) => self.new_token = Some(token.to_vec()),
+                QuicFrame::NewConnectionId { .. } if self.dcid.len == 0 => {
+                    return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: NEW_CONNECTION_ID for a zero-length CID"));
+                }
+                QuicFrame::NewConnectionId { sequence, retire_prior_to, cid, reset_token } => {
+                    if let Some(dcid) = self.remote_cids.on_new_connection_id(sequence, retire_prior_to, ConnectionId::from_slice(cid), reset_token)? {
+                        self.dcid = dcid;
+                    }
+                }
+                QuicFrame::RetireConnectionId(sequence) => self.local_cids.on_retire(sequence, &packet_dcid)?,
+                QuicFrame::PathChallenge(data) => challenges.push(data),
+                QuicFrame::PathResponse(data) => self.on_path_response(&data),
+                QuicFrame::Datagram(data) => self.receive_datagram(data)?,
+                QuicFrame::MaxData(max) => self.streams.on_max_data(max),
+                QuicFrame::MaxStreams { bidi, max } => self.streams.on_max_streams(bidi, max),
+                QuicFrame::ConnectionClose { error_code, frame_type, reason } => {
+                    self.on_peer_close(error_code, frame_type, reason, now);
+                    return Ok(None);
+                }
+                _ => {} // the blocked signals need no answer; the rest is not acted upon yet
+            }
+        }
+        /* keys may have been discarded while processing, the packet is not acknowledged then */
+        if self.spaces[space as usize].keys.is_some() {
+            self.spaces[space as usize].on_packet_received(pn, ack_eliciting, now);
+        }
+        if space == SpaceId::Data {
+            self.on_path_packet(source, pn, probing, len, challenges, now);
+        }
+
+        /*
+        a server drops its Initial keys once the client proves it has Handshake keys (RFC 9001 §4.9.1),
+        which also proves the client owns its address (RFC 9000 §8.1)
+        */
+        if space == SpaceId::Handshake && !self.is_client() {
+            self.discard_space(SpaceId::Initial);
+            self.address_validated = true;
+        }
+        Ok(Some(pn))
+    }
+
+    fn on_ack(&mut self, space: SpaceId, ack: &AckFrame, now: Instant) -> Result<()> {
+        let outcome = self.recovery.on_ack_received(space, ack, now)?;
+        for frame in outcome.acked.iter().flat_map(|p| &p.frames) {
+            self.streams.on_frame_acked(frame);
+            if let SentFrame::Stream { id, .. } = frame {
+                self.dispatch_stream(*id);
+            }
+        }
+        if !outcome.acked.is_empty() {
+            self.congestion.on_packets_acked(now, &outcome.acked, self.recovery.rtt(), self.recovery.bytes_in_flight());
+        }
+        self.on_packets_lost(space, outcome.lost, outcome.persistent_congestion, now);
+        Ok(())
+    }
+
+    /* Tells the congestion controller and queues what lost packets carried to be sent again. */
+    pub(crate) fn on_packets_lost(&mut self, space: SpaceId, packets: Vec<SentPacket>, persistent_congestion: bool, now: Instant) {
+        if packets.is_empty() { return; }
+        self.congestion.on_packets_lost(now, &packets, persistent_congestion, self.recovery.bytes_in_flight());
+        for frame in packets.into_iter().flat_map(|p| p.frames) {
+            self.resend(space, frame);
+        }
+    }
+
+    pub(crate) fn resend(&mut self, space: SpaceId, frame: SentFrame) {
+        match frame {
+            SentFrame::Crypto { offset, data } => self.spaces[space as usize].requeue_crypto(offset, data),
+            SentFrame::HandshakeDone => self.handshake_done_pending = true,
+            SentFrame::NewToken(token) => self.new_token = Some(token),
+            SentFrame::NewConnectionId(sequence) => self.local_cids.on_lost(sequence),
+            SentFrame::RetireConnectionId(sequence) => self.remote_cids.on_retire_lost(sequence),
+            SentFrame::PathChallenge => {
+                if let Some(path) = self.path.as_mut() {
+                    path.pending = true;
+                }
+            }
+            frame => self.streams.on_frame_lost(frame),
+        }
+    }
+
+    /* Hands a DATAGRAM frame to `on_datagram`; one larger than we advertised is a PROTOCOL_VIOLATION (RFC 9221 §3). */
+    fn receive_datagram(&mut self, data: &[u8]) -> Result<()> {
+        if 1 + varint_len(data.len() as u64) + data.len() > MAX_DATAGRAM_FRAME_SIZE as usize {
+            return Err(Error::new(ErrorKind::InvalidData, "PROTOCOL_VIOLATION: DATAGRAM frame above max_datagram_frame_size"));
+        }
+        let Some(mut handler) = self.ondatagram_handler.take() else { return Ok(()); };
+        handler(&mut QuicDatagram::new(self, data.to_vec()));
+        if self.ondatagram_handler.is_none() {
+            self.ondatagram_handler = Some(handler);
+        }
+        Ok(())
+    }
+
+    /*
+    Validates a stream the peer referred to and reports the streams that opens to `on_stream`;
+    `early` when that happened in a 0-RTT packet.
+    */
+    fn accept_stream(&mut self, id: u64, early: bool) -> Result<()> {
+        for opened in self.streams.accept(id, early)? {
+            let Some(mut handler) = self.onstream_handler.take() else { break; };
+            handler(&mut QuicStream::new(opened, self));
+            if self.onstream_handler.is_none() {
+                self.onstream_handler = Some(handler);
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    Hands data that arrived in order to HTTP/3 or the stream's on_data handler, fires on_close once nothing
+    more will arrive, and forgets streams that are done in both directions.
+    */
+    pub(crate) fn dispatch_stream(&mut self, id: u64) {
+        if self.h3.is_some() {
+            self.dispatch_h3(id);
+        }
+        if self.streams.get(id).is_some_and(|s| s.ondata_handler.is_some()) {
+            let data = self.streams.read_all(id);
+            if let Some(handler) = self.streams.get_mut(id).and_then(|s| s.ondata_handler.as_mut()) {
+                if !data.is_empty() {
+                    handler(&data);
+                }
+            }
+        }
+        if let Some(stream) = self.streams.get_mut(id) {
+            if !stream.closed && stream.finished() {
+                stream.closed = true;
+                if let Some(handler) = stream.onclose_handler.as_mut() {
+                    handler();
+                }
+            }
+        }
+        self.streams.collect(id);
+    }
+
+    /* Drops the keys and all recovery state of a space that is done with (RFC 9001 §4.9). */
+    pub(crate) fn discard_space(&mut self, space: SpaceId) {
+        if space == SpaceId::Initial {
+            self.original_initial = None;
+        }
+        /* with the handshake confirmed, reordered 0-RTT packets are no longer waited for (RFC 9001 §4.9.3) */
+        if space == SpaceId::Handshake {
+            self.zero_rtt_keys = None;
+        }
+        if self.spaces[space as usize].keys.is_some() {
+            self.spaces[space as usize].discard();
+            self.recovery.discard_space(space);
+        }
+    }
+}
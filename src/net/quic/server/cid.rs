@@ -0,0 +1,95 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use ring::hmac;
+use crate::net::connection::ConnectionId;
+
+/*
+Server CIDs that name the worker owning the connection, so a datagram that reaches another worker can be
+handed to the right one. The layout follows QUIC-LB (draft-ietf-quic-load-balancers): a first octet with
+the config rotation bits and the CID length, the server id (here the worker), then a random nonce.
+With a key, everything after the first octet goes through a four-round Feistel network keyed with
+HMAC-SHA256, so observers can neither link CIDs of one connection nor tell which worker they belong to.
+*/
+
+const CONFIG_ROTATION: u8 = 0b000;
+/* The first octet and the worker id; QUIC-LB wants at least 4 bytes of nonce after them. */
+const MIN_CONNECTION_ID_LEN: usize = 2 + 4;
+
+pub(crate) struct CidRouter {
+    len: usize,
+    workers: usize,
+    key: Option<hmac::Key>,
+    reset_key: hmac::Key, // stateless reset tokens, the same on every worker
+}
+
+impl CidRouter {
+    pub(crate) fn new(len: usize, workers: usize, key: Option<&[u8; 16]>, reset_key: &[u8; 32]) -> Self {
+        debug_assert!((MIN_CONNECTION_ID_LEN..=20).contains(&len) && (1..=256).contains(&workers));
+        Self {
+            len,
+            workers,
+            key: key.map(|key| hmac::Key::new(hmac::HMAC_SHA256, key)),
+            reset_key: hmac::Key::new(hmac::HMAC_SHA256, reset_key),
+        }
+    }
+
+    fn first_octet(&self) -> u8 {
+        CONFIG_ROTATION << 5 | (self.len - 1) as u8
+    }
+
+    /* A fresh CID for a connection owned by `worker`. */
+    pub(crate) fn generate(&self, worker: usize) -> ConnectionId {
+        let mut cid = [0u8; 20];
+        cid[0] = self.first_octet();
+        cid[1] = worker as u8;
+        cid[2..self.len].copy_from_slice(&rand::random::<[u8; 18]>()[..self.len - 2]);
+        if let Some(key) = &self.key {
+            feistel(key, &mut cid[1..self.len], false);
+        }
+        ConnectionId::from_slice(&cid[..self.len])
+    }
+
+    /*
+    The worker a datagram to `dcid` belongs to. A CID of ours names it; any other DCID, as a client picks
+    for its first Initials, is hashed so every worker agrees on one.
+    */
+    pub(crate) fn worker(&self, dcid: &[u8]) -> usize {
+        if dcid.len() == self.len && dcid[0] == self.first_octet() {
+            let mut body = [0u8; 19];
+            body[..self.len - 1].copy_from_slice(&dcid[1..]);
+            if let Some(key) = &self.key {
+                feistel(key, &mut body[..self.len - 1], true);
+            }
+            if (body[0] as usize) < self.workers {
+                return body[0] as usize;
+            }
+        }
+        let mut hasher = DefaultHasher::new();
+        dcid.hash(&mut hasher);
+        hasher.finish() as usize % self.workers
+    }
+
+    /*
+    The stateless reset token of `cid` (RFC 9000 §10.3.2), which any worker can work out again, and so can
+    a restarted server given the same key.
+    */
+    pub(crate) fn reset_token(&self, cid: &ConnectionId) -> [u8; 16] {
+        let tag = hmac::sign(&self.reset_key, cid.as_bytes());
+        tag.as_ref()[..16].try_into().expect("HMAC-SHA256 is 32 bytes")
+    }
+}
+
+/* Encrypts or decrypts `data` in place; each round masks one half with a keyed hash of the other. */
+fn feistel(key: &hmac::Key, data: &mut [u8], decrypt: bool) {
+    let (left, right) = data.split_at_mut(data.len() / 2);
+    for round in 0..4u8 {
+        let round = if decrypt { 3 - round } else { round };
+        let (source, target) = if round % 2 == 0 { (&*left, &mut *right) } else { (&*right, &mut *left) };
+        let mut ctx = hmac::Context::with_key(key);
+        ctx.update(&[round]);
+        ctx.update(source);
+        for (byte, mask) in target.iter_mut().zip(ctx.sign().as_ref()) {
+            *byte ^= mask;
+        }
+    }
+}
@@ -1,47 +1,58 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
 
-use ring::hkdf::{Salt, HKDF_SHA256};
-use crate::net::{QuicLongHeader, Socket, INITIAL_SALT};
-use crate::net::connection::{ConnectionId, QuicConnection};
+use rustls::ServerConfig;
+use crate::net::{CongestionAlgorithm, RetryPolicy, Socket, UdpForwarder, SUPPORTED_VERSIONS};
+use crate::net::connection::{ConnectionId, QuicConnection, DEFAULT_IDLE_TIMEOUT};
+use super::{CidRouter, TimerWheel, TokenKey};
 
 pub struct QuicConnectionEvent<'a> {
     pub connection: &'a mut QuicConnection,
 }
 
-pub type OnConnectionEvent<'a> = Arc<dyn Fn(QuicConnectionEvent) + Send + Sync + 'static>;
+pub type OnConnectionEvent = Arc<dyn Fn(QuicConnectionEvent) + Send + Sync + 'static>;
 
-pub struct QuicThreadContext<'a> {
+pub struct QuicThreadContext {
     pub(crate) id: usize,
     pub(crate) udp_socket: Socket,
     pub(crate) connections: HashMap<ConnectionId, QuicConnection>,
-    pub(crate) initial_salt: Salt,
-    pub(crate) client_init_buf: [u8; 32],
-    pub(crate) hp_key_buf: [u8; 16],
-    pub(crate) aead_key_buf: [u8; 16],
-    pub(crate) aead_iv_buf:  [u8; 12],
-    pub(crate) curr_long_hdr: QuicLongHeader<'a>,
-    pub(crate) onconnection_handler: OnConnectionEvent<'a>,
+    pub(crate) initial_routes: HashMap<ConnectionId, ConnectionId>, // client-chosen DCID => our CID, until Initial keys are dropped
+    pub(crate) cid_routes: HashMap<ConnectionId, ConnectionId>, // CIDs issued with NEW_CONNECTION_ID => the first one
+    pub(crate) pending: Vec<ConnectionId>, // connections with something to send once the datagram is processed
+    pub(crate) timers: TimerWheel,
+    pub(crate) tls_config: Arc<ServerConfig>,
+    pub(crate) congestion: CongestionAlgorithm,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) token_key: Arc<TokenKey>, // shared by all workers of a server
+    pub(crate) versions: Vec<u32>, // in order of preference
+    pub(crate) idle_timeout: Duration,
+    pub(crate) datagram_len: usize,
+    pub(crate) cid_len: usize, // length of the connection IDs this server issues, needed to parse short headers
+    pub(crate) cids: Arc<CidRouter>, // shared by all workers of a server
+    pub(crate) forwarder: Option<UdpForwarder>, // to the other workers
+    pub(crate) onconnection_handler: OnConnectionEvent,
     pub(crate) debug_mode: bool,
 }
 
-impl<'a> QuicThreadContext<'a> {
-    pub fn new(id: usize, udp_socket: Socket, onconnection_handler: OnConnectionEvent<'a>) -> Self {
+impl QuicThreadContext {
+    pub fn new(id: usize, udp_socket: Socket, tls_config: Arc<ServerConfig>, onconnection_handler: OnConnectionEvent) -> Self {
         Self {
             id,
             udp_socket,
             connections: HashMap::new(),
-            initial_salt: Salt::new(HKDF_SHA256, &INITIAL_SALT),
-            client_init_buf: [0; 32],
-            hp_key_buf: [0; 16],
-            aead_key_buf: [0; 16],
-            aead_iv_buf: [0; 12],
-            curr_long_hdr: QuicLongHeader {
-                flags: 0,
-                version: 0,
-                dcid: &[],
-                scid: &[],
-                header_size: 0,
-            },
+            initial_routes: HashMap::new(),
+            cid_routes: HashMap::new(),
+            pending: Vec::new(),
+            timers: TimerWheel::new(Instant::now()),
+            tls_config,
+            congestion: CongestionAlgorithm::default(),
+            retry_policy: RetryPolicy::default(),
+            token_key: Arc::new(TokenKey::generate()),
+            versions: SUPPORTED_VERSIONS.to_vec(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            datagram_len: 0,
+            cid_len: 8,
+            cids: Arc::new(CidRouter::new(8, 1, None, &rand::random())),
+            forwarder: None,
             onconnection_handler,
             debug_mode: true,
         }
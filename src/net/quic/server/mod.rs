@@ -2,5 +2,15 @@ mod server;
 pub use server::*;
 mod context;
 pub use context::*;
+mod cid;
+pub(crate) use cid::CidRouter;
+mod token;
+pub use token::{NEW_TOKEN_LIFETIME, RETRY_TOKEN_LIFETIME};
+pub(crate) use token::{AddressToken, TokenKey};
+mod tickets;
+pub use tickets::SESSION_TICKET_LIFETIME;
+pub(crate) use tickets::SessionTickets;
+mod timers;
+pub(crate) use timers::{TimerWheel, TICK};
 mod processor;
-pub(crate) use processor::{exec_quic_packet};
\ No newline at end of file
+pub(crate) use processor::{exec_quic_packet, flush_quic_connections, forward_quic_datagram, tick_quic_connections};
\ No newline at end of file
@@ -1,8 +1,10 @@
 use std::net::SocketAddr;
-use ring::hkdf::{Prk, HKDF_SHA256};
-use crate::net::{aes_block, expand_label, varint};
-use crate::net::connection::{ConnectionId, QuicConnection, QuicConnectionType};
-use super::QuicThreadContext;
+use std::time::Instant;
+use crate::dprintln;
+use crate::net::{open_initial_packet, write_retry_packet, write_stateless_reset, write_version_negotiation, QuicFrame, QuicFrames, QuicLongHeader, QuicPacket, QuicPacketType, RetryPolicy, QUIC_VERSION_NEGOTIATION};
+use crate::net::connection::{ConnectionId, QuicConnection, QuicConnectionState, QuicConnectionType};
+use crate::net::quic::space::SpaceId;
+use super::{AddressToken, QuicThreadContext, TokenKey};
 
 /*
 Initial Packet {
@@ -26,172 +28,210 @@ Initial Packet {
 }
 */
 
+/* Finds the connection a packet belongs to, by one of our CIDs or by the DCID the client picked for its first Initial. */
 #[cfg_attr(not(debug_assertions), inline(always))]
-fn update_connection<'ctx>(
-    ctx: &'ctx mut QuicThreadContext,
-    scid: ConnectionId,
-    dcid: ConnectionId,
-    packet_number: u32,
-    source_address: &SocketAddr,
-) -> &'ctx mut QuicConnection {
-    use std::collections::hash_map::Entry;
-    match ctx.connections.entry(scid) {
-        Entry::Occupied(entry) => {
-            let conn = entry.into_mut();
-            conn.last_packet_number = packet_number;
-            conn.address = *source_address;
-            conn
-        }
-        Entry::Vacant(entry) => {
-            entry.insert(QuicConnection::new(
-                scid,
-                dcid,
-                packet_number,
-                source_address,
-                QuicConnectionType::Server,
-            ))
-        }
+fn route(ctx: &QuicThreadContext, dcid: &ConnectionId) -> Option<ConnectionId> {
+    if ctx.connections.contains_key(dcid) {
+        return Some(*dcid);
     }
+    ctx.cid_routes.get(dcid).or_else(|| ctx.initial_routes.get(dcid)).copied()
 }
 
-#[cfg_attr(not(debug_assertions), inline(always))]
-pub(crate) fn exec_quic_initial(ctx: &mut QuicThreadContext, packet: &mut [u8], source_address: &SocketAddr) -> usize {
-    let dcid_len = packet[5] as usize;
-    let dcid_start = 6;
-    let dcid_end = dcid_start + dcid_len;
-
-    if dcid_len > 20 || dcid_end >= packet.len() { return 0; }
-
-    let dcid = ConnectionId::from_slice(&packet[6..dcid_end]);
-
-    let scid_len = packet[dcid_end] as usize;
-    let scid_start = dcid_end + 1;
-    let scid_end = scid_start + scid_len;
-
-    if scid_len > 20 || scid_end > packet.len() { return 0; }
-
-    let scid = ConnectionId::from_slice(&packet[scid_start .. scid_end]);
-
-    /* token length */
-    let Some((token_len, tlen_len)) = varint(&packet[scid_end..]) else { return 0; };
-    let token_start = scid_end + tlen_len;
-    let token_end = token_start + token_len;
-    if token_end > packet.len() { return 0; }
-    let token_slice = &packet[token_start..token_end];
-
-    /* derive secrets */
-    let initial_secret = ctx.initial_salt.extract(dcid.as_bytes());
-    expand_label(&initial_secret, b"client in", &mut ctx.client_init_buf);
-    let prk = Prk::new_less_safe(HKDF_SHA256, &ctx.client_init_buf);
-    expand_label(&prk, b"quic hp", &mut ctx.hp_key_buf);
-
-    /* locate packet-number offset */
-    let mut off = token_end;
-    let Some((packet_length, pl)) = varint(&packet[off..]) else { return 0; };
-    off += pl; // PN offset
-    if off + 20 > packet.len() { return 0; }
-    let sample: &[u8;16] = (&packet[off+4..off+20]).try_into().unwrap();
-    let mask = aes_block(&ctx.hp_key_buf, sample);
-
-    /* decrypt the protected 4 bits of the first byte */
-    packet[0] = packet[0] ^ (mask[0] & 0b00001111);
-
-    /* get the packet number length */
-    let pn_len = ((packet[0] & 0b00000011) + 1) as usize;
-
-    /* reserved bits (2); always 0b00 */
-    if packet[0] & 0b00001100 != 0 { return 0; }
-
-    /* decrypt the packet number bytes */
-    let packet_number = match pn_len {
-        1 => {
-            packet[off] ^= mask[1];
-            let pn = &packet[off..off + pn_len];
-            pn[0] as u32
-        },
-        2 => {
-            packet[off] ^= mask[1]; packet[off + 1] ^= mask[2];
-            let pn = &packet[off..off + pn_len];
-            ((pn[0] as u32) << 8) | (pn[1] as u32)
-        },
-        3 => {
-            packet[off] ^= mask[1]; packet[off + 1] ^= mask[2]; packet[off + 2] ^= mask[3];
-            let pn = &packet[off..off + pn_len];
-            ((pn[0] as u32) << 16) | ((pn[1] as u32) << 8) | (pn[2] as u32)
-        },
-        4 => {
-            packet[off] ^= mask[1]; packet[off + 1] ^= mask[2]; packet[off + 2] ^= mask[3]; packet[off + 3] ^= mask[4];
-            let pn = &packet[off..off + pn_len];
-            ((pn[0] as u32) << 24) | ((pn[1] as u32) << 16) | ((pn[2] as u32) << 8) | (pn[3] as u32)
-        },
-        _ => return 0, // unsupported packet number length
-    };
-
-    let payload_start  = off + pn_len;
-    let quic_end = off + packet_length;
-
-    if quic_end > packet.len() { return 0; } // invalid packet length
-
-    /* derive aead key & iv */
-    expand_label(&prk, b"quic key", &mut ctx.aead_key_buf);
-    expand_label(&prk, b"quic iv",  &mut ctx.aead_iv_buf);
-
-    use ring::aead::{self, LessSafeKey, Nonce, UnboundKey, Aad};
-
-    let aead_key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &ctx.aead_key_buf).unwrap());
+/* Whether a client without a token is asked to prove its address before the server keeps any state for it. */
+fn wants_retry(ctx: &QuicThreadContext) -> bool {
+    match ctx.retry_policy {
+        RetryPolicy::Never => false,
+        RetryPolicy::Always => true,
+        RetryPolicy::UnderLoad(handshakes) => ctx.connections.values().filter(|conn| conn.is_handshaking()).count() >= handshakes,
+    }
+}
 
-    /* build nonce = iv XOR padded packet_number (big-endian, 8 bytes) */
+/* Answers a client Initial with a Retry pointing it to a fresh CID of ours (RFC 9000 §8.1.2, §17.2.5). */
+fn send_retry(ctx: &QuicThreadContext, version: u32, dcid: &ConnectionId, scid: &ConnectionId, source_address: &SocketAddr) {
+    let retry_scid = ctx.cids.generate(ctx.id);
+    let token = ctx.token_key.retry_token(source_address, dcid, &retry_scid);
+    let packet = write_retry_packet(version, scid.as_bytes(), retry_scid.as_bytes(), dcid.as_bytes(), &token);
+    if let Err(e) = ctx.send_udp_packet(&packet, source_address) {
+        dprintln!(ctx, "[QUIC] Server => {}: Retry failed: {}", source_address, e);
+    }
+}
 
-    let mut nonce_bytes = [0u8; 12];
-    nonce_bytes.copy_from_slice(&ctx.aead_iv_buf);
-    let pn_be = (packet_number as u64).to_be_bytes();
-    for i in 0..8 { nonce_bytes[nonce_bytes.len() - 8 + i] ^= pn_be[i]; }
+/*
+The versions the client lists in the version_information of its ClientHello, read from a decrypted copy
+of its first Initial. None when the ClientHello does not fit in that packet.
+*/
+fn client_versions(packet: &[u8]) -> Option<Vec<u32>> {
+    let mut copy = packet.to_vec();
+    let opened = open_initial_packet(&mut copy)?;
+    let hello = QuicFrames::new(&copy[opened.payload]).find_map(|frame| match frame {
+        Ok(QuicFrame::Crypto { offset: 0, data }) => Some(data),
+        _ => None,
+    })?;
+    Some(QuicConnection::client_hello_versions(hello)?.available_versions)
+}
 
-    /* decrypt in place */
-    let (header, rest) = packet.split_at_mut(payload_start);
-    let aad = Aad::from(header); // header + pn
-    let cipher_slice = &mut rest[..(quic_end - payload_start)];
-    aead_key.open_in_place(Nonce::assume_unique_for_key(nonce_bytes), aad, cipher_slice).unwrap();
+/*
+Compatible version negotiation (RFC 9368 §2.3): the connection moves to the first of our versions the client
+also speaks, or stays in the version of its first flight.
+*/
+fn choose_version(ctx: &QuicThreadContext, version: u32, packet: &[u8]) -> u32 {
+    if ctx.versions.first() == Some(&version) {
+        return version;
+    }
+    let Some(available) = client_versions(packet) else { return version; };
+    ctx.versions.iter().copied().find(|v| available.contains(v)).unwrap_or(version)
+}
 
-    /* parse CRYPTO frames here. */
+/*
+Accepts a new connection for a client Initial packet (RFC 9000 §7.2, §14.1), or sends a Retry instead.
+A valid token validates the client's address; a Retry token that does not check out means the packet is
+dropped, as the client will not follow a second Retry (RFC 9000 §8.1.2).
+*/
+fn accept_connection(ctx: &mut QuicThreadContext, packet: &[u8], dcid: &ConnectionId, scid: &ConnectionId, token: &[u8], source_address: &SocketAddr) -> Option<ConnectionId> {
+    let original_version = u32::from_be_bytes([packet[1], packet[2], packet[3], packet[4]]);
+    if ctx.datagram_len < 1200 || dcid.len < 8 { return None; }
+    let (original_dcid, retry_scid, validated) = match ctx.token_key.validate(token, source_address) {
+        Some(AddressToken::Retry { original_dcid, retry_scid }) if retry_scid == *dcid => (original_dcid, Some(retry_scid), true),
+        Some(AddressToken::Retry { .. }) => return None,
+        Some(AddressToken::NewToken) => (*dcid, None, true),
+        None if TokenKey::is_retry_token(token) => return None,
+        None if wants_retry(ctx) => {
+            send_retry(ctx, original_version, dcid, scid, source_address);
+            return None;
+        }
+        None => (*dcid, None, false),
+    };
+    let id = ctx.cids.generate(ctx.id);
+    let mut conn = QuicConnection::new(id, *scid, source_address, QuicConnectionType::Server);
+    conn.set_congestion_control(&ctx.congestion);
+    conn.idle_timeout = ctx.idle_timeout;
+    conn.address_validated = validated;
+    conn.versions = ctx.versions.clone();
+    conn.original_version = original_version;
+    conn.version = choose_version(ctx, original_version, packet);
+    if let Err(e) = conn.accept_tls(ctx.tls_config.clone(), dcid, &original_dcid, retry_scid.as_ref(), ctx.cids.reset_token(&id)) {
+        dprintln!(ctx, "[QUIC] {} => Server: cannot start TLS: {}", source_address, e);
+        return None;
+    }
+    ctx.initial_routes.insert(*dcid, id);
+    ctx.connections.insert(id, conn);
+    Some(id)
+}
 
-    let mut off = 0;
+fn drop_connection(ctx: &mut QuicThreadContext, id: &ConnectionId) {
+    ctx.connections.remove(id);
+    ctx.initial_routes.retain(|_, routed| routed != id);
+    ctx.cid_routes.retain(|_, routed| routed != id);
+    ctx.pending.retain(|pending| pending != id);
+    ctx.timers.schedule(*id, None);
+}
 
-    /* update connection */
+/*
+Hands a packet to its connection, reporting the connection once its handshake completes, or as soon as it
+takes the client's 0-RTT data so that can be answered right away. A connection that ends here still sends
+CONNECTION_CLOSE, so it is flushed either way.
+*/
+fn exec_protected(ctx: &mut QuicThreadContext, id: ConnectionId, space: SpaceId, packet_type: QuicPacketType, packet: &mut [u8], pn_offset: usize, source_address: &SocketAddr) {
+    let Some(conn) = ctx.connections.get_mut(&id) else { return; };
+    let was_handshaking = conn.is_handshaking();
+    let was_established = conn.is_established();
+    let was_open = conn.is_open();
+    let had_initial_keys = conn.spaces[SpaceId::Initial as usize].keys.is_some();
+    let handled = conn.handle_packet(space, packet_type, packet, pn_offset, source_address, Instant::now());
+    if !ctx.pending.contains(&id) {
+        ctx.pending.push(id);
+    }
+    if was_open && !conn.is_open() {
+        dprintln!(ctx, "[QUIC] {}: {}", id, conn.close_reason().map(|reason| reason.to_string()).unwrap_or_default());
+    }
+    if handled.is_none() || !conn.is_open() { return; }
+    if had_initial_keys && conn.spaces[SpaceId::Initial as usize].keys.is_none() {
+        ctx.initial_routes.retain(|_, routed| *routed != id);
+    }
+    /* the client gets as many CIDs as it takes, each naming this worker, and those it retired stop routing here */
+    for cid in conn.local_cids.take_retired() {
+        ctx.cid_routes.remove(&cid);
+    }
+    for _ in 0..conn.local_cids.wanted() {
+        let cid = ctx.cids.generate(ctx.id);
+        conn.local_cids.issue(cid, ctx.cids.reset_token(&cid));
+        ctx.cid_routes.insert(cid, id);
+    }
+    if was_handshaking && !conn.is_handshaking() {
+        /* a token for the client's next connection, so it can skip the Retry (RFC 9000 §8.1.3) */
+        conn.new_token = Some(ctx.token_key.new_token(&conn.address));
+    }
+    if !was_established && conn.is_established() {
+        let mut conn = ctx.connections.remove(&id).unwrap();
+        ctx.notify_on_connection(&mut conn);
+        ctx.connections.insert(id, conn);
+    }
+}
 
-    // does a connection really form here in the initial packet? maybe we must reply first with a ServerHello (Sever's own Initial Packet), and wait for the Client to respond with another Initial Packet containing the Server's DCID as SCID, before we can consider this a valid connection.
-    //let conn = update_connection(ctx, scid, dcid, packet_number, source_address);
-    // debugging output, always on for now
-    if true {
-        //println!("[QUIC] {} => Server: Initial, DCID={}, SCID={}, PKN={}, ClientHello", source_address, dcid, scid, packet_number);
-        //println!("[QUIC] <Server> {} => {}@{} (#{}): Initial Packet", conn.dcid, conn.id, source_address, conn.last_packet_number);
+#[cfg_attr(not(debug_assertions), inline(always))]
+pub(crate) fn exec_quic_initial(ctx: &mut QuicThreadContext, packet: &mut [u8], dcid: &ConnectionId, scid: &ConnectionId, token: &[u8], pn_offset: usize, source_address: &SocketAddr) {
+    if let Some(id) = route(ctx, dcid) {
+        return exec_protected(ctx, id, SpaceId::Initial, QuicPacketType::Initial, packet, pn_offset, source_address);
+    }
+    let Some(id) = accept_connection(ctx, packet, dcid, scid, token, source_address) else { return; };
+    exec_protected(ctx, id, SpaceId::Initial, QuicPacketType::Initial, packet, pn_offset, source_address);
+    /* nothing is kept for a first packet that does not authenticate */
+    if ctx.connections.get(&id).is_some_and(|conn| conn.is_open() && conn.spaces[SpaceId::Initial as usize].largest_received().is_none()) {
+        drop_connection(ctx, &id);
     }
-    /*
-    println!("[QUIC] <Client> {}@{} => <Server> {} (#{}): ClientHello (Initial - 1 Crypto Frame)", conn.id, conn.address, conn.dcid, conn.last_packet_number);
-    println!("[QUIC] <Server> {} => <Client> {}@{} (#{}): ServerHello (Handshake - 1 Crypto Frame)", conn.dcid, conn.id, source_address, conn.last_packet_number);
-    */
-    quic_end
 }
 
+/* 0-RTT packets are read once the connection took early data; otherwise the client sends it again in 1-RTT packets. */
 #[cfg_attr(not(debug_assertions), inline(always))]
-pub(crate) fn exec_quic_hanshake(ctx: &mut QuicThreadContext, packet: &mut [u8], source: &SocketAddr) -> usize {
-    0
+pub(crate) fn exec_quic_0rtt(ctx: &mut QuicThreadContext, packet: &mut [u8], dcid: &ConnectionId, pn_offset: usize, source_address: &SocketAddr) {
+    let Some(id) = route(ctx, dcid) else { return; };
+    exec_protected(ctx, id, SpaceId::Data, QuicPacketType::ZeroRtt, packet, pn_offset, source_address);
 }
 
 #[cfg_attr(not(debug_assertions), inline(always))]
-pub(crate) fn exec_quic_retry(ctx: &mut QuicThreadContext, packet: &mut [u8], source: &SocketAddr) -> usize {
-    0
+pub(crate) fn exec_quic_handshake(ctx: &mut QuicThreadContext, packet: &mut [u8], dcid: &ConnectionId, pn_offset: usize, source_address: &SocketAddr) {
+    let Some(id) = route(ctx, dcid) else { return; };
+    exec_protected(ctx, id, SpaceId::Handshake, QuicPacketType::Handshake, packet, pn_offset, source_address);
 }
 
 #[cfg_attr(not(debug_assertions), inline(always))]
-pub(crate) fn exec_quic_1rtt(ctx: &mut QuicThreadContext, packet: &mut [u8], source: &SocketAddr) -> usize {
-    0
+pub(crate) fn exec_quic_retry(ctx: &mut QuicThreadContext, packet: &mut [u8], source: &SocketAddr) {
+    // only servers send Retry packets; one sent to a server is dropped
 }
 
 #[cfg_attr(not(debug_assertions), inline(always))]
-pub(crate) fn exec_quic_version_negotiation(ctx: &mut QuicThreadContext, packet: &mut [u8], source: &SocketAddr) -> usize {
-    0
+pub(crate) fn exec_quic_1rtt(ctx: &mut QuicThreadContext, packet: &mut [u8], dcid: &ConnectionId, pn_offset: usize, source_address: &SocketAddr) {
+    let Some(id) = route(ctx, dcid) else { return send_stateless_reset(ctx, dcid, source_address); };
+    exec_protected(ctx, id, SpaceId::Data, QuicPacketType::OneRtt, packet, pn_offset, source_address);
 }
+
+#[cfg_attr(not(debug_assertions), inline(always))]
+pub(crate) fn exec_quic_version_negotiation(ctx: &mut QuicThreadContext, packet: &mut [u8], source: &SocketAddr) {
+    // only servers send Version Negotiation packets; one sent to a server is dropped (RFC 9000 §6.1)
+}
+
+/*
+Answers a long header packet in a version we do not speak with the versions we do (RFC 9000 §6.1).
+Only datagrams large enough to start a connection get one, so the answer is never an amplification.
+*/
+fn send_version_negotiation(ctx: &QuicThreadContext, header: &QuicLongHeader, source_address: &SocketAddr) {
+    if ctx.datagram_len < 1200 { return; }
+    let packet = write_version_negotiation(header.scid(), header.dcid(), &ctx.versions);
+    if let Err(e) = ctx.send_udp_packet(&packet, source_address) {
+        dprintln!(ctx, "[QUIC] Server => {}: Version Negotiation failed: {}", source_address, e);
+    }
+}
+/*
+Tells the sender of a 1-RTT packet for a connection we know nothing of, e.g. one from before a restart,
+that it is gone (RFC 9000 §10.3). The token is the one the connection was given for that CID.
+*/
+fn send_stateless_reset(ctx: &QuicThreadContext, dcid: &ConnectionId, source_address: &SocketAddr) {
+    let Some(packet) = write_stateless_reset(&ctx.cids.reset_token(dcid), ctx.datagram_len) else { return; };
+    if let Err(e) = ctx.send_udp_packet(&packet, source_address) {
+        dprintln!(ctx, "[QUIC] Server => {}: stateless reset failed: {}", source_address, e);
+    }
+}
+
 fn format_as_vec_literal(bytes: &[u8]) -> String {
     let mut out = String::from("vec![");
     for (i, b) in bytes.iter().enumerate() {
@@ -204,24 +244,85 @@ fn format_as_vec_literal(bytes: &[u8]) -> String {
     out
 }
 
-/* Process a QUIC Packet and returns its size. */
+/*
+Hands a datagram for a connection of another worker over to it; all packets of a datagram go to one
+connection (RFC 9000 §12.2). Returns whether it did.
+*/
+pub(crate) fn forward_quic_datagram(ctx: &QuicThreadContext, datagram: &[u8], source_address: &SocketAddr) -> bool {
+    let Some(forwarder) = ctx.forwarder.as_ref().filter(|forwarder| forwarder.worker_count() > 1) else { return false; };
+    let dcid = match QuicLongHeader::parse(datagram) {
+        Some(header) => header.dcid(),
+        None if datagram[0] & 0x80 == 0 => match datagram.get(1..1 + ctx.cid_len) {
+            Some(dcid) => dcid,
+            None => return false,
+        },
+        None => return false,
+    };
+    let worker = ctx.cids.worker(dcid);
+    worker != ctx.id && forwarder.forward(worker, *source_address, datagram)
+}
+
+/* Process the first QUIC packet of a datagram and return its size, or 0 if it does not parse. */
 #[cfg_attr(not(debug_assertions), inline(always))]
 pub(crate) fn exec_quic_packet(ctx: &mut QuicThreadContext, packet: &mut [u8], source_address: &SocketAddr) -> usize {
     //println!("{}", format_as_vec_literal(packet));
-    if packet[0] & 0b10000000 == 0 { /* short header */
-        0
-    } else { /* long header */
-
-        let quic_version = u32::from_be_bytes(packet[1..5].try_into().unwrap());
-        if quic_version != 1 { return 0; }
-        if packet[0] & 0b01000000 == 0 { return exec_quic_version_negotiation(ctx, packet, &source_address); }
-        let packet_type = (packet[0] & 0b00110000) >> 4;
-        match packet_type {
-            0b00 => exec_quic_initial(ctx, packet, &source_address),
-            0b01 => exec_quic_hanshake(ctx, packet, &source_address),
-            0b10 => exec_quic_retry(ctx, packet, &source_address),
-            0b11 => exec_quic_1rtt(ctx, packet, &source_address),
-            _ => 0,
+    if let Some(header) = QuicLongHeader::parse(packet).filter(|h| h.version() != QUIC_VERSION_NEGOTIATION && !ctx.versions.contains(&h.version())) {
+        send_version_negotiation(ctx, &header, source_address);
+        return 0;
+    }
+    let Some(parsed) = QuicPacket::parse(packet, ctx.cid_len) else { return 0; };
+    let (packet_type, len) = (parsed.header.packet_type(), parsed.len());
+    let protected = parsed.header.protected().map(|h| (ConnectionId::from_slice(h.dcid), ConnectionId::from_slice(h.scid), h.token.to_vec(), h.pn_offset));
+    let packet = &mut packet[..len];
+    /* a packet that fails to decrypt does not invalidate the ones coalesced after it */
+    match (packet_type, protected) {
+        (QuicPacketType::Initial, Some((dcid, scid, token, pn_offset))) => exec_quic_initial(ctx, packet, &dcid, &scid, &token, pn_offset, source_address),
+        (QuicPacketType::ZeroRtt, Some((dcid, _, _, pn_offset))) => exec_quic_0rtt(ctx, packet, &dcid, pn_offset, source_address),
+        (QuicPacketType::Handshake, Some((dcid, _, _, pn_offset))) => exec_quic_handshake(ctx, packet, &dcid, pn_offset, source_address),
+        (QuicPacketType::OneRtt, Some((dcid, _, _, pn_offset))) => exec_quic_1rtt(ctx, packet, &dcid, pn_offset, source_address),
+        (QuicPacketType::Retry, _) => exec_quic_retry(ctx, packet, source_address),
+        (QuicPacketType::VersionNegotiation, _) => exec_quic_version_negotiation(ctx, packet, source_address),
+        _ => {}
+    }
+    len
+}
+
+/*
+Sends whatever the connections touched by the last datagram or a timer have queued, sets their next timer
+and forgets those that are closed.
+*/
+pub(crate) fn flush_quic_connections(ctx: &mut QuicThreadContext) {
+    let now = Instant::now();
+    for id in std::mem::take(&mut ctx.pending) {
+        let Some(conn) = ctx.connections.get_mut(&id) else { continue; };
+        if conn.poll_timeout().is_some_and(|at| at <= now) {
+            conn.handle_timeout(now);
+        }
+        if conn.state() == QuicConnectionState::Closed {
+            drop_connection(ctx, &id);
+            continue;
+        }
+        let mut datagrams: Vec<(SocketAddr, Vec<u8>)> = std::iter::from_fn(|| conn.poll_off_path_response(now)).collect();
+        let address = conn.address;
+        datagrams.extend(std::iter::from_fn(|| conn.poll_transmit(now)).map(|datagram| (address, datagram)));
+        /* HANDSHAKE_DONE is out: the handshake is confirmed and Handshake keys go (RFC 9001 §4.9.2) */
+        if conn.handshake_confirmed {
+            conn.discard_space(SpaceId::Handshake);
+        }
+        ctx.timers.schedule(id, conn.poll_timeout());
+        for (address, datagram) in datagrams {
+            if let Err(e) = ctx.send_udp_packet(&datagram, &address) {
+                dprintln!(ctx, "[QUIC] {}: send failed: {}", id, e);
+                break;
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+/* Handles the timers that expired since the last tick. */
+pub(crate) fn tick_quic_connections(ctx: &mut QuicThreadContext) {
+    let expired = ctx.timers.expired(Instant::now());
+    if expired.is_empty() { return; }
+    ctx.pending.extend(expired);
+    flush_quic_connections(ctx);
+}
@@ -1,8 +1,14 @@
+use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::net::{QuicConnectionEvent, UdpServer, UdpServerThreadContext};
-use super::{exec_quic_packet, QuicThreadContext};
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use crate::net::{CongestionAlgorithm, QuicConnectionEvent, UdpServer, UdpServerThreadContext, SUPPORTED_VERSIONS};
+use crate::net::connection::DEFAULT_IDLE_TIMEOUT;
+use crate::net::h3::{H3Request, H3RequestHandler, H3Response, H3_ALPN};
+use super::{exec_quic_packet, flush_quic_connections, forward_quic_datagram, tick_quic_connections, CidRouter, QuicThreadContext, SessionTickets, TokenKey, SESSION_TICKET_LIFETIME, TICK};
 
 type OnConnectionEvent = Arc<dyn Fn(QuicConnectionEvent) + Send + Sync + 'static>;
 
@@ -11,10 +17,31 @@ pub enum DispatchMode {
     Async,
 }
 
+/* When a client's address is validated with a Retry before any connection state is kept (RFC 9000 §8.1.2). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetryPolicy {
+    #[default]
+    Never,
+    Always,
+    /* once a worker has this many handshakes in progress */
+    UnderLoad(usize),
+}
+
 pub struct QuicServer {
     udp_server: UdpServer,
     datagram_dispatch_mode: DispatchMode,
     onconnection_handler: Option<OnConnectionEvent>,
+    onrequest_handler: Option<H3RequestHandler>,
+    tls_config: Option<ServerConfig>,
+    alpn_protocols: Vec<Vec<u8>>,
+    congestion: CongestionAlgorithm,
+    retry_policy: RetryPolicy,
+    versions: Vec<u32>,
+    cid_key: Option<[u8; 16]>,
+    reset_key: [u8; 32],
+    idle_timeout: Duration,
+    ticket_lifetime: Duration,
+    early_data: bool,
 }
 
 impl QuicServer {
@@ -23,6 +50,17 @@ impl QuicServer {
             udp_server: UdpServer::new(address),
             datagram_dispatch_mode: DispatchMode::Direct,
             onconnection_handler: None,
+            onrequest_handler: None,
+            tls_config: None,
+            alpn_protocols: Vec::new(),
+            congestion: CongestionAlgorithm::default(),
+            retry_policy: RetryPolicy::default(),
+            versions: SUPPORTED_VERSIONS.to_vec(),
+            cid_key: None,
+            reset_key: rand::random(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            ticket_lifetime: SESSION_TICKET_LIFETIME,
+            early_data: false,
         }
     }
 
@@ -35,6 +73,7 @@ impl QuicServer {
         self
     }
 
+    /* Called for every new connection once its handshake completes, or earlier when it takes 0-RTT data. */
     pub fn on_connection<H>(&mut self, h: H) -> &mut Self
     where
         H: Fn(QuicConnectionEvent) + Send + Sync + 'static,
@@ -43,29 +82,179 @@ impl QuicServer {
         self
     }
 
+    /*
+    Serves HTTP/3: connections that negotiate "h3", which is added to the ALPN protocols, answer every request
+    with `h`. `on_connection` still sees every connection, after HTTP/3 was set up on it.
+    */
+    pub fn on_request<H>(&mut self, h: H) -> &mut Self
+    where
+        H: Fn(&H3Request) -> H3Response + Send + Sync + 'static,
+    {
+        self.onrequest_handler = Some(Arc::new(h));
+        self
+    }
+
+    /* The certificate chain (leaf first) and private key presented in the TLS handshake; TLS 1.3 only, as QUIC requires. */
+    pub fn set_certificate_chain(&mut self, chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> std::io::Result<&mut Self> {
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.tls_config = Some(config);
+        Ok(self)
+    }
+
+    /* Application protocols offered through ALPN, in order of preference. */
+    pub fn set_alpn_protocols(&mut self, protocols: &[&[u8]]) -> &mut Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    /* Congestion controller every accepted connection starts with; NewReno unless set. */
+    pub fn set_congestion_control(&mut self, algorithm: CongestionAlgorithm) -> &mut Self {
+        self.congestion = algorithm;
+        self
+    }
+
+    /*
+    Whether new clients are sent a Retry first. Without one the server still sends at most three times
+    what an unvalidated client sent it, and clients holding a NEW_TOKEN token never need one.
+    */
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /*
+    QUIC versions accepted, in order of preference. Clients in another one get a Version Negotiation packet;
+    clients that also speak a preferred one are moved to it during the handshake (RFC 9368).
+    */
+    pub fn set_versions(&mut self, versions: &[u32]) -> std::io::Result<&mut Self> {
+        if versions.is_empty() || versions.iter().any(|v| !SUPPORTED_VERSIONS.contains(v)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "unsupported QUIC version"));
+        }
+        self.versions = versions.to_vec();
+        Ok(self)
+    }
+
+    /*
+    Encrypts the worker id our connection IDs carry, so observers can neither tell which worker a
+    connection is on nor link the CIDs of one connection. Without a key the worker id is in the clear.
+    */
+    pub fn set_connection_id_key(&mut self, key: [u8; 16]) -> &mut Self {
+        self.cid_key = Some(key);
+        self
+    }
+
+    /*
+    The key stateless reset tokens are derived from. A server restarted with the same key can tell clients of
+    connections it lost that they are gone; by default every server gets a random one.
+    */
+    pub fn set_stateless_reset_key(&mut self, key: [u8; 32]) -> &mut Self {
+        self.reset_key = key;
+        self
+    }
+
+    /* The max_idle_timeout connections advertise; the smaller of both ends' applies, zero means none (RFC 9000 §10.1). */
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /*
+    How long the session tickets handed to clients can resume a connection; each one works once.
+    Zero means no tickets and a full handshake every time.
+    */
+    pub fn set_session_ticket_lifetime(&mut self, lifetime: Duration) -> &mut Self {
+        self.ticket_lifetime = lifetime;
+        self
+    }
+
+    /*
+    Whether clients resuming a session may send 0-RTT data. Tickets work once, so a captured first flight
+    is not taken twice, but 0-RTT data still lacks the guarantees the handshake gives later data
+    (RFC 9001 §9.2); streams tell whether they started in it. Off unless set.
+    */
+    pub fn set_early_data(&mut self, enabled: bool) -> &mut Self {
+        self.early_data = enabled;
+        self
+    }
+
     pub fn start(&mut self, num_workers: usize) {
-        let handler = self
-            .onconnection_handler
+        let handler = match (self.onconnection_handler.take(), self.onrequest_handler.take()) {
+            (handler, Some(onrequest)) => {
+                if !self.alpn_protocols.iter().any(|p| p == H3_ALPN) {
+                    self.alpn_protocols.push(H3_ALPN.to_vec());
+                }
+                Arc::new(move |event: QuicConnectionEvent| {
+                    if event.connection.alpn_protocol() == Some(H3_ALPN) {
+                        let _ = event.connection.serve_h3(onrequest.clone());
+                    }
+                    if let Some(handler) = handler.as_ref() {
+                        handler(event);
+                    }
+                })
+            }
+            (Some(handler), None) => handler,
+            (None, None) => panic!("on_connection or on_request handler must be set before starting the server"),
+        };
+        let mut tls_config = self
+            .tls_config
             .take()
-            .expect("on_accept handler must be set before starting the server");
+            .expect("a certificate chain must be set before starting the server");
+        tls_config.alpn_protocols = self.alpn_protocols.clone();
+        tls_config.session_storage = Arc::new(SessionTickets::new(self.ticket_lifetime));
+        if self.ticket_lifetime.is_zero() {
+            tls_config.send_tls13_tickets = 0;
+        }
+        /* QUIC takes early data without a size limit or none at all (RFC 9001 §4.6.1) */
+        tls_config.max_early_data_size = if self.early_data { u32::MAX } else { 0 };
+        let tls_config = Arc::new(tls_config);
+        let congestion = self.congestion.clone();
+        let retry_policy = self.retry_policy;
+        let token_key = Arc::new(TokenKey::generate());
+        let versions = self.versions.clone();
+        let cids = Arc::new(CidRouter::new(8, num_workers, self.cid_key.as_ref(), &self.reset_key));
+        let idle_timeout = self.idle_timeout;
+        /* timers and datagrams forwarded by other workers wait at most a tick */
+        self.udp_server.set_recv_timeout(TICK);
         self.udp_server.thread({
             move |mut udp_ctx: UdpServerThreadContext| {
                 let onconnection_handler = handler.clone();
-                let mut quic_ctx = QuicThreadContext::new(udp_ctx.id, udp_ctx.socket, onconnection_handler.clone());
+                let mut quic_ctx = QuicThreadContext::new(udp_ctx.id, udp_ctx.socket, tls_config.clone(), onconnection_handler.clone());
+                quic_ctx.congestion = congestion.clone();
+                quic_ctx.retry_policy = retry_policy;
+                quic_ctx.token_key = token_key.clone();
+                quic_ctx.versions = versions.clone();
+                quic_ctx.cids = cids.clone();
+                quic_ctx.idle_timeout = idle_timeout;
+                quic_ctx.forwarder = Some(udp_ctx.forwarder());
+                /* datagrams and timers are handled on the worker's thread, the lock is never contended */
+                let quic_ctx = Arc::new(Mutex::new(quic_ctx));
+                let timer_ctx = quic_ctx.clone();
                 udp_ctx.on_datagram(move |src, data| {
                     if data.len() < 8 {
                         return; // Not enough data for a QUIC packet
                     }
+                    let mut quic_ctx = quic_ctx.lock().unwrap();
+                    /* a datagram for a connection of another worker goes to that worker */
+                    if forward_quic_datagram(&quic_ctx, data, &src) {
+                        return;
+                    }
+                    quic_ctx.datagram_len = data.len();
                     let mut i = 0;
-                    while i + 7 < data.len() {
+                    while i < data.len() {
                         let pkt = &mut data[i..];
                         let processed_bytes = exec_quic_packet(&mut quic_ctx, pkt, &src);
                         if processed_bytes == 0 {
-                            return; // Stop processing if exec_quic_packet returns 0
+                            break; // Stop processing if exec_quic_packet returns 0
                         }
                         i += processed_bytes;
                     }
+                    /* answer once per datagram so the replies to coalesced packets are coalesced too */
+                    flush_quic_connections(&mut quic_ctx);
                 });
+                udp_ctx.on_tick(move || tick_quic_connections(&mut timer_ctx.lock().unwrap()));
                 udp_ctx.run().expect("UDP server thread failed");
             }
         });
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rustls::server::StoresServerSessions;
+
+/*
+Session tickets for resumption (RFC 8446 §4.6.1). The server keeps the sessions and a ticket is only the key
+the client shows back, which is what TLS needs to accept 0-RTT data at all.
+
+Anti-replay (RFC 8446 §8.1): taking a ticket removes it, so each one resumes a single connection and the
+0-RTT data of a captured first flight is not accepted a second time. Tickets also expire, which bounds how
+long a client holding one can count on 0-RTT. Every worker of a server shares the store.
+*/
+
+/* How long a ticket can be used after it was issued. */
+pub const SESSION_TICKET_LIFETIME: Duration = Duration::from_secs(10 * 60);
+/* Sessions kept at most; when full, the oldest go first. */
+const MAX_SESSIONS: usize = 4096;
+
+type Sessions = HashMap<Vec<u8>, (Vec<u8>, Instant)>; // ticket => session, issued at
+
+#[derive(Debug)]
+pub(crate) struct SessionTickets {
+    sessions: Mutex<Sessions>,
+    lifetime: Duration,
+}
+
+impl SessionTickets {
+    pub(crate) fn new(lifetime: Duration) -> Self {
+        Self { sessions: Mutex::new(HashMap::new()), lifetime }
+    }
+}
+
+impl StoresServerSessions for SessionTickets {
+    fn put(&self, ticket: Vec<u8>, session: Vec<u8>) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= MAX_SESSIONS {
+            sessions.retain(|_, (_, issued)| issued.elapsed() < self.lifetime);
+        }
+        if sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions.iter().min_by_key(|(_, (_, issued))| *issued).map(|(ticket, _)| ticket.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+        sessions.insert(ticket, (session, Instant::now()));
+        true
+    }
+
+    /* TLS 1.3 only ever takes tickets, there is nothing to look up without using it. */
+    fn get(&self, _ticket: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn take(&self, ticket: &[u8]) -> Option<Vec<u8>> {
+        let (session, issued) = self.sessions.lock().unwrap().remove(ticket)?;
+        (issued.elapsed() < self.lifetime).then_some(session)
+    }
+
+    fn can_cache(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::net::connection::ConnectionId;
+
+/*
+The timers of a worker's connections: loss detection, probes, delayed ACKs, idle timeouts and the end of
+closing and draining. A hashed timer wheel (Varghese & Lauck) keeps each connection's next deadline in the
+slot of its tick, so looking for expired timers only touches the slots of the ticks that went by.
+Deadlines are rounded up to the next tick and fire at most one tick late.
+*/
+
+/* How often a worker looks at its timers; also the longest it waits in recv. */
+pub(crate) const TICK: Duration = Duration::from_millis(5);
+/* One turn of the wheel is a little over 2.5 seconds, later deadlines stay in their slot for more turns. */
+const SLOTS: usize = 512;
+
+pub(crate) struct TimerWheel {
+    start: Instant,
+    slots: Vec<Vec<(ConnectionId, Instant)>>,
+    next_tick: u64, // the first tick whose slot has not been looked at yet
+    deadlines: HashMap<ConnectionId, Instant>, // the one that counts per connection, older slot entries are stale
+}
+
+impl TimerWheel {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self { start: now, slots: vec![Vec::new(); SLOTS], next_tick: 0, deadlines: HashMap::new() }
+    }
+
+    fn tick(&self, at: Instant) -> u64 {
+        (at.saturating_duration_since(self.start).as_nanos() / TICK.as_nanos()) as u64
+    }
+
+    /* Sets when the connection wants its timers handled next, None for never. */
+    pub(crate) fn schedule(&mut self, id: ConnectionId, at: Option<Instant>) {
+        let Some(at) = at else {
+            self.deadlines.remove(&id);
+            return;
+        };
+        if self.deadlines.insert(id, at) == Some(at) { return; }
+        let tick = (self.tick(at) + 1).max(self.next_tick);
+        self.slots[(tick % SLOTS as u64) as usize].push((id, at));
+    }
+
+    /* The connections whose deadline passed by `now`; each is reported once per deadline. */
+    pub(crate) fn expired(&mut self, now: Instant) -> Vec<ConnectionId> {
+        let now_tick = self.tick(now);
+        let mut expired = Vec::new();
+        if now_tick < self.next_tick { return expired; }
+        /* after a long pause one turn over all slots finds everything */
+        let turns = (now_tick - self.next_tick + 1).min(SLOTS as u64);
+        for tick in self.next_tick..self.next_tick + turns {
+            let deadlines = &mut self.deadlines;
+            self.slots[(tick % SLOTS as u64) as usize].retain(|(id, at)| {
+                if *at > now { return true; }
+                if deadlines.get(id) == Some(at) {
+                    deadlines.remove(id);
+                    expired.push(*id);
+                }
+                false
+            });
+        }
+        self.next_tick = now_tick + 1;
+        expired
+    }
+}
@@ -0,0 +1,105 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use crate::net::connection::ConnectionId;
+
+/*
+Address validation tokens (RFC 9000 §8.1). Only the server that issues a token reads it back, so the
+format is ours: a kind byte, a random nonce and an AES-256-GCM sealed body, authenticated together with
+the client's address. Every worker of a server shares the key, whichever one the client reaches next.
+
+Retry tokens are bound to the full address and carry the client's original DCID and the CID of the Retry;
+NEW_TOKEN tokens are bound to the IP alone, since a client usually comes back from another port.
+*/
+
+/* A Retry token is used right away; it only has to survive a round trip. */
+pub const RETRY_TOKEN_LIFETIME: Duration = Duration::from_secs(10);
+/* A NEW_TOKEN token is meant for a connection some time later. */
+pub const NEW_TOKEN_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+const RETRY_TOKEN: u8 = 0;
+const NEW_TOKEN: u8 = 1;
+
+/* What a token that checks out says about the client. */
+pub(crate) enum AddressToken {
+    Retry { original_dcid: ConnectionId, retry_scid: ConnectionId },
+    NewToken,
+}
+
+pub(crate) struct TokenKey {
+    key: LessSafeKey,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn aad(kind: u8, address: &SocketAddr) -> Vec<u8> {
+    let mut aad = vec![kind];
+    match address.ip() {
+        IpAddr::V4(ip) => aad.extend_from_slice(&ip.octets()),
+        IpAddr::V6(ip) => aad.extend_from_slice(&ip.octets()),
+    }
+    if kind == RETRY_TOKEN {
+        aad.extend_from_slice(&address.port().to_be_bytes());
+    }
+    aad
+}
+
+impl TokenKey {
+    pub(crate) fn generate() -> Self {
+        let key = UnboundKey::new(&AES_256_GCM, &rand::random::<[u8; 32]>()).expect("valid AES-256 key");
+        Self { key: LessSafeKey::new(key) }
+    }
+
+    fn seal(&self, kind: u8, address: &SocketAddr, body: &[u8]) -> Vec<u8> {
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let mut sealed = now_secs().to_be_bytes().to_vec();
+        sealed.extend_from_slice(body);
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad(kind, address)), &mut sealed)
+            .expect("tokens are far below the AEAD size limit");
+        let mut token = vec![kind];
+        token.extend_from_slice(&nonce);
+        token.extend_from_slice(&sealed);
+        token
+    }
+
+    /* The token of a Retry sent to `address`, whose Initial went to `original_dcid`, asking it to use `retry_scid`. */
+    pub(crate) fn retry_token(&self, address: &SocketAddr, original_dcid: &ConnectionId, retry_scid: &ConnectionId) -> Vec<u8> {
+        let mut body = vec![original_dcid.len as u8];
+        body.extend_from_slice(original_dcid.as_bytes());
+        body.extend_from_slice(retry_scid.as_bytes());
+        self.seal(RETRY_TOKEN, address, &body)
+    }
+
+    /* A token for NEW_TOKEN, letting the client skip the Retry next time. */
+    pub(crate) fn new_token(&self, address: &SocketAddr) -> Vec<u8> {
+        self.seal(NEW_TOKEN, address, &[])
+    }
+
+    pub(crate) fn is_retry_token(token: &[u8]) -> bool {
+        token.first() == Some(&RETRY_TOKEN)
+    }
+
+    /* Opens a token the client at `address` sent; None if it was not ours, was altered, is for another address or expired. */
+    pub(crate) fn validate(&self, token: &[u8], address: &SocketAddr) -> Option<AddressToken> {
+        let (&kind, rest) = token.split_first()?;
+        let (nonce, sealed) = rest.split_at_checked(NONCE_LEN)?;
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut sealed = sealed.to_vec();
+        let body = self.key.open_in_place(nonce, Aad::from(aad(kind, address)), &mut sealed).ok()?;
+        let (issued, body) = body.split_at_checked(8)?;
+        let age = now_secs().checked_sub(u64::from_be_bytes(issued.try_into().ok()?))?;
+        match kind {
+            RETRY_TOKEN if age <= RETRY_TOKEN_LIFETIME.as_secs() => {
+                let (&len, body) = body.split_first()?;
+                let (original_dcid, retry_scid) = body.split_at_checked(len as usize)?;
+                Some(AddressToken::Retry { original_dcid: ConnectionId::from_slice(original_dcid), retry_scid: ConnectionId::from_slice(retry_scid) })
+            }
+            NEW_TOKEN if age <= NEW_TOKEN_LIFETIME.as_secs() => Some(AddressToken::NewToken),
+            _ => None,
+        }
+    }
+}
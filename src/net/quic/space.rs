@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::net::{AckFrame, AckTracker, PacketProtector, DEFAULT_ACK_DELAY_EXPONENT, DEFAULT_MAX_ACK_DELAY};
+
+/* The three packet number spaces (RFC 9000 §12.3); 0-RTT and 1-RTT share `Data`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpaceId {
+    Initial = 0,
+    Handshake = 1,
+    Data = 2,
+}
+
+impl SpaceId {
+    pub const ALL: [SpaceId; 3] = [SpaceId::Initial, SpaceId::Handshake, SpaceId::Data];
+}
+
+/* CRYPTO data a peer may send ahead of what TLS has consumed (RFC 9000 §7.5). */
+const MAX_CRYPTO_BUFFER: u64 = 64 * 1024;
+
+pub(crate) struct PacketSpace {
+    pub(crate) keys: Option<PacketProtector>,
+    pub(crate) next_pn: u64,
+    pub(crate) acks: AckTracker,
+    crypto_offset: u64,           // next CRYPTO byte TLS expects
+    crypto_pending: BTreeMap<u64, Vec<u8>>,
+    pub(crate) crypto_send: Vec<u8>, // handshake bytes not yet put in a CRYPTO frame
+    pub(crate) crypto_send_offset: u64,
+    crypto_lost: BTreeMap<u64, Vec<u8>>, // CRYPTO data to send again, by offset
+    pub(crate) probes: u8, // ack-eliciting packets owed to a probe timeout
+}
+
+impl PacketSpace {
+    pub(crate) fn new(id: SpaceId) -> Self {
+        /* only application data may be acknowledged late (RFC 9000 §13.2.1) */
+        let max_ack_delay = if id == SpaceId::Data { DEFAULT_MAX_ACK_DELAY } else { Duration::ZERO };
+        Self {
+            keys: None,
+            next_pn: 0,
+            acks: AckTracker::new(max_ack_delay),
+            crypto_offset: 0,
+            crypto_pending: BTreeMap::new(),
+            crypto_send: Vec::new(),
+            crypto_send_offset: 0,
+            crypto_lost: BTreeMap::new(),
+            probes: 0,
+        }
+    }
+
+    pub(crate) fn largest_received(&self) -> Option<u64> {
+        self.acks.largest()
+    }
+
+    pub(crate) fn is_duplicate(&self, pn: u64) -> bool {
+        self.acks.is_duplicate(pn)
+    }
+
+    pub(crate) fn on_packet_received(&mut self, pn: u64, ack_eliciting: bool, now: Instant) {
+        self.acks.on_packet_received(pn, ack_eliciting, now);
+    }
+
+    /* An ACK to send now, or one that can ride along with other frames when `piggyback` is set. */
+    pub(crate) fn ack_frame(&mut self, now: Instant, piggyback: bool) -> Option<AckFrame> {
+        let due = self.acks.wants_ack(now) || (piggyback && self.acks.has_pending());
+        if !due { return None; }
+        self.acks.ack_frame(now, DEFAULT_ACK_DELAY_EXPONENT)
+    }
+
+    /* A congested space only has ACKs and probes to send. */
+    pub(crate) fn has_outgoing(&self, now: Instant, congested: bool) -> bool {
+        self.keys.is_some() && (self.acks.wants_ack(now) || self.probes > 0 || (!congested && self.has_crypto()))
+    }
+
+    pub(crate) fn has_crypto(&self) -> bool {
+        !self.crypto_send.is_empty() || !self.crypto_lost.is_empty()
+    }
+
+    /* Buffers a CRYPTO frame and returns the bytes that became contiguous, or None if the peer sent too far ahead. */
+    pub(crate) fn on_crypto(&mut self, offset: u64, data: &[u8]) -> Option<Vec<u8>> {
+        let end = offset + data.len() as u64;
+        if end > self.crypto_offset + MAX_CRYPTO_BUFFER { return None; } // CRYPTO_BUFFER_EXCEEDED
+        if end > self.crypto_offset {
+            let skip = self.crypto_offset.saturating_sub(offset);
+            let entry = self.crypto_pending.entry(offset + skip).or_default();
+            if entry.len() < data.len() - skip as usize {
+                *entry = data[skip as usize..].to_vec();
+            }
+        }
+        let mut ready = Vec::new();
+        while let Some(entry) = self.crypto_pending.first_entry() {
+            if *entry.key() > self.crypto_offset { break; }
+            let (start, chunk) = entry.remove_entry();
+            let skip = (self.crypto_offset - start) as usize;
+            if skip < chunk.len() {
+                ready.extend_from_slice(&chunk[skip..]);
+                self.crypto_offset += (chunk.len() - skip) as u64;
+            }
+        }
+        Some(ready)
+    }
+
+    /* Queues CRYPTO data from a lost packet to be sent again ahead of new data. */
+    pub(crate) fn requeue_crypto(&mut self, offset: u64, data: Vec<u8>) {
+        if self.keys.is_some() {
+            self.crypto_lost.insert(offset, data);
+        }
+    }
+
+    /* Takes up to `max` bytes of pending handshake data along with their CRYPTO stream offset. */
+    pub(crate) fn take_crypto(&mut self, max: usize) -> Option<(u64, Vec<u8>)> {
+        if max == 0 { return None; }
+        if let Some((offset, mut data)) = self.crypto_lost.pop_first() {
+            if data.len() > max {
+                self.crypto_lost.insert(offset + max as u64, data.split_off(max));
+            }
+            return Some((offset, data));
+        }
+        if self.crypto_send.is_empty() { return None; }
+        let n = max.min(self.crypto_send.len());
+        let offset = self.crypto_send_offset;
+        self.crypto_send_offset += n as u64;
+        Some((offset, self.crypto_send.drain(..n).collect()))
+    }
+
+    /* Drops the keys and everything queued once a space is done with (RFC 9001 §4.9). */
+    pub(crate) fn discard(&mut self) {
+        self.keys = None;
+        self.acks.clear();
+        self.crypto_pending.clear();
+        self.crypto_send.clear();
+        self.crypto_lost.clear();
+        self.probes = 0;
+    }
+}
+
+/* Recovers a full packet number from its truncated encoding (RFC 9000 §A.3). */
+pub(crate) fn decode_packet_number(largest: Option<u64>, truncated: u64, pn_len: usize) -> u64 {
+    let expected = largest.map_or(0, |pn| pn + 1);
+    let win = 1u64 << (pn_len * 8);
+    let hwin = win / 2;
+    let mask = win - 1;
+    let candidate = (expected & !mask) | truncated;
+    if candidate + hwin <= expected && candidate < (1 << 62) - win {
+        candidate + win
+    } else if candidate > expected + hwin && candidate >= win {
+        candidate - win
+    } else {
+        candidate
+    }
+}
@@ -2,24 +2,24 @@ use std::net::SocketAddr;
 
 use super::connection::QuicConnection;
 
+/*
+A handle on one stream of a connection. Writes are queued and go out with the connection's next
+packets; what the peer sends is handed to `on_data` as it arrives in order, or kept for `read`.
+*/
 pub struct QuicStream<'a> {
-    pub(crate) id: u32,
-    pub(crate) src: &'a QuicConnection,
-    pub(crate) ondata_handler: Option<Box<dyn FnMut(&[u8]) + Send>>,
-    pub(crate) onclose_handler: Option<Box<dyn FnMut() + Send>>,
+    pub(crate) id: u64,
+    pub(crate) src: &'a mut QuicConnection,
 }
 
 impl<'a> QuicStream<'a> {
-    pub fn new(id: u32, src: &'a QuicConnection) -> Self {
+    pub fn new(id: u64, src: &'a mut QuicConnection) -> Self {
         Self {
             id,
             src,
-            ondata_handler: None,
-            onclose_handler: None,
         }
     }
 
-    pub fn id(&self) -> u32 {
+    pub fn id(&self) -> u64 {
         self.id
     }
 
@@ -27,20 +27,55 @@ impl<'a> QuicStream<'a> {
         self.src
     }
 
-    pub fn write(&self, data: &[u8]) -> Result<(), String> {
-        Err("QuicStream::write is not implemented yet".to_string())
+    pub fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.src.streams.write(self.id, data).map_err(|e| e.to_string())
     }
 
-    pub fn on_data<F>(&mut self, h: F) where F: FnMut(&[u8]) + Send + 'static {
-        self.ondata_handler = Some(Box::new(h));
+    /* Ends the stream after what was written so far (FIN). */
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.src.streams.finish(self.id).map_err(|e| e.to_string())
     }
 
-    pub fn read(&self, buf: &mut [u8]) -> Result<usize, String> {
-        Err("QuicStream::read is not implemented yet".to_string())
+    /* Abandons sending; unsent data is dropped and the peer gets RESET_STREAM with `error_code`. */
+    pub fn reset(&mut self, error_code: u64) -> Result<(), String> {
+        self.src.streams.reset(self.id, error_code).map_err(|e| e.to_string())
     }
 
-    pub fn on_close<F>(&mut self, h: F) where F: FnMut() + Send + 'static {
-        self.onclose_handler = Some(Box::new(h));
+    /* Tells the peer we no longer read, with STOP_SENDING; unread data is dropped. */
+    pub fn stop_sending(&mut self, error_code: u64) -> Result<(), String> {
+        self.src.streams.stop_sending(self.id, error_code).map_err(|e| e.to_string())
+    }
+
+    /* Data buffered before the handler was set is handed to it right away. */
+    pub fn on_data<F>(&mut self, h: F) where F: FnMut(&[u8]) + Send + Sync + 'static {
+        let Some(stream) = self.src.streams.get_mut(self.id) else { return; };
+        stream.ondata_handler = Some(Box::new(h));
+        self.src.dispatch_stream(self.id);
+    }
+
+    /* Reads in-order data that no `on_data` handler took; Ok(0) means nothing is buffered right now. */
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, String> {
+        self.src.streams.read(self.id, buf).map_err(|e| e.to_string())
+    }
+
+    /*
+    The peer opened the stream in 0-RTT data, which an attacker may have replayed (RFC 9001 §9.2);
+    only requests that are safe to repeat should be acted upon before the handshake is complete.
+    */
+    pub fn is_early_data(&self) -> bool {
+        self.src.streams.get(self.id).is_some_and(|s| s.early_data)
+    }
+
+    /* The peer finished the stream and everything it sent was read. */
+    pub fn is_finished(&self) -> bool {
+        self.src.streams.is_finished(self.id)
+    }
+
+    /* Called once no more data will arrive: the peer finished or reset the stream; for our unidirectional streams, once everything was delivered. */
+    pub fn on_close<F>(&mut self, h: F) where F: FnMut() + Send + Sync + 'static {
+        let Some(stream) = self.src.streams.get_mut(self.id) else { return; };
+        stream.onclose_handler = Some(Box::new(h));
+        self.src.dispatch_stream(self.id);
     }
 
     pub fn src(&self) -> SocketAddr {
@@ -52,4 +87,4 @@ impl<'a> std::fmt::Display for QuicStream<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "QuicStream(id: {}, src: {})", self.id, self.src.address)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,703 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::net::{QuicFrame, SentFrame};
+use super::frame::varint_len;
+
+/*
+Streams of a connection (RFC 9000 §2-4): reassembly of what the peer sends, buffering of what the
+application writes, and flow control and stream limits in both directions.
+*/
+
+/* Credit this endpoint grants; a window is extended once half of it is used up. */
+pub const DEFAULT_MAX_DATA: u64 = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_STREAM_DATA: u64 = 1024 * 1024;
+pub const DEFAULT_MAX_STREAMS: u64 = 100;
+
+pub(crate) type DataHandler = Box<dyn FnMut(&[u8]) + Send + Sync + 'static>;
+pub(crate) type CloseHandler = Box<dyn FnMut() + Send + Sync + 'static>;
+
+/* The transport parameters that govern streams (RFC 9000 §18.2). */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamLimits {
+    pub max_data: u64,
+    pub max_stream_data_bidi_local: u64,
+    pub max_stream_data_bidi_remote: u64,
+    pub max_stream_data_uni: u64,
+    pub max_streams_bidi: u64,
+    pub max_streams_uni: u64,
+}
+
+impl StreamLimits {
+    /* What this endpoint advertises. */
+    pub fn local() -> Self {
+        Self {
+            max_data: DEFAULT_MAX_DATA,
+            max_stream_data_bidi_local: DEFAULT_MAX_STREAM_DATA,
+            max_stream_data_bidi_remote: DEFAULT_MAX_STREAM_DATA,
+            max_stream_data_uni: DEFAULT_MAX_STREAM_DATA,
+            max_streams_bidi: DEFAULT_MAX_STREAMS,
+            max_streams_uni: DEFAULT_MAX_STREAMS,
+        }
+    }
+}
+
+/* Stream ID layout (RFC 9000 §2.1): bit 0 is the initiator, bit 1 the direction. */
+pub fn is_unidirectional(id: u64) -> bool {
+    id & 0x2 != 0
+}
+
+pub(crate) fn is_server_initiated(id: u64) -> bool {
+    id & 0x1 != 0
+}
+
+/* Index into the [bidirectional, unidirectional] stream counters. */
+fn kind(id: u64) -> usize {
+    is_unidirectional(id) as usize
+}
+
+fn stream_error(code: &str, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", code, msg))
+}
+
+#[derive(Default)]
+pub(crate) struct SendState {
+    pending: Vec<u8>, // written, not sent yet
+    offset: u64,      // stream offset of `pending`, i.e. how much was sent
+    lost: BTreeMap<u64, (Vec<u8>, bool)>, // data and FIN to send again, by offset
+    max_data: u64,    // MAX_STREAM_DATA from the peer
+    fin: bool,        // the application is done writing
+    fin_sent: bool,
+    in_flight: usize, // STREAM frames not acknowledged yet
+    reset: Option<u64>, // error code the stream was reset with
+    reset_pending: bool,
+    blocked_at: Option<u64>, // limit a STREAM_DATA_BLOCKED went out for
+    blocked_pending: bool,
+}
+
+impl SendState {
+    /* Everything was sent and acknowledged, or the reset went out. */
+    fn is_done(&self) -> bool {
+        match self.reset {
+            Some(_) => !self.reset_pending,
+            None => self.fin_sent && self.pending.is_empty() && self.lost.is_empty() && self.in_flight == 0,
+        }
+    }
+
+    fn reset(&mut self, error_code: u64) {
+        if self.reset.is_some() || self.is_done() { return; }
+        self.pending.clear();
+        self.lost.clear();
+        self.reset = Some(error_code);
+        self.reset_pending = true;
+        self.blocked_pending = false;
+    }
+
+    fn has_data(&self, connection_credit: bool) -> bool {
+        !self.lost.is_empty()
+            || (!self.pending.is_empty() && self.offset < self.max_data && connection_credit)
+            || (self.fin && !self.fin_sent && self.pending.is_empty())
+    }
+}
+
+pub(crate) struct RecvState {
+    chunks: BTreeMap<u64, Vec<u8>>, // data ahead of `offset`
+    offset: u64,      // next byte in order
+    readable: Vec<u8>, // in order, not taken by the application yet
+    consumed: u64,    // bytes the application took
+    highest: u64,     // highest offset received, what flow control counts
+    final_size: Option<u64>,
+    max_data: u64,    // MAX_STREAM_DATA we granted
+    max_data_pending: bool,
+    reset: Option<u64>, // error code of the peer's RESET_STREAM
+    stop: Option<u64>,  // error code we asked the peer to stop sending with
+    stop_pending: bool,
+}
+
+impl RecvState {
+    fn new(max_data: u64) -> Self {
+        Self {
+            chunks: BTreeMap::new(),
+            offset: 0,
+            readable: Vec::new(),
+            consumed: 0,
+            highest: 0,
+            final_size: None,
+            max_data,
+            max_data_pending: false,
+            reset: None,
+            stop: None,
+            stop_pending: false,
+        }
+    }
+
+    /* Nothing more will arrive: all data was taken, the peer reset the stream or we asked it to stop. */
+    fn is_done(&self) -> bool {
+        self.reset.is_some() || (self.stop.is_some() && !self.stop_pending) || self.final_size == Some(self.consumed)
+    }
+
+    fn insert(&mut self, offset: u64, data: &[u8]) {
+        let end = offset + data.len() as u64;
+        if end > self.offset {
+            let skip = self.offset.saturating_sub(offset);
+            let entry = self.chunks.entry(offset + skip).or_default();
+            if entry.len() < data.len() - skip as usize {
+                *entry = data[skip as usize..].to_vec();
+            }
+        }
+        while let Some(entry) = self.chunks.first_entry() {
+            if *entry.key() > self.offset { break; }
+            let (start, chunk) = entry.remove_entry();
+            let skip = (self.offset - start) as usize;
+            if skip < chunk.len() {
+                self.readable.extend_from_slice(&chunk[skip..]);
+                self.offset += (chunk.len() - skip) as u64;
+            }
+        }
+    }
+
+    /* Drops what was not read; returns how many bytes that gives back to connection flow control. */
+    fn discard(&mut self, up_to: u64) -> u64 {
+        self.readable.clear();
+        self.chunks.clear();
+        self.max_data_pending = false;
+        let released = up_to.saturating_sub(self.consumed);
+        self.consumed = self.consumed.max(up_to);
+        released
+    }
+}
+
+pub(crate) struct StreamState {
+    pub(crate) send: Option<SendState>, // None for the peer's unidirectional streams
+    pub(crate) recv: Option<RecvState>, // None for our unidirectional streams
+    pub(crate) ondata_handler: Option<DataHandler>,
+    pub(crate) onclose_handler: Option<CloseHandler>,
+    pub(crate) closed: bool, // on_close fired
+    pub(crate) early_data: bool, // the peer opened it in a 0-RTT packet
+}
+
+impl StreamState {
+    /* No more data will arrive; for send-only streams, everything was delivered. */
+    pub(crate) fn finished(&self) -> bool {
+        match &self.recv {
+            Some(recv) => recv.is_done(),
+            None => self.send.as_ref().is_none_or(SendState::is_done),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.send.as_ref().is_none_or(SendState::is_done) && self.recv.as_ref().is_none_or(RecvState::is_done)
+    }
+}
+
+pub(crate) struct Streams {
+    server: bool,
+    map: BTreeMap<u64, StreamState>,
+    peer: StreamLimits, // the peer's transport parameters
+    opened: [u64; 2],        // streams we opened
+    max_streams: [u64; 2],   // streams the peer lets us open
+    streams_blocked_pending: [bool; 2],
+    peer_opened: [u64; 2],   // streams the peer opened
+    max_peer_streams: [u64; 2], // streams we let the peer open
+    max_streams_pending: [bool; 2],
+    max_data: u64,           // MAX_DATA from the peer
+    data_sent: u64,
+    data_blocked_at: Option<u64>,
+    data_blocked_pending: bool,
+    max_peer_data: u64,      // MAX_DATA we granted
+    data_received: u64,      // sum of the highest offsets received on every stream
+    data_consumed: u64,
+    max_data_pending: bool,
+    cursor: u64,             // stream the next STREAM frames start at, so streams take turns
+}
+
+impl Streams {
+    pub(crate) fn new(is_client: bool) -> Self {
+        let local = StreamLimits::local();
+        Self {
+            server: !is_client,
+            map: BTreeMap::new(),
+            peer: StreamLimits::default(),
+            opened: [0; 2],
+            max_streams: [0; 2],
+            streams_blocked_pending: [false; 2],
+            peer_opened: [0; 2],
+            max_peer_streams: [local.max_streams_bidi, local.max_streams_uni],
+            max_streams_pending: [false; 2],
+            max_data: 0,
+            data_sent: 0,
+            data_blocked_at: None,
+            data_blocked_pending: false,
+            max_peer_data: local.max_data,
+            data_received: 0,
+            data_consumed: 0,
+            max_data_pending: false,
+            cursor: 0,
+        }
+    }
+
+    fn is_local(&self, id: u64) -> bool {
+        is_server_initiated(id) == self.server
+    }
+
+    /* The peer's initial MAX_STREAM_DATA for what we send on `id`. */
+    fn initial_send_limit(&self, id: u64) -> u64 {
+        if is_unidirectional(id) {
+            self.peer.max_stream_data_uni
+        } else if self.is_local(id) {
+            self.peer.max_stream_data_bidi_remote
+        } else {
+            self.peer.max_stream_data_bidi_local
+        }
+    }
+
+    fn new_stream(&self, id: u64) -> StreamState {
+        let local = self.is_local(id);
+        let uni = is_unidirectional(id);
+        StreamState {
+            send: (!uni || local).then(|| SendState { max_data: self.initial_send_limit(id), ..Default::default() }),
+            recv: (!uni || !local).then(|| RecvState::new(DEFAULT_MAX_STREAM_DATA)),
+            ondata_handler: None,
+            onclose_handler: None,
+            closed: false,
+            early_data: false,
+        }
+    }
+
+    /* Applies the peer's transport parameters once the handshake delivered them. */
+    pub(crate) fn set_peer_limits(&mut self, limits: StreamLimits) {
+        self.peer = limits;
+        self.max_data = self.max_data.max(limits.max_data);
+        self.max_streams[0] = self.max_streams[0].max(limits.max_streams_bidi);
+        self.max_streams[1] = self.max_streams[1].max(limits.max_streams_uni);
+        let ids: Vec<u64> = self.map.keys().copied().collect();
+        for id in ids {
+            let limit = self.initial_send_limit(id);
+            if let Some(send) = self.map.get_mut(&id).and_then(|s| s.send.as_mut()) {
+                send.max_data = send.max_data.max(limit);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<&StreamState> {
+        self.map.get(&id)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: u64) -> Option<&mut StreamState> {
+        self.map.get_mut(&id)
+    }
+
+    /* Opens the next stream of ours, unless the peer's stream limit is reached (RFC 9000 §4.6). */
+    pub(crate) fn open(&mut self, unidirectional: bool) -> Result<u64> {
+        let k = unidirectional as usize;
+        if self.opened[k] >= self.max_streams[k] {
+            self.streams_blocked_pending[k] = true;
+            return Err(Error::new(ErrorKind::WouldBlock, "the peer's stream limit is reached"));
+        }
+        let id = self.opened[k] << 2 | (unidirectional as u64) << 1 | self.server as u64;
+        self.opened[k] += 1;
+        self.map.insert(id, self.new_stream(id));
+        Ok(id)
+    }
+
+    /*
+    Checks a stream ID the peer referred to, opening its streams up to that one (RFC 9000 §3.2);
+    `early` when it did so in 0-RTT. Returns the streams that were opened.
+    */
+    pub(crate) fn accept(&mut self, id: u64, early: bool) -> Result<Vec<u64>> {
+        let (k, index) = (kind(id), id >> 2);
+        if self.is_local(id) {
+            if index >= self.opened[k] {
+                return Err(stream_error("STREAM_STATE_ERROR", "frame for a stream that was never opened"));
+            }
+            return Ok(Vec::new());
+        }
+        if index >= self.max_peer_streams[k] {
+            return Err(stream_error("STREAM_LIMIT_ERROR", "the peer opened more streams than allowed"));
+        }
+        let mut opened = Vec::new();
+        while self.peer_opened[k] <= index {
+            let id = self.peer_opened[k] << 2 | (id & 0x3);
+            self.map.insert(id, StreamState { early_data: early, ..self.new_stream(id) });
+            self.peer_opened[k] += 1;
+            opened.push(id);
+        }
+        Ok(opened)
+    }
+
+    /* Extends connection flow control once half of the window was consumed. */
+    fn consume(&mut self, n: u64) {
+        self.data_consumed += n;
+        if self.max_peer_data - self.data_consumed < DEFAULT_MAX_DATA / 2 {
+            self.max_peer_data = self.data_consumed + DEFAULT_MAX_DATA;
+            self.max_data_pending = true;
+        }
+    }
+
+    /* Counts a new highest offset against connection flow control. */
+    fn receive(&mut self, grown: u64) -> Result<()> {
+        if self.data_received + grown > self.max_peer_data {
+            return Err(stream_error("FLOW_CONTROL_ERROR", "the peer exceeded MAX_DATA"));
+        }
+        self.data_received += grown;
+        Ok(())
+    }
+
+    pub(crate) fn on_stream_frame(&mut self, id: u64, offset: u64, data: &[u8], fin: bool) -> Result<()> {
+        let Some(stream) = self.map.get_mut(&id) else { return Ok(()); }; // closed already
+        let recv = stream.recv.as_mut().ok_or_else(|| stream_error("STREAM_STATE_ERROR", "STREAM frame for a send-only stream"))?;
+        let end = offset + data.len() as u64;
+        if recv.final_size.is_some_and(|size| end > size || (fin && end != size)) || (fin && end < recv.highest) {
+            return Err(stream_error("FINAL_SIZE_ERROR", "STREAM frame beyond or changing the final size"));
+        }
+        if end > recv.max_data {
+            return Err(stream_error("FLOW_CONTROL_ERROR", "the peer exceeded MAX_STREAM_DATA"));
+        }
+        let grown = end.saturating_sub(recv.highest);
+        recv.highest = recv.highest.max(end);
+        if fin {
+            recv.final_size = Some(end);
+        }
+        /* data nobody will read gives its credit straight back */
+        let released = if recv.reset.is_some() || recv.stop.is_some() {
+            recv.discard(recv.highest)
+        } else {
+            recv.insert(offset, data);
+            0
+        };
+        self.receive(grown)?;
+        self.consume(released);
+        Ok(())
+    }
+
+    pub(crate) fn on_reset_stream(&mut self, id: u64, error_code: u64, final_size: u64) -> Result<()> {
+        let Some(stream) = self.map.get_mut(&id) else { return Ok(()); };
+        let recv = stream.recv.as_mut().ok_or_else(|| stream_error("STREAM_STATE_ERROR", "RESET_STREAM for a send-only stream"))?;
+        if final_size < recv.highest || recv.final_size.is_some_and(|size| size != final_size) {
+            return Err(stream_error("FINAL_SIZE_ERROR", "RESET_STREAM changes the final size"));
+        }
+        if final_size > recv.max_data {
+            return Err(stream_error("FLOW_CONTROL_ERROR", "the peer exceeded MAX_STREAM_DATA"));
+        }
+        let grown = final_size - recv.highest;
+        recv.highest = final_size;
+        recv.final_size = Some(final_size);
+        let mut released = 0;
+        if recv.reset.is_none() {
+            recv.reset = Some(error_code);
+            released = recv.discard(final_size);
+        }
+        self.receive(grown)?;
+        self.consume(released);
+        Ok(())
+    }
+
+    /* The peer no longer reads: the stream is reset with the code it gave (RFC 9000 §3.5). */
+    pub(crate) fn on_stop_sending(&mut self, id: u64, error_code: u64) -> Result<()> {
+        let Some(stream) = self.map.get_mut(&id) else { return Ok(()); };
+        let send = stream.send.as_mut().ok_or_else(|| stream_error("STREAM_STATE_ERROR", "STOP_SENDING for a receive-only stream"))?;
+        send.reset(error_code);
+        Ok(())
+    }
+
+    pub(crate) fn on_max_data(&mut self, max: u64) {
+        self.max_data = self.max_data.max(max);
+    }
+
+    pub(crate) fn on_max_stream_data(&mut self, id: u64, max: u64) -> Result<()> {
+        let Some(stream) = self.map.get_mut(&id) else { return Ok(()); };
+        let send = stream.send.as_mut().ok_or_else(|| stream_error("STREAM_STATE_ERROR", "MAX_STREAM_DATA for a receive-only stream"))?;
+        send.max_data = send.max_data.max(max);
+        Ok(())
+    }
+
+    pub(crate) fn on_max_streams(&mut self, bidi: bool, max: u64) {
+        let k = !bidi as usize;
+        self.max_streams[k] = self.max_streams[k].max(max);
+    }
+
+    fn send_state(&mut self, id: u64) -> Result<&mut SendState> {
+        let stream = self.map.get_mut(&id).ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown or closed stream"))?;
+        stream.send.as_mut().ok_or_else(|| Error::new(ErrorKind::Unsupported, "the stream is receive-only"))
+    }
+
+    fn recv_state(&mut self, id: u64) -> Result<&mut RecvState> {
+        let stream = self.map.get_mut(&id).ok_or_else(|| Error::new(ErrorKind::NotFound, "unknown or closed stream"))?;
+        stream.recv.as_mut().ok_or_else(|| Error::new(ErrorKind::Unsupported, "the stream is send-only"))
+    }
+
+    /* Queues data; it goes out as flow control and congestion control allow. */
+    pub(crate) fn write(&mut self, id: u64, data: &[u8]) -> Result<()> {
+        let send = self.send_state(id)?;
+        if send.reset.is_some() || send.fin {
+            return Err(Error::new(ErrorKind::BrokenPipe, "the stream was finished or reset"));
+        }
+        send.pending.extend_from_slice(data);
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self, id: u64) -> Result<()> {
+        let send = self.send_state(id)?;
+        if send.reset.is_some() {
+            return Err(Error::new(ErrorKind::BrokenPipe, "the stream was reset"));
+        }
+        send.fin = true;
+        Ok(())
+    }
+
+    pub(crate) fn reset(&mut self, id: u64, error_code: u64) -> Result<()> {
+        self.send_state(id)?.reset(error_code);
+        Ok(())
+    }
+
+    /* Asks the peer to stop sending and drops what was not read (RFC 9000 §3.5). */
+    pub(crate) fn stop_sending(&mut self, id: u64, error_code: u64) -> Result<()> {
+        let recv = self.recv_state(id)?;
+        if recv.is_done() { return Ok(()); }
+        recv.stop = Some(error_code);
+        recv.stop_pending = true;
+        let released = recv.discard(recv.highest);
+        self.consume(released);
+        Ok(())
+    }
+
+    /* Copies in-order data into `buf`; 0 when nothing is buffered. A reset stream is an error. */
+    pub(crate) fn read(&mut self, id: u64, buf: &mut [u8]) -> Result<usize> {
+        let recv = self.recv_state(id)?;
+        if let Some(code) = recv.reset {
+            return Err(Error::new(ErrorKind::ConnectionReset, format!("the peer reset the stream with error {}", code)));
+        }
+        let n = buf.len().min(recv.readable.len());
+        buf[..n].copy_from_slice(&recv.readable[..n]);
+        recv.readable.drain(..n);
+        self.on_read(id, n as u64);
+        Ok(n)
+    }
+
+    /* Takes everything readable, for the on_data handler. */
+    pub(crate) fn read_all(&mut self, id: u64) -> Vec<u8> {
+        let Ok(recv) = self.recv_state(id) else { return Vec::new(); };
+        let data = std::mem::take(&mut recv.readable);
+        self.on_read(id, data.len() as u64);
+        data
+    }
+
+    /* Extends the stream's flow control window once half of it was read. */
+    fn on_read(&mut self, id: u64, n: u64) {
+        if n == 0 { return; }
+        if let Some(recv) = self.map.get_mut(&id).and_then(|s| s.recv.as_mut()) {
+            recv.consumed += n;
+            if recv.final_size.is_none() && recv.max_data - recv.consumed < DEFAULT_MAX_STREAM_DATA / 2 {
+                recv.max_data = recv.consumed + DEFAULT_MAX_STREAM_DATA;
+                recv.max_data_pending = true;
+            }
+        }
+        self.consume(n);
+    }
+
+    pub(crate) fn is_finished(&self, id: u64) -> bool {
+        self.map.get(&id).is_none_or(|s| s.recv.as_ref().is_some_and(|r| r.final_size == Some(r.consumed)))
+    }
+
+    /* The peer reset the stream, what it sent is gone. */
+    pub(crate) fn is_reset(&self, id: u64) -> bool {
+        self.map.get(&id).is_some_and(|s| s.recv.as_ref().is_some_and(|r| r.reset.is_some()))
+    }
+
+    /* The streams of the peer that are still open. */
+    pub(crate) fn peer_stream_ids(&self) -> Vec<u64> {
+        self.map.keys().copied().filter(|&id| !self.is_local(id)).collect()
+    }
+
+    pub(crate) fn on_frame_acked(&mut self, frame: &SentFrame) {
+        if let SentFrame::Stream { id, .. } = frame {
+            if let Some(send) = self.map.get_mut(id).and_then(|s| s.send.as_mut()) {
+                send.in_flight = send.in_flight.saturating_sub(1);
+            }
+        }
+    }
+
+    /* Queues what a lost packet carried again, unless it became obsolete. */
+    pub(crate) fn on_frame_lost(&mut self, frame: SentFrame) {
+        match frame {
+            SentFrame::Stream { id, offset, data, fin } => {
+                if let Some(send) = self.map.get_mut(&id).and_then(|s| s.send.as_mut()) {
+                    send.in_flight = send.in_flight.saturating_sub(1);
+                    if send.reset.is_none() {
+                        send.lost.insert(offset, (data, fin));
+                    }
+                }
+            }
+            SentFrame::ResetStream { id, .. } => {
+                if let Some(send) = self.map.get_mut(&id).and_then(|s| s.send.as_mut()) {
+                    send.reset_pending = true;
+                }
+            }
+            SentFrame::StopSending { id, .. } => {
+                if let Some(recv) = self.map.get_mut(&id).and_then(|s| s.recv.as_mut()) {
+                    recv.stop_pending = recv.reset.is_none();
+                }
+            }
+            SentFrame::MaxStreamData(id) => {
+                if let Some(recv) = self.map.get_mut(&id).and_then(|s| s.recv.as_mut()) {
+                    recv.max_data_pending = recv.final_size.is_none();
+                }
+            }
+            SentFrame::MaxData => self.max_data_pending = true,
+            SentFrame::MaxStreams { bidi } => self.max_streams_pending[!bidi as usize] = true,
+            SentFrame::Crypto { .. } | SentFrame::HandshakeDone | SentFrame::NewToken(_) | SentFrame::NewConnectionId(_) | SentFrame::RetireConnectionId(_) | SentFrame::PathChallenge => {}
+        }
+    }
+
+    /* Forgets a stream that is done in both directions; the peer may then open another one (RFC 9000 §4.6). */
+    pub(crate) fn collect(&mut self, id: u64) {
+        if !self.map.get(&id).is_some_and(|s| s.closed && s.is_done()) { return; }
+        self.map.remove(&id);
+        if !self.is_local(id) {
+            self.max_peer_streams[kind(id)] += 1;
+            self.max_streams_pending[kind(id)] = true;
+        }
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        let credit = self.data_sent < self.max_data;
+        self.max_data_pending
+            || self.data_blocked_pending
+            || self.max_streams_pending.contains(&true)
+            || self.streams_blocked_pending.contains(&true)
+            || self.map.values().any(|s| {
+                s.recv.as_ref().is_some_and(|r| r.max_data_pending || r.stop_pending)
+                    || s.send.as_ref().is_some_and(|send| send.reset_pending || send.blocked_pending || send.has_data(credit))
+            })
+    }
+
+    /*
+    Writes flow control and stream state frames, then STREAM frames until `out` reaches `limit`,
+    recording what must be sent again if the packet is lost. Returns whether anything was written.
+    */
+    pub(crate) fn write_frames(&mut self, out: &mut Vec<u8>, limit: usize, sent: &mut Vec<SentFrame>) -> bool {
+        let start = out.len();
+        if self.max_data_pending && push(out, limit, QuicFrame::MaxData(self.max_peer_data)) {
+            self.max_data_pending = false;
+            sent.push(SentFrame::MaxData);
+        }
+        for k in 0..2 {
+            let bidi = k == 0;
+            if self.max_streams_pending[k] && push(out, limit, QuicFrame::MaxStreams { bidi, max: self.max_peer_streams[k] }) {
+                self.max_streams_pending[k] = false;
+                sent.push(SentFrame::MaxStreams { bidi });
+            }
+            /* the blocked signals are informational and not sent again */
+            if self.streams_blocked_pending[k] && push(out, limit, QuicFrame::StreamsBlocked { bidi, limit: self.max_streams[k] }) {
+                self.streams_blocked_pending[k] = false;
+            }
+        }
+        if self.data_blocked_pending && push(out, limit, QuicFrame::DataBlocked(self.max_data)) {
+            self.data_blocked_pending = false;
+        }
+        for (&id, stream) in self.map.iter_mut() {
+            if let Some(recv) = stream.recv.as_mut() {
+                if recv.max_data_pending && push(out, limit, QuicFrame::MaxStreamData { stream_id: id, max: recv.max_data }) {
+                    recv.max_data_pending = false;
+                    sent.push(SentFrame::MaxStreamData(id));
+                }
+                if let Some(error_code) = recv.stop.filter(|_| recv.stop_pending) {
+                    if push(out, limit, QuicFrame::StopSending { stream_id: id, error_code }) {
+                        recv.stop_pending = false;
+                        sent.push(SentFrame::StopSending { id, error_code });
+                    }
+                }
+            }
+            if let Some(send) = stream.send.as_mut() {
+                if let Some(error_code) = send.reset.filter(|_| send.reset_pending) {
+                    let final_size = send.offset;
+                    if push(out, limit, QuicFrame::ResetStream { stream_id: id, error_code, final_size }) {
+                        send.reset_pending = false;
+                        sent.push(SentFrame::ResetStream { id, error_code, final_size });
+                    }
+                }
+                if send.blocked_pending && push(out, limit, QuicFrame::StreamDataBlocked { stream_id: id, limit: send.max_data }) {
+                    send.blocked_pending = false;
+                }
+            }
+        }
+
+        /* streams take turns, starting after the one that filled the last packet */
+        let ids: Vec<u64> = self.map.range(self.cursor..).chain(self.map.range(..self.cursor)).map(|(&id, _)| id).collect();
+        for id in ids {
+            let Some(send) = self.map.get_mut(&id).and_then(|s| s.send.as_mut()) else { continue; };
+            let mut full = false;
+            while let Some((offset, (mut data, fin))) = send.lost.pop_first() {
+                let Some((n, fin)) = push_stream(out, limit, id, offset, &data, fin) else {
+                    send.lost.insert(offset, (data, fin));
+                    full = true;
+                    break;
+                };
+                if n < data.len() {
+                    send.lost.insert(offset + n as u64, (data.split_off(n), fin));
+                    full = true;
+                }
+                data.truncate(n);
+                sent.push(SentFrame::Stream { id, offset, data, fin });
+                send.in_flight += 1;
+                if full { break; }
+            }
+            if !full {
+                let credit = (send.max_data.saturating_sub(send.offset)).min(self.max_data - self.data_sent);
+                let n = send.pending.len().min(credit as usize);
+                let fin = send.fin && !send.fin_sent && n == send.pending.len();
+                if n > 0 || fin {
+                    match push_stream(out, limit, id, send.offset, &send.pending[..n], fin) {
+                        Some((written, fin)) => {
+                            let data: Vec<u8> = send.pending.drain(..written).collect();
+                            sent.push(SentFrame::Stream { id, offset: send.offset, data, fin });
+                            send.offset += written as u64;
+                            send.fin_sent |= fin;
+                            send.in_flight += 1;
+                            self.data_sent += written as u64;
+                            full = written < n;
+                        }
+                        None => full = true,
+                    }
+                }
+            }
+            /* out of credit with data left: say so once per limit (RFC 9000 §4.1) */
+            if !send.pending.is_empty() && send.offset >= send.max_data && send.blocked_at != Some(send.max_data) {
+                send.blocked_at = Some(send.max_data);
+                send.blocked_pending = true;
+            }
+            if !send.pending.is_empty() && self.data_sent >= self.max_data && self.data_blocked_at != Some(self.max_data) {
+                self.data_blocked_at = Some(self.max_data);
+                self.data_blocked_pending = true;
+            }
+            if full {
+                self.cursor = id + 1;
+                break;
+            }
+        }
+        out.len() > start
+    }
+}
+
+/* Appends `frame` if it fits within `limit`. */
+fn push(out: &mut Vec<u8>, limit: usize, frame: QuicFrame) -> bool {
+    let start = out.len();
+    if frame.encode(out).is_err() || out.len() > limit {
+        out.truncate(start);
+        return false;
+    }
+    true
+}
+
+/*
+Appends a STREAM frame with as much of `data` as fits within `limit`; the FIN only goes with the last byte.
+Returns how much was written and whether the FIN was, or None if not even the frame header fits.
+*/
+fn push_stream(out: &mut Vec<u8>, limit: usize, id: u64, offset: u64, data: &[u8], fin: bool) -> Option<(usize, bool)> {
+    let header = 1 + varint_len(id) + varint_len(offset) + 2;
+    let room = limit.checked_sub(out.len() + header)?;
+    let n = data.len().min(room);
+    if n == 0 && !data.is_empty() { return None; }
+    let fin = fin && n == data.len();
+    push(out, limit, QuicFrame::Stream { stream_id: id, offset, data: &data[..n], fin }).then_some((n, fin))
+}
@@ -0,0 +1,290 @@
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::net::{QuicFrame, QuicPacketType, RecoveryTimeout, SentFrame, SentPacket};
+use crate::net::connection::QuicConnection;
+use crate::net::quic::frame::varint_len;
+use crate::net::quic::packets::{write_long_header, write_short_header, TAG_LEN};
+use crate::net::quic::space::SpaceId;
+
+/* Datagrams are kept within the size every path must support (RFC 9000 §14). */
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 1200;
+/* Room for at least an ACK or a useful CRYPTO chunk before another packet is started. */
+const MIN_PACKET_ROOM: usize = 48;
+
+/* A packet written into the datagram but not sealed yet, so padding can still go into it. */
+struct Unsealed {
+    space: SpaceId,
+    start: usize,
+    pn_offset: usize,
+    pn: u64,
+    ack_eliciting: bool,
+    frames: Vec<SentFrame>,
+}
+
+impl QuicConnection {
+    fn has_outgoing(&self, space: SpaceId, now: Instant, congested: bool) -> bool {
+        if self.spaces[space as usize].has_outgoing(now, congested) { return true; }
+        if space != SpaceId::Data { return false; }
+        /* until it has 1-RTT keys, a client with 0-RTT keys sends stream data and datagrams with those (RFC 9001 §4.6.1) */
+        if self.spaces[space as usize].keys.is_none() {
+            return self.is_client() && self.zero_rtt_keys.is_some() && !congested && (!self.datagrams.is_empty() || self.streams.has_pending());
+        }
+        /* PATH_RESPONSE frames are never held back (RFC 9000 §8.2.2) */
+        !self.path_responses.is_empty()
+            || (!congested && (self.handshake_done_pending || self.has_new_token_pending() || self.local_cids.has_pending() || self.remote_cids.has_pending() || self.has_path_pending() || !self.datagrams.is_empty() || self.streams.has_pending()))
+    }
+
+    fn has_path_pending(&self) -> bool {
+        self.ping_pending || self.path.as_ref().is_some_and(|path| path.pending)
+    }
+
+    fn has_new_token_pending(&self) -> bool {
+        self.new_token.is_some() && !self.is_client()
+    }
+
+    /* Whether the congestion window or the pacer hold back another ack-eliciting datagram (RFC 9002 §7). */
+    fn is_congested(&mut self, now: Instant) -> bool {
+        if self.recovery.bytes_in_flight() + MAX_DATAGRAM_SIZE > self.congestion.window() {
+            self.pacing_until = None; // an ACK has to open the window first
+            return true;
+        }
+        let rate = self.congestion.pacing_rate(self.recovery.rtt());
+        self.pacing_until = self.pacer.delay(now, MAX_DATAGRAM_SIZE, rate);
+        self.pacing_until.is_some()
+    }
+
+    fn seal(&mut self, packet: Unsealed, out: &mut Vec<u8>, padded: bool, now: Instant) -> Option<()> {
+        let keys = match self.spaces[packet.space as usize].keys.as_mut() {
+            Some(keys) => keys,
+            None if packet.space == SpaceId::Data => self.zero_rtt_keys.as_mut()?,
+            None => return None,
+        };
+        keys.seal(out, packet.start, packet.pn_offset, packet.pn).ok()?;
+        let size = out.len() - packet.start;
+        let in_flight = packet.ack_eliciting || padded;
+        self.recovery.on_packet_sent(packet.space, SentPacket {
+            pn: packet.pn,
+            time_sent: now,
+            size,
+            ack_eliciting: packet.ack_eliciting,
+            in_flight,
+            frames: packet.frames,
+        });
+        if in_flight {
+            self.congestion.on_packet_sent(now, size, self.recovery.bytes_in_flight());
+            self.pacer.on_sent(size);
+        }
+        /* the first ack-eliciting packet after one received restarts the idle timer (RFC 9000 §10.1) */
+        if packet.ack_eliciting && self.restart_idle_on_send {
+            self.last_activity = now;
+            self.restart_idle_on_send = false;
+        }
+        Some(())
+    }
+
+    /*
+    Builds the next datagram to send, coalescing one packet per space that has something queued
+    (RFC 9000 §12.2); before a client has 1-RTT keys, application data goes in 0-RTT packets.
+    Returns None when nothing is left. Datagrams carrying a client's Initial packets,
+    or a server's ack-eliciting ones, are padded to 1200 bytes (RFC 9000 §14.1).
+    While congested only ACKs and probes go out, and a server whose client has not proven its address yet
+    stops at three times the bytes it received from it (RFC 9000 §8.1). On a new path smaller datagrams
+    may use up what is left of that, the handshake needs full ones. Once closed only CONNECTION_CLOSE goes out.
+    */
+    pub(crate) fn poll_transmit(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if !self.is_open() {
+            return self.poll_close();
+        }
+        let size = match self.address_validated {
+            true => MAX_DATAGRAM_SIZE,
+            false => (3 * self.bytes_received).saturating_sub(self.bytes_sent).min(MAX_DATAGRAM_SIZE),
+        };
+        if size < MAX_DATAGRAM_SIZE && self.path.is_none() {
+            return None;
+        }
+        let mut out = Vec::with_capacity(MAX_DATAGRAM_SIZE);
+        let mut pad = false;
+        let mut sent_handshake = false;
+        let mut unsealed: Option<Unsealed> = None;
+        let congested = self.is_congested(now);
+        let is_server = !self.is_client();
+
+        for space in SpaceId::ALL {
+            if !self.has_outgoing(space, now, congested) { continue; }
+            let early = space == SpaceId::Data && self.spaces[space as usize].keys.is_none();
+            let header_len = match space {
+                SpaceId::Data if early => 7 + self.dcid.len + 1 + self.id.len + 2 + 4,
+                SpaceId::Data => 1 + self.dcid.len + 4,
+                SpaceId::Initial => 7 + self.dcid.len + 1 + self.id.len + varint_len(self.token.len() as u64) + self.token.len() + 2 + 4,
+                SpaceId::Handshake => 7 + self.dcid.len + 1 + self.id.len + 2 + 4,
+            };
+            if out.len() + TAG_LEN + header_len + MIN_PACKET_ROOM > size { break; }
+            if let Some(packet) = unsealed.take() {
+                self.seal(packet, &mut out, false, now)?;
+            }
+
+            let state = &mut self.spaces[space as usize];
+            let pn = state.next_pn;
+            state.next_pn += 1;
+            let start = out.len();
+            let pn_offset = match space {
+                SpaceId::Initial => write_long_header(&mut out, QuicPacketType::Initial, self.version, self.dcid.as_bytes(), self.id.as_bytes(), &self.token, pn),
+                SpaceId::Handshake => write_long_header(&mut out, QuicPacketType::Handshake, self.version, self.dcid.as_bytes(), self.id.as_bytes(), &[], pn),
+                SpaceId::Data if early => write_long_header(&mut out, QuicPacketType::ZeroRtt, self.version, self.dcid.as_bytes(), self.id.as_bytes(), &[], pn),
+                SpaceId::Data => write_short_header(&mut out, self.dcid.as_bytes(), pn),
+            };
+
+            let mut packet = Unsealed { space, start, pn_offset, pn, ack_eliciting: false, frames: Vec::new() };
+            if let Some(ack) = state.ack_frame(now, true) {
+                QuicFrame::Ack(ack).encode(&mut out).ok()?;
+            }
+            /* probes are sent regardless of the congestion window (RFC 9002 §7.5) */
+            let may_send = !congested || state.probes > 0;
+            if may_send && space == SpaceId::Data && self.handshake_done_pending {
+                QuicFrame::HandshakeDone.encode(&mut out).ok()?;
+                packet.frames.push(SentFrame::HandshakeDone);
+                packet.ack_eliciting = true;
+                self.handshake_done_pending = false;
+                if !self.handshake_confirmed {
+                    self.handshake_confirmed = true;
+                    self.recovery.set_handshake_confirmed();
+                }
+            }
+            if may_send && space == SpaceId::Data && is_server && self.new_token.is_some() {
+                let token = self.new_token.take()?;
+                QuicFrame::NewToken(&token).encode(&mut out).ok()?;
+                packet.frames.push(SentFrame::NewToken(token));
+                packet.ack_eliciting = true;
+            }
+            if may_send && space == SpaceId::Data {
+                while let Some((sequence, issued)) = self.local_cids.pop_pending() {
+                    QuicFrame::NewConnectionId { sequence, retire_prior_to: 0, cid: issued.cid.as_bytes(), reset_token: issued.reset_token }.encode(&mut out).ok()?;
+                    packet.frames.push(SentFrame::NewConnectionId(sequence));
+                    packet.ack_eliciting = true;
+                }
+                while let Some(sequence) = self.remote_cids.pop_retire() {
+                    QuicFrame::RetireConnectionId(sequence).encode(&mut out).ok()?;
+                    packet.frames.push(SentFrame::RetireConnectionId(sequence));
+                    packet.ack_eliciting = true;
+                }
+                /* datagrams with PATH_CHALLENGE or PATH_RESPONSE are padded to 1200 bytes where the limit allows (RFC 9000 §8.2) */
+                if let Some(path) = self.path.as_mut().filter(|path| path.pending) {
+                    QuicFrame::PathChallenge(path.next_challenge()).encode(&mut out).ok()?;
+                    packet.frames.push(SentFrame::PathChallenge);
+                    packet.ack_eliciting = true;
+                    pad = true;
+                }
+                if self.ping_pending {
+                    QuicFrame::Ping.encode(&mut out).ok()?;
+                    packet.ack_eliciting = true;
+                    self.ping_pending = false;
+                }
+            }
+            if space == SpaceId::Data {
+                for data in self.path_responses.drain(..) {
+                    QuicFrame::PathResponse(data).encode(&mut out).ok()?;
+                    packet.ack_eliciting = true;
+                    pad = true;
+                }
+            }
+            /* frame type, an offset of up to 8 bytes and a 2-byte length */
+            let room = (size - TAG_LEN).saturating_sub(out.len() + 1 + 8 + 2);
+            let crypto = if may_send { state.take_crypto(room) } else { None };
+            if let Some((offset, data)) = crypto {
+                QuicFrame::Crypto { offset, data: &data }.encode(&mut out).ok()?;
+                packet.frames.push(SentFrame::Crypto { offset, data });
+                packet.ack_eliciting = true;
+            }
+            /* datagrams go ahead of stream data; they are never sent again (RFC 9221 §5.2) */
+            if may_send && space == SpaceId::Data {
+                while let Some(data) = self.datagrams.pop((size - TAG_LEN).saturating_sub(out.len())) {
+                    QuicFrame::Datagram(&data).encode(&mut out).ok()?;
+                    packet.ack_eliciting = true;
+                }
+            }
+            if may_send && space == SpaceId::Data && self.streams.write_frames(&mut out, size - TAG_LEN, &mut packet.frames) {
+                packet.ack_eliciting = true;
+            }
+            /* a probe must be ack-eliciting even when there is nothing to resend (RFC 9002 §6.2.4) */
+            if state.probes > 0 {
+                state.probes -= 1;
+                if !packet.ack_eliciting {
+                    QuicFrame::Ping.encode(&mut out).ok()?;
+                    packet.ack_eliciting = true;
+                }
+            }
+            pad |= space == SpaceId::Initial && (packet.ack_eliciting || self.is_client());
+            sent_handshake |= space == SpaceId::Handshake;
+            unsealed = Some(packet);
+        }
+
+        let packet = unsealed?;
+        if pad && out.len() + TAG_LEN < size {
+            out.resize(size - TAG_LEN, 0); // PADDING frames
+        }
+        self.seal(packet, &mut out, pad, now)?;
+        if !self.address_validated {
+            self.bytes_sent += out.len();
+        }
+        /* a client drops its Initial keys once it first sends a Handshake packet (RFC 9001 §4.9.1) */
+        if sent_handshake && self.is_client() {
+            self.discard_space(SpaceId::Initial);
+        }
+        Some(out)
+    }
+
+    /*
+    Builds a datagram answering a PATH_CHALLENGE that came from another address than the one the connection
+    sends to, and returns where it goes (RFC 9000 §8.2.2).
+    */
+    pub(crate) fn poll_off_path_response(&mut self, now: Instant) -> Option<(SocketAddr, Vec<u8>)> {
+        if !self.is_open() { return None; }
+        let response = self.off_path_responses.pop()?;
+        let state = &mut self.spaces[SpaceId::Data as usize];
+        state.keys.as_ref()?;
+        let pn = state.next_pn;
+        state.next_pn += 1;
+        let mut out = Vec::with_capacity(MAX_DATAGRAM_SIZE);
+        let pn_offset = write_short_header(&mut out, self.dcid.as_bytes(), pn);
+        QuicFrame::PathResponse(response.data).encode(&mut out).ok()?;
+        if response.padded {
+            out.resize(MAX_DATAGRAM_SIZE - TAG_LEN, 0);
+        }
+        let packet = Unsealed { space: SpaceId::Data, start: 0, pn_offset, pn, ack_eliciting: true, frames: Vec::new() };
+        self.seal(packet, &mut out, response.padded, now)?;
+        Some((response.address, out))
+    }
+
+    /*
+    The next time there is something to do: loss detection, a probe, a delayed ACK, a paced packet, giving up
+    on a new path or the idle timeout; for a closed connection, the end of closing or draining.
+    */
+    pub(crate) fn poll_timeout(&self) -> Option<Instant> {
+        if !self.is_open() {
+            return self.close_timeout();
+        }
+        let acks = self.spaces.iter().filter(|s| s.keys.is_some()).filter_map(|s| s.acks.deadline());
+        let path = self.path.as_ref().map(|path| path.deadline);
+        self.recovery.timeout().into_iter().chain(acks).chain(self.pacing_until).chain(path).chain(self.close_timeout()).min()
+    }
+
+    pub(crate) fn handle_timeout(&mut self, now: Instant) {
+        if self.handle_close_timeout(now) || !self.is_open() { return; }
+        if self.path.as_ref().is_some_and(|path| path.deadline <= now) {
+            self.abandon_path();
+        }
+        match self.recovery.on_timeout(now) {
+            Some(RecoveryTimeout::Lost { space, packets, persistent_congestion }) => self.on_packets_lost(space, packets, persistent_congestion, now),
+            Some(RecoveryTimeout::Probe { space }) => {
+                /* probes carry the oldest unacknowledged data again if there is any */
+                let frames: Vec<SentFrame> = self.recovery.unacked(space).find(|p| p.ack_eliciting).map(|p| p.frames.clone()).unwrap_or_default();
+                for frame in frames {
+                    self.resend(space, frame);
+                }
+                self.spaces[space as usize].probes = 2;
+            }
+            None => {}
+        }
+    }
+}
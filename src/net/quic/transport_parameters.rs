@@ -0,0 +1,340 @@
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::net::{encode_varint_into, varint};
+use crate::net::connection::ConnectionId;
+
+/*
+Parameter ids of RFC 9000 §18.2, RFC 9221 (max_datagram_frame_size), RFC 9287 (grease_quic_bit)
+and RFC 9368 (version_information).
+*/
+const ORIGINAL_DESTINATION_CONNECTION_ID: u64 = 0x00;
+const MAX_IDLE_TIMEOUT: u64 = 0x01;
+const STATELESS_RESET_TOKEN: u64 = 0x02;
+const MAX_UDP_PAYLOAD_SIZE: u64 = 0x03;
+const INITIAL_MAX_DATA: u64 = 0x04;
+const INITIAL_MAX_STREAM_DATA_BIDI_LOCAL: u64 = 0x05;
+const INITIAL_MAX_STREAM_DATA_BIDI_REMOTE: u64 = 0x06;
+const INITIAL_MAX_STREAM_DATA_UNI: u64 = 0x07;
+const INITIAL_MAX_STREAMS_BIDI: u64 = 0x08;
+const INITIAL_MAX_STREAMS_UNI: u64 = 0x09;
+const ACK_DELAY_EXPONENT: u64 = 0x0a;
+const MAX_ACK_DELAY: u64 = 0x0b;
+const DISABLE_ACTIVE_MIGRATION: u64 = 0x0c;
+const PREFERRED_ADDRESS: u64 = 0x0d;
+const ACTIVE_CONNECTION_ID_LIMIT: u64 = 0x0e;
+const INITIAL_SOURCE_CONNECTION_ID: u64 = 0x0f;
+const RETRY_SOURCE_CONNECTION_ID: u64 = 0x10;
+const VERSION_INFORMATION: u64 = 0x11;
+const MAX_DATAGRAM_FRAME_SIZE: u64 = 0x20;
+const GREASE_QUIC_BIT: u64 = 0x2ab2;
+
+/* A server's preferred address (RFC 9000 §9.6, §18.2). */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreferredAddress {
+    pub ipv4: Option<SocketAddrV4>,
+    pub ipv6: Option<SocketAddrV6>,
+    pub connection_id: ConnectionId,
+    pub stateless_reset_token: [u8; 16],
+}
+
+/*
+The version an endpoint's first flight uses and the versions it supports, in order of preference,
+for compatible version negotiation and downgrade prevention (RFC 9368 §3).
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionInformation {
+    pub chosen_version: u32,
+    pub available_versions: Vec<u32>,
+}
+
+impl VersionInformation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.chosen_version.to_be_bytes());
+        for version in &self.available_versions {
+            out.extend_from_slice(&version.to_be_bytes());
+        }
+    }
+
+    fn decode(value: &[u8]) -> Result<Self> {
+        if value.len() < 4 || !value.len().is_multiple_of(4) {
+            return Err(parameter_error("malformed version_information"));
+        }
+        let mut versions = value.chunks_exact(4).map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]));
+        let chosen_version = versions.next().unwrap();
+        let available_versions: Vec<u32> = versions.collect();
+        /* version 0 is Version Negotiation, which can be neither chosen nor available */
+        if chosen_version == 0 || available_versions.contains(&0) {
+            return Err(parameter_error("version_information with version 0"));
+        }
+        Ok(Self { chosen_version, available_versions })
+    }
+}
+
+/*
+The transport parameters one endpoint sends the other during the handshake (RFC 9000 §7.4, §18).
+Absent parameters take their default values, which is what `Default` gives.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransportParameters {
+    pub original_destination_connection_id: Option<ConnectionId>,
+    pub max_idle_timeout: Duration, // zero when there is none
+    pub stateless_reset_token: Option<[u8; 16]>,
+    pub max_udp_payload_size: u64,
+    pub initial_max_data: u64,
+    pub initial_max_stream_data_bidi_local: u64,
+    pub initial_max_stream_data_bidi_remote: u64,
+    pub initial_max_stream_data_uni: u64,
+    pub initial_max_streams_bidi: u64,
+    pub initial_max_streams_uni: u64,
+    pub ack_delay_exponent: u8,
+    pub max_ack_delay: Duration,
+    pub disable_active_migration: bool,
+    pub preferred_address: Option<PreferredAddress>,
+    pub active_connection_id_limit: u64,
+    pub initial_source_connection_id: Option<ConnectionId>,
+    pub retry_source_connection_id: Option<ConnectionId>,
+    pub max_datagram_frame_size: Option<u64>, // None: no datagrams
+    pub grease_quic_bit: bool,
+    pub version_information: Option<VersionInformation>,
+}
+
+impl Default for TransportParameters {
+    fn default() -> Self {
+        Self {
+            original_destination_connection_id: None,
+            max_idle_timeout: Duration::ZERO,
+            stateless_reset_token: None,
+            max_udp_payload_size: 65527,
+            initial_max_data: 0,
+            initial_max_stream_data_bidi_local: 0,
+            initial_max_stream_data_bidi_remote: 0,
+            initial_max_stream_data_uni: 0,
+            initial_max_streams_bidi: 0,
+            initial_max_streams_uni: 0,
+            ack_delay_exponent: 3,
+            max_ack_delay: Duration::from_millis(25),
+            disable_active_migration: false,
+            preferred_address: None,
+            active_connection_id_limit: 2,
+            initial_source_connection_id: None,
+            retry_source_connection_id: None,
+            max_datagram_frame_size: None,
+            grease_quic_bit: false,
+            version_information: None,
+        }
+    }
+}
+
+fn parameter_error(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("TRANSPORT_PARAMETER_ERROR: {}", msg))
+}
+
+fn write_parameter(out: &mut Vec<u8>, id: u64, value: &[u8]) {
+    encode_varint_into(id, out);
+    encode_varint_into(value.len() as u64, out);
+    out.extend_from_slice(value);
+}
+
+fn write_varint_parameter(out: &mut Vec<u8>, id: u64, value: u64) {
+    let mut encoded = Vec::with_capacity(8);
+    encode_varint_into(value, &mut encoded);
+    write_parameter(out, id, &encoded);
+}
+
+/* Values other than the defaults are sent, so absent parameters keep meaning the default. */
+fn write_if_not(out: &mut Vec<u8>, id: u64, value: u64, default: u64) {
+    if value != default {
+        write_varint_parameter(out, id, value);
+    }
+}
+
+fn read_varint(value: &[u8]) -> Result<u64> {
+    match varint(value) {
+        Some((v, n)) if n == value.len() => Ok(v as u64),
+        _ => Err(parameter_error("malformed integer parameter")),
+    }
+}
+
+fn read_connection_id(value: &[u8]) -> Result<ConnectionId> {
+    if value.len() > 20 { return Err(parameter_error("connection ID longer than 20 bytes")); }
+    Ok(ConnectionId::from_slice(value))
+}
+
+fn read_token(value: &[u8]) -> Result<[u8; 16]> {
+    value.try_into().map_err(|_| parameter_error("stateless reset token is not 16 bytes"))
+}
+
+impl PreferredAddress {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let ipv4 = self.ipv4.unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+        let ipv6 = self.ipv6.unwrap_or(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0));
+        out.extend_from_slice(&ipv4.ip().octets());
+        out.extend_from_slice(&ipv4.port().to_be_bytes());
+        out.extend_from_slice(&ipv6.ip().octets());
+        out.extend_from_slice(&ipv6.port().to_be_bytes());
+        out.push(self.connection_id.len as u8);
+        out.extend_from_slice(self.connection_id.as_bytes());
+        out.extend_from_slice(&self.stateless_reset_token);
+    }
+
+    fn decode(value: &[u8]) -> Result<Self> {
+        let malformed = || parameter_error("malformed preferred_address");
+        if value.len() < 4 + 2 + 16 + 2 + 1 { return Err(malformed()); }
+        let ipv4 = SocketAddrV4::new(Ipv4Addr::from(<[u8; 4]>::try_from(&value[..4]).unwrap()), u16::from_be_bytes([value[4], value[5]]));
+        let ipv6 = SocketAddrV6::new(Ipv6Addr::from(<[u8; 16]>::try_from(&value[6..22]).unwrap()), u16::from_be_bytes([value[22], value[23]]), 0, 0);
+        let cid_len = value[24] as usize;
+        /* a zero-length CID cannot be moved to (RFC 9000 §18.2) */
+        if cid_len == 0 || cid_len > 20 || value.len() != 25 + cid_len + 16 { return Err(malformed()); }
+        Ok(Self {
+            ipv4: (!ipv4.ip().is_unspecified() || ipv4.port() != 0).then_some(ipv4),
+            ipv6: (!ipv6.ip().is_unspecified() || ipv6.port() != 0).then_some(ipv6),
+            connection_id: ConnectionId::from_slice(&value[25..25 + cid_len]),
+            stateless_reset_token: value[25 + cid_len..].try_into().unwrap(),
+        })
+    }
+}
+
+impl TransportParameters {
+    /*
+    Encodes the parameters in the transport_parameters TLS extension format (RFC 9000 §18), adding
+    one reserved parameter so peers keep ignoring unknown ones (RFC 9000 §18.1).
+    */
+    pub fn encode(&self) -> Vec<u8> {
+        let defaults = Self::default();
+        let mut out = Vec::with_capacity(128);
+        if let Some(cid) = &self.original_destination_connection_id {
+            write_parameter(&mut out, ORIGINAL_DESTINATION_CONNECTION_ID, cid.as_bytes());
+        }
+        write_if_not(&mut out, MAX_IDLE_TIMEOUT, self.max_idle_timeout.as_millis() as u64, 0);
+        if let Some(token) = &self.stateless_reset_token {
+            write_parameter(&mut out, STATELESS_RESET_TOKEN, token);
+        }
+        write_if_not(&mut out, MAX_UDP_PAYLOAD_SIZE, self.max_udp_payload_size, defaults.max_udp_payload_size);
+        write_if_not(&mut out, INITIAL_MAX_DATA, self.initial_max_data, 0);
+        write_if_not(&mut out, INITIAL_MAX_STREAM_DATA_BIDI_LOCAL, self.initial_max_stream_data_bidi_local, 0);
+        write_if_not(&mut out, INITIAL_MAX_STREAM_DATA_BIDI_REMOTE, self.initial_max_stream_data_bidi_remote, 0);
+        write_if_not(&mut out, INITIAL_MAX_STREAM_DATA_UNI, self.initial_max_stream_data_uni, 0);
+        write_if_not(&mut out, INITIAL_MAX_STREAMS_BIDI, self.initial_max_streams_bidi, 0);
+        write_if_not(&mut out, INITIAL_MAX_STREAMS_UNI, self.initial_max_streams_uni, 0);
+        write_if_not(&mut out, ACK_DELAY_EXPONENT, self.ack_delay_exponent as u64, defaults.ack_delay_exponent as u64);
+        write_if_not(&mut out, MAX_ACK_DELAY, self.max_ack_delay.as_millis() as u64, defaults.max_ack_delay.as_millis() as u64);
+        if self.disable_active_migration {
+            write_parameter(&mut out, DISABLE_ACTIVE_MIGRATION, &[]);
+        }
+        if let Some(address) = &self.preferred_address {
+            let mut value = Vec::with_capacity(61);
+            address.encode(&mut value);
+            write_parameter(&mut out, PREFERRED_ADDRESS, &value);
+        }
+        write_if_not(&mut out, ACTIVE_CONNECTION_ID_LIMIT, self.active_connection_id_limit, defaults.active_connection_id_limit);
+        if let Some(cid) = &self.initial_source_connection_id {
+            write_parameter(&mut out, INITIAL_SOURCE_CONNECTION_ID, cid.as_bytes());
+        }
+        if let Some(cid) = &self.retry_source_connection_id {
+            write_parameter(&mut out, RETRY_SOURCE_CONNECTION_ID, cid.as_bytes());
+        }
+        if let Some(size) = self.max_datagram_frame_size {
+            write_varint_parameter(&mut out, MAX_DATAGRAM_FRAME_SIZE, size);
+        }
+        if self.grease_quic_bit {
+            write_parameter(&mut out, GREASE_QUIC_BIT, &[]);
+        }
+        if let Some(info) = &self.version_information {
+            let mut value = Vec::with_capacity(4 + 4 * info.available_versions.len());
+            info.encode(&mut value);
+            write_parameter(&mut out, VERSION_INFORMATION, &value);
+        }
+        let mut rng = rand::rng();
+        let reserved = 31 * rng.random_range(0..1u64 << 16) + 27;
+        let value: [u8; 4] = rng.random();
+        write_parameter(&mut out, reserved, &value[..rng.random_range(0..=4)]);
+        out
+    }
+
+    /*
+    Decodes what the peer sent; `from_server` tells which side that is. Duplicates, values out of range
+    and parameters a client must not send are TRANSPORT_PARAMETER_ERROR (RFC 9000 §7.4, §18.2).
+    Unknown parameters are skipped.
+    */
+    pub fn decode(mut buf: &[u8], from_server: bool) -> Result<Self> {
+        let mut params = Self::default();
+        let mut seen: Vec<u64> = Vec::new();
+        while !buf.is_empty() {
+            let (id, n) = varint(buf).ok_or_else(|| parameter_error("truncated parameter id"))?;
+            buf = &buf[n..];
+            let (len, n) = varint(buf).ok_or_else(|| parameter_error("truncated parameter length"))?;
+            buf = &buf[n..];
+            let value = buf.get(..len).ok_or_else(|| parameter_error("parameter runs past the end"))?;
+            buf = &buf[len..];
+
+            let id = id as u64;
+            if seen.contains(&id) {
+                return Err(parameter_error(&format!("parameter {:#x} appears twice", id)));
+            }
+            seen.push(id);
+            let server_only = matches!(id, ORIGINAL_DESTINATION_CONNECTION_ID | STATELESS_RESET_TOKEN | PREFERRED_ADDRESS | RETRY_SOURCE_CONNECTION_ID);
+            if server_only && !from_server {
+                return Err(parameter_error(&format!("parameter {:#x} sent by a client", id)));
+            }
+            match id {
+                ORIGINAL_DESTINATION_CONNECTION_ID => params.original_destination_connection_id = Some(read_connection_id(value)?),
+                MAX_IDLE_TIMEOUT => params.max_idle_timeout = Duration::from_millis(read_varint(value)?),
+                STATELESS_RESET_TOKEN => params.stateless_reset_token = Some(read_token(value)?),
+                MAX_UDP_PAYLOAD_SIZE => {
+                    params.max_udp_payload_size = read_varint(value)?;
+                    if params.max_udp_payload_size < 1200 { return Err(parameter_error("max_udp_payload_size below 1200")); }
+                }
+                INITIAL_MAX_DATA => params.initial_max_data = read_varint(value)?,
+                INITIAL_MAX_STREAM_DATA_BIDI_LOCAL => params.initial_max_stream_data_bidi_local = read_varint(value)?,
+                INITIAL_MAX_STREAM_DATA_BIDI_REMOTE => params.initial_max_stream_data_bidi_remote = read_varint(value)?,
+                INITIAL_MAX_STREAM_DATA_UNI => params.initial_max_stream_data_uni = read_varint(value)?,
+                INITIAL_MAX_STREAMS_BIDI | INITIAL_MAX_STREAMS_UNI => {
+                    let max = read_varint(value)?;
+                    if max > 1 << 60 { return Err(parameter_error("stream limit above 2^60")); }
+                    match id {
+                        INITIAL_MAX_STREAMS_BIDI => params.initial_max_streams_bidi = max,
+                        _ => params.initial_max_streams_uni = max,
+                    }
+                }
+                ACK_DELAY_EXPONENT => {
+                    let exponent = read_varint(value)?;
+                    if exponent > 20 { return Err(parameter_error("ack_delay_exponent above 20")); }
+                    params.ack_delay_exponent = exponent as u8;
+                }
+                MAX_ACK_DELAY => {
+                    let delay = read_varint(value)?;
+                    if delay >= 1 << 14 { return Err(parameter_error("max_ack_delay of 2^14 ms or more")); }
+                    params.max_ack_delay = Duration::from_millis(delay);
+                }
+                DISABLE_ACTIVE_MIGRATION | GREASE_QUIC_BIT => {
+                    if !value.is_empty() { return Err(parameter_error("flag parameter with a value")); }
+                    match id {
+                        DISABLE_ACTIVE_MIGRATION => params.disable_active_migration = true,
+                        _ => params.grease_quic_bit = true,
+                    }
+                }
+                PREFERRED_ADDRESS => params.preferred_address = Some(PreferredAddress::decode(value)?),
+                ACTIVE_CONNECTION_ID_LIMIT => {
+                    params.active_connection_id_limit = read_varint(value)?;
+                    if params.active_connection_id_limit < 2 { return Err(parameter_error("active_connection_id_limit below 2")); }
+                }
+                INITIAL_SOURCE_CONNECTION_ID => params.initial_source_connection_id = Some(read_connection_id(value)?),
+                RETRY_SOURCE_CONNECTION_ID => params.retry_source_connection_id = Some(read_connection_id(value)?),
+                MAX_DATAGRAM_FRAME_SIZE => params.max_datagram_frame_size = Some(read_varint(value)?),
+                VERSION_INFORMATION => params.version_information = Some(VersionInformation::decode(value)?),
+                _ => {}
+            }
+        }
+        if params.initial_source_connection_id.is_none() {
+            return Err(parameter_error("initial_source_connection_id missing"));
+        }
+        if from_server && params.original_destination_connection_id.is_none() {
+            return Err(parameter_error("original_destination_connection_id missing"));
+        }
+        Ok(params)
+    }
+}
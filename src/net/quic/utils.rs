@@ -1,5 +1,4 @@
 use rand::RngCore;
-use ring::aead::Nonce;
 
 pub fn generate_connection_id(len: usize) -> Vec<u8> {
     assert!(len <= 20);
@@ -8,6 +7,18 @@ pub fn generate_connection_id(len: usize) -> Vec<u8> {
     cid
 }
 
+/* Decodes the variable-length integer at the start of `buf`: (value, encoded length), or None when it is cut short. */
+#[inline(always)]
+pub(crate) fn varint(buf: &[u8]) -> Option<(usize, usize)> {
+    if buf.is_empty() { return None }
+    let first = buf[0];
+    let (bytes, mask) = match first >> 6 { 0 => (1,0b00111111), 1 => (2,0b00111111), 2 => (4,0b00111111), _ => (8,0b00111111) };
+    if buf.len() < bytes { return None }
+    let mut v = (first & mask) as usize;
+    for i in 1..bytes { v = (v << 8) | buf[i] as usize; }
+    Some((v, bytes))
+}
+
 pub fn encode_varint_into(mut v: u64, out: &mut Vec<u8>) {
     if v <= 63 {
         out.push(v as u8);
@@ -30,14 +41,3 @@ pub fn encode_varint_into(mut v: u64, out: &mut Vec<u8>) {
         out.push((v & 0xFF) as u8);
     }
 }
-
-pub fn make_nonce(packet_number: u32, iv: &[u8;12]) -> Nonce {
-    let mut nonce = *iv;
-    let pn_be = (packet_number as u64).to_be_bytes();
-
-    for i in 0..8 {
-        let j = nonce.len() - 8 + i;
-        nonce[j] ^= pn_be[i];
-    }
-    Nonce::assume_unique_for_key(nonce)
-}
\ No newline at end of file
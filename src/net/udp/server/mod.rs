@@ -1,5 +1,5 @@
 mod server;
 pub use server::*;
 mod worker;
-pub use worker::{UdpServerThreadContext};
+pub use worker::{UdpForwarder, UdpServerThreadContext, INBOX_CAPACITY};
 mod modes;
\ No newline at end of file
@@ -10,6 +10,7 @@ impl UdpServerThreadContext {
                 "Kernel support is currently only supported on Unix systems (XDP mode)",
             ));
         }
+        /* The XDP path below is disabled until the AF_XDP rings are finished; kernel mode does not attach anything yet. */
         /*
         // Attempting to use XDP for IPv4 UDP...
         #[cfg(unix)]
@@ -178,7 +179,7 @@ impl UdpServerThreadContext {
             };
             /*
             let elf = Elf::load_from_bytes(XDP_OBJ).expect("Failed to load XDP ELF object");
-            println!("All sections: {:?}", elf.sections);
+            dprintln!(self, "[{} INFO] XDP ELF sections: {:?}", self.name, elf.sections);
             let text_section = elf.get_section_data_by_name("xdp_sock").expect("Failed to find .text section in XDP ELF object");
             dprintln!(self, "[{} INFO] XDP ELF text section found at offset: {:?}", self.name, text_section);
             let license = CString::new("GPL").unwrap();
@@ -199,14 +200,15 @@ impl UdpServerThreadContext {
             }*/
             let prog_fd = load_xdp(XDP_OBJ, ".maps").expect("Failed to load XDP program from embedded object");
             dprintln!(self, "[{} INFO] BPF program loaded with fd: {}", self.name, prog_fd);
-            let attach_ret = unsafe {
-                bpf_set_link_xdp_fd(ifindex, prog_fd.as_raw_fd(), XDP_FLAGS_SKB_MODE | XDP_FLAGS_UPDATE_IF_NOEXIST)
+            // Detached when `attachment` goes out of scope at the end of the poll loop.
+            let attachment = match XdpAttachment::attach(prog_fd, ifindex, XdpMode::Native) {
+                Ok(attachment) => attachment,
+                Err(e) => {
+                    dprintln!(self, "[{} Error] Failed to attach XDP program to interface {}: {}", self.name, iface, e);
+                    return Err(e);
+                }
             };
-            if attach_ret < 0 {
-                dprintln!(self, "[{} Error] Failed to attach XDP program to interface {}: {}", self.name, iface, std::io::Error::last_os_error());
-                return Err(std::io::Error::last_os_error());
-            }
-            dprintln!(self, "[{} INFO] XDP program loaded with fd: {}", self.name, prog_fd);
+            dprintln!(self, "[{} INFO] XDP program attached in {:?} mode with fd: {}", self.name, attachment.mode(), prog_fd);
             for i in 0..desc_cnt {
                 unsafe { std::ptr::write(fill_ring.add(i as usize), (i as u64) * self.frame_size as u64) }; // addr = i*chunk
             }
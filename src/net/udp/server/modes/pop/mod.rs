@@ -6,6 +6,7 @@ impl UdpServerThreadContext {
         let mut handler = self.datagram_handler.take().expect("No Datagram handler set for UdpServerThreadContext");
         let mut buf = vec![0u8; 2048];
         let mut buf_len = 0;
+        let mut tick = self.tick_handler.take();
         self.make_ready();
         while self.server_running.load(Ordering::Relaxed) {
             match self.socket.popmsg(&mut buf, &mut buf_len, 0) {
@@ -17,9 +18,13 @@ impl UdpServerThreadContext {
                         self.c = 0;
                     }
                 }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {}
                 Err(e) => { dprintln!(self, "Error receiving data: {e}"); break }
             }
+            self.drain_inbox(&mut handler);
+            if let Some(tick) = tick.as_mut() {
+                tick();
+            }
         }
         Ok(())
     }
@@ -7,9 +7,11 @@ impl UdpServerThreadContext {
         let drain_capacity = 8;
         let mut bucket = Ipv4Bucket::new(drain_capacity, 2048);
         let bucket_ref = &mut bucket;
+        let mut tick = self.tick_handler.take();
         self.make_ready();
         while self.server_running.load(Ordering::Relaxed) {
-            match self.socket.vecrecv(bucket_ref, 0) {
+            /* return with what arrived instead of waiting for a full bucket */
+            match self.socket.vecrecv(bucket_ref, MSG_WAITFORONE) {
                 Ok(count) => {
                     for i in 0..count {
                         let (addrv4, buf) = unsafe { bucket_ref.unsafe_peek(i) };
@@ -22,14 +24,16 @@ impl UdpServerThreadContext {
                         self.c = 0;
                     }
                 }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {
-                    continue;
-                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {}
                 Err(e) => {
                     dprintln!(self, "Error receiving data: {e}");
                     break;
                 }
             }
+            self.drain_inbox(&mut handler);
+            if let Some(tick) = tick.as_mut() {
+                tick();
+            }
         }
         Ok(())
     }
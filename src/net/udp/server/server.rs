@@ -10,8 +10,11 @@ pub struct UdpServer {
     threads: Vec<std::thread::JoinHandle<()>>,
     thread_handler: Option<Arc<dyn Fn(UdpServerThreadContext) + Send + Sync + 'static>>,
     debug_mode: bool,
+    filter: Option<Arc<SocketFilter>>,
+    recv_timeout: Duration,
     pub(crate) processed_packets: Vec<Arc<AtomicUsize>>,
     pub total_processed_packets: Arc<AtomicUsize>,
+    forward_drops: Arc<AtomicUsize>,
 }
 
 impl UdpServer {
@@ -22,8 +25,11 @@ impl UdpServer {
             threads: Vec::new(),
             thread_handler: None,
             debug_mode: false,
+            filter: None,
+            recv_timeout: Duration::from_millis(500),
             processed_packets: Vec::new(),
             total_processed_packets: Arc::new(AtomicUsize::new(0)),
+            forward_drops: Arc::new(AtomicUsize::new(0)),
         }
     }
     
@@ -35,8 +41,21 @@ impl UdpServer {
             ));
         }
         dprintln!(self, "[UdpServer] Starting at {}", self.address);
+        if let Some(filter) = &self.filter {
+            /* a filter the platform or the kernel refuses fails `start`, not the workers */
+            let probe = if self.address.is_ipv4() {
+                Socket::new(AfInet, SockDgram, IpProtoUdp)
+            } else {
+                Socket::new(AfInet6, SockDgram, IpProtoUdp)
+            }?;
+            let attached = probe.attach_filter(filter);
+            let _ = probe.close();
+            attached?;
+        }
         let (ready_tx, ready_rx) = mpsc::channel();
-        for id in 0..num_workers {
+        let (inboxes, receivers): (Vec<_>, Vec<_>) = (0..num_workers).map(|_| mpsc::sync_channel(INBOX_CAPACITY)).unzip();
+        let forwarder = UdpForwarder::new(inboxes, self.forward_drops.clone());
+        for (id, inbox) in receivers.into_iter().enumerate() {
             if id > 0 {
                 ready_rx.recv().unwrap();
             }
@@ -52,6 +71,9 @@ impl UdpServer {
                     let counter = counter.clone();
                     let ready_signal = ready_tx.clone();
                     let context_setup_handler = self.thread_handler.as_ref().map(Arc::clone);
+                    let filter = self.filter.clone();
+                    let recv_timeout = self.recv_timeout;
+                    let forwarder = forwarder.clone();
                     move || {
                         let socket = (if address.is_ipv4() {
                             Socket::new(AfInet, SockDgram, IpProtoUdp)
@@ -59,8 +81,13 @@ impl UdpServer {
                             Socket::new(AfInet6, SockDgram, IpProtoUdp)
                         }).unwrap();
                         socket.set_socket_option(SoRecvBufSize, 32768).expect("Failed to set SoRecvBufSize");
-                        socket.set_socket_option(SoRecvTimeout, Duration::from_millis(500)).expect("Failed to set SoRecvTimeout");
+                        socket.set_socket_option(SoRecvTimeout, recv_timeout).expect("Failed to set SoRecvTimeout");
                         socket.set_socket_option(SoReuseAddr, true).expect("Failed to set SoReuseAddr");
+                        if let Some(filter) = filter {
+                            if let Err(e) = socket.attach_filter(&filter) {
+                                if debug { println!("[{}] Failed to attach socket filter: {}", thread_name, e); }
+                            }
+                        }
                         socket.bind(&address).expect("Failed to bind socket");
 
                         let mut ctx = UdpServerThreadContext::new(socket, address, ready_signal);
@@ -69,6 +96,8 @@ impl UdpServer {
                         ctx.processed_counter = counter.clone();
                         ctx.server_running = running;
                         ctx.debug_mode = debug;
+                        ctx.forwarder = forwarder;
+                        ctx.inbox = inbox;
                         
                         if debug { println!("[{}] Started", thread_name); }
 
@@ -125,6 +154,21 @@ impl UdpServer {
         }
     }
     
+    /* Datagrams not matching `filter` are dropped by the kernel on every worker socket. Applies to sockets created by the next `start`. */
+    pub fn filter(&mut self, filter: SocketFilter) -> &mut Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /*
+    How long a worker waits for a datagram before it looks at its inbox of forwarded datagrams, runs its
+    tick handler and checks whether the server stopped. Applies to sockets created by the next `start`.
+    */
+    pub fn set_recv_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.recv_timeout = timeout;
+        self
+    }
+
     pub fn debug(&mut self, debug: bool) -> &mut Self {
         self.debug_mode = debug;
         self
@@ -141,6 +185,11 @@ impl UdpServer {
         self.threads.len()
     }
 
+    /* Datagrams one worker forwarded to another and dropped because the other's inbox was full. */
+    pub fn forward_drops(&self) -> usize {
+        self.forward_drops.load(Ordering::Relaxed)
+    }
+
     pub fn floodtest(&'_ self, local_port: u16) -> UdpFloodTest<'_> {
         UdpFloodTest::new(&self, local_port)
     }
@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::Sender, Arc}};
+use std::{net::SocketAddr, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::{self, Receiver, Sender, SyncSender, TrySendError}, Arc}};
 
 use crate::net::*;
 
@@ -11,6 +11,50 @@ macro_rules! dprintln {
     };
 }
 
+/* A datagram another worker received and its source. */
+pub(crate) type Forwarded = (SocketAddr, Vec<u8>);
+
+/* Datagrams a worker's inbox holds; more are dropped, as a full socket buffer would. */
+pub const INBOX_CAPACITY: usize = 1024;
+
+/* Hands datagrams to other workers of the same server, which process them as if they had received them. */
+#[derive(Clone)]
+pub struct UdpForwarder {
+    inboxes: Arc<Vec<SyncSender<Forwarded>>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl UdpForwarder {
+    pub(crate) fn new(inboxes: Vec<SyncSender<Forwarded>>, dropped: Arc<AtomicUsize>) -> Self {
+        Self { inboxes: Arc::new(inboxes), dropped }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.inboxes.len()
+    }
+
+    /*
+    Queues `datagram` from `addr` for `worker`, or drops it when the worker's inbox is full; false when there
+    is no such worker or it stopped.
+    */
+    pub fn forward(&self, worker: usize, addr: SocketAddr, datagram: &[u8]) -> bool {
+        let Some(inbox) = self.inboxes.get(worker) else { return false; };
+        match inbox.try_send((addr, datagram.to_vec())) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /* Datagrams dropped because the inbox of their worker was full. */
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
 pub struct UdpServerThreadContext {
     pub(crate) id: usize,
     pub(crate) name: String,
@@ -21,8 +65,11 @@ pub struct UdpServerThreadContext {
     pub(crate) c: usize,
     pub(crate) processed_counter: Arc<AtomicUsize>,
     pub(crate) datagram_handler: Option<Box<dyn FnMut(SocketAddr, &mut [u8]) + Send + Sync + 'static>>,
+    pub(crate) tick_handler: Option<Box<dyn FnMut() + Send + Sync + 'static>>,
     pub(crate) kernel_mode: bool,
     pub(crate) ready_tx: Sender<()>,
+    pub(crate) forwarder: UdpForwarder,
+    pub(crate) inbox: Receiver<Forwarded>, // datagrams other workers forwarded here
 }
 
 impl UdpServerThreadContext {
@@ -37,8 +84,24 @@ impl UdpServerThreadContext {
             c: 0,
             processed_counter: Arc::new(AtomicUsize::new(0)),
             datagram_handler: None,
+            tick_handler: None,
             kernel_mode: false,
-            ready_tx
+            ready_tx,
+            forwarder: UdpForwarder::new(Vec::new(), Arc::new(AtomicUsize::new(0))),
+            inbox: mpsc::channel().1,
+        }
+    }
+
+    /* A handle for passing datagrams to the other workers, e.g. from the datagram handler. */
+    pub fn forwarder(&self) -> UdpForwarder {
+        self.forwarder.clone()
+    }
+
+    /* Runs the handler on what other workers forwarded since the last time. */
+    #[inline(always)]
+    pub(crate) fn drain_inbox<F: FnMut(SocketAddr, &mut [u8]) + ?Sized>(&self, handler: &mut F) {
+        while let Ok((addr, mut datagram)) = self.inbox.try_recv() {
+            handler(addr, &mut datagram);
         }
     }
 
@@ -63,6 +126,17 @@ impl UdpServerThreadContext {
         self.datagram_handler = Some(Box::new(h));
     }
 
+    /*
+    Called after every datagram or batch of them, and whenever the receive timeout expires without one,
+    e.g. to run timers.
+    */
+    pub fn on_tick<F>(&mut self, h: F)
+    where
+        F: FnMut() + Send + Sync + 'static,
+    {
+        self.tick_handler = Some(Box::new(h));
+    }
+
     pub fn make_ready(&self) {
         self.ready_tx.send(()).expect("Failed to send ready signal");
         while !self.server_running.load(Ordering::Relaxed) {
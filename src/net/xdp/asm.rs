@@ -0,0 +1,313 @@
+use std::io::{Error, ErrorKind, Result};
+
+use crate::net::{BpfMapDef, BPF_MAP_TYPE_HASH, BPF_PSEUDO_MAP_FD};
+use super::{BpfObject, XdpMapSpec, BPF_FUNC_MAP_LOOKUP_ELEM};
+
+/*
+eBPF assembler.
+
+Emits the same instruction encoding clang does, so programs built here load through `load_bpf_object`
+and run unchanged in `XdpVm`. Jumps target `Label`s which may be bound before or after the jump;
+offsets are resolved by `build`.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg(u8);
+
+pub const R0: Reg = Reg(0);
+pub const R1: Reg = Reg(1);
+pub const R2: Reg = Reg(2);
+pub const R3: Reg = Reg(3);
+pub const R4: Reg = Reg(4);
+pub const R5: Reg = Reg(5);
+pub const R6: Reg = Reg(6);
+pub const R7: Reg = Reg(7);
+pub const R8: Reg = Reg(8);
+pub const R9: Reg = Reg(9);
+pub const R10: Reg = Reg(10); // read-only frame pointer
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i32),
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<i32> for Operand {
+    fn from(imm: i32) -> Self {
+        Operand::Imm(imm)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    B = 0x10,
+    H = 0x08,
+    W = 0x00,
+    DW = 0x18,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add = 0x00,
+    Sub = 0x10,
+    Mul = 0x20,
+    Div = 0x30,
+    Or = 0x40,
+    And = 0x50,
+    Lsh = 0x60,
+    Rsh = 0x70,
+    Mod = 0x90,
+    Xor = 0xa0,
+    Mov = 0xb0,
+    Arsh = 0xc0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JmpOp {
+    Eq = 0x10,
+    Gt = 0x20,
+    Ge = 0x30,
+    Set = 0x40,
+    Ne = 0x50,
+    Sgt = 0x60,
+    Sge = 0x70,
+    Lt = 0xa0,
+    Le = 0xb0,
+    Slt = 0xc0,
+    Sle = 0xd0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapRef(usize);
+
+const BPF_LD: u8 = 0x00;
+const BPF_LDX: u8 = 0x01;
+const BPF_ST: u8 = 0x02;
+const BPF_STX: u8 = 0x03;
+const BPF_ALU: u8 = 0x04;
+const BPF_JMP: u8 = 0x05;
+const BPF_JMP32: u8 = 0x06;
+const BPF_ALU64: u8 = 0x07;
+const BPF_MEM: u8 = 0x60;
+const BPF_IMM: u8 = 0x00;
+const BPF_X: u8 = 0x08;
+const BPF_K: u8 = 0x00;
+const BPF_END: u8 = 0xd0;
+const BPF_NEG: u8 = 0x80;
+const BPF_JA: u8 = 0x00;
+const BPF_CALL: u8 = 0x80;
+const BPF_EXIT: u8 = 0x90;
+
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    dst: u8,
+    src: u8,
+    off: i16,
+    imm: i32,
+}
+
+#[derive(Default)]
+pub struct BpfAsm {
+    insns: Vec<Insn>,
+    labels: Vec<Option<usize>>,
+    jumps: Vec<(usize, Label)>,
+    maps: Vec<XdpMapSpec>,
+    map_relocations: Vec<(usize, usize)>,
+}
+
+impl BpfAsm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* Declares a map; the program references it with `ld_map`. */
+    pub fn map(&mut self, name: &str, def: BpfMapDef) -> MapRef {
+        self.maps.push(XdpMapSpec { name: name.to_string(), def });
+        MapRef(self.maps.len() - 1)
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /* Binds `label` to the next emitted instruction. */
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        self.labels[label.0] = Some(self.insns.len());
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.insns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.insns.is_empty()
+    }
+
+    pub fn alu64<O: Into<Operand>>(&mut self, op: AluOp, dst: Reg, src: O) -> &mut Self {
+        self.alu(BPF_ALU64, op, dst, src.into())
+    }
+
+    pub fn alu32<O: Into<Operand>>(&mut self, op: AluOp, dst: Reg, src: O) -> &mut Self {
+        self.alu(BPF_ALU, op, dst, src.into())
+    }
+
+    pub fn mov64<O: Into<Operand>>(&mut self, dst: Reg, src: O) -> &mut Self {
+        self.alu64(AluOp::Mov, dst, src)
+    }
+
+    pub fn add64<O: Into<Operand>>(&mut self, dst: Reg, src: O) -> &mut Self {
+        self.alu64(AluOp::Add, dst, src)
+    }
+
+    pub fn neg64(&mut self, dst: Reg) -> &mut Self {
+        self.emit(BPF_ALU64 | BPF_NEG, dst.0, 0, 0, 0)
+    }
+
+    /* Converts the low `bits` (16, 32 or 64) of `dst` between host and network byte order. */
+    pub fn to_be(&mut self, dst: Reg, bits: i32) -> &mut Self {
+        self.emit(BPF_ALU | BPF_END | BPF_X, dst.0, 0, 0, bits)
+    }
+
+    pub fn ld_imm64(&mut self, dst: Reg, imm: u64) -> &mut Self {
+        self.emit(BPF_LD | BPF_IMM | Size::DW as u8, dst.0, 0, 0, imm as u32 as i32);
+        self.emit(0, 0, 0, 0, (imm >> 32) as u32 as i32)
+    }
+
+    pub fn ld_map(&mut self, dst: Reg, map: MapRef) -> &mut Self {
+        self.map_relocations.push((self.insns.len(), map.0));
+        self.emit(BPF_LD | BPF_IMM | Size::DW as u8, dst.0, BPF_PSEUDO_MAP_FD, 0, map.0 as i32);
+        self.emit(0, 0, 0, 0, 0)
+    }
+
+    /* dst = *(size *)(src + off) */
+    pub fn ldx(&mut self, size: Size, dst: Reg, src: Reg, off: i16) -> &mut Self {
+        self.emit(BPF_LDX | BPF_MEM | size as u8, dst.0, src.0, off, 0)
+    }
+
+    /* *(size *)(dst + off) = imm */
+    pub fn st(&mut self, size: Size, dst: Reg, off: i16, imm: i32) -> &mut Self {
+        self.emit(BPF_ST | BPF_MEM | size as u8, dst.0, 0, off, imm)
+    }
+
+    /* *(size *)(dst + off) = src */
+    pub fn stx(&mut self, size: Size, dst: Reg, off: i16, src: Reg) -> &mut Self {
+        self.emit(BPF_STX | BPF_MEM | size as u8, dst.0, src.0, off, 0)
+    }
+
+    pub fn ja(&mut self, target: Label) -> &mut Self {
+        self.jumps.push((self.insns.len(), target));
+        self.emit(BPF_JMP | BPF_JA, 0, 0, 0, 0)
+    }
+
+    pub fn jmp<O: Into<Operand>>(&mut self, op: JmpOp, dst: Reg, src: O, target: Label) -> &mut Self {
+        self.cond_jmp(BPF_JMP, op, dst, src.into(), target)
+    }
+
+    pub fn jmp32<O: Into<Operand>>(&mut self, op: JmpOp, dst: Reg, src: O, target: Label) -> &mut Self {
+        self.cond_jmp(BPF_JMP32, op, dst, src.into(), target)
+    }
+
+    /* Calls a kernel helper (`BPF_FUNC_*`); arguments go in r1-r5, the result comes back in r0. */
+    pub fn call(&mut self, helper: i32) -> &mut Self {
+        self.emit(BPF_JMP | BPF_CALL, 0, 0, 0, helper)
+    }
+
+    pub fn exit(&mut self) -> &mut Self {
+        self.emit(BPF_JMP | BPF_EXIT, 0, 0, 0, 0)
+    }
+
+    /* r0 = value; exit */
+    pub fn ret(&mut self, value: i32) -> &mut Self {
+        self.mov64(R0, value).exit()
+    }
+
+    /* Resolves jump offsets and encodes the program. */
+    pub fn build(&self) -> Result<BpfObject> {
+        let mut insns = self.insns.clone();
+        for &(at, label) in self.jumps.iter() {
+            let target = self.labels[label.0]
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Label {} is never bound", label.0)))?;
+            let off = i16::try_from(target as i64 - at as i64 - 1)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Jump offset does not fit in 16 bits"))?;
+            insns[at].off = off;
+        }
+        let mut code = Vec::with_capacity(insns.len() * 8);
+        for insn in insns.iter() {
+            code.push(insn.code);
+            code.push((insn.src << 4) | insn.dst);
+            code.extend_from_slice(&insn.off.to_le_bytes());
+            code.extend_from_slice(&insn.imm.to_le_bytes());
+        }
+        let maps = self.maps.iter().map(|m| XdpMapSpec { name: m.name.clone(), def: m.def }).collect();
+        Ok(BpfObject { code, maps, map_relocations: self.map_relocations.clone() })
+    }
+
+    fn alu(&mut self, class: u8, op: AluOp, dst: Reg, src: Operand) -> &mut Self {
+        match src {
+            Operand::Reg(src) => self.emit(class | op as u8 | BPF_X, dst.0, src.0, 0, 0),
+            Operand::Imm(imm) => self.emit(class | op as u8 | BPF_K, dst.0, 0, 0, imm),
+        }
+    }
+
+    fn cond_jmp(&mut self, class: u8, op: JmpOp, dst: Reg, src: Operand, target: Label) -> &mut Self {
+        self.jumps.push((self.insns.len(), target));
+        match src {
+            Operand::Reg(src) => self.emit(class | op as u8 | BPF_X, dst.0, src.0, 0, 0),
+            Operand::Imm(imm) => self.emit(class | op as u8 | BPF_K, dst.0, 0, 0, imm),
+        }
+    }
+
+    fn emit(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) -> &mut Self {
+        self.insns.push(Insn { code, dst, src, off, imm });
+        self
+    }
+}
+
+/*
+XDP program dropping IPv4 UDP datagrams to `port` unless their source address is a key of the
+`allowed_sources` hash map (u32 address in network byte order -> u8). Everything else passes.
+*/
+pub fn xdp_udp_port_guard(port: u16, max_sources: u32) -> Result<BpfObject> {
+    let mut asm = BpfAsm::new();
+    let allowed = asm.map("allowed_sources", BpfMapDef { type_: BPF_MAP_TYPE_HASH, key_size: 4, value_size: 1, max_entries: max_sources, map_flags: 0 });
+    let pass = asm.label();
+    let drop = asm.label();
+
+    asm.ldx(Size::W, R2, R1, 0)             // r2 = ctx->data
+        .ldx(Size::W, R3, R1, 4)            // r3 = ctx->data_end
+        .mov64(R4, R2)
+        .add64(R4, 14 + 20 + 8)             // ethernet + minimal ipv4 + udp
+        .jmp(JmpOp::Gt, R4, R3, pass)
+        .ldx(Size::H, R4, R2, 12)           // h_proto
+        .jmp(JmpOp::Ne, R4, (0x0800u16.to_be()) as i32, pass)
+        .ldx(Size::B, R4, R2, 14)           // version + ihl
+        .jmp(JmpOp::Ne, R4, 0x45, pass)     // ip options would move the udp header
+        .ldx(Size::B, R4, R2, 14 + 9)       // protocol
+        .jmp(JmpOp::Ne, R4, 17, pass)
+        .ldx(Size::H, R4, R2, 14 + 20 + 2)  // udp dest port
+        .jmp(JmpOp::Ne, R4, port.to_be() as i32, pass)
+        .ldx(Size::W, R4, R2, 14 + 12)      // source address
+        .stx(Size::W, R10, -4, R4)
+        .ld_map(R1, allowed)
+        .mov64(R2, R10)
+        .add64(R2, -4)
+        .call(BPF_FUNC_MAP_LOOKUP_ELEM)
+        .jmp(JmpOp::Eq, R0, 0, drop)
+        .bind(pass)
+        .ret(2)                             // XDP_PASS
+        .bind(drop)
+        .ret(1);                            // XDP_DROP
+    asm.build()
+}
@@ -0,0 +1,179 @@
+use std::{ffi::CString, io::{Error, ErrorKind, Result}, os::fd::RawFd};
+
+use crate::net::{bpf_link_create, bpf_link_detach, bpf_link_update, bpf_obj_get_info_by_fd, bpf_prog_get_fd_by_id, close, if_nametoindex, BpfLinkInfoXdp, BPF_XDP, ENOTSUPP, EOPNOTSUPP, XDP_FLAGS_DRV_MODE, XDP_FLAGS_HW_MODE, XDP_FLAGS_SKB_MODE};
+use super::{open_pinned_fd, XdpFd};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpMode {
+    Generic, // SKB mode, works on every interface
+    Native,  // driver mode
+    Offload, // NIC hardware offload
+}
+
+impl XdpMode {
+    pub fn flags(self) -> u32 {
+        match self {
+            XdpMode::Generic => XDP_FLAGS_SKB_MODE,
+            XdpMode::Native => XDP_FLAGS_DRV_MODE,
+            XdpMode::Offload => XDP_FLAGS_HW_MODE,
+        }
+    }
+
+    /* Next mode to try when the driver rejects this one. */
+    pub fn fallback(self) -> Option<XdpMode> {
+        match self {
+            XdpMode::Offload => Some(XdpMode::Native),
+            XdpMode::Native => Some(XdpMode::Generic),
+            XdpMode::Generic => None,
+        }
+    }
+}
+
+impl std::fmt::Display for XdpMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XdpMode::Generic => write!(f, "generic"),
+            XdpMode::Native => write!(f, "native"),
+            XdpMode::Offload => write!(f, "offload"),
+        }
+    }
+}
+
+/// An XDP program attached to an interface through a `bpf_link`.
+///
+/// The program stays attached for as long as the guard lives and is detached on drop,
+/// so a crashed or exited process never leaves a stale program on the interface.
+/// Pinned links are the exception: they stay attached until explicitly detached.
+#[derive(Debug)]
+pub struct XdpAttachment {
+    link_fd: RawFd,
+    ifindex: u32,
+    mode: Option<XdpMode>,
+    prog: XdpFd,
+    owns_prog: bool, // `prog` was opened by `open_pinned` and is closed with the attachment
+    pub(crate) pinned: bool,
+}
+
+impl XdpAttachment {
+    /* Attaches in `mode`, falling back to the next less demanding mode if the driver does not support it. */
+    pub fn attach(prog: XdpFd, ifindex: u32, mode: XdpMode) -> Result<Self> {
+        let mut mode = mode;
+        loop {
+            match Self::attach_exact(prog, ifindex, mode) {
+                Ok(attachment) => return Ok(attachment),
+                Err(e) if is_unsupported_mode(&e) => match mode.fallback() {
+                    Some(next) => mode = next,
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn attach_exact(prog: XdpFd, ifindex: u32, mode: XdpMode) -> Result<Self> {
+        let link_fd = unsafe { bpf_link_create(prog.as_raw_fd(), ifindex, BPF_XDP, mode.flags()) };
+        if link_fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self { link_fd, ifindex, mode: Some(mode), prog, owns_prog: false, pinned: false })
+    }
+
+    /*
+    Reopens a link pinned with `pin`; the kernel does not report the attach mode, so `mode()` is `None`. The
+    program fd it opens for `program()` is closed with the attachment.
+    */
+    pub fn open_pinned(path: &str) -> Result<Self> {
+        let link_fd = open_pinned_fd(path)?;
+        let mut info = BpfLinkInfoXdp::default();
+        let ret = unsafe { bpf_obj_get_info_by_fd(link_fd, &mut info as *mut _ as *mut u8, std::mem::size_of::<BpfLinkInfoXdp>() as u32) };
+        let prog_fd = if ret < 0 { ret } else { unsafe { bpf_prog_get_fd_by_id(info.prog_id) } };
+        if prog_fd < 0 {
+            let err = Error::last_os_error();
+            unsafe { close(link_fd) };
+            return Err(err);
+        }
+        Ok(Self { link_fd, ifindex: info.ifindex, mode: None, prog: XdpFd(prog_fd), owns_prog: true, pinned: true })
+    }
+
+    pub fn attach_by_name(prog: XdpFd, iface: &str, mode: XdpMode) -> Result<Self> {
+        Self::attach(prog, interface_index(iface)?, mode)
+    }
+
+    /* Atomically swaps the attached program; fails if someone else replaced it in the meantime. */
+    pub fn replace(&mut self, prog: XdpFd) -> Result<()> {
+        let ret = unsafe { bpf_link_update(self.link_fd, prog.as_raw_fd(), Some(self.prog.as_raw_fd())) };
+        if ret < 0 {
+            return Err(Error::last_os_error());
+        }
+        if std::mem::take(&mut self.owns_prog) {
+            unsafe { close(self.prog.as_raw_fd()) };
+        }
+        self.prog = prog;
+        Ok(())
+    }
+
+    pub fn detach(mut self) -> Result<()> {
+        self.release()
+    }
+
+    pub fn mode(&self) -> Option<XdpMode> {
+        self.mode
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    pub fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+
+    pub fn program(&self) -> XdpFd {
+        self.prog
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.link_fd
+    }
+
+    fn release(&mut self) -> Result<()> {
+        let detached = unsafe { bpf_link_detach(self.link_fd) };
+        let err = (detached < 0).then(Error::last_os_error);
+        unsafe { close(self.link_fd) };
+        self.link_fd = -1;
+        match err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for XdpAttachment {
+    fn drop(&mut self) {
+        if std::mem::take(&mut self.owns_prog) {
+            unsafe { close(self.prog.as_raw_fd()) };
+        }
+        if self.link_fd < 0 {
+            return;
+        }
+        if self.pinned {
+            unsafe { close(self.link_fd) };
+        } else {
+            let _ = self.release();
+        }
+    }
+}
+
+pub fn interface_index(iface: &str) -> Result<u32> {
+    let c_iface = CString::new(iface).map_err(|_| Error::new(ErrorKind::InvalidInput, "Interface name contains a NUL byte"))?;
+    let ifindex = unsafe { if_nametoindex(c_iface.as_ptr() as _) };
+    if ifindex == 0 {
+        return Err(Error::new(ErrorKind::NotFound, format!("Interface '{iface}' not found")));
+    }
+    Ok(ifindex)
+}
+
+/* Only "not supported" falls back; EINVAL means a bad program or interface, which no other mode fixes. */
+fn is_unsupported_mode(e: &Error) -> bool {
+    matches!(e.raw_os_error(), Some(EOPNOTSUPP) | Some(ENOTSUPP))
+}
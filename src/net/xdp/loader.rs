@@ -1,6 +1,7 @@
 use std::{collections::HashMap, ffi::CString, io::{Error, ErrorKind, Result}, mem::zeroed, os::fd::RawFd};
 
-use crate::net::{bpf, bpf_attr, BpfInsn, BpfMapDef, Elf, Elf64Rel, Elf64Sym, BPF_MAP_CREATE, BPF_PROG_LOAD, BPF_PROG_TYPE_XDP};
+use crate::net::{bpf, bpf_attr, close, BpfInsn, BpfMapDef, BpfProgType, BPF_MAP_CREATE, BPF_PROG_LOAD, BPF_PROG_TYPE_XDP, BPF_PSEUDO_MAP_FD};
+use super::{BpfMapFd, BpfObject};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct XdpFd(pub i32);
@@ -21,101 +22,73 @@ impl std::fmt::Display for XdpFd {
     }
 }
 
+pub struct XdpObject {
+    pub prog: XdpFd,
+    pub maps: HashMap<String, BpfMapFd>,
+}
+
+impl XdpObject {
+    pub fn map(&self, name: &str) -> Option<BpfMapFd> {
+        self.maps.get(name).copied()
+    }
+}
+
 pub fn load_xdp(elf_data: &[u8], maps_section_name: &str) -> Result<XdpFd> {
-    let elf = Elf::load_from_bytes(elf_data).expect("Failed to load XDP ELF object");
-    let strtab = elf.get_strtab_header()
-        .ok_or_else(|| Error::new(ErrorKind::NotFound, "String table section not found in ELF."))?;
-    let strtab_data = &elf.data[strtab.sh_offset as usize .. strtab.sh_offset as usize + strtab.sh_size as usize];
-
-    let xdp_section = elf.section_headers.iter()
-        .find(|header| {
-            header.sh_type == 1 && {
-                let name_offset = header.sh_name as usize;
-                name_offset < strtab_data.len() && {
-                    let section_name = &strtab_data[name_offset..];
-                    if let Some(end) = section_name.iter().position(|&c| c == 0) {
-                        section_name[..end].starts_with(b"xdp")
-                    } else { false }
+    load_xdp_object(elf_data, maps_section_name, None).map(|obj| obj.prog)
+}
+
+/* Loads an XDP object; with a `pin_dir`, every map is reused from `<pin_dir>/<map name>` if already pinned there, or pinned there after creation. */
+pub fn load_xdp_object(elf_data: &[u8], maps_section_name: &str, pin_dir: Option<&str>) -> Result<XdpObject> {
+    let object = BpfObject::parse(elf_data, maps_section_name)?;
+    load_bpf_object(&object, BPF_PROG_TYPE_XDP, pin_dir)
+}
+
+/* Loads a parsed or `BpfAsm`-built program as `prog_type`, creating (or reusing pinned) maps the same way as `load_xdp_object`. */
+pub fn load_bpf_object(object: &BpfObject, prog_type: BpfProgType, pin_dir: Option<&str>) -> Result<XdpObject> {
+    let mut code = checked_code(object)?;
+    let mut map_fds = Vec::with_capacity(object.maps.len());
+    let mut maps = HashMap::new();
+    for spec in object.maps.iter() {
+        let fd = match pin_dir {
+            Some(dir) => {
+                let path = format!("{}/{}", dir.trim_end_matches('/'), spec.name);
+                match BpfMapFd::open_pinned(&path) {
+                    Ok(map) => {
+                        if let Err(e) = check_pinned_layout(map, &spec.def, &path) {
+                            unsafe { close(map.as_raw_fd()) };
+                            return Err(e);
+                        }
+                        map.as_raw_fd()
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        let fd = unsafe { create_map_fd(&spec.def)? };
+                        if let Err(e) = BpfMapFd(fd).pin(&path) {
+                            unsafe { close(fd) };
+                            return Err(e);
+                        }
+                        fd
+                    }
+                    Err(e) => return Err(e),
                 }
             }
-        }).ok_or_else(|| Error::new(ErrorKind::NotFound, "XDP section not found in ELF."))?;
-
-    let xdp_data = &elf.data[xdp_section.sh_offset as usize .. xdp_section.sh_offset as usize + xdp_section.sh_size as usize];
-
-    let maps_section = elf.section_headers.iter().find(|header| {
-        header.sh_type == 1 && {
-            let name_offset = header.sh_name as usize;
-            name_offset < strtab_data.len() && {
-                let section_name = &strtab_data[name_offset..];
-                if let Some(end) = section_name.iter().position(|&c| c == 0) {
-                    &section_name[..end] == maps_section_name.as_bytes()
-                } else { false }
-            }
-        }
-    }).ok_or_else(|| Error::new(ErrorKind::NotFound, "No maps section found in ELF."))?;
-
-    let maps_data = &elf.data[maps_section.sh_offset as usize .. maps_section.sh_offset as usize + maps_section.sh_size as usize];
-    let map_cnt = maps_data.len() / std::mem::size_of::<BpfMapDef>();
-
-    // Build offset->fd map for correct relocation
-    let mut offset_to_fd = HashMap::new();
-    for i in 0..map_cnt {
-        let offset = i * std::mem::size_of::<BpfMapDef>();
-        let def: BpfMapDef = unsafe {
-            std::ptr::read_unaligned(
-                maps_data[offset..].as_ptr() as *const BpfMapDef
-            )
+            None => unsafe { create_map_fd(&spec.def)? },
         };
-        let fd = unsafe { create_map_fd(&def)? };
-        offset_to_fd.insert(offset, fd);
+        map_fds.push(fd);
+        maps.insert(spec.name.clone(), BpfMapFd(fd));
     }
-
-    let relocation_section = elf.section_headers.iter().find(|header| {
-        header.sh_type == 9 && {
-            let name_offset = header.sh_name as usize;
-            name_offset < strtab_data.len() && {
-                let section_name = &strtab_data[name_offset..];
-                if let Some(end) = section_name.iter().position(|&c| c == 0) {
-                    let name = &section_name[..end];
-                    name.starts_with(b".relxdp")
-                } else { false }
-            }
-        }
-    }).ok_or_else(|| Error::new(ErrorKind::NotFound, "No relocation entries found in ELF."))?;
-
-    let rel_data = &elf.data[relocation_section.sh_offset as usize .. relocation_section.sh_offset as usize + relocation_section.sh_size as usize];
-    let rel_cnt = rel_data.len() / std::mem::size_of::<Elf64Rel>();
-
-    let symtab_section = elf.section_headers.iter().find(|header| {
-        header.sh_type == 2
-    }).ok_or_else(|| Error::new(ErrorKind::NotFound, ".symtab not found in ELF."))?;
-    let symtab_data = &elf.data[symtab_section.sh_offset as usize .. symtab_section.sh_offset as usize + symtab_section.sh_size as usize];
-    let symtab_cnt = symtab_data.len() / std::mem::size_of::<Elf64Sym>();
-
-    let mut code = xdp_data.to_vec();
-    for i in 0..rel_cnt {
-        let rel: Elf64Rel = unsafe { std::ptr::read_unaligned(&rel_data[i * std::mem::size_of::<Elf64Rel>()..] as *const _ as *const Elf64Rel) };
-        let sym_idx = (rel.r_info >> 32) as usize;
-        if sym_idx >= symtab_cnt { continue; }
-        let sym: Elf64Sym = unsafe { std::ptr::read_unaligned(&symtab_data[sym_idx * std::mem::size_of::<Elf64Sym>()..] as *const _ as *const Elf64Sym) };
-        let map_offset = sym.st_value as usize;
-        let fd = match offset_to_fd.get(&map_offset) {
-            Some(fd) => *fd,
-            None => continue,
-        };
-        let insn_off = rel.r_offset as usize / std::mem::size_of::<BpfInsn>();
-        let code_ptr = code.as_mut_ptr() as *mut BpfInsn;
-        unsafe {
-            let insn = code_ptr.add(insn_off);
-            (*insn).imm = fd;
-            (*insn.add(1)).imm = 0;
-        }
+    for &(insn_index, map_index) in object.map_relocations.iter() {
+        /* both checked by checked_code */
+        let insn = &mut code[insn_index * INSN_SIZE..];
+        insn[1] = (insn[1] & 0x0f) | (BPF_PSEUDO_MAP_FD << 4);
+        insn[4..8].copy_from_slice(&map_fds[map_index].to_ne_bytes());
+        insn[12..16].copy_from_slice(&0i32.to_ne_bytes());
     }
 
     let mut log_buf = vec![0u8; 65536];
     let license = CString::new("GPL").unwrap();
     let prog_fd = unsafe {
         bpf_prog_load(
+            prog_type,
             code.as_ptr() as *const BpfInsn,
             (code.len() / std::mem::size_of::<BpfInsn>()) as u32,
             license.as_ptr(),
@@ -124,10 +97,47 @@ pub fn load_xdp(elf_data: &[u8], maps_section_name: &str) -> Result<XdpFd> {
         )
     };
     if prog_fd < 0 {
-        let msg = String::from_utf8_lossy(&log_buf);
+        let log_len = log_buf.iter().position(|&c| c == 0).unwrap_or(log_buf.len());
+        let msg = String::from_utf8_lossy(&log_buf[..log_len]);
         return Err(Error::new(ErrorKind::Other, format!("BPF verifier: {msg}")));
     }
-    Ok(XdpFd(prog_fd))
+    Ok(XdpObject { prog: XdpFd(prog_fd), maps })
+}
+
+const INSN_SIZE: usize = std::mem::size_of::<BpfInsn>();
+
+/*
+A copy of the program code, after checking that every map relocation names a map of the object and the
+first half of a complete 16-byte ld_imm64 instruction; the fields of `BpfObject` are public, so nothing else guarantees it.
+*/
+fn checked_code(object: &BpfObject) -> Result<Vec<u8>> {
+    if !object.code.len().is_multiple_of(INSN_SIZE) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("BPF code length {} is not a multiple of {}", object.code.len(), INSN_SIZE)));
+    }
+    let insn_count = object.code.len() / INSN_SIZE;
+    for &(insn_index, map_index) in object.map_relocations.iter() {
+        if insn_index.checked_add(2).is_none_or(|end| end > insn_count) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Map relocation at instruction {insn_index} is outside the program")));
+        }
+        if map_index >= object.maps.len() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Map relocation at instruction {insn_index} names map {map_index}, the object has {}", object.maps.len())));
+        }
+    }
+    Ok(object.code.clone())
+}
+
+/* A map pinned by an older build may have another layout; reusing it would hand the program the wrong map. */
+fn check_pinned_layout(map: BpfMapFd, def: &BpfMapDef, path: &str) -> Result<()> {
+    let info = map.info()?;
+    let pinned = (info.type_, info.key_size, info.value_size, info.max_entries);
+    let wanted = (def.type_, def.key_size, def.value_size, def.max_entries);
+    if pinned != wanted {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Pinned map {path} has (type, key size, value size, max entries) {pinned:?}, the object expects {wanted:?}"),
+        ));
+    }
+    Ok(())
 }
 
 
@@ -149,6 +159,7 @@ unsafe fn create_map_fd(def: &BpfMapDef) -> Result<RawFd> {
 }
 
 unsafe fn bpf_prog_load(
+    prog_type: BpfProgType,
     insns: *const BpfInsn,
     insn_cnt: u32,
     license: *const i8,
@@ -158,7 +169,7 @@ unsafe fn bpf_prog_load(
     let mut attr: bpf_attr = std::mem::zeroed();
     {
         let prog_load = &mut attr.prog_load;
-        prog_load.prog_type = BPF_PROG_TYPE_XDP;
+        prog_load.prog_type = prog_type;
         prog_load.insn_cnt = insn_cnt;
         prog_load.insns = insns as u64;
         prog_load.license = license as u64;
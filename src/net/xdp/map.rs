@@ -0,0 +1,49 @@
+use std::io::{Error, Result};
+
+use crate::net::{bpf_map_delete_elem, bpf_map_lookup_elem, bpf_map_update_elem, bpf_obj_get_info_by_fd, BpfMapInfo, ENOENT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfMapFd(pub i32);
+
+impl BpfMapFd {
+    pub fn new(fd: i32) -> Self {
+        BpfMapFd(fd)
+    }
+
+    pub fn as_raw_fd(&self) -> i32 {
+        self.0
+    }
+
+    /* `key` and `value` must match the key_size/value_size the map was created with. */
+    pub fn update(&self, key: &[u8], value: &[u8], flags: u64) -> Result<()> {
+        let ret = unsafe { bpf_map_update_elem(self.0, key.as_ptr(), value.as_ptr(), flags) };
+        if ret < 0 { Err(Error::last_os_error()) } else { Ok(()) }
+    }
+
+    pub fn lookup(&self, key: &[u8], value: &mut [u8]) -> Result<bool> {
+        let ret = unsafe { bpf_map_lookup_elem(self.0, key.as_ptr(), value.as_mut_ptr()) };
+        if ret == 0 { return Ok(true); }
+        let err = Error::last_os_error();
+        if err.raw_os_error() == Some(ENOENT) { Ok(false) } else { Err(err) }
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<bool> {
+        let ret = unsafe { bpf_map_delete_elem(self.0, key.as_ptr()) };
+        if ret == 0 { return Ok(true); }
+        let err = Error::last_os_error();
+        if err.raw_os_error() == Some(ENOENT) { Ok(false) } else { Err(err) }
+    }
+
+    /* Type, sizes and flags the kernel reports for the map. */
+    pub fn info(&self) -> Result<BpfMapInfo> {
+        let mut info = BpfMapInfo::default();
+        let ret = unsafe { bpf_obj_get_info_by_fd(self.0, &mut info as *mut _ as *mut u8, std::mem::size_of::<BpfMapInfo>() as u32) };
+        if ret < 0 { Err(Error::last_os_error()) } else { Ok(info) }
+    }
+}
+
+impl std::fmt::Display for BpfMapFd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BpfMapFd({})", self.0)
+    }
+}
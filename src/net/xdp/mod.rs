@@ -1,4 +1,22 @@
 #[cfg(unix)]
 pub mod loader;
 #[cfg(unix)]
-pub use loader::*;
\ No newline at end of file
+pub use loader::*;
+#[cfg(unix)]
+pub mod attach;
+#[cfg(unix)]
+pub use attach::*;
+#[cfg(unix)]
+pub mod map;
+#[cfg(unix)]
+pub use map::*;
+#[cfg(unix)]
+pub mod pin;
+#[cfg(unix)]
+pub use pin::*;
+pub mod object;
+pub use object::*;
+pub mod vm;
+pub use vm::*;
+pub mod asm;
+pub use asm::*;
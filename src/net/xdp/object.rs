@@ -0,0 +1,74 @@
+use std::{collections::HashMap, io::{Error, ErrorKind, Result}};
+
+use crate::net::{BpfInsn, BpfMapDef, Elf, ElfEndian, SHT_PROGBITS, SHT_REL};
+
+pub struct XdpMapSpec {
+    pub name: String,
+    pub def: BpfMapDef,
+}
+
+/* An eBPF program and the maps it references, parsed from a clang-built object or assembled with `BpfAsm`; runnable in the kernel or in `XdpVm`. */
+pub struct BpfObject {
+    pub code: Vec<u8>,
+    pub maps: Vec<XdpMapSpec>,
+    pub map_relocations: Vec<(usize, usize)>, // (instruction index, index into `maps`)
+}
+
+impl BpfObject {
+    pub fn parse(elf_data: &[u8], maps_section_name: &str) -> Result<Self> {
+        let elf = Elf::load_from_bytes(elf_data)?;
+        let xdp_section = elf.sections()
+            .find(|section| section.header.sh_type == SHT_PROGBITS && section.name.starts_with("xdp"))
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "XDP section not found in ELF."))?;
+        let maps_section = elf.sections()
+            .find(|section| section.header.sh_type == SHT_PROGBITS && section.name == maps_section_name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No maps section found in ELF."))?;
+        let symtab = elf.symbol_table()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, ".symtab not found in ELF."))?;
+
+        // Map definitions are named by the symbols that point into the maps section
+        let mut offset_to_name = HashMap::new();
+        for sym in elf.symbols(&symtab)? {
+            if sym.shndx as usize == maps_section.index && !sym.name.is_empty() {
+                offset_to_name.insert(sym.value as usize, sym.name.to_string());
+            }
+        }
+
+        let def_size = std::mem::size_of::<BpfMapDef>();
+        let mut offset_to_index = HashMap::new();
+        let mut maps = Vec::with_capacity(maps_section.data.len() / def_size);
+        for (i, raw) in maps_section.data.chunks_exact(def_size).enumerate() {
+            let field = |n: usize| {
+                let bytes: [u8; 4] = raw[n * 4..n * 4 + 4].try_into().unwrap();
+                if elf.endian == ElfEndian::Big { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+            };
+            let def = BpfMapDef { type_: field(0), key_size: field(1), value_size: field(2), max_entries: field(3), map_flags: field(4) };
+            let offset = i * def_size;
+            let name = offset_to_name.remove(&offset).unwrap_or_else(|| format!("map_{i}"));
+            offset_to_index.insert(offset, i);
+            maps.push(XdpMapSpec { name, def });
+        }
+
+        let relocation_section = elf.sections()
+            .find(|section| section.header.sh_type == SHT_REL && section.header.sh_info as usize == xdp_section.index)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No relocation entries found in ELF."))?;
+
+        let insn_cnt = xdp_section.data.len() / std::mem::size_of::<BpfInsn>();
+        let mut map_relocations = Vec::new();
+        for rel in elf.relocations(&relocation_section)? {
+            let Some(sym) = elf.symbol(&symtab, rel.symbol as usize) else { continue };
+            let map_index = match offset_to_index.get(&(sym.value as usize)) {
+                Some(index) if sym.shndx as usize == maps_section.index => *index,
+                _ => continue,
+            };
+            let insn_off = rel.offset as usize / std::mem::size_of::<BpfInsn>();
+            // map loads are 16-byte ld_imm64 instructions
+            if insn_off.checked_add(1).is_none_or(|next| next >= insn_cnt) {
+                return Err(Error::new(ErrorKind::InvalidData, "Relocation points outside of the XDP section."));
+            }
+            map_relocations.push((insn_off, map_index));
+        }
+
+        Ok(BpfObject { code: xdp_section.data.to_vec(), maps, map_relocations })
+    }
+}
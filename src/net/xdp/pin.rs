@@ -0,0 +1,61 @@
+use std::{ffi::CString, io::{Error, ErrorKind, Result}};
+
+use crate::net::{bpf_obj_get, bpf_obj_pin};
+use super::{BpfMapFd, XdpAttachment, XdpFd};
+
+pub const BPF_FS_ROOT: &str = "/sys/fs/bpf";
+
+/* Relative paths are resolved under the bpffs mount point. */
+pub fn bpffs_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{BPF_FS_ROOT}/{path}")
+    }
+}
+
+pub fn pin_fd(fd: i32, path: &str) -> Result<()> {
+    let c_path = CString::new(bpffs_path(path)).map_err(|_| Error::new(ErrorKind::InvalidInput, "Pin path contains a NUL byte"))?;
+    let ret = unsafe { bpf_obj_pin(fd, c_path.as_ptr()) };
+    if ret < 0 { Err(Error::last_os_error()) } else { Ok(()) }
+}
+
+pub fn open_pinned_fd(path: &str) -> Result<i32> {
+    let c_path = CString::new(bpffs_path(path)).map_err(|_| Error::new(ErrorKind::InvalidInput, "Pin path contains a NUL byte"))?;
+    let fd = unsafe { bpf_obj_get(c_path.as_ptr(), 0) };
+    if fd < 0 { Err(Error::last_os_error()) } else { Ok(fd) }
+}
+
+/* Removing the bpffs entry drops the reference it held; the object lives on while other fds or links use it. */
+pub fn unpin(path: &str) -> Result<()> {
+    std::fs::remove_file(bpffs_path(path))
+}
+
+impl XdpFd {
+    pub fn pin(&self, path: &str) -> Result<()> {
+        pin_fd(self.0, path)
+    }
+
+    pub fn open_pinned(path: &str) -> Result<Self> {
+        open_pinned_fd(path).map(XdpFd)
+    }
+}
+
+impl BpfMapFd {
+    pub fn pin(&self, path: &str) -> Result<()> {
+        pin_fd(self.0, path)
+    }
+
+    pub fn open_pinned(path: &str) -> Result<Self> {
+        open_pinned_fd(path).map(BpfMapFd)
+    }
+}
+
+impl XdpAttachment {
+    /* A pinned link keeps the program attached after this process exits, so dropping the guard no longer detaches it. */
+    pub fn pin(&mut self, path: &str) -> Result<()> {
+        pin_fd(self.as_raw_fd(), path)?;
+        self.pinned = true;
+        Ok(())
+    }
+}
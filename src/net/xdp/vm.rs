@@ -0,0 +1,684 @@
+use std::{collections::HashMap, io::{Error, ErrorKind, Result}};
+
+use rand::RngCore;
+
+use crate::net::{BpfMapDef, BPF_EXIST, BPF_MAP_TYPE_ARRAY, BPF_MAP_TYPE_PERCPU_ARRAY, BPF_NOEXIST, BPF_PSEUDO_MAP_FD};
+use super::{BpfObject, XdpMapSpec};
+
+/*
+User-space eBPF interpreter for XDP programs.
+
+Runs the same instructions the kernel would (decoded from the ELF object or built by hand) over a packet
+buffer against in-memory maps, so filters can be unit tested without root or a real interface.
+Pointers live in a virtual address space with one fixed base per region (stack, xdp_md context, packet,
+map values handed out by helpers); every load and store must fall entirely inside one of them.
+*/
+
+pub const XDP_PACKET_HEADROOM: usize = 256;
+
+const STACK_SIZE: usize = 512;
+const MAX_CALL_DEPTH: usize = 8;
+const MAX_EXECUTED_INSNS: usize = 1_000_000;
+/* Array maps are allocated up front; their size comes from the object file, so it is capped. */
+const MAX_ARRAY_MAP_BYTES: u64 = 64 * 1024 * 1024;
+const MAP_HANDLE_TAG: u64 = 0xffff_ff00_0000_0000;
+
+const STACK_BASE: u64 = 0x1000_0000;
+const CTX_BASE: u64 = 0x2000_0000;
+const XDP_MD_SIZE: u64 = 24;
+const PACKET_BASE: u64 = 0x3000_0000;
+const MAP_VALUE_BASE: u64 = 0x1_0000_0000;
+const MAP_VALUE_SPAN: u64 = 0x1_0000_0000;
+
+// Linux errno values returned to the program by helpers
+const ENOENT: i64 = 2;
+const E2BIG: i64 = 7;
+const EEXIST: i64 = 17;
+const EINVAL: i64 = 22;
+
+pub const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+pub const BPF_FUNC_MAP_UPDATE_ELEM: i32 = 2;
+pub const BPF_FUNC_MAP_DELETE_ELEM: i32 = 3;
+pub const BPF_FUNC_KTIME_GET_NS: i32 = 5;
+pub const BPF_FUNC_GET_PRANDOM_U32: i32 = 7;
+pub const BPF_FUNC_GET_SMP_PROCESSOR_ID: i32 = 8;
+pub const BPF_FUNC_REDIRECT: i32 = 23;
+pub const BPF_FUNC_XDP_ADJUST_HEAD: i32 = 44;
+pub const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpAction {
+    Aborted = 0,
+    Drop = 1,
+    Pass = 2,
+    Tx = 3,
+    Redirect = 4,
+}
+
+impl XdpAction {
+    pub fn from_u64(value: u64) -> Self {
+        match value {
+            1 => XdpAction::Drop,
+            2 => XdpAction::Pass,
+            3 => XdpAction::Tx,
+            4 => XdpAction::Redirect,
+            _ => XdpAction::Aborted, // the kernel warns and drops on unknown actions
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XdpRedirect {
+    Map { map: String, key: u32 },
+    Ifindex(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct XdpRunResult {
+    pub action: XdpAction,
+    pub packet: Vec<u8>, // packet as left by the program, after any xdp_adjust_head
+    pub redirect: Option<XdpRedirect>,
+    pub executed_insns: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XdpRxMeta {
+    pub ingress_ifindex: u32,
+    pub rx_queue_index: u32,
+}
+
+pub struct XdpVmMap {
+    name: String,
+    def: BpfMapDef,
+    entries: HashMap<Vec<u8>, Box<[u8]>>,
+}
+
+impl XdpVmMap {
+    pub fn new(name: &str, def: BpfMapDef) -> Result<Self> {
+        let mut entries = HashMap::new();
+        if is_array(&def) {
+            if def.max_entries as u64 * def.value_size as u64 > MAX_ARRAY_MAP_BYTES {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "Array map '{name}' of {} entries of {} bytes is larger than {MAX_ARRAY_MAP_BYTES} bytes", def.max_entries, def.value_size
+                )));
+            }
+            for i in 0..def.max_entries {
+                entries.insert(i.to_le_bytes().to_vec(), vec![0u8; def.value_size as usize].into_boxed_slice());
+            }
+        }
+        Ok(Self { name: name.to_string(), def, entries })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn def(&self) -> &BpfMapDef {
+        &self.def
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lookup(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(|v| &v[..])
+    }
+
+    pub fn update(&mut self, key: &[u8], value: &[u8], flags: u64) -> Result<()> {
+        match self.update_raw(key, value, flags) {
+            0 => Ok(()),
+            errno => Err(errno_to_error(errno)),
+        }
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<bool> {
+        match self.delete_raw(key) {
+            0 => Ok(true),
+            errno if errno == -ENOENT => Ok(false),
+            errno => Err(errno_to_error(errno)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.entries.iter().map(|(k, v)| (&k[..], &v[..]))
+    }
+
+    /* Returns 0 or a negative errno, like the in-kernel helper. Existing values are overwritten in place so pointers handed to the program stay valid. */
+    fn update_raw(&mut self, key: &[u8], value: &[u8], flags: u64) -> i64 {
+        if key.len() != self.def.key_size as usize || value.len() != self.def.value_size as usize || flags > BPF_EXIST {
+            return -EINVAL;
+        }
+        if let Some(existing) = self.entries.get_mut(key) {
+            if flags == BPF_NOEXIST { return -EEXIST; }
+            existing.copy_from_slice(value);
+            return 0;
+        }
+        if is_array(&self.def) { return -E2BIG; }
+        if flags == BPF_EXIST { return -ENOENT; }
+        if self.entries.len() >= self.def.max_entries as usize { return -E2BIG; }
+        self.entries.insert(key.to_vec(), value.to_vec().into_boxed_slice());
+        0
+    }
+
+    fn delete_raw(&mut self, key: &[u8]) -> i64 {
+        if key.len() != self.def.key_size as usize || is_array(&self.def) {
+            return -EINVAL;
+        }
+        if self.entries.remove(key).is_some() { 0 } else { -ENOENT }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    dst: usize,
+    src: usize,
+    off: i16,
+    imm: i32,
+}
+
+pub struct XdpVm {
+    insns: Vec<Insn>,
+    maps: Vec<XdpVmMap>,
+    clock: Box<dyn FnMut() -> u64 + Send>,
+}
+
+impl XdpVm {
+    /* `code` is raw eBPF; ld_imm64 map references (src_reg = BPF_PSEUDO_MAP_FD) use the index into `maps` as their immediate. */
+    pub fn new(code: &[u8], maps: Vec<XdpMapSpec>) -> Result<Self> {
+        if !code.len().is_multiple_of(8) {
+            return Err(Error::new(ErrorKind::InvalidData, "eBPF code length is not a multiple of 8 bytes"));
+        }
+        let insns: Vec<Insn> = code.chunks_exact(8).map(|raw| Insn {
+            code: raw[0],
+            dst: (raw[1] & 0x0f) as usize,
+            src: (raw[1] >> 4) as usize,
+            off: i16::from_le_bytes([raw[2], raw[3]]),
+            imm: i32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]),
+        }).collect();
+        for (pc, insn) in insns.iter().enumerate() {
+            check_registers(pc, insn)?;
+        }
+        let maps = maps.iter().map(|spec| XdpVmMap::new(&spec.name, spec.def)).collect::<Result<_>>()?;
+        let start = std::time::Instant::now();
+        Ok(Self {
+            insns,
+            maps,
+            clock: Box::new(move || start.elapsed().as_nanos() as u64),
+        })
+    }
+
+    pub fn from_elf(elf_data: &[u8], maps_section_name: &str) -> Result<Self> {
+        Self::from_object(BpfObject::parse(elf_data, maps_section_name)?)
+    }
+
+    pub fn from_object(object: BpfObject) -> Result<Self> {
+        let mut vm = Self::new(&object.code, object.maps)?;
+        for &(insn_off, map_index) in object.map_relocations.iter() {
+            let insn = vm.insns.get_mut(insn_off)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Map relocation points outside of the program"))?;
+            insn.src = BPF_PSEUDO_MAP_FD as usize;
+            insn.imm = map_index as i32;
+        }
+        Ok(vm)
+    }
+
+    pub fn map(&self, name: &str) -> Option<&XdpVmMap> {
+        self.maps.iter().find(|m| m.name == name)
+    }
+
+    pub fn map_mut(&mut self, name: &str) -> Option<&mut XdpVmMap> {
+        self.maps.iter_mut().find(|m| m.name == name)
+    }
+
+    pub fn maps(&self) -> &[XdpVmMap] {
+        &self.maps
+    }
+
+    /* Replaces the source of bpf_ktime_get_ns, e.g. with a simulated clock. */
+    pub fn set_clock<F>(&mut self, clock: F)
+    where
+        F: FnMut() -> u64 + Send + 'static,
+    {
+        self.clock = Box::new(clock);
+    }
+
+    pub fn run(&mut self, packet: &[u8]) -> Result<XdpRunResult> {
+        self.run_with(packet, XdpRxMeta::default())
+    }
+
+    pub fn run_with(&mut self, packet: &[u8], meta: XdpRxMeta) -> Result<XdpRunResult> {
+        let mut run = Run::new(packet, meta);
+        let ret = run.execute(self)?;
+        let action = XdpAction::from_u64(ret);
+        let redirect = if action == XdpAction::Redirect { run.redirect.take() } else { None };
+        Ok(XdpRunResult {
+            action,
+            packet: run.buf[run.data..run.data_end].to_vec(),
+            redirect,
+            executed_insns: run.executed,
+        })
+    }
+}
+
+struct Frame {
+    return_pc: usize,
+    saved: [u64; 4], // r6-r9
+}
+
+struct Run {
+    regs: [u64; 11],
+    stack: Vec<u8>,
+    buf: Vec<u8>,
+    data: usize,
+    data_end: usize,
+    meta: XdpRxMeta,
+    map_values: Vec<(usize, Vec<u8>)>, // (map index, key) of every value pointer handed out, indexed by slot
+    frames: Vec<Frame>,
+    redirect: Option<XdpRedirect>,
+    executed: usize,
+}
+
+impl Run {
+    fn new(packet: &[u8], meta: XdpRxMeta) -> Self {
+        let mut buf = vec![0u8; XDP_PACKET_HEADROOM + packet.len()];
+        buf[XDP_PACKET_HEADROOM..].copy_from_slice(packet);
+        Self {
+            regs: [0; 11],
+            stack: vec![0u8; STACK_SIZE * MAX_CALL_DEPTH],
+            buf,
+            data: XDP_PACKET_HEADROOM,
+            data_end: XDP_PACKET_HEADROOM + packet.len(),
+            meta,
+            map_values: Vec::new(),
+            frames: Vec::new(),
+            redirect: None,
+            executed: 0,
+        }
+    }
+
+    fn frame_top(&self) -> u64 {
+        STACK_BASE + (STACK_SIZE * (self.frames.len() + 1)) as u64
+    }
+
+    fn execute(&mut self, vm: &mut XdpVm) -> Result<u64> {
+        self.regs[1] = CTX_BASE;
+        self.regs[10] = self.frame_top();
+        let mut pc = 0usize;
+        loop {
+            let Some(&insn) = vm.insns.get(pc) else {
+                return Err(fault(pc, "fell off the end of the program"));
+            };
+            self.executed += 1;
+            if self.executed > MAX_EXECUTED_INSNS {
+                return Err(fault(pc, "instruction limit exceeded"));
+            }
+            pc += 1;
+            match insn.code & 0x07 {
+                0x00 => { /* LD */
+                    if insn.code != 0x18 {
+                        return Err(fault(pc - 1, "legacy packet loads are not available to XDP programs"));
+                    }
+                    let Some(next) = vm.insns.get(pc) else { return Err(fault(pc - 1, "truncated ld_imm64")); };
+                    pc += 1;
+                    self.regs[insn.dst] = match insn.src {
+                        0 => (insn.imm as u32 as u64) | ((next.imm as u32 as u64) << 32),
+                        1 => {
+                            let index = insn.imm as usize;
+                            if index >= vm.maps.len() { return Err(fault(pc - 2, "reference to an unknown map")); }
+                            MAP_HANDLE_TAG | index as u64
+                        }
+                        _ => return Err(fault(pc - 2, "unsupported ld_imm64 pseudo source")),
+                    };
+                }
+                0x01 => { /* LDX */
+                    let size = mem_size(insn.code);
+                    let addr = self.regs[insn.src].wrapping_add(insn.off as i64 as u64);
+                    let value = self.load(vm, addr, size).ok_or_else(|| fault(pc - 1, "out-of-bounds load"))?;
+                    self.regs[insn.dst] = if insn.code & 0xe0 == 0x80 { sign_extend(value, size) } else { value };
+                }
+                0x02 | 0x03 => { /* ST, STX */
+                    let size = mem_size(insn.code);
+                    let addr = self.regs[insn.dst].wrapping_add(insn.off as i64 as u64);
+                    if insn.code & 0xe0 == 0xc0 {
+                        self.atomic(vm, addr, size, insn).ok_or_else(|| fault(pc - 1, "invalid atomic operation"))?;
+                    } else {
+                        let value = if insn.code & 0x07 == 0x02 { insn.imm as i64 as u64 } else { self.regs[insn.src] };
+                        self.store(vm, addr, size, value).ok_or_else(|| fault(pc - 1, "out-of-bounds store"))?;
+                    }
+                }
+                0x04 | 0x07 => { /* ALU, ALU64 */
+                    let is64 = insn.code & 0x07 == 0x07;
+                    let src = if insn.code & 0x08 != 0 { self.regs[insn.src] } else { insn.imm as i64 as u64 };
+                    let dst = self.regs[insn.dst];
+                    self.regs[insn.dst] = alu(insn, dst, src, is64).ok_or_else(|| fault(pc - 1, "invalid ALU instruction"))?;
+                }
+                _ => { /* JMP, JMP32 */
+                    let is32 = insn.code & 0x07 == 0x06;
+                    match insn.code & 0xf0 {
+                        0x00 => {
+                            let delta = if is32 { insn.imm as i64 } else { insn.off as i64 };
+                            pc = jump(pc, delta).ok_or_else(|| fault(pc - 1, "jump out of range"))?;
+                        }
+                        0x80 => {
+                            if insn.src == 1 {
+                                if self.frames.len() + 1 >= MAX_CALL_DEPTH { return Err(fault(pc - 1, "call depth exceeded")); }
+                                self.frames.push(Frame { return_pc: pc, saved: [self.regs[6], self.regs[7], self.regs[8], self.regs[9]] });
+                                self.regs[10] = self.frame_top();
+                                pc = jump(pc, insn.imm as i64).ok_or_else(|| fault(pc - 1, "call out of range"))?;
+                            } else {
+                                self.regs[0] = self.call_helper(vm, insn.imm).ok_or_else(|| fault(pc - 1, "invalid helper call"))?;
+                            }
+                        }
+                        0x90 => {
+                            match self.frames.pop() {
+                                Some(frame) => {
+                                    self.regs[6..10].copy_from_slice(&frame.saved);
+                                    self.regs[10] = self.frame_top();
+                                    pc = frame.return_pc;
+                                }
+                                None => return Ok(self.regs[0]),
+                            }
+                        }
+                        op => {
+                            let src = if insn.code & 0x08 != 0 { self.regs[insn.src] } else { insn.imm as i64 as u64 };
+                            let dst = self.regs[insn.dst];
+                            let taken = condition(op, dst, src, is32).ok_or_else(|| fault(pc - 1, "invalid jump instruction"))?;
+                            if taken {
+                                pc = jump(pc, insn.off as i64).ok_or_else(|| fault(pc - 1, "jump out of range"))?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /* Resolves a virtual address to the backing bytes; the whole access must fit in one region. */
+    fn memory<'a>(&'a mut self, vm: &'a mut XdpVm, addr: u64, size: usize) -> Option<&'a mut [u8]> {
+        let within = |base: u64, start: usize, end: usize| -> Option<std::ops::Range<usize>> {
+            let off = usize::try_from(addr.checked_sub(base)?).ok()?;
+            (off >= start && off.checked_add(size)? <= end).then(|| off..off + size)
+        };
+        if addr >= MAP_VALUE_BASE {
+            let slot = ((addr - MAP_VALUE_BASE) / MAP_VALUE_SPAN) as usize;
+            let (map, key) = self.map_values.get(slot)?;
+            let value = vm.maps[*map].entries.get_mut(key)?; // deleted entries fault
+            let range = within(MAP_VALUE_BASE + slot as u64 * MAP_VALUE_SPAN, 0, value.len())?;
+            return Some(&mut value[range]);
+        }
+        if let Some(range) = within(PACKET_BASE, self.data, self.data_end) {
+            return Some(&mut self.buf[range]);
+        }
+        let range = within(STACK_BASE, 0, self.stack.len())?;
+        Some(&mut self.stack[range])
+    }
+
+    fn load(&mut self, vm: &mut XdpVm, addr: u64, size: usize) -> Option<u64> {
+        if (CTX_BASE..CTX_BASE + XDP_MD_SIZE).contains(&addr) {
+            // xdp_md fields hold 32-bit offsets in UAPI, but the kernel rewrites data/data_end/data_meta loads into pointers
+            if size != 4 { return None; }
+            return match addr - CTX_BASE {
+                0 | 8 => Some(PACKET_BASE + self.data as u64),
+                4 => Some(PACKET_BASE + self.data_end as u64),
+                12 => Some(self.meta.ingress_ifindex as u64),
+                16 => Some(self.meta.rx_queue_index as u64),
+                20 => Some(0),
+                _ => None,
+            };
+        }
+        let bytes = self.memory(vm, addr, size)?;
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(bytes);
+        Some(u64::from_le_bytes(raw))
+    }
+
+    fn store(&mut self, vm: &mut XdpVm, addr: u64, size: usize, value: u64) -> Option<()> {
+        let bytes = self.memory(vm, addr, size)?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..size]);
+        Some(())
+    }
+
+    fn atomic(&mut self, vm: &mut XdpVm, addr: u64, size: usize, insn: Insn) -> Option<()> {
+        if size != 4 && size != 8 { return None; }
+        let old = self.load(vm, addr, size)?;
+        let src = self.regs[insn.src];
+        let mask = if size == 4 { u32::MAX as u64 } else { u64::MAX };
+        let new = match insn.imm & !0x01 {
+            0x00 => old.wrapping_add(src),
+            0x40 => old | src,
+            0x50 => old & src,
+            0xa0 => old ^ src,
+            0xe0 => src, // xchg
+            0xf0 => if old == self.regs[0] & mask { src } else { old }, // cmpxchg
+            _ => return None,
+        };
+        self.store(vm, addr, size, new)?;
+        match insn.imm {
+            0xf1 => self.regs[0] = old,
+            imm if imm & 0x01 != 0 => self.regs[insn.src] = old,
+            _ => {}
+        }
+        Some(())
+    }
+
+    fn read_bytes(&mut self, vm: &mut XdpVm, addr: u64, len: usize) -> Option<Vec<u8>> {
+        self.memory(vm, addr, len).map(|bytes| bytes.to_vec())
+    }
+
+    fn map_index(&self, vm: &XdpVm, handle: u64) -> Option<usize> {
+        let index = (handle & !MAP_HANDLE_TAG) as usize;
+        (handle & MAP_HANDLE_TAG == MAP_HANDLE_TAG && index < vm.maps.len()).then_some(index)
+    }
+
+    fn call_helper(&mut self, vm: &mut XdpVm, id: i32) -> Option<u64> {
+        let [_, r1, r2, r3, r4, ..] = self.regs;
+        match id {
+            BPF_FUNC_MAP_LOOKUP_ELEM => {
+                let map = self.map_index(vm, r1)?;
+                let key = self.read_bytes(vm, r2, vm.maps[map].def.key_size as usize)?;
+                if !vm.maps[map].entries.contains_key(&key) {
+                    return Some(0);
+                }
+                let slot = match self.map_values.iter().position(|(m, k)| *m == map && *k == key) {
+                    Some(slot) => slot,
+                    None => {
+                        self.map_values.push((map, key));
+                        self.map_values.len() - 1
+                    }
+                };
+                Some(MAP_VALUE_BASE + slot as u64 * MAP_VALUE_SPAN)
+            }
+            BPF_FUNC_MAP_UPDATE_ELEM => {
+                let map = self.map_index(vm, r1)?;
+                let key = self.read_bytes(vm, r2, vm.maps[map].def.key_size as usize)?;
+                let value = self.read_bytes(vm, r3, vm.maps[map].def.value_size as usize)?;
+                Some(vm.maps[map].update_raw(&key, &value, r4) as u64)
+            }
+            BPF_FUNC_MAP_DELETE_ELEM => {
+                let map = self.map_index(vm, r1)?;
+                let key = self.read_bytes(vm, r2, vm.maps[map].def.key_size as usize)?;
+                Some(vm.maps[map].delete_raw(&key) as u64)
+            }
+            BPF_FUNC_KTIME_GET_NS => Some((vm.clock)()),
+            BPF_FUNC_GET_PRANDOM_U32 => Some(rand::rng().next_u32() as u64),
+            BPF_FUNC_GET_SMP_PROCESSOR_ID => Some(0),
+            BPF_FUNC_REDIRECT => {
+                self.redirect = Some(XdpRedirect::Ifindex(r1 as u32));
+                Some(XdpAction::Redirect as u64)
+            }
+            BPF_FUNC_XDP_ADJUST_HEAD => {
+                if r1 != CTX_BASE { return None; }
+                let data = self.data as i64 + r2 as i32 as i64;
+                // the kernel keeps at least an Ethernet header between data and data_end
+                if data < 0 || data > self.data_end as i64 - 14 {
+                    return Some(-EINVAL as u64);
+                }
+                self.data = data as usize;
+                Some(0)
+            }
+            BPF_FUNC_REDIRECT_MAP => {
+                let map = self.map_index(vm, r1)?;
+                let key = r2 as u32;
+                if vm.maps[map].entries.contains_key(&key.to_le_bytes()[..]) {
+                    self.redirect = Some(XdpRedirect::Map { map: vm.maps[map].name.clone(), key });
+                    Some(XdpAction::Redirect as u64)
+                } else {
+                    Some(r3 & 0x3) // lower bits of flags are the fallback action
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_array(def: &BpfMapDef) -> bool {
+    def.type_ == BPF_MAP_TYPE_ARRAY || def.type_ == BPF_MAP_TYPE_PERCPU_ARRAY
+}
+
+fn errno_to_error(errno: i64) -> Error {
+    match -errno {
+        ENOENT => Error::new(ErrorKind::NotFound, "No such map entry"),
+        EEXIST => Error::new(ErrorKind::AlreadyExists, "Map entry already exists"),
+        E2BIG => Error::new(ErrorKind::OutOfMemory, "Map is full"),
+        _ => Error::new(ErrorKind::InvalidInput, "Invalid map key, value or flags"),
+    }
+}
+
+/* Like the kernel verifier: only r0-r10 exist, and r10, the frame pointer, is read-only. */
+fn check_registers(pc: usize, insn: &Insn) -> Result<()> {
+    if insn.dst > 10 || insn.src > 10 {
+        return Err(Error::new(ErrorKind::InvalidData, format!("eBPF instruction {pc} uses register r{}", insn.dst.max(insn.src))));
+    }
+    let writes_dst = matches!(insn.code & 0x07, 0x00 | 0x01 | 0x04 | 0x07); // LD, LDX, ALU, ALU64
+    let fetches_into_src = insn.code & 0xe0 == 0xc0 && insn.imm & 0x01 != 0 && insn.imm != 0xf1; // atomics with BPF_FETCH but cmpxchg
+    if (writes_dst && insn.dst == 10) || (fetches_into_src && insn.src == 10) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("eBPF instruction {pc} writes the read-only frame pointer r10")));
+    }
+    Ok(())
+}
+
+fn fault(pc: usize, what: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("eBPF fault at instruction {pc}: {what}"))
+}
+
+fn jump(pc: usize, delta: i64) -> Option<usize> {
+    usize::try_from(pc as i64 + delta).ok()
+}
+
+fn mem_size(code: u8) -> usize {
+    match code & 0x18 {
+        0x00 => 4,
+        0x08 => 2,
+        0x10 => 1,
+        _ => 8,
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> u64 {
+    match size {
+        1 => value as u8 as i8 as i64 as u64,
+        2 => value as u16 as i16 as i64 as u64,
+        4 => value as u32 as i32 as i64 as u64,
+        _ => value,
+    }
+}
+
+fn alu(insn: Insn, dst: u64, src: u64, is64: bool) -> Option<u64> {
+    let signed = insn.off == 1; // sdiv/smod
+    if is64 {
+        Some(match insn.code & 0xf0 {
+            0x00 => dst.wrapping_add(src),
+            0x10 => dst.wrapping_sub(src),
+            0x20 => dst.wrapping_mul(src),
+            0x30 if signed => if src == 0 { 0 } else { (dst as i64).wrapping_div(src as i64) as u64 },
+            0x30 => dst.checked_div(src).unwrap_or(0),
+            0x40 => dst | src,
+            0x50 => dst & src,
+            0x60 => dst << (src & 63),
+            0x70 => dst >> (src & 63),
+            0x80 => (dst as i64).wrapping_neg() as u64,
+            0x90 if signed => if src == 0 { dst } else { (dst as i64).wrapping_rem(src as i64) as u64 },
+            0x90 => if src == 0 { dst } else { dst % src },
+            0xa0 => dst ^ src,
+            0xb0 => match insn.off {
+                0 => src,
+                8 | 16 | 32 => sign_extend(src, insn.off as usize / 8),
+                _ => return None,
+            },
+            0xc0 => ((dst as i64) >> (src & 63)) as u64,
+            0xd0 => match insn.imm { // unconditional byte swap
+                16 => (dst as u16).swap_bytes() as u64,
+                32 => (dst as u32).swap_bytes() as u64,
+                64 => dst.swap_bytes(),
+                _ => return None,
+            },
+            _ => return None,
+        })
+    } else {
+        let (d, s) = (dst as u32, src as u32);
+        let result = match insn.code & 0xf0 {
+            0x00 => d.wrapping_add(s),
+            0x10 => d.wrapping_sub(s),
+            0x20 => d.wrapping_mul(s),
+            0x30 if signed => if s == 0 { 0 } else { (d as i32).wrapping_div(s as i32) as u32 },
+            0x30 => d.checked_div(s).unwrap_or(0),
+            0x40 => d | s,
+            0x50 => d & s,
+            0x60 => d << (s & 31),
+            0x70 => d >> (s & 31),
+            0x80 => (d as i32).wrapping_neg() as u32,
+            0x90 if signed => if s == 0 { d } else { (d as i32).wrapping_rem(s as i32) as u32 },
+            0x90 => if s == 0 { d } else { d % s },
+            0xa0 => d ^ s,
+            0xb0 => match insn.off {
+                0 => s,
+                8 | 16 => sign_extend(s as u64, insn.off as usize / 8) as u32,
+                _ => return None,
+            },
+            0xc0 => ((d as i32) >> (s & 31)) as u32,
+            0xd0 => {
+                // BPF_TO_LE (source bit clear) only truncates on little-endian hosts, BPF_TO_BE swaps
+                let to_be = insn.code & 0x08 != 0;
+                return match (insn.imm, to_be) {
+                    (16, false) => Some(dst as u16 as u64),
+                    (32, false) => Some(dst as u32 as u64),
+                    (64, false) => Some(dst),
+                    (16, true) => Some((dst as u16).to_be() as u64),
+                    (32, true) => Some((dst as u32).to_be() as u64),
+                    (64, true) => Some(dst.to_be()),
+                    _ => None,
+                };
+            }
+            _ => return None,
+        };
+        Some(result as u64)
+    }
+}
+
+fn condition(op: u8, dst: u64, src: u64, is32: bool) -> Option<bool> {
+    let (dst, src, sdst, ssrc) = if is32 {
+        (dst as u32 as u64, src as u32 as u64, dst as i32 as i64, src as i32 as i64)
+    } else {
+        (dst, src, dst as i64, src as i64)
+    };
+    Some(match op {
+        0x10 => dst == src,
+        0x20 => dst > src,
+        0x30 => dst >= src,
+        0x40 => dst & src != 0,
+        0x50 => dst != src,
+        0x60 => sdst > ssrc,
+        0x70 => sdst >= ssrc,
+        0xa0 => dst < src,
+        0xb0 => dst <= src,
+        0xc0 => sdst < ssrc,
+        0xd0 => sdst <= ssrc,
+        _ => return None,
+    })
+}
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use voidio::net::{Elf, ElfClass, ElfEndian, ElfFile, SymbolBinding, SymbolType, PT_NOTE, SHT_NOTE, SHT_REL, SHT_RELA};
+
+    static XDP_OBJ: &[u8] = include_bytes!("../src/net/udp/xdp/build/xdp.o");
+
+    /* Big-endian ELF32 with a symbol, a RELA entry, a note and a PT_NOTE segment. */
+    fn elf32_be() -> Vec<u8> {
+        fn be16(v: &mut Vec<u8>, x: u16) { v.extend_from_slice(&x.to_be_bytes()) }
+        fn be32(v: &mut Vec<u8>, x: u32) { v.extend_from_slice(&x.to_be_bytes()) }
+
+        let shstrtab = b"\0.shstrtab\0.strtab\0.symtab\0.rela.text\0.note\0.text\0";
+        let strtab = b"\0main\0";
+        let mut symtab = vec![0u8; 16];
+        be32(&mut symtab, 1); be32(&mut symtab, 0x10); be32(&mut symtab, 4);
+        symtab.extend_from_slice(&[0x12, 0]); be16(&mut symtab, 6); // GLOBAL FUNC in .text
+        let mut rela = Vec::new();
+        be32(&mut rela, 4); be32(&mut rela, (1 << 8) | 2); be32(&mut rela, (-8i32) as u32);
+        let mut note = Vec::new();
+        be32(&mut note, 4); be32(&mut note, 4); be32(&mut note, 3);
+        note.extend_from_slice(b"GNU\0"); note.extend_from_slice(&[1, 2, 3, 4]);
+        let text = [0u8; 8];
+
+        let mut body = Vec::new();
+        let mut place = |data: &[u8]| { let off = 52 + 32 + body.len(); body.extend_from_slice(data); (off as u32, data.len() as u32) };
+        let sections = [
+            (0, 0, (0, 0), 0, 0, 0),
+            (1, 3, place(shstrtab), 0, 0, 0),
+            (11, 3, place(strtab), 0, 0, 0),
+            (19, 2, place(&symtab), 2, 1, 16),
+            (27, 4, place(&rela), 3, 6, 12),
+            (38, 7, place(&note), 0, 0, 0),
+            (44, 1, place(&text), 0, 0, 0),
+        ];
+        let note_at = sections[5].2;
+        let shoff = 52 + 32 + body.len() as u32;
+
+        let mut elf = b"\x7fELF\x01\x02\x01\0\0\0\0\0\0\0\0\0".to_vec();
+        be16(&mut elf, 1); be16(&mut elf, 0xf7); be32(&mut elf, 1);
+        be32(&mut elf, 0); be32(&mut elf, 52); be32(&mut elf, shoff); be32(&mut elf, 0);
+        be16(&mut elf, 52); be16(&mut elf, 32); be16(&mut elf, 1); be16(&mut elf, 40); be16(&mut elf, sections.len() as u16); be16(&mut elf, 1);
+        be32(&mut elf, PT_NOTE); be32(&mut elf, note_at.0); be32(&mut elf, 0); be32(&mut elf, 0);
+        be32(&mut elf, note_at.1); be32(&mut elf, note_at.1); be32(&mut elf, 4); be32(&mut elf, 4);
+        elf.extend_from_slice(&body);
+        for (name, kind, (off, size), link, info, entsize) in sections {
+            for field in [name, kind, 0, 0, off, size, link, info, 4, entsize] {
+                be32(&mut elf, field);
+            }
+        }
+        elf
+    }
+
+    #[test]
+    fn elf_reads_bpf_object() {
+        let elf = Elf::load_from_bytes(XDP_OBJ).unwrap();
+        assert_eq!(elf.class, ElfClass::Elf64);
+        assert_eq!(elf.endian, ElfEndian::Little);
+        let names: Vec<&str> = elf.sections().map(|s| s.name).collect();
+        assert!(names.contains(&"xdp_sock") && names.contains(&".maps") && names.contains(&"license"));
+
+        let symtab = elf.symbol_table().unwrap();
+        let map = elf.symbols(&symtab).unwrap().find(|s| s.name == "port_filter").unwrap();
+        assert_eq!(map.binding, SymbolBinding::Global);
+        assert_eq!(map.kind, SymbolType::Object);
+        assert_eq!(map.shndx as usize, elf.section_by_name(".maps").unwrap().index);
+
+        let rel = elf.sections().find(|s| s.header.sh_type == SHT_REL).unwrap();
+        let targets: Vec<&str> = elf.relocations(&rel).unwrap()
+            .map(|r| elf.symbol(&symtab, r.symbol as usize).unwrap().name)
+            .collect();
+        assert!(targets.contains(&"port_filter") && targets.contains(&"xsks_map"));
+        assert_eq!(elf.get_section_data_by_name("license").unwrap(), b"GPL\0");
+    }
+
+    #[test]
+    fn elf_reads_big_endian_elf32() {
+        let data = elf32_be();
+        let elf = Elf::load_from_bytes(&data).unwrap();
+        assert_eq!(elf.class, ElfClass::Elf32);
+        assert_eq!(elf.endian, ElfEndian::Big);
+        assert_eq!(elf.header.e_machine, 0xf7);
+
+        let symtab = elf.symbol_table().unwrap();
+        let main = elf.symbol(&symtab, 1).unwrap();
+        assert_eq!((main.name, main.value, main.size), ("main", 0x10, 4));
+        assert_eq!((main.binding, main.kind), (SymbolBinding::Global, SymbolType::Func));
+
+        let rela = elf.sections().find(|s| s.header.sh_type == SHT_RELA).unwrap();
+        assert_eq!(rela.name, ".rela.text");
+        let relocs: Vec<_> = elf.relocations(&rela).unwrap().collect();
+        assert_eq!(relocs.len(), 1);
+        assert_eq!((relocs[0].offset, relocs[0].symbol, relocs[0].kind, relocs[0].addend), (4, 1, 2, Some(-8)));
+
+        let note_section = elf.sections().find(|s| s.header.sh_type == SHT_NOTE).unwrap();
+        let notes: Vec<_> = elf.notes(note_section.data, note_section.header.sh_addralign).collect();
+        assert_eq!(notes.len(), 1);
+        assert_eq!((notes[0].name, notes[0].kind, notes[0].desc), (&b"GNU"[..], 3, &[1u8, 2, 3, 4][..]));
+
+        assert_eq!(elf.program_headers.len(), 1);
+        let segment = elf.program_data(&elf.program_headers[0]).unwrap();
+        assert_eq!(elf.notes(segment, 4).count(), 1);
+    }
+
+    #[test]
+    fn elf_rejects_malformed_input() {
+        assert!(Elf::load_from_bytes(b"").is_err());
+        assert!(Elf::load_from_bytes(b"\x7fELF\x03\x01").is_err());
+        for len in 0..XDP_OBJ.len().min(512) {
+            assert!(Elf::load_from_bytes(&XDP_OBJ[..len]).is_err());
+        }
+        // Section header count pointing far beyond the file.
+        let mut data = elf32_be();
+        data[48..50].copy_from_slice(&0xfff0u16.to_be_bytes());
+        assert!(Elf::load_from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn elf_file_owned_and_mapped() {
+        let path = std::env::temp_dir().join(format!("voidio-elf-{}.o", std::process::id()));
+        std::fs::write(&path, elf32_be()).unwrap();
+        let path = path.to_str().unwrap();
+
+        let owned = ElfFile::open(path).unwrap();
+        assert_eq!(owned.elf().section_by_name(".rela.text").unwrap().index, 4);
+        #[cfg(unix)]
+        {
+            /* reloading repeatedly must neither leak nor keep stale mappings around */
+            for _ in 0..1000 {
+                let mapped = voidio::net::MappedElfFile::open(path).unwrap();
+                assert_eq!(mapped.data(), owned.data());
+                let elf = mapped.elf();
+                let symtab = elf.symbol_table().unwrap();
+                assert_eq!(elf.symbol(&symtab, 1).unwrap().name, "main");
+            }
+        }
+
+        std::fs::write(path, b"not an elf").unwrap();
+        assert!(ElfFile::open(path).is_err());
+        #[cfg(unix)]
+        assert!(voidio::net::MappedElfFile::open(path).is_err());
+        std::fs::write(path, b"").unwrap();
+        #[cfg(unix)]
+        assert!(voidio::net::MappedElfFile::open(path).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        let owned = ElfFile::from_vec(XDP_OBJ.to_vec()).unwrap();
+        assert!(owned.elf().section_by_name("xdp_sock").is_some());
+        assert_eq!(owned.into_vec(), XDP_OBJ);
+    }
+}
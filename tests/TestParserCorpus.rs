@@ -0,0 +1,56 @@
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+    use voidio::net::fuzz::{walk_elf, walk_quic_packets};
+    use voidio::net::{open_initial_packet, Elf};
+    use voidio::net::xdp::BpfObject;
+
+    /* Inputs that once crashed a parser or sit on a boundary; also the seed corpus of the fuzz targets. */
+    fn corpus(kind: &str) -> Vec<(String, Vec<u8>)> {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/corpus").join(kind);
+        let mut files: Vec<_> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read(&path).unwrap()))
+            .collect();
+        files.sort();
+        assert!(!files.is_empty());
+        files
+    }
+
+    #[test]
+    fn corpus_elf_never_panics() {
+        for (name, data) in corpus("elf") {
+            walk_elf(&data);
+            if name.starts_with("xdp-truncated") || name.starts_with("bad-") || name == "magic-only.o" {
+                assert!(Elf::load_from_bytes(&data).is_err(), "{name}");
+            }
+        }
+        let valid = fs::read(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/elf/xdp-valid.o")).unwrap();
+        assert!(BpfObject::parse(&valid, ".maps").is_ok());
+    }
+
+    #[test]
+    fn corpus_quic_never_panics() {
+        for (name, data) in corpus("quic") {
+            let opened = walk_quic_packets(&data);
+            let expected = if name == "initial-valid.bin" || name == "initial-token-pn1.bin" || name == "initial-coalesced-garbage.bin" { 1 } else { 0 };
+            assert_eq!(opened, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn corpus_quic_initial_contents() {
+        let mut packet = corpus("quic").into_iter().find(|(name, _)| name == "initial-valid.bin").unwrap().1;
+        let initial = open_initial_packet(&mut packet).unwrap();
+        assert_eq!(initial.dcid.as_bytes(), [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08]);
+        assert_eq!(initial.scid.as_bytes(), [0xc2, 0xa1]);
+        assert_eq!((initial.packet_number, initial.len), (2, packet.len()));
+        assert_eq!(&packet[initial.payload.start..initial.payload.start + 4], [0x06, 0x00, 0x20, 0x00]);
+
+        let mut packet = corpus("quic").into_iter().find(|(name, _)| name == "initial-token-pn1.bin").unwrap().1;
+        let initial = open_initial_packet(&mut packet).unwrap();
+        assert_eq!(&packet[initial.token.clone()], b"tok");
+        assert_eq!((initial.packet_number, initial.payload.len()), (7, 41));
+        assert!(initial.scid.as_bytes().is_empty());
+    }
+}
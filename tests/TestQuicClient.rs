@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::QuicClient;
+    use voidio::net::connection::QuicConnection;
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn quinn_server() -> quinn::Endpoint {
+        let chain = CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap();
+        let key = PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap();
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap()
+    }
+
+    fn client_for(address: SocketAddr) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", address.port()));
+        client.set_address(address).set_alpn_protocols(&[ALPN]);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_quic_client_with_quinn_server() {
+        let endpoint = quinn_server();
+        let address = endpoint.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let incoming = endpoint.accept().await.expect("endpoint closed");
+            let connection = incoming.await.expect("handshake failed");
+            let handshake = connection.handshake_data().unwrap().downcast::<quinn::crypto::rustls::HandshakeData>().unwrap();
+            assert_eq!(handshake.protocol.as_deref(), Some(ALPN));
+            assert_eq!(handshake.server_name.as_deref(), Some("localhost"));
+            /* keep the connection up until the client is done with it */
+            let _ = tokio::time::timeout(Duration::from_secs(2), connection.closed()).await;
+        });
+
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut client = client_for(address);
+            client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+            client.on_open(move |conn: &mut QuicConnection| {
+                opened_tx.send((conn.is_handshaking(), conn.alpn_protocol().map(|p| p.to_vec()))).unwrap();
+            });
+            client.connect()
+        })
+        .await
+        .unwrap();
+        assert_eq!(result, Ok(()));
+
+        let (handshaking, alpn) = opened_rx.recv_timeout(Duration::from_secs(1)).expect("on_open was not called");
+        assert!(!handshaking);
+        assert_eq!(alpn.as_deref(), Some(ALPN));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_quic_client_rejects_untrusted_certificate() {
+        let endpoint = quinn_server();
+        let address = endpoint.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            /* quinn only runs the handshake of connections it was asked to accept */
+            let incoming = endpoint.accept().await.expect("endpoint closed");
+            let _ = incoming.await;
+        });
+
+        /* the test CA is not among the bundled webpki roots */
+        let result = tokio::task::spawn_blocking(move || client_for(address).connect()).await.unwrap();
+        let error = result.expect_err("the handshake must fail");
+        assert!(error.contains("certificate"), "{}", error);
+        server.abort();
+    }
+}
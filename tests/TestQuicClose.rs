@@ -0,0 +1,258 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::connection::QuicConnectionState;
+    use voidio::net::{QuicClient, QuicCloseReason, QuicConnectionEvent, QuicServer, QuicStream};
+
+    const ALPN: &[u8] = b"voidio-test";
+    const RESET_KEY: [u8; 32] = *b"thirty-two bytes of a reset key!";
+
+    fn certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    /* A server answering every bidirectional stream with "welcome" and reporting why connections ended to `closed`. */
+    fn voidio_server(port: u16, idle_timeout: Duration, closed: mpsc::Sender<Option<QuicCloseReason>>) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server
+            .set_certificate_chain(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN])
+            .set_idle_timeout(idle_timeout)
+            .set_stateless_reset_key(RESET_KEY);
+        let closed = Mutex::new(closed);
+        server.on_connection(move |event: QuicConnectionEvent| {
+            let closed = Mutex::new(closed.lock().unwrap().clone());
+            event.connection.on_close(move |conn| closed.lock().unwrap().send(conn.close_reason().cloned()).unwrap());
+            event.connection.on_stream(|stream: &mut QuicStream| {
+                if !voidio::net::is_unidirectional(stream.id()) {
+                    stream.write(b"welcome").unwrap();
+                    stream.finish().unwrap();
+                }
+            });
+        });
+        server.start(2);
+        server
+    }
+
+    fn voidio_client(port: u16) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", port));
+        client.set_address(SocketAddr::from(([127, 0, 0, 1], port))).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client
+    }
+
+    /* Sends a request on a new stream and returns the answer; polling errors end it early. */
+    fn request(client: &mut QuicClient) -> Result<Vec<u8>, String> {
+        let answer = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let mut stream = client.open_bistream()?;
+        stream.write(b"hello")?;
+        stream.finish()?;
+        let sink = answer.clone();
+        stream.on_data(move |chunk| sink.lock().unwrap().extend_from_slice(chunk));
+        let done = closed.clone();
+        stream.on_close(move || done.store(true, Ordering::Relaxed));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !closed.load(Ordering::Relaxed) && Instant::now() < deadline {
+            client.poll(Duration::from_millis(20))?;
+        }
+        let answer = std::mem::take(&mut *answer.lock().unwrap());
+        Ok(answer)
+    }
+
+    fn quinn_endpoint() -> quinn::Endpoint {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        endpoint
+    }
+
+    async fn quinn_request(connection: &quinn::Connection) -> Result<Vec<u8>, quinn::ConnectionError> {
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(b"hello").await.map_err(|_| connection.close_reason().unwrap())?;
+        send.finish().unwrap();
+        let answer = tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(64)).await.unwrap();
+        answer.map_err(|_| connection.close_reason().unwrap())
+    }
+
+    #[test]
+    fn idle_connections_time_out_on_both_ends() {
+        const PORT: u16 = 4487;
+        let (closed_tx, closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, Duration::from_millis(300), closed_tx);
+        let mut client = voidio_client(PORT);
+        client.connect().unwrap();
+        assert_eq!(request(&mut client).unwrap(), b"welcome");
+
+        /* the server's timers run without anything coming in */
+        assert_eq!(closed_rx.recv_timeout(Duration::from_secs(3)).unwrap(), Some(QuicCloseReason::IdleTimeout));
+        let error = client.poll(Duration::from_millis(20)).unwrap_err();
+        assert!(error.contains("idle timeout"), "{}", error);
+        assert_eq!(client.connection().unwrap().state(), QuicConnectionState::Closed);
+        server.stop();
+    }
+
+    #[test]
+    fn traffic_keeps_a_connection_alive() {
+        const PORT: u16 = 4488;
+        let (closed_tx, closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, Duration::from_millis(300), closed_tx);
+        let mut client = voidio_client(PORT);
+        client.connect().unwrap();
+        for _ in 0..8 {
+            assert_eq!(request(&mut client).unwrap(), b"welcome");
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert!(closed_rx.try_recv().is_err());
+
+        /* an application close reaches the server, which drains */
+        client.close(0x2a, "done").unwrap();
+        let reason = closed_rx.recv_timeout(Duration::from_secs(3)).unwrap();
+        assert_eq!(reason, Some(QuicCloseReason::Application { code: 0x2a, reason: "done".to_string(), remote: true }));
+        let error = client.poll(Duration::from_millis(20)).unwrap_err();
+        assert!(error.contains("application error 0x2a: done"), "{}", error);
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quinn_sees_an_application_close() {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = endpoint.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+            recv.read_to_end(64).await.unwrap();
+            send.write_all(b"welcome").await.unwrap();
+            send.finish().unwrap();
+            connection.closed().await
+        });
+
+        tokio::task::spawn_blocking(move || {
+            let mut client = voidio_client(port);
+            client.connect().unwrap();
+            assert_eq!(request(&mut client).unwrap(), b"welcome");
+            client.close(0x101, "go away").unwrap();
+        })
+        .await
+        .unwrap();
+        match tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap() {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(u64::from(close.error_code), 0x101);
+                assert_eq!(&close.reason[..], b"go away");
+            }
+            reason => panic!("{:?}", reason),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_close_before_the_handshake_is_confirmed_hides_the_reason() {
+        const PORT: u16 = 4489;
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], PORT)));
+        server
+            .set_certificate_chain(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN]);
+        /* the server hangs up as soon as the handshake is complete, before HANDSHAKE_DONE went out */
+        server.on_connection(|event: QuicConnectionEvent| event.connection.close(0x101, "go away"));
+        server.start(1);
+
+        let endpoint = quinn_endpoint();
+        let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap();
+        /* Handshake packets carry APPLICATION_ERROR in a transport close instead (RFC 9000 §10.2.3) */
+        match tokio::time::timeout(Duration::from_secs(5), connection.closed()).await.unwrap() {
+            quinn::ConnectionError::ConnectionClosed(close) => {
+                assert_eq!(u64::from(close.error_code), voidio::net::APPLICATION_ERROR);
+                assert!(close.reason.is_empty());
+            }
+            reason => panic!("{:?}", reason),
+        }
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_quinn_close_fires_on_close() {
+        const PORT: u16 = 4490;
+        let (closed_tx, closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, Duration::from_secs(30), closed_tx);
+        let endpoint = quinn_endpoint();
+        let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap();
+        assert_eq!(quinn_request(&connection).await.unwrap(), b"welcome");
+        connection.close(7u32.into(), b"bye");
+        let reason = tokio::task::spawn_blocking(move || closed_rx.recv_timeout(Duration::from_secs(3))).await.unwrap().unwrap();
+        assert_eq!(reason, Some(QuicCloseReason::Application { code: 7, reason: "bye".to_string(), remote: true }));
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_restarted_server_resets_quinn_connections() {
+        const PORT: u16 = 4491;
+        let (closed_tx, _closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, Duration::from_secs(30), closed_tx.clone());
+        let endpoint = quinn_endpoint();
+        let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap();
+        assert_eq!(quinn_request(&connection).await.unwrap(), b"welcome");
+
+        /* the new server knows nothing of the connection but derives the same reset tokens */
+        server.stop();
+        let mut server = voidio_server(PORT, Duration::from_secs(30), closed_tx);
+        let error = quinn_request(&connection).await.unwrap_err();
+        assert_eq!(error, quinn::ConnectionError::Reset);
+        server.stop();
+    }
+
+    #[test]
+    fn voidio_client_detects_a_stateless_reset() {
+        const PORT: u16 = 4492;
+        let (closed_tx, _closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, Duration::from_secs(30), closed_tx.clone());
+        let mut client = voidio_client(PORT);
+        client.connect().unwrap();
+        assert_eq!(request(&mut client).unwrap(), b"welcome");
+
+        server.stop();
+        let mut server = voidio_server(PORT, Duration::from_secs(30), closed_tx);
+        let error = request(&mut client).unwrap_err();
+        assert!(error.contains("stateless reset"), "{}", error);
+        assert_eq!(client.connection().unwrap().close_reason(), Some(&QuicCloseReason::StatelessReset));
+        assert_eq!(client.connection().unwrap().state(), QuicConnectionState::Draining);
+        server.stop();
+    }
+
+    #[test]
+    fn a_tls_failure_closes_with_a_crypto_error() {
+        const PORT: u16 = 4493;
+        let (closed_tx, _closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, Duration::from_secs(30), closed_tx);
+        let mut client = voidio_client(PORT);
+        client.set_alpn_protocols(&[b"something-else"]);
+        /* no_application_protocol is TLS alert 120 (RFC 9001 §8.1) */
+        let error = client.connect().unwrap_err();
+        assert!(error.contains("closed by the peer with CRYPTO_ERROR (0x178)"), "{}", error);
+        server.stop();
+    }
+}
@@ -0,0 +1,279 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use voidio::net::connection::{ConnectionId, QuicConnection, QuicConnectionType};
+    use voidio::net::{
+        AckFrame, AckTracker, Bbr, BbrState, CongestionAlgorithm, CongestionController, Cubic, LossDetection, NewReno, Pacer, RecoveryTimeout,
+        RttEstimator, SentPacket, SpaceId, INITIAL_WINDOW, MINIMUM_WINDOW,
+    };
+
+    const MSS: usize = 1200;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn packet(pn: u64, time_sent: Instant) -> SentPacket {
+        SentPacket { pn, time_sent, size: MSS, ack_eliciting: true, in_flight: true, frames: vec![] }
+    }
+
+    /* A single bottleneck: a drop-tail FIFO in front of a fixed-rate link, with a fixed delay on either side. */
+    struct Bottleneck {
+        bytes_per_sec: u64,
+        one_way: Duration,
+        buffer: usize,
+        last_departure: Option<Instant>,
+        queue: VecDeque<(Instant, usize, u64)>, // departure, flow, packet number
+    }
+
+    impl Bottleneck {
+        fn send(&mut self, now: Instant, flow: usize, pn: u64) {
+            let queued = self.queue.iter().filter(|&&(at, _, _)| at > now).count() * MSS;
+            if queued + MSS > self.buffer { return; } // dropped
+            let start = self.last_departure.map_or(now, |last| last.max(now));
+            let departure = start + Duration::from_nanos(MSS as u64 * 1_000_000_000 / self.bytes_per_sec);
+            self.last_departure = Some(departure);
+            self.queue.push_back((departure, flow, pn));
+        }
+
+        fn receive(&mut self, now: Instant) -> Option<(usize, u64)> {
+            if self.queue.front().is_some_and(|&(at, _, _)| at + self.one_way <= now) {
+                return self.queue.pop_front().map(|(_, flow, pn)| (flow, pn));
+            }
+            None
+        }
+    }
+
+    struct Flow<'a> {
+        controller: &'a mut dyn CongestionController,
+        sender: LossDetection,
+        pacer: Pacer,
+        receiver: AckTracker,
+        acks: VecDeque<(Instant, AckFrame)>,
+        next_pn: u64,
+        received: usize,
+    }
+
+    /* Runs bulk transfers through a 12 Mbit/s, 40 ms RTT bottleneck with one BDP of buffer; returns the bytes each flow got across after `warmup`. */
+    fn simulate(controllers: Vec<&mut dyn CongestionController>, duration: Duration, warmup: Duration) -> Vec<usize> {
+        const ONE_WAY: Duration = Duration::from_millis(20);
+        let bytes_per_sec = 1_500_000;
+        let start = Instant::now();
+        let mut link = Bottleneck { bytes_per_sec, one_way: ONE_WAY, buffer: (bytes_per_sec / 25) as usize, last_departure: None, queue: VecDeque::new() };
+        let mut flows: Vec<Flow> = controllers
+            .into_iter()
+            .map(|controller| {
+                let mut sender = LossDetection::new(false);
+                sender.set_handshake_confirmed();
+                Flow { controller, sender, pacer: Pacer::default(), receiver: AckTracker::new(ms(25)), acks: VecDeque::new(), next_pn: 0, received: 0 }
+            })
+            .collect();
+
+        let mut now = start;
+        while now - start < duration {
+            while let Some((id, pn)) = link.receive(now) {
+                let flow = &mut flows[id];
+                if now - start >= warmup {
+                    flow.received += MSS;
+                }
+                flow.receiver.on_packet_received(pn, true, now);
+            }
+            for (id, flow) in flows.iter_mut().enumerate() {
+                if flow.receiver.wants_ack(now) {
+                    let frame = flow.receiver.ack_frame(now, 3).unwrap();
+                    flow.acks.push_back((now + ONE_WAY, frame));
+                }
+                while flow.acks.front().is_some_and(|(at, _)| *at <= now) {
+                    let (_, frame) = flow.acks.pop_front().unwrap();
+                    let outcome = flow.sender.on_ack_received(SpaceId::Data, &frame, now).unwrap();
+                    let in_flight = flow.sender.bytes_in_flight();
+                    if !outcome.acked.is_empty() {
+                        flow.controller.on_packets_acked(now, &outcome.acked, flow.sender.rtt(), in_flight);
+                    }
+                    if !outcome.lost.is_empty() {
+                        flow.controller.on_packets_lost(now, &outcome.lost, outcome.persistent_congestion, in_flight);
+                    }
+                }
+                let mut probe = false;
+                if flow.sender.timeout().is_some_and(|at| at <= now) {
+                    match flow.sender.on_timeout(now) {
+                        Some(RecoveryTimeout::Lost { packets, persistent_congestion, .. }) => {
+                            let in_flight = flow.sender.bytes_in_flight();
+                            flow.controller.on_packets_lost(now, &packets, persistent_congestion, in_flight);
+                        }
+                        Some(RecoveryTimeout::Probe { .. }) => probe = true,
+                        None => {}
+                    }
+                }
+
+                /* the sender always has data; every packet carries new bytes */
+                loop {
+                    let rate = flow.controller.pacing_rate(flow.sender.rtt());
+                    let allowed = flow.sender.bytes_in_flight() + MSS <= flow.controller.window() && flow.pacer.delay(now, MSS, rate).is_none();
+                    if !allowed && !probe { break; }
+                    probe = false;
+                    let pn = flow.next_pn;
+                    flow.next_pn += 1;
+                    flow.sender.on_packet_sent(SpaceId::Data, packet(pn, now));
+                    flow.controller.on_packet_sent(now, MSS, flow.sender.bytes_in_flight());
+                    flow.pacer.on_sent(MSS);
+                    link.send(now, id, pn);
+                }
+            }
+            now += Duration::from_micros(100);
+        }
+        flows.iter().map(|f| f.received).collect()
+    }
+
+    /* Fraction of the bottleneck a transfer used after the warmup. */
+    fn utilization(bytes: usize, duration: Duration, warmup: Duration) -> f64 {
+        bytes as f64 / ((duration - warmup).as_secs_f64() * 1_500_000.0)
+    }
+
+    /* Jain's fairness index: 1 when every flow got the same share. */
+    fn jain(shares: &[usize]) -> f64 {
+        let sum: f64 = shares.iter().map(|&s| s as f64).sum();
+        let squares: f64 = shares.iter().map(|&s| (s as f64).powi(2)).sum();
+        sum * sum / (shares.len() as f64 * squares)
+    }
+
+    #[test]
+    fn newreno_halves_once_per_round_trip() {
+        let t = Instant::now();
+        let rtt = RttEstimator::new();
+        let mut reno = NewReno::new();
+        assert_eq!(reno.window(), INITIAL_WINDOW);
+
+        /* slow start grows by what was acknowledged */
+        let acked: Vec<SentPacket> = (0..4).map(|pn| packet(pn, t)).collect();
+        reno.on_packets_acked(t + ms(50), &acked, &rtt, 0);
+        assert_eq!(reno.window(), INITIAL_WINDOW + 4 * MSS);
+
+        reno.on_packets_lost(t + ms(60), &[packet(5, t + ms(10))], false, 0);
+        assert_eq!(reno.window(), (INITIAL_WINDOW + 4 * MSS) / 2);
+        assert_eq!(reno.ssthresh(), reno.window());
+        /* a packet sent before recovery started does not reduce the window again, nor does its ACK grow it */
+        reno.on_packets_lost(t + ms(70), &[packet(6, t + ms(20))], false, 0);
+        reno.on_packets_acked(t + ms(70), &[packet(7, t + ms(30))], &rtt, 0);
+        assert_eq!(reno.window(), (INITIAL_WINDOW + 4 * MSS) / 2);
+
+        /* congestion avoidance: one datagram per window acknowledged */
+        let window = reno.window();
+        let acked: Vec<SentPacket> = (10..10 + (window / MSS) as u64).map(|pn| packet(pn, t + ms(80))).collect();
+        reno.on_packets_acked(t + ms(130), &acked, &rtt, 0);
+        assert_eq!(reno.window(), window + MSS);
+
+        reno.on_packets_lost(t + ms(200), &[packet(40, t + ms(150))], true, 0);
+        assert_eq!(reno.window(), MINIMUM_WINDOW);
+    }
+
+    #[test]
+    fn cubic_grows_back_past_the_last_maximum() {
+        let t = Instant::now();
+        let mut rtt = RttEstimator::new();
+        rtt.update(ms(200), Duration::ZERO);
+        let mut cubic = Cubic::new();
+        let acked: Vec<SentPacket> = (0..90).map(|pn| packet(pn, t)).collect();
+        cubic.on_packets_acked(t + ms(200), &acked, &rtt, 0);
+        let w_max = cubic.window();
+        assert_eq!(w_max, 100 * MSS);
+
+        cubic.on_packets_lost(t + ms(250), &[packet(100, t + ms(210))], false, 0);
+        assert_eq!(cubic.window(), 70 * MSS);
+
+        /* a window's worth of ACKs every RTT: K = cbrt(30 / 0.4) ≈ 4.2 s until the curve is back at w_max.
+        The RTT is long enough for the cubic curve to outgrow what Reno would have reached. */
+        let mut pn = 200;
+        let mut now = t + ms(260);
+        let mut at_k = 0;
+        while now < t + ms(7000) {
+            let count = cubic.window() / MSS;
+            let acked: Vec<SentPacket> = (pn..pn + count as u64).map(|pn| packet(pn, now)).collect();
+            pn += count as u64;
+            now += ms(200);
+            cubic.on_packets_acked(now, &acked, &rtt, 0);
+            if now <= t + ms(4460) {
+                at_k = cubic.window();
+            }
+        }
+        /* concave up to w_max, plateau around it, then probing beyond */
+        assert!(at_k > 95 * MSS && at_k <= 105 * MSS, "{}", at_k / MSS);
+        assert!(cubic.window() > w_max + 5 * MSS, "{}", cubic.window() / MSS);
+    }
+
+    #[test]
+    fn pacer_spreads_packets_over_time() {
+        let t = Instant::now();
+        let mut pacer = Pacer::new(2 * MSS as u64);
+        let rate = 1_200_000; // a datagram per millisecond
+        for _ in 0..2 {
+            assert_eq!(pacer.delay(t, MSS, rate), None);
+            pacer.on_sent(MSS);
+        }
+        assert_eq!(pacer.delay(t, MSS, rate), Some(t + ms(1)));
+        assert_eq!(pacer.delay(t + ms(1), MSS, rate), None);
+        pacer.on_sent(MSS);
+        /* idle time only refills up to the burst size */
+        assert_eq!(pacer.delay(t + ms(100), MSS, rate), None);
+        pacer.on_sent(MSS);
+        pacer.on_sent(MSS);
+        assert!(pacer.delay(t + ms(100), MSS, rate).is_some());
+    }
+
+    #[test]
+    fn single_flow_fills_the_bottleneck() {
+        let (duration, warmup) = (Duration::from_secs(20), Duration::from_secs(2));
+        let mut reno = NewReno::new();
+        let mut cubic = Cubic::new();
+        let mut bbr = Bbr::new();
+        for (name, controller) in [("newreno", &mut reno as &mut dyn CongestionController), ("cubic", &mut cubic), ("bbr", &mut bbr)] {
+            let received = simulate(vec![controller], duration, warmup);
+            let utilization = utilization(received[0], duration, warmup);
+            assert!(utilization > 0.8, "{}: {:.2}", name, utilization);
+        }
+
+        /* BBR found the bottleneck rate and left startup */
+        assert_eq!(bbr.state(), BbrState::ProbeBw);
+        let bandwidth = bbr.bandwidth() as f64;
+        assert!(bandwidth > 1_300_000.0 && bandwidth < 1_800_000.0, "{}", bandwidth);
+    }
+
+    #[test]
+    fn competing_flows_share_the_bottleneck() {
+        let (duration, warmup) = (Duration::from_secs(40), Duration::from_secs(10));
+        let (mut a, mut b) = (NewReno::new(), NewReno::new());
+        let shares = simulate(vec![&mut a, &mut b], duration, warmup);
+        assert!(jain(&shares) > 0.9, "newreno {:?}", shares);
+        assert!(utilization(shares.iter().sum(), duration, warmup) > 0.8, "newreno {:?}", shares);
+
+        let (mut a, mut b) = (Cubic::new(), Cubic::new());
+        let shares = simulate(vec![&mut a, &mut b], duration, warmup);
+        assert!(jain(&shares) > 0.9, "cubic {:?}", shares);
+        assert!(utilization(shares.iter().sum(), duration, warmup) > 0.8, "cubic {:?}", shares);
+
+        let (mut a, mut b) = (Bbr::new(), Bbr::new());
+        let shares = simulate(vec![&mut a, &mut b], duration, warmup);
+        assert!(jain(&shares) > 0.8, "bbr {:?}", shares);
+    }
+
+    #[test]
+    fn connections_use_the_selected_algorithm() {
+        let address: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        let mut conn = QuicConnection::new(ConnectionId::from_slice(&[1; 8]), ConnectionId::from_slice(&[2; 8]), &address, QuicConnectionType::Server);
+        assert_eq!(conn.congestion_window(), INITIAL_WINDOW);
+
+        let custom = CongestionAlgorithm::Custom(Arc::new(|| {
+            let mut reno = NewReno::new();
+            reno.on_packets_lost(Instant::now(), &[packet(0, Instant::now())], true, 0);
+            Box::new(reno)
+        }));
+        conn.set_congestion_control(&custom);
+        assert_eq!(conn.congestion_window(), MINIMUM_WINDOW);
+        conn.set_congestion_control(&CongestionAlgorithm::Bbr);
+        assert_eq!(conn.congestion_window(), INITIAL_WINDOW);
+    }
+}
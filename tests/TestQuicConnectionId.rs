@@ -0,0 +1,143 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::{QuicClient, QuicConnectionEvent, QuicServer, QuicStream, ACTIVE_CONNECTION_ID_LIMIT};
+
+    const ALPN: &[u8] = b"voidio-test";
+    const WORKERS: usize = 4;
+
+    /* A server on `WORKERS` workers answering every bidirectional stream with "welcome". */
+    fn voidio_server(port: u16, key: Option<[u8; 16]>) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server
+            .set_certificate_chain(
+                CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap(),
+                PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap(),
+            )
+            .unwrap()
+            .set_alpn_protocols(&[ALPN]);
+        if let Some(key) = key {
+            server.set_connection_id_key(key);
+        }
+        server.on_connection(|event: QuicConnectionEvent| {
+            event.connection.on_stream(|stream: &mut QuicStream| {
+                if !voidio::net::is_unidirectional(stream.id()) {
+                    stream.write(b"welcome").unwrap();
+                    stream.finish().unwrap();
+                }
+            });
+        });
+        server.start(WORKERS);
+        server
+    }
+
+    fn voidio_client(port: u16) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", port));
+        client.set_address(SocketAddr::from(([127, 0, 0, 1], port))).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client
+    }
+
+    /* Sends a request on a new stream and returns the server's answer. */
+    fn request(client: &mut QuicClient) -> Vec<u8> {
+        let answer = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let mut stream = client.open_bistream().unwrap();
+        stream.write(b"hello").unwrap();
+        stream.finish().unwrap();
+        let sink = answer.clone();
+        stream.on_data(move |chunk| sink.lock().unwrap().extend_from_slice(chunk));
+        let done = closed.clone();
+        stream.on_close(move || done.store(true, Ordering::Relaxed));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !closed.load(Ordering::Relaxed) && Instant::now() < deadline {
+            client.poll(Duration::from_millis(20)).unwrap();
+        }
+        let answer = std::mem::take(&mut *answer.lock().unwrap());
+        answer
+    }
+
+    fn quinn_endpoint() -> quinn::Endpoint {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        endpoint
+    }
+
+    #[test]
+    fn server_cids_name_the_worker() {
+        const PORT: u16 = 4481;
+        let mut server = voidio_server(PORT, None);
+        /* the kernel spreads clients over the workers by address, the CIDs route them to the one that took the Initial */
+        for _ in 0..12 {
+            let mut client = voidio_client(PORT);
+            client.connect().unwrap();
+            let scid = client.connection().unwrap().peer_transport_parameters().unwrap().initial_source_connection_id.unwrap();
+            let scid = scid.as_bytes();
+            assert_eq!(scid.len(), 8);
+            assert_eq!(scid[0], 7, "the first octet carries the length");
+            assert!((scid[1] as usize) < WORKERS, "worker {} of {}", scid[1], WORKERS);
+            assert_eq!(request(&mut client), b"welcome");
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn encrypted_cids_still_route() {
+        const PORT: u16 = 4482;
+        let mut server = voidio_server(PORT, Some(*b"sixteen byte key"));
+        let mut worker_bytes = Vec::new();
+        for _ in 0..12 {
+            let mut client = voidio_client(PORT);
+            client.connect().unwrap();
+            let scid = client.connection().unwrap().peer_transport_parameters().unwrap().initial_source_connection_id.unwrap();
+            assert_eq!(scid.as_bytes()[0], 7);
+            worker_bytes.push(scid.as_bytes()[1]);
+            assert_eq!(request(&mut client), b"welcome");
+        }
+        /* the worker id no longer shows in the clear */
+        assert!(worker_bytes.iter().any(|&byte| byte as usize >= WORKERS), "{:?}", worker_bytes);
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quinn_clients_get_new_connection_ids() {
+        const PORT: u16 = 4483;
+        let mut server = voidio_server(PORT, Some(rand::random()));
+        let mut tasks = Vec::new();
+        for _ in 0..16 {
+            tasks.push(tokio::spawn(async move {
+                let endpoint = quinn_endpoint();
+                let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+                let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.expect("handshake timed out").expect("handshake failed");
+                let (mut send, mut recv) = connection.open_bi().await.unwrap();
+                send.write_all(b"hello").await.unwrap();
+                send.finish().unwrap();
+                assert_eq!(tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(64)).await.unwrap().unwrap(), b"welcome");
+
+                /* as many as the limit allows, less the handshake CID */
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                assert_eq!(connection.stats().frame_rx.new_connection_id, ACTIVE_CONNECTION_ID_LIMIT - 1);
+                assert!(connection.close_reason().is_none(), "{:?}", connection.close_reason());
+                connection.close(0u32.into(), b"done");
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        server.stop();
+    }
+}
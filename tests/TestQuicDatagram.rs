@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::{DispatchMode, QuicClient, QuicConnectionEvent, QuicDatagram, QuicServer};
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    fn quinn_server(datagrams: bool) -> quinn::Endpoint {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        let mut transport = quinn::TransportConfig::default();
+        if !datagrams {
+            transport.datagram_receive_buffer_size(None);
+        }
+        config.transport_config(Arc::new(transport));
+        quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap()
+    }
+
+    fn voidio_client(server: SocketAddr) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", server.port()));
+        client.set_address(server).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client.connect().unwrap();
+        client
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn server_echoes_quinn_datagrams() {
+        const PORT: u16 = 4451;
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], PORT)));
+        server.set_datagram_dispatch_mode(DispatchMode::Direct);
+        server
+            .set_certificate_chain(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN]);
+        server.on_connection(|event: QuicConnectionEvent| {
+            event.connection.on_datagram(|datagram: &mut QuicDatagram| {
+                let data = datagram.data().to_vec();
+                datagram.reply(&data).unwrap();
+            });
+        });
+        server.start(2);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap();
+
+        assert!(connection.max_datagram_size().is_some(), "the server did not offer datagrams");
+        for i in 0..50u8 {
+            connection.send_datagram(vec![i; 100 + i as usize].into()).unwrap();
+            let echo = tokio::time::timeout(Duration::from_secs(2), connection.read_datagram()).await.unwrap().unwrap();
+            assert_eq!(&echo[..], &vec![i; 100 + i as usize][..]);
+        }
+        connection.close(0u32.into(), b"done");
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn client_datagrams_with_quinn_server() {
+        let endpoint = quinn_server(true);
+        let address = endpoint.local_addr().unwrap();
+        /* echoes every datagram and reports what it got */
+        let server = tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let mut received = Vec::new();
+            while let Ok(Ok(datagram)) = tokio::time::timeout(Duration::from_secs(2), connection.read_datagram()).await {
+                connection.send_datagram(datagram.clone()).unwrap();
+                received.push(datagram.to_vec());
+            }
+            received
+        });
+
+        let received = tokio::task::spawn_blocking(move || {
+            let mut client = voidio_client(address);
+            let max = client.connection().unwrap().max_datagram_size().expect("quinn offers datagrams");
+            assert!(client.send_datagram(&vec![0; max + 1]).is_err());
+
+            let echoes = Arc::new(Mutex::new(Vec::new()));
+            let sink = echoes.clone();
+            client.on_datagram(move |datagram| sink.lock().unwrap().push(datagram.data().to_vec())).unwrap();
+            for i in 0..20u8 {
+                client.send_datagram(&[i; 32]).unwrap();
+            }
+            client.send_datagram(&vec![0xAA; max]).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while echoes.lock().unwrap().len() < 21 && Instant::now() < deadline {
+                client.poll(Duration::from_millis(20)).unwrap();
+            }
+            let echoes = std::mem::take(&mut *echoes.lock().unwrap());
+            assert_eq!(echoes.len(), 21);
+            assert_eq!(echoes[20], vec![0xAA; max]);
+
+            /* a full queue drops the oldest datagrams */
+            let conn = client.connection().unwrap();
+            conn.set_datagram_queue_capacity(4);
+            for i in 100..110u8 {
+                conn.send_datagram(&[i]).unwrap();
+            }
+            assert_eq!(conn.dropped_datagrams(), 6);
+            client.poll(Duration::from_millis(200)).unwrap();
+        })
+        .await;
+        let received_by_server = server.await.unwrap();
+        received.unwrap();
+        let tail: Vec<Vec<u8>> = (106..110u8).map(|i| vec![i]).collect();
+        assert_eq!(received_by_server.len(), 25);
+        assert_eq!(&received_by_server[21..], &tail[..]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn datagrams_need_the_peer_to_take_them() {
+        let endpoint = quinn_server(false);
+        let address = endpoint.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let _ = tokio::time::timeout(Duration::from_secs(2), connection.closed()).await;
+        });
+        tokio::task::spawn_blocking(move || {
+            let mut client = voidio_client(address);
+            assert_eq!(client.connection().unwrap().max_datagram_size(), None);
+            assert!(client.send_datagram(b"nobody listens").is_err());
+        })
+        .await
+        .unwrap();
+        server.abort();
+    }
+}
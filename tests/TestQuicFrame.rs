@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use voidio::net::{AckFrame, EcnCounts, QuicFrame, QuicFrames, QuicPacketType, VARINT_MAX};
+
+    /* Owned inputs a frame can borrow from; `frame` maps them onto every frame type. */
+    #[derive(Debug, Clone)]
+    struct Input {
+        kind: u8,
+        v: [u64; 4],
+        bytes: Vec<u8>,
+        flag: bool,
+        ranges: Vec<(u64, u64)>,
+        token: [u8; 16],
+    }
+
+    fn input() -> impl Strategy<Value = Input> {
+        let varint = prop_oneof![0..64u64, 0..16384u64, 0..1u64 << 30, 0..=VARINT_MAX];
+        (
+            0..21u8,
+            [varint.clone(), varint.clone(), varint.clone(), varint],
+            prop::collection::vec(any::<u8>(), 0..64),
+            any::<bool>(),
+            prop::collection::vec((0..=VARINT_MAX, 0..=VARINT_MAX), 0..8),
+            any::<[u8; 16]>(),
+        )
+            .prop_map(|(kind, v, bytes, flag, ranges, token)| Input { kind, v, bytes, flag, ranges, token })
+    }
+
+    fn frame(input: &Input) -> QuicFrame<'_> {
+        let [a, b, c, d] = input.v;
+        let data = &input.bytes[..];
+        let offset = a.min(VARINT_MAX - data.len() as u64);
+        match input.kind {
+            0 => QuicFrame::Padding(1 + data.len()),
+            1 => QuicFrame::Ping,
+            2 => {
+                /* fold the random ranges into ones that stay above packet number 0 */
+                let first_range = b % (a + 1);
+                let mut smallest = a - first_range;
+                let mut ranges = Vec::new();
+                for &(gap, len) in &input.ranges {
+                    if smallest < 2 { break; }
+                    let gap = gap % (smallest - 1);
+                    let largest = smallest - gap - 2;
+                    let len = len % (largest + 1);
+                    smallest = largest - len;
+                    ranges.push((gap, len));
+                }
+                let ecn = input.flag.then_some(EcnCounts { ect0: b, ect1: c, ce: d });
+                QuicFrame::Ack(AckFrame { largest: a, delay: c, first_range, ranges, ecn })
+            }
+            3 => QuicFrame::ResetStream { stream_id: a, error_code: b, final_size: c },
+            4 => QuicFrame::StopSending { stream_id: a, error_code: b },
+            5 => QuicFrame::Crypto { offset, data },
+            6 => QuicFrame::NewToken(if data.is_empty() { &input.token } else { data }),
+            7 => QuicFrame::Stream { stream_id: b, offset, data, fin: input.flag },
+            8 => QuicFrame::MaxData(a),
+            9 => QuicFrame::MaxStreamData { stream_id: a, max: b },
+            10 => QuicFrame::MaxStreams { bidi: input.flag, max: a >> 2 },
+            11 => QuicFrame::DataBlocked(a),
+            12 => QuicFrame::StreamDataBlocked { stream_id: a, limit: b },
+            13 => QuicFrame::StreamsBlocked { bidi: input.flag, limit: a >> 2 },
+            14 => QuicFrame::NewConnectionId {
+                sequence: a.max(b),
+                retire_prior_to: a.min(b),
+                cid: &input.token[..1 + (c % 16) as usize],
+                reset_token: input.token,
+            },
+            15 => QuicFrame::RetireConnectionId(a),
+            16 => QuicFrame::PathChallenge(input.token[..8].try_into().unwrap()),
+            17 => QuicFrame::PathResponse(input.token[8..].try_into().unwrap()),
+            18 => QuicFrame::ConnectionClose { error_code: a, frame_type: input.flag.then_some(b), reason: data },
+            19 => QuicFrame::HandshakeDone,
+            _ => QuicFrame::Datagram(data),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn quic_frame_round_trip(input in input()) {
+            let frame = frame(&input);
+            let mut out = vec![0xaa];
+            frame.encode(&mut out).unwrap();
+            let (decoded, len) = QuicFrame::decode(&out[1..]).unwrap();
+            prop_assert_eq!(len, out.len() - 1);
+            prop_assert_eq!(decoded.frame_type(), frame.frame_type());
+            prop_assert_eq!(decoded, frame);
+        }
+
+        #[test]
+        fn quic_frame_payload_round_trip(inputs in prop::collection::vec(input(), 1..12)) {
+            let frames: Vec<_> = inputs.iter().map(frame).collect();
+            let mut payload = Vec::new();
+            let mut expected = Vec::new();
+            for frame in &frames {
+                /* adjacent PADDING runs decode as one */
+                match (expected.last_mut(), frame) {
+                    (Some(QuicFrame::Padding(run)), QuicFrame::Padding(more)) => *run += more,
+                    _ => expected.push(frame.clone()),
+                }
+                frame.encode(&mut payload).unwrap();
+            }
+            let decoded: Vec<_> = QuicFrames::new(&payload).collect::<Result<_, _>>().unwrap();
+            prop_assert_eq!(decoded, expected);
+        }
+
+        #[test]
+        fn quic_frame_decode_arbitrary(bytes in prop::collection::vec(any::<u8>(), 0..96)) {
+            /* whatever decodes re-encodes to something that decodes identically */
+            if let Ok((frame, len)) = QuicFrame::decode(&bytes) {
+                prop_assert!(len <= bytes.len());
+                let mut out = Vec::new();
+                frame.encode(&mut out).unwrap();
+                prop_assert_eq!(QuicFrame::decode(&out).unwrap().0, frame);
+            }
+        }
+
+        #[test]
+        fn quic_frame_ack_ranges(input in input()) {
+            let input = Input { kind: 2, ..input };
+            let QuicFrame::Ack(ack) = frame(&input) else { unreachable!() };
+            let acked: Vec<_> = ack.acked().collect();
+            prop_assert_eq!(acked.len(), ack.ranges.len() + 1);
+            prop_assert_eq!(acked[0].1, ack.largest);
+            for pair in acked.windows(2) {
+                prop_assert!(pair[0].0 <= pair[0].1);
+                prop_assert!(pair[1].1 + 1 < pair[0].0, "ranges are separated by at least one unacknowledged packet");
+            }
+        }
+    }
+
+    #[test]
+    fn quic_frame_known_encodings() {
+        let ack = AckFrame { largest: 10, delay: 3, first_range: 2, ranges: vec![(1, 2)], ecn: None };
+        assert_eq!(ack.acked().collect::<Vec<_>>(), [(8, 10), (3, 5)]);
+        let mut out = Vec::new();
+        QuicFrame::Ack(ack).encode(&mut out).unwrap();
+        assert_eq!(out, [0x02, 10, 3, 1, 2, 1, 2]);
+
+        /* STREAM without LEN runs to the end of the packet; DATAGRAM likewise with type 0x30 */
+        let (frame, len) = QuicFrame::decode(&[0x0d, 0x04, 0x40, 0x80, b'h', b'i']).unwrap();
+        assert_eq!((frame, len), (QuicFrame::Stream { stream_id: 4, offset: 0x80, data: b"hi", fin: true }, 6));
+        assert_eq!(QuicFrame::decode(&[0x30, 1, 2]).unwrap().0, QuicFrame::Datagram(&[1, 2]));
+        assert_eq!(QuicFrame::decode(&[0, 0, 0, 1]).unwrap(), (QuicFrame::Padding(3), 3));
+    }
+
+    #[test]
+    fn quic_frame_rejects_invalid() {
+        for bytes in [
+            &[0x02, 1, 0, 0, 2][..],             // first ACK range below 0
+            &[0x02, 5, 0, 1, 0, 4, 0],           // second ACK range below 0
+            &[0x06, 0, 5, 1, 2],                 // CRYPTO data truncated
+            &[0x07, 0],                          // empty NEW_TOKEN
+            &[0x12, 0xd0, 0, 0, 0, 0, 0, 0, 1],  // MAX_STREAMS above 2^60
+            &[0x18, 1, 2, 4, 1, 2, 3, 4],        // Retire Prior To above Sequence Number
+            &[0x18, 2, 1, 21],                   // connection ID too long
+            &[0x1a, 1, 2, 3],                    // PATH_CHALLENGE truncated
+            &[0x1f],                             // unknown frame type
+            &[0x40],                             // truncated frame type
+            &[0x40, 0x01],                       // PING with a two byte frame type
+        ] {
+            assert!(QuicFrame::decode(bytes).is_err(), "{bytes:02x?}");
+        }
+
+        let mut out = vec![7];
+        assert!(QuicFrame::MaxData(VARINT_MAX + 1).encode(&mut out).is_err());
+        assert!(QuicFrame::Crypto { offset: VARINT_MAX, data: b"x" }.encode(&mut out).is_err());
+        assert!(QuicFrame::NewToken(b"").encode(&mut out).is_err());
+        assert_eq!(out, [7], "failed encodes leave the buffer untouched");
+
+        let mut frames = QuicFrames::new(&[0x01, 0x1f, 0x01]);
+        assert!(matches!(frames.next(), Some(Ok(QuicFrame::Ping))));
+        assert!(frames.next().unwrap().is_err());
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn quic_frame_packet_types() {
+        use QuicPacketType::*;
+        let crypto = QuicFrame::Crypto { offset: 0, data: b"" };
+        let stream = QuicFrame::Stream { stream_id: 0, offset: 0, data: b"", fin: false };
+        let app_close = QuicFrame::ConnectionClose { error_code: 0, frame_type: None, reason: b"" };
+        let transport_close = QuicFrame::ConnectionClose { error_code: 0, frame_type: Some(0), reason: b"" };
+        assert!(crypto.is_allowed_in(Initial) && crypto.is_allowed_in(Handshake) && !crypto.is_allowed_in(ZeroRtt));
+        assert!(stream.is_allowed_in(ZeroRtt) && !stream.is_allowed_in(Initial));
+        assert!(transport_close.is_allowed_in(Initial) && !app_close.is_allowed_in(Initial));
+        assert!(QuicFrame::HandshakeDone.is_allowed_in(OneRtt) && !QuicFrame::HandshakeDone.is_allowed_in(ZeroRtt));
+        assert!(QuicFrame::Datagram(b"").is_allowed_in(ZeroRtt) && !QuicFrame::Datagram(b"").is_allowed_in(Handshake));
+        assert!(QuicFrame::Ping.is_ack_eliciting() && !QuicFrame::Padding(1).is_ack_eliciting() && !transport_close.is_ack_eliciting());
+    }
+}
@@ -0,0 +1,380 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::{Buf, Bytes};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::h3::{
+        H3Frame, H3Request, H3Response, H3Settings, QpackDecoder, QpackEncoder, DynamicTable, H3_FRAME_UNEXPECTED, H3_MESSAGE_ERROR,
+        H3_MISSING_SETTINGS, H3_REQUEST_REJECTED, H3_SETTINGS_ERROR, QPACK_DECOMPRESSION_FAILED,
+    };
+    use voidio::net::QuicServer;
+
+    /* Answers /echo with the request body, /bye with GOAWAY and anything else with what it asked for. */
+    fn handler(request: &H3Request) -> H3Response {
+        let mut response = H3Response::new(200);
+        response.add_header("X-Handler", "voidio");
+        match request.path() {
+            "/echo" => response.set_body(request.body()),
+            "/bye" => response.set_goaway().set_body(b"bye"),
+            _ => response.set_body(format!("{} {} {}", request.method(), request.authority(), request.path()).as_bytes()),
+        };
+        response
+    }
+
+    fn h3_server(port: u16) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        let chain = CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap();
+        server.set_certificate_chain(chain, PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap()).unwrap();
+        server.on_request(handler);
+        server.start(1);
+        server
+    }
+
+    /* The client's control and QPACK streams; dropping them would finish them, which closes the connection. */
+    struct H3Client {
+        connection: quinn::Connection,
+        control: quinn::SendStream,
+        encoder: quinn::SendStream,
+        _decoder: quinn::SendStream,
+    }
+
+    async fn quinn_connection(port: u16) -> quinn::Connection {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        endpoint.connect(SocketAddr::from(([127, 0, 0, 1], port)), "localhost").unwrap().await.unwrap()
+    }
+
+    async fn h3_client(port: u16, settings: bool) -> H3Client {
+        let connection = quinn_connection(port).await;
+        let mut control = connection.open_uni().await.unwrap();
+        let mut preface = vec![0x00];
+        if settings {
+            H3Frame::Settings(H3Settings { qpack_max_table_capacity: 0, qpack_blocked_streams: 0, max_field_section_size: Some(16384) }).encode(&mut preface);
+        }
+        control.write_all(&preface).await.unwrap();
+        let mut encoder = connection.open_uni().await.unwrap();
+        encoder.write_all(&[0x02]).await.unwrap();
+        let mut decoder = connection.open_uni().await.unwrap();
+        decoder.write_all(&[0x03]).await.unwrap();
+        H3Client { connection, control, encoder, _decoder: decoder }
+    }
+
+    /* A request with the header section `section` (already QPACK-encoded) and the body in `chunks`, one DATA frame each. */
+    async fn send_request(client: &H3Client, section: &[u8], chunks: &[&[u8]]) -> quinn::RecvStream {
+        let (mut send, recv) = client.connection.open_bi().await.unwrap();
+        let mut out = Vec::new();
+        H3Frame::Headers(section).encode(&mut out);
+        for chunk in chunks {
+            H3Frame::Data(chunk).encode(&mut out);
+        }
+        send.write_all(&out).await.unwrap();
+        send.finish().unwrap();
+        recv
+    }
+
+    fn static_section(method: &str, path: &str, extra: &[(&str, &str)]) -> Vec<u8> {
+        let mut fields: Vec<(&[u8], &[u8])> =
+            vec![(b":method", method.as_bytes()), (b":scheme", b"https"), (b":authority", b"localhost"), (b":path", path.as_bytes())];
+        fields.extend(extra.iter().map(|(n, v)| (n.as_bytes(), v.as_bytes())));
+        QpackEncoder::new().encode(&fields)
+    }
+
+    /* The response on `recv`: its fields and body. */
+    async fn read_response(mut recv: quinn::RecvStream) -> (Vec<(String, String)>, Vec<u8>) {
+        let data = tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(1 << 20)).await.unwrap().unwrap();
+        let (mut fields, mut body) = (Vec::new(), Vec::new());
+        let mut off = 0;
+        while let Some((frame, n)) = H3Frame::decode(&data[off..]).unwrap() {
+            off += n;
+            match frame {
+                H3Frame::Headers(section) => {
+                    let decoded = QpackDecoder::new(0, 0).decode(0, section).unwrap().unwrap();
+                    fields = decoded.into_iter().map(|(n, v)| (String::from_utf8(n).unwrap(), String::from_utf8(v).unwrap())).collect();
+                }
+                H3Frame::Data(data) => body.extend_from_slice(data),
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+        assert_eq!(off, data.len());
+        (fields, body)
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /* The code a request stream was reset with. */
+    async fn reset_code(mut recv: quinn::RecvStream) -> u64 {
+        match tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(1 << 20)).await.unwrap() {
+            Err(quinn::ReadToEndError::Read(quinn::ReadError::Reset(code))) => code.into_inner(),
+            other => panic!("stream not reset: {:?}", other),
+        }
+    }
+
+    /* Reads the server's control stream until `done` accepts one of its frames. */
+    async fn read_control(connection: &quinn::Connection, done: impl Fn(&H3Frame) -> bool) -> Vec<u64> {
+        loop {
+            let mut recv = connection.accept_uni().await.unwrap();
+            let mut data = Vec::new();
+            while data.is_empty() {
+                data.extend_from_slice(&recv.read_chunk(1024, true).await.unwrap().unwrap().bytes);
+            }
+            if data[0] != 0x00 {
+                continue;
+            }
+            let (mut seen, mut off) = (Vec::new(), 1);
+            loop {
+                while let Some((frame, n)) = H3Frame::decode(&data[off..]).unwrap() {
+                    off += n;
+                    seen.push(frame.frame_type());
+                    if done(&frame) {
+                        return seen;
+                    }
+                }
+                data.extend_from_slice(&recv.read_chunk(1024, true).await.unwrap().unwrap().bytes);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serves_a_get_and_a_post() {
+        let mut server = h3_server(4500);
+        let client = h3_client(4500, true).await;
+
+        let recv = send_request(&client, &static_section("GET", "/index.html", &[("user-agent", "quinn")]), &[]).await;
+        let (fields, body) = read_response(recv).await;
+        assert_eq!(field(&fields, ":status"), Some("200"));
+        assert_eq!(field(&fields, "x-handler"), Some("voidio"));
+        assert_eq!(field(&fields, "content-length"), Some("25"));
+        assert_eq!(body, b"GET localhost /index.html");
+
+        let payload = vec![0x5au8; 40000];
+        let section = static_section("POST", "/echo", &[("content-length", "40000")]);
+        let recv = send_request(&client, &section, &[&payload[..10000], &payload[10000..]]).await;
+        let (fields, body) = read_response(recv).await;
+        assert_eq!(field(&fields, ":status"), Some("200"));
+        assert_eq!(body, payload);
+
+        /* HEAD gets the fields of a GET and no body */
+        let recv = send_request(&client, &static_section("HEAD", "/index.html", &[]), &[]).await;
+        let (fields, body) = read_response(recv).await;
+        assert_eq!(field(&fields, "content-length"), Some("26"));
+        assert!(body.is_empty());
+
+        let frames = read_control(&client.connection, |f| matches!(f, H3Frame::Settings(_))).await;
+        assert_eq!(frames, vec![0x04]);
+        server.stop();
+    }
+
+    /* A client of the h3 crate, so that requests come from an HTTP/3 stack other than ours; its driver runs in the background. */
+    async fn h3_crate_client(port: u16) -> h3::client::SendRequest<h3_quinn::OpenStreams, Bytes> {
+        let connection = h3_quinn::Connection::new(quinn_connection(port).await);
+        let (mut driver, send_request) = h3::client::new(connection).await.unwrap();
+        tokio::spawn(async move { driver.wait_idle().await });
+        send_request
+    }
+
+    /* Sends `request` with the body in `chunks` and returns the response with its body. */
+    async fn h3_crate_request(
+        send_request: &mut h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>,
+        request: http::Request<()>,
+        chunks: &[&[u8]],
+    ) -> (http::Response<()>, Vec<u8>) {
+        let mut stream = send_request.send_request(request).await.unwrap();
+        for chunk in chunks {
+            stream.send_data(Bytes::copy_from_slice(chunk)).await.unwrap();
+        }
+        stream.finish().await.unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(5), stream.recv_response()).await.unwrap().unwrap();
+        let mut body = Vec::new();
+        while let Some(mut data) = stream.recv_data().await.unwrap() {
+            body.extend_from_slice(&data.copy_to_bytes(data.remaining()));
+        }
+        (response, body)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serves_the_h3_crate_client() {
+        let mut server = h3_server(4506);
+        let mut client = h3_crate_client(4506).await;
+
+        let request = http::Request::get("https://localhost/index.html").header("user-agent", "h3").body(()).unwrap();
+        let (response, body) = h3_crate_request(&mut client, request, &[]).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["x-handler"], "voidio");
+        assert_eq!(response.headers()["content-length"], "25");
+        assert_eq!(body, b"GET localhost /index.html");
+
+        let payload = vec![0x5au8; 40000];
+        let request = http::Request::post("https://localhost/echo").header("content-length", "40000").body(()).unwrap();
+        let (response, body) = h3_crate_request(&mut client, request, &[&payload[..10000], &payload[10000..]]).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(body, payload);
+
+        let request = http::Request::head("https://localhost/index.html").body(()).unwrap();
+        let (response, body) = h3_crate_request(&mut client, request, &[]).await;
+        assert_eq!(response.headers()["content-length"], "26");
+        assert!(body.is_empty());
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn serves_concurrent_requests_of_the_h3_crate_client() {
+        let mut server = h3_server(4507);
+        let client = h3_crate_client(4507).await;
+        let requests = (0..8).map(|i| {
+            let mut client = client.clone();
+            tokio::spawn(async move {
+                let request = http::Request::get(format!("https://localhost/{}", i)).body(()).unwrap();
+                h3_crate_request(&mut client, request, &[]).await.1
+            })
+        });
+        for (i, request) in requests.collect::<Vec<_>>().into_iter().enumerate() {
+            assert_eq!(request.await.unwrap(), format!("GET localhost /{}", i).as_bytes());
+        }
+        server.stop();
+    }
+
+    /* The encoder instructions and header section of RFC 9204 Appendix B.2, plus :method GET and :scheme https. */
+    const INSERTS: &[u8] = &[
+        0x3f, 0xbd, 0x01, 0xc0, 0x0f, b'w', b'w', b'w', b'.', b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm', 0xc1, 0x0c,
+        b'/', b's', b'a', b'm', b'p', b'l', b'e', b'/', b'p', b'a', b't', b'h',
+    ];
+    const DYNAMIC_SECTION: &[u8] = &[0x03, 0x81, 0xd1, 0xd7, 0x10, 0x11];
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn decodes_references_to_the_dynamic_table() {
+        let mut server = h3_server(4501);
+        let mut client = h3_client(4501, true).await;
+        client.encoder.write_all(INSERTS).await.unwrap();
+        let recv = send_request(&client, DYNAMIC_SECTION, &[]).await;
+        let (_, body) = read_response(recv).await;
+        assert_eq!(body, b"GET www.example.com /sample/path");
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_blocked_request_is_answered_once_the_inserts_arrive() {
+        let mut server = h3_server(4502);
+        let mut client = h3_client(4502, true).await;
+        let mut recv = send_request(&client, DYNAMIC_SECTION, &[]).await;
+        assert!(tokio::time::timeout(Duration::from_millis(200), recv.read_chunk(1024, true)).await.is_err());
+        client.encoder.write_all(INSERTS).await.unwrap();
+        let (_, body) = read_response(recv).await;
+        assert_eq!(body, b"GET www.example.com /sample/path");
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn goaway_rejects_later_requests() {
+        let mut server = h3_server(4503);
+        let client = h3_client(4503, true).await;
+        let (_, body) = read_response(send_request(&client, &static_section("GET", "/bye", &[]), &[]).await).await;
+        assert_eq!(body, b"bye");
+        let frames = read_control(&client.connection, |f| matches!(f, H3Frame::Goaway(4))).await;
+        assert_eq!(frames, vec![0x04, 0x07]);
+
+        let recv = send_request(&client, &static_section("GET", "/index.html", &[]), &[]).await;
+        assert_eq!(reset_code(recv).await, H3_REQUEST_REJECTED);
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_malformed_request_resets_its_stream_only() {
+        let mut server = h3_server(4504);
+        let client = h3_client(4504, true).await;
+        let uppercase = send_request(&client, &static_section("GET", "/", &[("User-Agent", "quinn")]), &[]).await;
+        assert_eq!(reset_code(uppercase).await, H3_MESSAGE_ERROR);
+        let no_path = QpackEncoder::new().encode(&[(b":method", b"GET"), (b":scheme", b"https")]);
+        assert_eq!(reset_code(send_request(&client, &no_path, &[]).await).await, H3_MESSAGE_ERROR);
+        let wrong_length = static_section("POST", "/echo", &[("content-length", "10")]);
+        assert_eq!(reset_code(send_request(&client, &wrong_length, &[b"short"]).await).await, H3_MESSAGE_ERROR);
+
+        let (_, body) = read_response(send_request(&client, &static_section("GET", "/still", &[]), &[]).await).await;
+        assert_eq!(body, b"GET localhost /still");
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_control_stream_without_settings_closes_the_connection() {
+        let mut server = h3_server(4505);
+        let mut client = h3_client(4505, false).await;
+        let mut goaway = Vec::new();
+        H3Frame::Goaway(0).encode(&mut goaway);
+        client.control.write_all(&goaway).await.unwrap();
+        match tokio::time::timeout(Duration::from_secs(5), client.connection.closed()).await.unwrap() {
+            quinn::ConnectionError::ApplicationClosed(close) => assert_eq!(close.error_code.into_inner(), H3_MISSING_SETTINGS),
+            other => panic!("closed with {:?}", other),
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn qpack_round_trip_with_huffman() {
+        let fields: Vec<(&[u8], &[u8])> = vec![
+            (b":status", b"200"),
+            (b"cache-control", b"private"),
+            (b"date", b"Mon, 21 Oct 2013 20:13:21 GMT"),
+            (b"location", b"https://www.example.com"),
+            (b"x-custom", b"\x00\xffbinary"),
+        ];
+        let section = QpackEncoder::new().encode(&fields);
+        let decoded = QpackDecoder::new(0, 0).decode(0, &section).unwrap().unwrap();
+        assert_eq!(decoded, fields.iter().map(|(n, v)| (n.to_vec(), v.to_vec())).collect::<Vec<_>>());
+        /* "www.example.com" Huffman-encoded is 12 bytes rather than 15 (RFC 7541 C.4.1) */
+        assert!(section.len() < fields.iter().map(|(n, v)| n.len() + v.len()).sum::<usize>());
+    }
+
+    #[test]
+    fn dynamic_table_evicts_the_oldest_entries() {
+        let mut table = DynamicTable::new(100);
+        table.set_capacity(100).unwrap();
+        table.insert(b"a".to_vec(), b"1".to_vec()).unwrap(); // 34 bytes
+        table.insert(b"b".to_vec(), b"2".to_vec()).unwrap();
+        table.insert(b"c".to_vec(), b"3".to_vec()).unwrap();
+        assert_eq!(table.insert_count(), 3);
+        assert_eq!(table.size(), 68);
+        assert!(table.get(0).is_none());
+        assert_eq!(table.get(2), Some(&(b"c".to_vec(), b"3".to_vec())));
+        assert!(table.set_capacity(101).is_err());
+        assert!(table.insert(vec![b'x'; 80], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn the_decoder_blocks_and_acknowledges() {
+        let mut decoder = QpackDecoder::new(4096, 1);
+        assert_eq!(decoder.decode(0, DYNAMIC_SECTION).unwrap(), None);
+        assert!(decoder.is_blocked(0));
+        /* a second blocked stream is over the limit of one */
+        assert_eq!(decoder.decode(4, DYNAMIC_SECTION).unwrap_err().code, QPACK_DECOMPRESSION_FAILED);
+        decoder.on_encoder_stream(&INSERTS[..10]).unwrap();
+        decoder.on_encoder_stream(&INSERTS[10..]).unwrap();
+        assert_eq!(decoder.table().insert_count(), 2);
+        let fields = decoder.decode(0, DYNAMIC_SECTION).unwrap().unwrap();
+        assert_eq!(fields[2], (b":authority".to_vec(), b"www.example.com".to_vec()));
+        assert_eq!(fields[3], (b":path".to_vec(), b"/sample/path".to_vec()));
+        /* Insert Count Increment of 2, then Section Acknowledgment of stream 0 */
+        assert_eq!(decoder.take_instructions(), vec![0x02, 0x80]);
+    }
+
+    #[test]
+    fn http2_frames_and_reserved_settings_are_errors() {
+        assert_eq!(H3Frame::decode(&[0x06, 0x00]).unwrap_err().code, H3_FRAME_UNEXPECTED);
+        assert_eq!(H3Frame::decode(&[0x04, 0x02, 0x02, 0x00]).unwrap_err().code, H3_SETTINGS_ERROR);
+        assert_eq!(H3Frame::decode(&[0x04, 0x04, 0x01, 0x00, 0x01, 0x00]).unwrap_err().code, H3_SETTINGS_ERROR);
+        assert_eq!(H3Frame::decode(&[0x21, 0x01, 0xff, 0x00]).unwrap(), Some((H3Frame::Unknown(0x21), 3)));
+        assert_eq!(H3Frame::decode(&[0x00, 0x05, b'a']).unwrap(), None);
+    }
+}
@@ -0,0 +1,259 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::{QuicClient, QuicConnectionEvent, QuicServer, QuicStream};
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    /* A server answering every bidirectional stream with "welcome", reporting migrations to `migrated`. */
+    fn voidio_server(port: u16, migrated: mpsc::Sender<(SocketAddr, SocketAddr)>) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server
+            .set_certificate_chain(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN]);
+        let migrated = Mutex::new(migrated);
+        server.on_connection(move |event: QuicConnectionEvent| {
+            let migrated = Mutex::new(migrated.lock().unwrap().clone());
+            event.connection.on_migrate(move |from, to| migrated.lock().unwrap().send((*from, *to)).unwrap());
+            event.connection.on_stream(|stream: &mut QuicStream| {
+                if !voidio::net::is_unidirectional(stream.id()) {
+                    stream.write(b"welcome").unwrap();
+                    stream.finish().unwrap();
+                }
+            });
+        });
+        server.start(2);
+        server
+    }
+
+    fn voidio_client(address: SocketAddr) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", address.port()));
+        client.set_address(address).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client
+    }
+
+    /* Sends `data` on a new stream and returns the answer, empty if none came within `timeout`. */
+    fn request(client: &mut QuicClient, data: &[u8], timeout: Duration) -> Vec<u8> {
+        let answer = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let mut stream = client.open_bistream().unwrap();
+        stream.write(data).unwrap();
+        stream.finish().unwrap();
+        let sink = answer.clone();
+        stream.on_data(move |chunk| sink.lock().unwrap().extend_from_slice(chunk));
+        let done = closed.clone();
+        stream.on_close(move || done.store(true, Ordering::Relaxed));
+        let deadline = Instant::now() + timeout;
+        while !closed.load(Ordering::Relaxed) && Instant::now() < deadline {
+            client.poll(Duration::from_millis(20)).unwrap();
+        }
+        let answer = std::mem::take(&mut *answer.lock().unwrap());
+        answer
+    }
+
+    /*
+    Relays datagrams between one client and `server`, from a second port once `rebind` is set, as a NAT
+    rebinding would. With `blackhole` nothing the server sends to that second port gets through.
+    */
+    struct Relay {
+        address: SocketAddr,
+        rebind: Arc<AtomicBool>,
+        stop: Arc<AtomicBool>,
+        to_server: Arc<AtomicUsize>,   // bytes sent from the second port
+        from_server: Arc<AtomicUsize>, // bytes the server sent to the second port
+    }
+
+    impl Drop for Relay {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn relay(server: SocketAddr, blackhole: bool) -> Relay {
+        let front = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let backs = [UdpSocket::bind("127.0.0.1:0").unwrap(), UdpSocket::bind("127.0.0.1:0").unwrap()];
+        front.set_nonblocking(true).unwrap();
+        backs.iter().for_each(|back| back.set_nonblocking(true).unwrap());
+        let relay = Relay {
+            address: front.local_addr().unwrap(),
+            rebind: Arc::new(AtomicBool::new(false)),
+            stop: Arc::new(AtomicBool::new(false)),
+            to_server: Arc::new(AtomicUsize::new(0)),
+            from_server: Arc::new(AtomicUsize::new(0)),
+        };
+        let (rebind, stop, to_server, from_server) = (relay.rebind.clone(), relay.stop.clone(), relay.to_server.clone(), relay.from_server.clone());
+        std::thread::spawn(move || {
+            let mut client = None;
+            let mut buf = [0u8; 2048];
+            while !stop.load(Ordering::Relaxed) {
+                let mut idle = true;
+                if let Ok((len, from)) = front.recv_from(&mut buf) {
+                    idle = false;
+                    client = Some(from);
+                    let back = rebind.load(Ordering::Relaxed) as usize;
+                    backs[back].send_to(&buf[..len], server).unwrap();
+                    if back == 1 {
+                        to_server.fetch_add(len, Ordering::Relaxed);
+                    }
+                }
+                for (i, back) in backs.iter().enumerate() {
+                    let Ok((len, _)) = back.recv_from(&mut buf) else { continue; };
+                    idle = false;
+                    if i == 1 {
+                        from_server.fetch_add(len, Ordering::Relaxed);
+                        if blackhole { continue; }
+                    }
+                    if let Some(client) = client {
+                        front.send_to(&buf[..len], client).unwrap();
+                    }
+                }
+                if idle {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        });
+        relay
+    }
+
+    fn quinn_client_config() -> quinn::ClientConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap()))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn server_follows_a_rebinding_quinn_client() {
+        const PORT: u16 = 4484;
+        let (migrated_tx, migrated_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, migrated_tx);
+        let endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        let old = endpoint.local_addr().unwrap();
+        let connecting = endpoint.connect_with(quinn_client_config(), SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap();
+        let request = |connection: quinn::Connection| async move {
+            let (mut send, mut recv) = connection.open_bi().await.unwrap();
+            send.write_all(b"hello").await.unwrap();
+            send.finish().unwrap();
+            tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(64)).await.unwrap().unwrap()
+        };
+        assert_eq!(request(connection.clone()).await, b"welcome");
+
+        /* quinn moves to a new port and a CID the server has not seen */
+        endpoint.rebind(UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+        let new = endpoint.local_addr().unwrap();
+        assert_eq!(request(connection.clone()).await, b"welcome");
+        assert_eq!(migrated_rx.recv_timeout(Duration::from_secs(5)).unwrap(), (old, new));
+
+        /* the server validated the new path, and left the CID it used on the old one */
+        let stats = connection.stats();
+        assert!(stats.frame_rx.path_challenge >= 1, "{:?}", stats.frame_rx);
+        assert!(stats.frame_tx.path_response >= 1, "{:?}", stats.frame_tx);
+        assert!(stats.frame_rx.retire_connection_id >= 1, "{:?}", stats.frame_rx);
+        assert!(connection.close_reason().is_none(), "{:?}", connection.close_reason());
+        connection.close(0u32.into(), b"done");
+        server.stop();
+    }
+
+    #[test]
+    fn voidio_client_migrates() {
+        const PORT: u16 = 4485;
+        let (migrated_tx, migrated_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, migrated_tx);
+        let mut client = voidio_client(SocketAddr::from(([127, 0, 0, 1], PORT)));
+        client.connect().unwrap();
+        assert_eq!(request(&mut client, b"hello", Duration::from_secs(5)), b"welcome");
+
+        client.migrate().unwrap();
+        assert_eq!(request(&mut client, b"hello", Duration::from_secs(5)), b"welcome");
+        let (from, to) = migrated_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(from.ip(), to.ip());
+        assert_ne!(from.port(), to.port());
+
+        /* and again, each time to a new port */
+        client.migrate().unwrap();
+        assert_eq!(request(&mut client, b"hello", Duration::from_secs(5)), b"welcome");
+        assert_eq!(migrated_rx.recv_timeout(Duration::from_secs(5)).unwrap().0, to);
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn voidio_client_answers_path_challenges_behind_a_rebinding_nat() {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let relay = relay(endpoint.local_addr().unwrap(), false);
+
+        /* an echo server */
+        let (connection_tx, connection_rx) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            connection_tx.send(connection.clone()).unwrap();
+            while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                let data = recv.read_to_end(64).await.unwrap();
+                send.write_all(&data).await.unwrap();
+                send.finish().unwrap();
+            }
+        });
+
+        let (address, rebind) = (relay.address, relay.rebind.clone());
+        tokio::task::spawn_blocking(move || {
+            let mut client = voidio_client(address);
+            client.connect().unwrap();
+            assert_eq!(request(&mut client, b"before", Duration::from_secs(5)), b"before");
+            rebind.store(true, Ordering::Relaxed);
+            assert_eq!(request(&mut client, b"after", Duration::from_secs(5)), b"after");
+            client.poll(Duration::from_millis(200)).unwrap();
+        })
+        .await
+        .unwrap();
+        /* quinn saw the new port and had it validated by the client */
+        let stats = connection_rx.await.unwrap().stats();
+        assert!(stats.frame_tx.path_challenge >= 1, "{:?}", stats.frame_tx);
+        assert!(stats.frame_rx.path_response >= 1, "{:?}", stats.frame_rx);
+        server.abort();
+    }
+
+    #[test]
+    fn server_sends_at_most_three_times_what_an_unvalidated_path_sent() {
+        const PORT: u16 = 4486;
+        let (migrated_tx, migrated_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, migrated_tx);
+        let relay = relay(SocketAddr::from(([127, 0, 0, 1], PORT)), true);
+        let mut client = voidio_client(relay.address);
+        client.connect().unwrap();
+        assert_eq!(request(&mut client, b"hello", Duration::from_secs(5)), b"welcome");
+
+        /* the new port never answers the server's PATH_CHALLENGE */
+        relay.rebind.store(true, Ordering::Relaxed);
+        assert!(request(&mut client, &[7; 20_000], Duration::from_secs(2)).is_empty());
+        let (sent, received) = (relay.from_server.load(Ordering::Relaxed), relay.to_server.load(Ordering::Relaxed));
+        assert!(sent > 0, "the server did not follow the client");
+        assert!(sent <= 3 * received, "{} bytes sent for {} received", sent, received);
+        assert!(migrated_rx.try_recv().is_err(), "an unvalidated path was reported");
+        server.stop();
+    }
+}
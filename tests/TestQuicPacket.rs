@@ -0,0 +1,120 @@
+#[cfg(test)]
+mod tests {
+    use voidio::net::{QuicPacket, QuicPacketHeader, QuicPacketType, QuicPackets};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /* Removes header protection with a known mask, as RFC 9001 §5.4.1 describes for long (0x0f) and short (0x1f) headers. */
+    fn unmask(packet: &[u8], pn_offset: usize, mask: &[u8]) -> (u8, u32) {
+        let first = packet[0] ^ (mask[0] & if packet[0] & 0x80 != 0 { 0x0f } else { 0x1f });
+        let pn_len = (first & 0b11) as usize + 1;
+        let pn = (0..pn_len).fold(0u32, |pn, i| (pn << 8) | (packet[pn_offset + i] ^ mask[1 + i]) as u32);
+        (first, pn)
+    }
+
+    /* RFC 9001 A.2: the protected header, then the ciphertext starting with the header protection sample. */
+    fn client_initial() -> Vec<u8> {
+        let mut packet = hex("c000000001088394c8f03e5157080000449e7b9aec34d1b1c98dd7689fb8ec11d242b123dc9b");
+        packet.resize(1200, 0);
+        packet
+    }
+
+    const SERVER_INITIAL: &str = "cf000000010008f067a5502a4262b5004075c0d95a482cd0991cd25b0aac406a5816b6394100f37a1c69797554780bb38cc5a99f5ede4cf73c3ec2493a1839b3dbcba3f6ea46c5b7684df3548e7ddeb9c3bf9c73cc3f3bded74b562bfb19fb84022f8ef4cdd93795d77d06edbb7aaf2f58891850abbdca3d20398c276456cbc42158407dd074ee";
+    const RETRY: &str = "ff000000010008f067a5502a4262b5746f6b656e04a265ba2eff4d829058fb3f0f2496ba";
+    const CHACHA20_SHORT: &str = "4cfe4189655e5cd55c41f69080575d7999c25a5bfb";
+
+    #[test]
+    fn quic_packet_rfc9001_client_initial() {
+        let data = client_initial();
+        let packet = QuicPacket::parse(&data, 8).unwrap();
+        let QuicPacketHeader::Initial(header) = packet.header else { panic!("not an Initial packet") };
+        assert_eq!(header.version, 1);
+        assert_eq!(header.dcid, hex("8394c8f03e515708"));
+        assert!(header.scid.is_empty() && header.token.is_empty());
+        assert_eq!((header.pn_offset, header.length, packet.len()), (18, 1182, 1200));
+        assert_eq!(packet.sample().unwrap()[..], hex("d1b1c98dd7689fb8ec11d242b123dc9b"));
+        assert_eq!(unmask(packet.bytes, header.pn_offset, &hex("437b9aec36")), (0xc3, 2));
+    }
+
+    #[test]
+    fn quic_packet_rfc9001_server_initial() {
+        let data = hex(SERVER_INITIAL);
+        let packet = QuicPacket::parse(&data, 8).unwrap();
+        assert_eq!(packet.header.packet_type(), QuicPacketType::Initial);
+        let header = packet.header.protected().unwrap();
+        assert!(header.dcid.is_empty());
+        assert_eq!(header.scid, hex("f067a5502a4262b5"));
+        assert_eq!((header.pn_offset, header.length, packet.len()), (18, 117, 135));
+        assert_eq!(packet.sample().unwrap()[..], hex("2cd0991cd25b0aac406a5816b6394100"));
+        assert_eq!(unmask(packet.bytes, header.pn_offset, &hex("2ec0d8356a")), (0xc1, 1));
+    }
+
+    #[test]
+    fn quic_packet_rfc9001_retry() {
+        let data = hex(RETRY);
+        let packet = QuicPacket::parse(&data, 8).unwrap();
+        let QuicPacketHeader::Retry(retry) = packet.header else { panic!("not a Retry packet") };
+        assert!(retry.dcid.is_empty());
+        assert_eq!(retry.scid, hex("f067a5502a4262b5"));
+        assert_eq!(retry.token, b"token");
+        assert_eq!(retry.integrity_tag[..], hex("04a265ba2eff4d829058fb3f0f2496ba"));
+        assert!(packet.sample().is_none());
+        assert!(QuicPacket::parse(&data[..30], 8).is_none()); // 15 bytes left, no room for the tag
+    }
+
+    #[test]
+    fn quic_packet_rfc9001_chacha20_short_header() {
+        let data = hex(CHACHA20_SHORT);
+        let packet = QuicPacket::parse(&data, 0).unwrap();
+        let QuicPacketHeader::OneRtt(header) = packet.header else { panic!("not a 1-RTT packet") };
+        assert!(header.dcid.is_empty());
+        assert_eq!((header.pn_offset, header.length, packet.len()), (1, 20, 21));
+        assert_eq!(packet.sample().unwrap()[..], hex("5e5cd55c41f69080575d7999c25a5bfb"));
+        assert_eq!(unmask(packet.bytes, header.pn_offset, &hex("aefefe7d03")), (0x42, 0xbff4));
+
+        let with_cid = [&[0x40u8][..], &[7; 8], &[0; 24]].concat();
+        let header = *QuicPacket::parse(&with_cid, 8).unwrap().header.protected().unwrap();
+        assert_eq!((header.dcid, header.pn_offset, header.length), (&[7u8; 8][..], 9, 24));
+        assert!(QuicPacket::parse(&with_cid[..5], 8).is_none());
+    }
+
+    #[test]
+    fn quic_packet_coalesced() {
+        let handshake = hex("e00000000104aabbccdd02c0de0401020304");
+        let zero_rtt = hex("d00000000100000300ffff");
+        let datagram = [hex(SERVER_INITIAL), handshake.clone(), zero_rtt, hex("4100ff00"), hex("c3")].concat();
+
+        let packets: Vec<_> = QuicPackets::new(&datagram, 0).collect();
+        let kinds: Vec<_> = packets.iter().map(|p| p.header.packet_type()).collect();
+        assert_eq!(kinds, [QuicPacketType::Initial, QuicPacketType::Handshake, QuicPacketType::ZeroRtt, QuicPacketType::OneRtt]);
+        assert_eq!(packets[1].bytes, &handshake[..]);
+        assert_eq!(packets[1].header.dcid(), hex("aabbccdd"));
+        assert_eq!(packets[1].header.protected().unwrap().scid, hex("c0de"));
+        assert_eq!(packets[2].len(), 11);
+        assert_eq!(packets[3].bytes, hex("4100ff00c3"), "a short header packet extends to the end of the datagram");
+
+        /* a truncated Length stops the walk and leaves the rest unparsed */
+        let datagram = [hex(SERVER_INITIAL), handshake[..handshake.len() - 1].to_vec()].concat();
+        let mut iter = QuicPackets::new(&datagram, 0);
+        assert_eq!(iter.by_ref().count(), 1);
+        assert_eq!(iter.remaining(), &handshake[..handshake.len() - 1]);
+    }
+
+    #[test]
+    fn quic_packet_version_negotiation_and_rejects() {
+        let data = hex("8000000000040a0b0c0d00000000016b3343cf");
+        let QuicPacketHeader::VersionNegotiation(vn) = QuicPacket::parse(&data, 8).unwrap().header else { panic!("not Version Negotiation") };
+        assert_eq!(vn.dcid, hex("0a0b0c0d"));
+        assert!(vn.scid.is_empty());
+        assert_eq!(vn.versions().collect::<Vec<_>>(), [1, 0x6b3343cf]);
+        assert!(QuicPacket::parse(&data[..data.len() - 1], 8).is_none());
+
+        assert!(QuicPacket::parse(&hex("c0ff00001d0000"), 8).is_none(), "unknown version");
+        assert!(QuicPacket::parse(&hex("800000000100000100"), 8).is_none(), "fixed bit unset");
+        assert!(QuicPacket::parse(&hex("0000"), 0).is_none(), "fixed bit unset");
+        assert!(QuicPacket::parse(&hex("c000000001000004746f6b"), 8).is_none(), "token runs past the end");
+        assert!(QuicPacket::parse(&[], 8).is_none());
+    }
+}
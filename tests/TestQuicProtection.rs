@@ -0,0 +1,131 @@
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    use rustls::crypto::ring::{cipher_suite, default_provider};
+    use rustls::crypto::CryptoProvider;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::quic::{KeyChange, Version};
+    use rustls::{Side, SupportedCipherSuite};
+    use voidio::net::{EncryptionLevel, PacketProtector};
+
+    const DCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+    /* Runs a TLS handshake between two in-memory endpoints restricted to `suite` and returns their 1-RTT protection. */
+    fn one_rtt(suite: SupportedCipherSuite) -> (PacketProtector, PacketProtector) {
+        let provider = Arc::new(CryptoProvider { cipher_suites: vec![suite], ..default_provider() });
+        let chain = CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap();
+        let key = PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap();
+        let server_config = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let mut client: rustls::quic::Connection = rustls::quic::ClientConnection::new(Arc::new(client_config), Version::V1, "localhost".try_into().unwrap(), vec![0x0f, 0]).unwrap().into();
+        let mut server: rustls::quic::Connection = rustls::quic::ServerConnection::new(Arc::new(server_config), Version::V1, vec![0x0f, 0]).unwrap().into();
+        let (mut client_keys, mut server_keys) = (None, None);
+        for _ in 0..4 {
+            flight(&mut client, &mut server, &mut client_keys);
+            flight(&mut server, &mut client, &mut server_keys);
+        }
+        (client_keys.expect("client 1-RTT keys"), server_keys.expect("server 1-RTT keys"))
+    }
+
+    fn flight(from: &mut rustls::quic::Connection, to: &mut rustls::quic::Connection, keys: &mut Option<PacketProtector>) {
+        let mut buf = Vec::new();
+        while let Some(change) = from.write_hs(&mut buf) {
+            if let KeyChange::OneRtt { keys: one_rtt, next } = change {
+                *keys = Some(PacketProtector::one_rtt(one_rtt, next));
+            }
+        }
+        if !buf.is_empty() { to.read_hs(&buf).unwrap(); }
+    }
+
+    /* A short header packet to DCID; returns it with its packet number offset. */
+    fn short_packet(pn: u64, payload: &[u8]) -> (Vec<u8>, usize) {
+        let mut out = vec![0x43];
+        out.extend_from_slice(&DCID);
+        out.extend_from_slice(&(pn as u32).to_be_bytes());
+        out.extend_from_slice(payload);
+        (out, 1 + DCID.len())
+    }
+
+    fn send(from: &mut PacketProtector, to: &mut PacketProtector, pn: u64, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        let (mut packet, pn_offset) = short_packet(pn, payload);
+        from.seal(&mut packet, 0, pn_offset, pn)?;
+        let (opened, plaintext) = to.open(&mut packet, pn_offset, pn.checked_sub(1))?;
+        assert_eq!(opened, pn);
+        Ok(plaintext.to_vec())
+    }
+
+    #[test]
+    fn one_rtt_round_trip_for_every_suite() {
+        for suite in [cipher_suite::TLS13_AES_128_GCM_SHA256, cipher_suite::TLS13_AES_256_GCM_SHA384, cipher_suite::TLS13_CHACHA20_POLY1305_SHA256] {
+            let (mut client, mut server) = one_rtt(suite);
+            assert_eq!(client.level(), EncryptionLevel::OneRtt);
+            assert_eq!(send(&mut client, &mut server, 0, b"ping ping ping ping").unwrap(), b"ping ping ping ping");
+            assert_eq!(send(&mut server, &mut client, 0, b"pong pong pong pong").unwrap(), b"pong pong pong pong");
+
+            /* a flipped ciphertext bit is an error, not a panic */
+            let (mut packet, pn_offset) = short_packet(1, b"tampered payload....");
+            client.seal(&mut packet, 0, pn_offset, 1).unwrap();
+            packet[pn_offset + 6] ^= 1;
+            assert_eq!(server.open(&mut packet, pn_offset, Some(0)).unwrap_err().kind(), ErrorKind::InvalidData, "{:?}", suite);
+        }
+    }
+
+    #[test]
+    fn initial_protection_depends_on_the_client_dcid() {
+        let mut packet = vec![0xc3, 0, 0, 0, 1, DCID.len() as u8];
+        packet.extend_from_slice(&DCID);
+        packet.extend_from_slice(&[0, 0, 0x40, 0]); // no SCID, no token, Length
+        let pn_offset = packet.len();
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(&[0x01; 32]); // PING frames
+        PacketProtector::initial(&DCID, Side::Client).seal(&mut packet, 0, pn_offset, 0).unwrap();
+
+        let mut wrong = packet.clone();
+        assert!(PacketProtector::initial(&[0; 8], Side::Server).open(&mut wrong, pn_offset, None).is_err());
+        let mut server = PacketProtector::initial(&DCID, Side::Server);
+        let (pn, payload) = server.open(&mut packet, pn_offset, None).unwrap();
+        assert_eq!((pn, payload), (0, &[0x01; 32][..]));
+        assert_eq!(server.initiate_key_update().unwrap_err().kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn key_update_in_both_directions() {
+        let (mut client, mut server) = one_rtt(cipher_suite::TLS13_AES_128_GCM_SHA256);
+        /* nothing has been received in the first phase yet */
+        assert_eq!(client.initiate_key_update().unwrap_err().kind(), ErrorKind::WouldBlock);
+        send(&mut server, &mut client, 0, b"first phase, server").unwrap();
+
+        /* a packet sealed before the update but delivered after it still opens with the old keys */
+        let (mut late, late_offset) = short_packet(0, b"first phase, client");
+        client.seal(&mut late, 0, late_offset, 0).unwrap();
+        client.initiate_key_update().unwrap();
+        assert!(client.key_phase());
+        assert_eq!(client.initiate_key_update().unwrap_err().kind(), ErrorKind::WouldBlock);
+
+        /* the server follows the flipped key phase bit */
+        assert_eq!(send(&mut client, &mut server, 1, b"second phase, client").unwrap(), b"second phase, client");
+        assert!(server.key_phase());
+        assert_eq!(server.open(&mut late, late_offset, Some(1)).unwrap().1, b"first phase, client");
+        assert_eq!(send(&mut server, &mut client, 1, b"second phase, server").unwrap(), b"second phase, server");
+
+        /* and the server may start the next one */
+        server.initiate_key_update().unwrap();
+        assert_eq!(send(&mut server, &mut client, 2, b"third phase, server").unwrap(), b"third phase, server");
+        assert!(!client.key_phase() && !server.key_phase());
+    }
+}
@@ -0,0 +1,314 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, VecDeque};
+    use std::time::{Duration, Instant};
+
+    use voidio::net::{AckFrame, AckTracker, LossDetection, RecoveryTimeout, RttEstimator, SentFrame, SentPacket, SpaceId};
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn packet(pn: u64, time_sent: Instant) -> SentPacket {
+        SentPacket { pn, time_sent, size: 1200, ack_eliciting: true, in_flight: true, frames: vec![SentFrame::Crypto { offset: pn, data: vec![pn as u8] }] }
+    }
+
+    fn ack(largest: u64, first_range: u64, ranges: Vec<(u64, u64)>) -> AckFrame {
+        AckFrame { largest, delay: 0, first_range, ranges, ecn: None }
+    }
+
+    fn pns(packets: &[SentPacket]) -> Vec<u64> {
+        let mut pns: Vec<u64> = packets.iter().map(|p| p.pn).collect();
+        pns.sort();
+        pns
+    }
+
+    #[test]
+    fn rtt_estimator_follows_rfc_9002() {
+        let mut rtt = RttEstimator::new();
+        assert_eq!((rtt.smoothed(), rtt.rttvar()), (ms(333), Duration::from_micros(166_500)));
+        rtt.update(ms(100), ms(20)); // the first sample ignores the ACK delay
+        assert_eq!((rtt.smoothed(), rtt.rttvar(), rtt.min()), (ms(100), ms(50), ms(100)));
+        rtt.update(ms(140), ms(20));
+        assert_eq!(rtt.min(), ms(100));
+        assert_eq!(rtt.rttvar(), Duration::from_micros(42_500));
+        assert_eq!(rtt.smoothed(), Duration::from_micros(102_500));
+        assert_eq!(rtt.pto_base(), Duration::from_micros(272_500));
+        /* an ACK delay that would take the sample below min_rtt is not subtracted */
+        rtt.update(ms(105), ms(20));
+        assert_eq!(rtt.latest(), ms(105));
+        assert_eq!(rtt.smoothed(), Duration::from_nanos(102_812_500));
+    }
+
+    #[test]
+    fn packet_threshold_loss() {
+        let t = Instant::now();
+        let mut recovery = LossDetection::new(false);
+        for pn in 0..5 {
+            recovery.on_packet_sent(SpaceId::Initial, packet(pn, t + ms(pn)));
+        }
+        assert_eq!(recovery.bytes_in_flight(), 5 * 1200);
+        /* 0 and 2..=4 arrive, 1 is three packets behind the largest */
+        let outcome = recovery.on_ack_received(SpaceId::Initial, &ack(4, 2, vec![(0, 0)]), t + ms(50)).unwrap();
+        assert_eq!(pns(&outcome.acked), [0, 2, 3, 4]);
+        assert_eq!(pns(&outcome.lost), [1]);
+        assert_eq!(outcome.lost[0].frames, [SentFrame::Crypto { offset: 1, data: vec![1] }]);
+        assert_eq!(recovery.bytes_in_flight(), 0);
+        assert_eq!(recovery.rtt().latest(), ms(46));
+    }
+
+    #[test]
+    fn time_threshold_loss() {
+        let t = Instant::now();
+        let mut recovery = LossDetection::new(false);
+        recovery.on_packet_sent(SpaceId::Handshake, packet(0, t));
+        recovery.on_packet_sent(SpaceId::Handshake, packet(1, t + ms(10)));
+        let outcome = recovery.on_ack_received(SpaceId::Handshake, &ack(1, 0, vec![]), t + ms(110)).unwrap();
+        assert!(outcome.lost.is_empty());
+
+        /* 9/8 of the 100 ms RTT after it was sent */
+        let loss_time = t + Duration::from_micros(112_500);
+        assert_eq!(recovery.timeout(), Some(loss_time));
+        assert!(recovery.on_timeout(loss_time - ms(1)).is_none());
+        match recovery.on_timeout(loss_time) {
+            Some(RecoveryTimeout::Lost { space: SpaceId::Handshake, packets, persistent_congestion: false }) => assert_eq!(pns(&packets), [0]),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(recovery.timeout(), None);
+    }
+
+    #[test]
+    fn probe_timeout_backs_off() {
+        let t = Instant::now();
+        let mut recovery = LossDetection::new(false);
+        recovery.set_handshake_confirmed();
+        recovery.on_packet_sent(SpaceId::Data, packet(0, t));
+        /* 333 ms + 4 * 166.5 ms, plus max_ack_delay for application data */
+        let pto = ms(999) + ms(25);
+        assert_eq!(recovery.timeout(), Some(t + pto));
+        assert!(recovery.on_timeout(t + pto - ms(1)).is_none());
+        assert!(matches!(recovery.on_timeout(t + pto), Some(RecoveryTimeout::Probe { space: SpaceId::Data })));
+        assert_eq!(recovery.pto_count(), 1);
+        assert_eq!(recovery.timeout(), Some(t + pto * 2));
+
+        /* an acknowledgement resets the backoff */
+        recovery.on_packet_sent(SpaceId::Data, packet(1, t + pto));
+        recovery.on_ack_received(SpaceId::Data, &ack(1, 1, vec![]), t + pto + ms(100)).unwrap();
+        assert_eq!(recovery.pto_count(), 0);
+        assert_eq!(recovery.timeout(), None);
+    }
+
+    #[test]
+    fn application_data_is_not_probed_before_the_handshake_is_confirmed() {
+        let t = Instant::now();
+        let mut recovery = LossDetection::new(false);
+        recovery.on_packet_sent(SpaceId::Data, packet(0, t));
+        assert_eq!(recovery.timeout(), None);
+        recovery.on_packet_sent(SpaceId::Handshake, packet(0, t + ms(5)));
+        assert_eq!(recovery.timeout(), Some(t + ms(5) + ms(999)));
+    }
+
+    #[test]
+    fn client_probes_until_the_server_validated_its_address() {
+        let t = Instant::now();
+        let mut client = LossDetection::new(true);
+        /* an ACK-only Initial leaves nothing in flight, yet the server may be blocked by its amplification limit */
+        client.on_packet_sent(SpaceId::Initial, SentPacket { ack_eliciting: false, in_flight: false, frames: vec![], ..packet(0, t) });
+        assert_eq!(client.timeout(), Some(t + ms(999)));
+        assert!(matches!(client.on_timeout(t + ms(999)), Some(RecoveryTimeout::Probe { space: SpaceId::Initial })));
+
+        let mut server = LossDetection::new(false);
+        server.on_packet_sent(SpaceId::Initial, SentPacket { ack_eliciting: false, in_flight: false, frames: vec![], ..packet(0, t) });
+        assert_eq!(server.timeout(), None);
+    }
+
+    #[test]
+    fn persistent_congestion() {
+        for (spacing, expected) in [(ms(200), true), (ms(50), false)] {
+            let t = Instant::now();
+            let mut recovery = LossDetection::new(false);
+            recovery.set_handshake_confirmed();
+            recovery.on_packet_sent(SpaceId::Data, packet(0, t));
+            recovery.on_ack_received(SpaceId::Data, &ack(0, 0, vec![]), t + ms(100)).unwrap();
+
+            /* ten packets in a row are lost, then one gets through */
+            let start = t + ms(100);
+            for pn in 1..=11 {
+                recovery.on_packet_sent(SpaceId::Data, packet(pn, start + spacing * pn as u32));
+            }
+            let outcome = recovery.on_ack_received(SpaceId::Data, &ack(11, 0, vec![]), start + spacing * 11 + ms(100)).unwrap();
+            assert_eq!(pns(&outcome.lost), (1..=10).collect::<Vec<_>>());
+            assert_eq!(outcome.persistent_congestion, expected, "{:?}", spacing);
+        }
+    }
+
+    #[test]
+    fn ack_of_an_unsent_packet_is_an_error() {
+        let t = Instant::now();
+        let mut recovery = LossDetection::new(false);
+        assert!(recovery.on_ack_received(SpaceId::Initial, &ack(0, 0, vec![]), t).is_err());
+        recovery.on_packet_sent(SpaceId::Initial, packet(0, t));
+        assert!(recovery.on_ack_received(SpaceId::Initial, &ack(1, 1, vec![]), t).is_err());
+        assert!(recovery.on_ack_received(SpaceId::Initial, &ack(0, 0, vec![]), t).is_ok());
+    }
+
+    #[test]
+    fn discarding_a_space_forgets_its_packets() {
+        let t = Instant::now();
+        let mut recovery = LossDetection::new(false);
+        recovery.on_packet_sent(SpaceId::Initial, packet(0, t));
+        recovery.on_packet_sent(SpaceId::Handshake, packet(0, t));
+        recovery.discard_space(SpaceId::Initial);
+        assert_eq!(recovery.bytes_in_flight(), 1200);
+        assert_eq!(recovery.unacked(SpaceId::Initial).count(), 0);
+        assert_eq!(recovery.unacked(SpaceId::Handshake).count(), 1);
+    }
+
+    #[test]
+    fn ack_tracker_delays_and_ranges() {
+        let t = Instant::now();
+        let mut acks = AckTracker::new(ms(25));
+        acks.on_packet_received(0, true, t);
+        assert!(!acks.wants_ack(t) && acks.has_pending());
+        assert_eq!(acks.deadline(), Some(t + ms(25)));
+        assert!(acks.wants_ack(t + ms(25)));
+        /* every second ack-eliciting packet is acknowledged right away */
+        acks.on_packet_received(1, true, t + ms(1));
+        assert!(acks.wants_ack(t + ms(1)));
+        let frame = acks.ack_frame(t + ms(9), 3).unwrap();
+        assert_eq!((frame.largest, frame.first_range, frame.delay), (1, 1, 1000));
+        assert!(!acks.has_pending() && acks.deadline().is_none());
+
+        /* a gap is reported at once */
+        acks.on_packet_received(2, false, t + ms(10));
+        assert!(!acks.has_pending());
+        acks.on_packet_received(5, true, t + ms(11));
+        assert!(acks.wants_ack(t + ms(11)));
+        acks.on_packet_received(9, true, t + ms(12));
+        acks.on_packet_received(6, true, t + ms(13));
+        assert!(acks.is_duplicate(6) && !acks.is_duplicate(7));
+        let frame = acks.ack_frame(t + ms(12), 3).unwrap();
+        assert_eq!(frame.acked().collect::<Vec<_>>(), [(9, 9), (5, 6), (0, 2)]);
+        assert_eq!((frame.first_range, frame.ranges.clone()), (0, vec![(1, 1), (1, 2)]));
+
+        /* Initial and Handshake packets are acknowledged immediately */
+        let mut acks = AckTracker::new(Duration::ZERO);
+        acks.on_packet_received(0, true, t);
+        assert!(acks.wants_ack(t));
+    }
+
+    /* Deterministic xorshift, so every run sees the same losses. */
+    struct Rng(u64);
+
+    impl Rng {
+        fn chance(&mut self, percent: u64) -> bool {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % 100 < percent
+        }
+    }
+
+    struct Link {
+        delay: Duration,
+        loss_percent: u64,
+        rng: Rng,
+        queue: VecDeque<(Instant, u64, Vec<u8>)>, // arrival, packet number, payload
+    }
+
+    impl Link {
+        fn send(&mut self, now: Instant, pn: u64, payload: Vec<u8>) {
+            if !self.rng.chance(self.loss_percent) {
+                self.queue.push_back((now + self.delay, pn, payload));
+            }
+        }
+
+        fn receive(&mut self, now: Instant) -> Option<(u64, Vec<u8>)> {
+            if self.queue.front().is_some_and(|(at, _, _)| *at <= now) {
+                return self.queue.pop_front().map(|(_, pn, payload)| (pn, payload));
+            }
+            None
+        }
+    }
+
+    /* Returns how long 200 messages took to get across, the final RTT estimate and the number of resends. */
+    fn transfer_over_lossy_link(seed: u64) -> (Duration, RttEstimator, usize) {
+        const MESSAGES: u64 = 200;
+        let start = Instant::now();
+        let mut sender = LossDetection::new(false);
+        sender.set_handshake_confirmed();
+        let mut receiver = AckTracker::new(ms(25));
+        let mut data = Link { delay: ms(25), loss_percent: 10, rng: Rng(seed), queue: VecDeque::new() };
+        let mut acks = Link { delay: ms(25), loss_percent: 10, rng: Rng(seed.rotate_left(17) | 1), queue: VecDeque::new() };
+
+        let mut queued: VecDeque<SentFrame> = (0..MESSAGES).map(|i| SentFrame::Crypto { offset: i, data: vec![i as u8] }).collect();
+        let mut delivered = BTreeSet::new();
+        let (mut next_pn, mut resends, mut probes) = (0u64, 0usize, 0u8);
+        let mut now = start;
+        while delivered.len() < MESSAGES as usize {
+            assert!(now - start < Duration::from_secs(60), "stalled with {} of {} delivered", delivered.len(), MESSAGES);
+
+            while let Some((pn, payload)) = data.receive(now) {
+                if !receiver.is_duplicate(pn) {
+                    delivered.extend(payload);
+                    receiver.on_packet_received(pn, true, now);
+                }
+            }
+            if receiver.wants_ack(now) {
+                let frame = receiver.ack_frame(now, 3).unwrap();
+                let mut encoded = Vec::new();
+                voidio::net::QuicFrame::Ack(frame).encode(&mut encoded).unwrap();
+                acks.send(now, 0, encoded);
+            }
+
+            while let Some((_, encoded)) = acks.receive(now) {
+                let (voidio::net::QuicFrame::Ack(frame), _) = voidio::net::QuicFrame::decode(&encoded).unwrap() else { unreachable!() };
+                let outcome = sender.on_ack_received(SpaceId::Data, &frame, now).unwrap();
+                resends += outcome.lost.len();
+                queued.extend(outcome.lost.into_iter().flat_map(|p| p.frames));
+            }
+            if sender.timeout().is_some_and(|at| at <= now) {
+                match sender.on_timeout(now) {
+                    Some(RecoveryTimeout::Lost { packets, .. }) => {
+                        resends += packets.len();
+                        queued.extend(packets.into_iter().flat_map(|p| p.frames));
+                    }
+                    Some(RecoveryTimeout::Probe { .. }) => probes = 2,
+                    None => {}
+                }
+            }
+
+            /* one packet per millisecond, at most 16 in flight unless a probe is owed */
+            if sender.bytes_in_flight() < 16 * 1200 || probes > 0 {
+                let frames: Vec<SentFrame> = match queued.pop_front() {
+                    Some(frame) => vec![frame],
+                    None if probes > 0 => sender.unacked(SpaceId::Data).next().map(|p| p.frames.clone()).unwrap_or_default(),
+                    None => vec![],
+                };
+                if !frames.is_empty() || probes > 0 {
+                    probes = probes.saturating_sub(1);
+                    let payload = frames.iter().map(|f| match f { SentFrame::Crypto { offset, .. } => *offset as u8, _ => unreachable!() }).collect();
+                    sender.on_packet_sent(SpaceId::Data, SentPacket { pn: next_pn, time_sent: now, size: 1200, ack_eliciting: true, in_flight: true, frames });
+                    data.send(now, next_pn, payload);
+                    next_pn += 1;
+                }
+            }
+            now += ms(1);
+        }
+        (now - start, *sender.rtt(), resends)
+    }
+
+    #[test]
+    fn reliable_transfer_over_a_lossy_link() {
+        let (elapsed, rtt, resends) = transfer_over_lossy_link(0x2545_f491_4f6c_dd1d);
+        assert!(resends > 0, "the link should have lost something");
+        assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+        /* 50 ms round trip, plus up to max_ack_delay for ACKs that waited */
+        assert_eq!(rtt.min(), ms(50));
+        assert!(rtt.smoothed() >= ms(50) && rtt.smoothed() <= ms(80), "{:?}", rtt.smoothed());
+
+        /* the simulation only depends on the seed */
+        let again = transfer_over_lossy_link(0x2545_f491_4f6c_dd1d);
+        assert_eq!((elapsed, resends), (again.0, again.2));
+    }
+}
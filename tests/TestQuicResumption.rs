@@ -0,0 +1,292 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::{QuicClient, QuicConnectionEvent, QuicServer, QuicStream};
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    /* What the server saw: connections reported and, per bidirectional stream, whether it started in 0-RTT data. */
+    #[derive(Default)]
+    struct Seen {
+        connections: AtomicUsize,
+        streams: Mutex<Vec<bool>>,
+    }
+
+    /* A server answering every bidirectional stream with "welcome" as soon as it is opened. */
+    fn voidio_server(port: u16, early_data: bool, seen: Arc<Seen>) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server
+            .set_certificate_chain(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN])
+            .set_early_data(early_data);
+        server.on_connection(move |event: QuicConnectionEvent| {
+            seen.connections.fetch_add(1, Ordering::Relaxed);
+            let seen = seen.clone();
+            event.connection.on_stream(move |stream: &mut QuicStream| {
+                if !voidio::net::is_unidirectional(stream.id()) {
+                    seen.streams.lock().unwrap().push(stream.is_early_data());
+                    stream.write(b"welcome").unwrap();
+                    stream.finish().unwrap();
+                }
+            });
+        });
+        server.start(1);
+        server
+    }
+
+    fn voidio_client(port: u16) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", port));
+        client.set_address(SocketAddr::from(([127, 0, 0, 1], port))).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client
+    }
+
+    /* Sends "hello" on `stream` and collects the answer into `answer`, setting `done` once the stream closes. */
+    fn ask(stream: &mut QuicStream, answer: &Arc<Mutex<Vec<u8>>>, done: &Arc<AtomicBool>) {
+        stream.write(b"hello").unwrap();
+        stream.finish().unwrap();
+        let sink = answer.clone();
+        stream.on_data(move |chunk| sink.lock().unwrap().extend_from_slice(chunk));
+        let done = done.clone();
+        stream.on_close(move || done.store(true, Ordering::Relaxed));
+    }
+
+    fn wait_for_answer(client: &mut QuicClient, answer: &Arc<Mutex<Vec<u8>>>, done: &Arc<AtomicBool>) -> Vec<u8> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done.load(Ordering::Relaxed) && Instant::now() < deadline {
+            client.poll(Duration::from_millis(20)).unwrap();
+        }
+        std::mem::take(&mut *answer.lock().unwrap())
+    }
+
+    fn request(client: &mut QuicClient) -> Vec<u8> {
+        let (answer, done) = (Arc::new(Mutex::new(Vec::new())), Arc::new(AtomicBool::new(false)));
+        ask(&mut client.open_bistream().unwrap(), &answer, &done);
+        wait_for_answer(client, &answer, &done)
+    }
+
+    /* Asks on a stream opened in `on_early_data`, so the request goes out in 0-RTT when it can. */
+    fn early_request(client: &mut QuicClient) -> (Arc<Mutex<Vec<u8>>>, Arc<AtomicBool>) {
+        let (answer, done) = (Arc::new(Mutex::new(Vec::new())), Arc::new(AtomicBool::new(false)));
+        let (sink, closed) = (answer.clone(), done.clone());
+        client.on_early_data(move |conn| ask(&mut conn.open_bistream().unwrap(), &sink, &closed));
+        (answer, done)
+    }
+
+    #[test]
+    fn a_second_connection_resumes_the_session() {
+        const PORT: u16 = 4494;
+        let seen = Arc::new(Seen::default());
+        let mut server = voidio_server(PORT, false, seen.clone());
+        let mut client = voidio_client(PORT);
+        client.connect().unwrap();
+        assert!(!client.connection().unwrap().is_resumed());
+        assert_eq!(request(&mut client), b"welcome");
+        client.close(0, "").unwrap();
+
+        client.connect().unwrap();
+        let conn = client.connection().unwrap();
+        assert!(conn.is_resumed());
+        assert!(!conn.is_early_data_accepted());
+        assert_eq!(request(&mut client), b"welcome");
+        assert_eq!(*seen.streams.lock().unwrap(), vec![false, false]);
+        server.stop();
+    }
+
+    #[test]
+    fn early_data_is_answered_in_the_first_round_trip() {
+        const PORT: u16 = 4495;
+        let seen = Arc::new(Seen::default());
+        let mut server = voidio_server(PORT, true, seen.clone());
+        let mut client = voidio_client(PORT);
+        client.set_early_data(true);
+        let (answer, done) = early_request(&mut client);
+        /* nothing to resume yet, the first connection sends no 0-RTT data */
+        client.connect().unwrap();
+        assert!(!done.load(Ordering::Relaxed));
+        assert_eq!(request(&mut client), b"welcome");
+        client.close(0, "").unwrap();
+
+        client.connect().unwrap();
+        let conn = client.connection().unwrap();
+        assert!(conn.is_resumed());
+        assert!(conn.is_early_data_accepted());
+        assert_eq!(wait_for_answer(&mut client, &answer, &done), b"welcome");
+        assert_eq!(*seen.streams.lock().unwrap(), vec![false, true]);
+        server.stop();
+    }
+
+    #[test]
+    fn rejected_early_data_is_sent_again() {
+        const PORT: u16 = 4496;
+        let seen = Arc::new(Seen::default());
+        let mut server = voidio_server(PORT, true, seen.clone());
+        let mut client = voidio_client(PORT);
+        client.set_early_data(true);
+        client.connect().unwrap();
+        assert_eq!(request(&mut client), b"welcome");
+        client.close(0, "").unwrap();
+        server.stop();
+
+        /* a restarted server knows none of the tickets it handed out */
+        let mut server = voidio_server(PORT, true, seen.clone());
+        let (answer, done) = early_request(&mut client);
+        client.connect().unwrap();
+        let conn = client.connection().unwrap();
+        assert!(!conn.is_resumed());
+        assert!(!conn.is_early_data_accepted());
+        assert_eq!(wait_for_answer(&mut client, &answer, &done), b"welcome");
+        assert_eq!(*seen.streams.lock().unwrap(), vec![false, false]);
+        server.stop();
+    }
+
+    /* Relays datagrams between one client and the server, keeping what the client sent. */
+    fn proxy(port: u16, server: SocketAddr, captured: Arc<Mutex<Vec<Vec<u8>>>>, stop: Arc<AtomicBool>) -> std::thread::JoinHandle<()> {
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], port))).unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 65535];
+            let mut client = None;
+            while !stop.load(Ordering::Relaxed) {
+                let Ok((len, from)) = socket.recv_from(&mut buf) else { continue; };
+                if from == server {
+                    if let Some(client) = client {
+                        socket.send_to(&buf[..len], client).unwrap();
+                    }
+                } else {
+                    client = Some(from);
+                    captured.lock().unwrap().push(buf[..len].to_vec());
+                    socket.send_to(&buf[..len], server).unwrap();
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn a_replayed_first_flight_gets_no_early_data() {
+        const PORT: u16 = 4497;
+        const PROXY_PORT: u16 = 4498;
+        let seen = Arc::new(Seen::default());
+        let mut server = voidio_server(PORT, true, seen.clone());
+        let (captured, stop) = (Arc::new(Mutex::new(Vec::new())), Arc::new(AtomicBool::new(false)));
+        let relay = proxy(PROXY_PORT, SocketAddr::from(([127, 0, 0, 1], PORT)), captured.clone(), stop.clone());
+        let mut client = voidio_client(PORT);
+        client.set_address(SocketAddr::from(([127, 0, 0, 1], PROXY_PORT))).set_early_data(true);
+        client.connect().unwrap();
+        assert_eq!(request(&mut client), b"welcome");
+        client.close(0, "").unwrap();
+
+        captured.lock().unwrap().clear();
+        let (answer, done) = early_request(&mut client);
+        client.connect().unwrap();
+        assert!(client.connection().unwrap().is_early_data_accepted());
+        assert_eq!(wait_for_answer(&mut client, &answer, &done), b"welcome");
+        assert_eq!(seen.connections.load(Ordering::Relaxed), 2);
+
+        /* the same ClientHello and 0-RTT request from elsewhere: the ticket was used, no request reaches the application */
+        let first_flight = captured.lock().unwrap()[0].clone();
+        let attacker = UdpSocket::bind("127.0.0.1:0").unwrap();
+        attacker.send_to(&first_flight, SocketAddr::from(([127, 0, 0, 1], PORT))).unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(seen.connections.load(Ordering::Relaxed), 2);
+        assert_eq!(*seen.streams.lock().unwrap(), vec![false, true]);
+
+        stop.store(true, Ordering::Relaxed);
+        relay.join().unwrap();
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quinn_sends_early_data_to_a_voidio_server() {
+        const PORT: u16 = 4499;
+        let seen = Arc::new(Seen::default());
+        let mut server = voidio_server(PORT, true, seen.clone());
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        crypto.enable_early_data = true;
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        let address = SocketAddr::from(([127, 0, 0, 1], PORT));
+
+        let connection = endpoint.connect(address, "localhost").unwrap().await.unwrap();
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(recv.read_to_end(64).await.unwrap(), b"welcome");
+        connection.close(0u32.into(), b"");
+
+        let Ok((connection, accepted)) = endpoint.connect(address, "localhost").unwrap().into_0rtt() else { panic!("no 0-RTT") };
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.finish().unwrap();
+        let answer = tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(64)).await.unwrap().unwrap();
+        assert_eq!(answer, b"welcome");
+        assert!(accepted.await);
+        assert_eq!(*seen.streams.lock().unwrap(), vec![false, true]);
+        connection.close(0u32.into(), b"");
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_quinn_server_takes_voidio_early_data() {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        crypto.max_early_data_size = u32::MAX;
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = endpoint.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let connection = endpoint.accept().await.unwrap().await.unwrap();
+                tokio::spawn(async move {
+                    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                        recv.read_to_end(64).await.unwrap();
+                        send.write_all(b"welcome").await.unwrap();
+                        send.finish().unwrap();
+                    }
+                });
+            }
+        });
+
+        tokio::task::spawn_blocking(move || {
+            let mut client = voidio_client(port);
+            client.set_early_data(true);
+            client.connect().unwrap();
+            assert_eq!(request(&mut client), b"welcome");
+            client.close(0, "").unwrap();
+
+            let (answer, done) = early_request(&mut client);
+            client.connect().unwrap();
+            let conn = client.connection().unwrap();
+            assert!(conn.is_resumed());
+            assert!(conn.is_early_data_accepted());
+            assert_eq!(wait_for_answer(&mut client, &answer, &done), b"welcome");
+            client.close(0, "").unwrap();
+        })
+        .await
+        .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::{verify_retry_integrity, write_retry_packet, QuicClient, QuicConnectionEvent, QuicPacket, QuicPacketHeader, QuicServer, RetryPolicy, QUIC_VERSION_1, QUIC_VERSION_2};
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    fn voidio_server(port: u16, chain: Vec<CertificateDer<'static>>, policy: RetryPolicy) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server
+            .set_certificate_chain(chain, PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN])
+            .set_retry_policy(policy);
+        server.on_connection(|_: QuicConnectionEvent| {});
+        server.start(1);
+        server
+    }
+
+    fn voidio_client(port: u16, address: SocketAddr) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", port));
+        client.set_address(address).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client
+    }
+
+    #[test]
+    fn retry_integrity_tag() {
+        /* RFC 9001 Appendix A.4 */
+        let original_dcid = hex("8394c8f03e515708");
+        let packet = hex("ff000000010008f067a5502a4262b5746f6b656e04a265ba2eff4d829058fb3f0f2496ba");
+        assert!(verify_retry_integrity(&original_dcid, &packet));
+        assert!(!verify_retry_integrity(&hex("8394c8f03e515709"), &packet));
+        let mut altered = packet.clone();
+        altered[20] ^= 1;
+        assert!(!verify_retry_integrity(&original_dcid, &altered));
+        assert!(!verify_retry_integrity(&original_dcid, &packet[..10]));
+
+        /* the unused bits of the first byte are random, the rest is the same packet */
+        let written = write_retry_packet(QUIC_VERSION_1, &[], &hex("f067a5502a4262b5"), &original_dcid, b"token");
+        assert_eq!(written[0] & 0xf0, 0xf0);
+        assert_eq!(&written[1..written.len() - 16], &packet[1..packet.len() - 16]);
+        assert!(verify_retry_integrity(&original_dcid, &written));
+        let Some(QuicPacket { header: QuicPacketHeader::Retry(retry), .. }) = QuicPacket::parse(&written, 0) else { panic!("not a Retry") };
+        assert_eq!((retry.scid, retry.token), (&hex("f067a5502a4262b5")[..], &b"token"[..]));
+    }
+
+    #[test]
+    fn retry_integrity_tag_v2() {
+        /* RFC 9369 Appendix A.4 */
+        let original_dcid = hex("8394c8f03e515708");
+        let packet = hex("cf6b3343cf0008f067a5502a4262b5746f6b656ec8646ce8bfe33952d955543665dcc7b6");
+        assert!(verify_retry_integrity(&original_dcid, &packet));
+        /* the same packet under the v1 key does not verify */
+        let mut v1 = packet.clone();
+        v1[1..5].copy_from_slice(&QUIC_VERSION_1.to_be_bytes());
+        assert!(!verify_retry_integrity(&original_dcid, &v1));
+
+        let written = write_retry_packet(QUIC_VERSION_2, &[], &hex("f067a5502a4262b5"), &original_dcid, b"token");
+        assert_eq!(written[0] & 0xf0, 0xc0);
+        assert_eq!(&written[1..written.len() - 16], &packet[1..packet.len() - 16]);
+        assert!(verify_retry_integrity(&original_dcid, &written));
+        let Some(QuicPacket { header: QuicPacketHeader::Retry(retry), .. }) = QuicPacket::parse(&written, 0) else { panic!("not a Retry") };
+        assert_eq!(retry.version, QUIC_VERSION_2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quinn_client_follows_a_retry() {
+        const PORT: u16 = 4461;
+        let mut server = voidio_server(PORT, certificate_chain(), RetryPolicy::Always);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        /* quinn checks the integrity tag and that retry_source_connection_id names the Retry's CID */
+        for _ in 0..3 {
+            let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+            let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap();
+            connection.close(0u32.into(), b"done");
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn client_follows_a_retry_then_uses_its_new_token() {
+        const PORT: u16 = 4462;
+        let mut server = voidio_server(PORT, certificate_chain(), RetryPolicy::Always);
+        let mut client = voidio_client(PORT, SocketAddr::from(([127, 0, 0, 1], PORT)));
+
+        client.connect().unwrap();
+        let params = client.connection().unwrap().peer_transport_parameters().unwrap().clone();
+        assert!(params.retry_source_connection_id.is_some(), "the handshake did not go through a Retry");
+        assert_ne!(params.original_destination_connection_id, params.retry_source_connection_id);
+
+        /* the token from NEW_TOKEN validates the address of the next connection */
+        client.connect().unwrap();
+        let params = client.connection().unwrap().peer_transport_parameters().unwrap();
+        assert_eq!(params.retry_source_connection_id, None);
+
+        /* a token is used once */
+        client.connect().unwrap();
+        client.poll(Duration::from_millis(50)).unwrap();
+        client.connect().unwrap();
+        assert_eq!(client.connection().unwrap().peer_transport_parameters().unwrap().retry_source_connection_id, None);
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn client_follows_a_quinn_retry() {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let incoming = endpoint.accept().await.unwrap();
+            assert!(!incoming.remote_address_validated());
+            incoming.retry().unwrap();
+            let incoming = endpoint.accept().await.unwrap();
+            assert!(incoming.remote_address_validated());
+            let connection = incoming.await.unwrap();
+            let _ = tokio::time::timeout(Duration::from_secs(2), connection.closed()).await;
+        });
+        tokio::task::spawn_blocking(move || {
+            let mut client = voidio_client(address.port(), address);
+            client.connect().unwrap();
+            assert!(client.connection().unwrap().peer_transport_parameters().unwrap().retry_source_connection_id.is_some());
+        })
+        .await
+        .unwrap();
+        server.abort();
+    }
+
+    /*
+    Relays between a client and `server`, letting only the client's first datagram through until `release` is set.
+    Counts what each side sent.
+    */
+    fn holding_relay(server: SocketAddr, release: Arc<AtomicBool>, from_client: Arc<AtomicUsize>, from_server: Arc<AtomicUsize>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(15);
+            let mut buf = [0u8; 65535];
+            let mut client = None;
+            while Instant::now() < deadline {
+                let Ok((len, from)) = socket.recv_from(&mut buf) else { continue; };
+                if from == server {
+                    from_server.fetch_add(len, Ordering::SeqCst);
+                    if let Some(client) = client {
+                        socket.send_to(&buf[..len], client).unwrap();
+                    }
+                } else if client.is_none() || release.load(Ordering::SeqCst) {
+                    client = Some(from);
+                    from_client.fetch_add(len, Ordering::SeqCst);
+                    socket.send_to(&buf[..len], server).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn server_sends_three_times_what_it_received_before_validation() {
+        const PORT: u16 = 4463;
+        /* a long chain makes the server's first flight larger than it may send to an unvalidated address */
+        let mut chain = certificate_chain();
+        chain.extend(std::iter::repeat_n(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap(), 8));
+        let mut server = voidio_server(PORT, chain, RetryPolicy::Never);
+
+        let (release, from_client, from_server) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let relay = holding_relay(SocketAddr::from(([127, 0, 0, 1], PORT)), release.clone(), from_client.clone(), from_server.clone());
+        let client = thread::spawn(move || voidio_client(PORT, relay).connect());
+
+        thread::sleep(Duration::from_millis(700));
+        let (received, sent) = (from_client.load(Ordering::SeqCst), from_server.load(Ordering::SeqCst));
+        assert!(received >= 1200, "the client's first datagram is {} bytes", received);
+        assert!(sent > 0 && sent <= 3 * received, "{} bytes sent for {} received", sent, received);
+
+        /* once the client answers with a Handshake packet its address is validated and the rest follows */
+        release.store(true, Ordering::SeqCst);
+        client.join().unwrap().unwrap();
+        assert!(from_server.load(Ordering::SeqCst) > 3 * received);
+        server.stop();
+    }
+}
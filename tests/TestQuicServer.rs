@@ -1,37 +1,70 @@
 #[cfg(test)]
 mod tests {
     use std::net::SocketAddr;
-    use std::thread;
+    use std::sync::{mpsc, Arc};
     use std::time::Duration;
 
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
     use voidio::net::{DispatchMode, QuicConnectionEvent, QuicServer};
 
-    fn handle_connections(event: QuicConnectionEvent) {
-        let client = event.connection;
-        println!("New connection from: {}", client.address());
-        /*
-        client.on_stream(|stream| {
-            println!("New stream opened: {}", stream);
-            stream.on_data(|data| {
-                println!("Received stream data: {:?}", data);
-            });
-        });*/
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn load_certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    fn quinn_client_config() -> quinn::ClientConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap()))
     }
 
-    #[test]
-    fn test_quic_server_with_quinn_client() {
+    #[tokio::test]
+    async fn test_quic_server_with_quinn_client() {
         let address: SocketAddr = "127.0.0.1:4433".parse().unwrap();
+        let (connected_tx, connected_rx) = mpsc::channel();
 
-        // Start server in a background thread
-        thread::spawn(move || {
-            println!("Starting QUIC server on {}", address);
-            let mut server = QuicServer::new(address);
-            server.set_datagram_dispatch_mode(DispatchMode::Direct);
-            server.on_connection(handle_connections);
-            server.start(4);
+        let mut server = QuicServer::new(address);
+        server.set_datagram_dispatch_mode(DispatchMode::Direct);
+        server
+            .set_certificate_chain(load_certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[b"h3", ALPN]);
+        server.on_connection(move |event: QuicConnectionEvent| {
+            let client = event.connection;
+            println!("New connection from: {}", client.address());
+            connected_tx.send((*client.address(), client.alpn_protocol().map(|p| p.to_vec()))).unwrap();
         });
+        server.start(4);
+
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn_client_config());
+        let connecting = endpoint.connect(address, "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting)
+            .await
+            .expect("handshake timed out")
+            .expect("handshake failed");
+
+        let handshake = connection.handshake_data().unwrap().downcast::<quinn::crypto::rustls::HandshakeData>().unwrap();
+        assert_eq!(handshake.protocol.as_deref(), Some(ALPN));
+
+        /* the server reports the connection once it has the client's Finished */
+        let (peer, alpn) = connected_rx.recv_timeout(Duration::from_secs(5)).expect("on_connection was not called");
+        assert_eq!(peer, endpoint.local_addr().unwrap());
+        assert_eq!(alpn.as_deref(), Some(ALPN));
 
-        thread::sleep(Duration::from_millis(500)); // let server start
-        println!("Test finished");
+        /* HANDSHAKE_DONE confirms the handshake for quinn, and the connection stays up */
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(connection.stats().frame_rx.handshake_done, 1);
+        assert!(connection.close_reason().is_none(), "{:?}", connection.close_reason());
+        connection.close(0u32.into(), b"done");
+        server.stop();
     }
 }
@@ -1,5 +1,7 @@
 #[cfg(test)]
 mod tests {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
     use voidio::*;
     use voidio::net::{DispatchMode, QuicConnectionEvent, QuicServer, UdpFloodTest};
 
@@ -35,6 +37,8 @@ mod tests {
             let mut server = QuicServer::new(address);
             // Direct dispatch mode toggle (< 30ns overhead, useful for packets that purely modify memory or tick state such as gaming servers), no async processing tax.
             server.set_datagram_dispatch_mode(DispatchMode::Direct);
+            let chain = CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap();
+            server.set_certificate_chain(chain, PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap()).unwrap();
             // This is always directly dispatched by one of the worker threads initiated in the .start(N) method upon decrypting the Crypto frame of the Initial packet.
             server.on_connection(handle_connections);
             server.start(8); // Start the server with 32 worker threads.
@@ -0,0 +1,257 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::connection::QuicConnection;
+    use voidio::net::{DispatchMode, QuicClient, QuicConnectionEvent, QuicServer, QuicStream, DEFAULT_MAX_STREAMS, DEFAULT_MAX_STREAM_DATA};
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn certificate_chain() -> Vec<CertificateDer<'static>> {
+        CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    fn voidio_server<H>(port: u16, handler: H) -> QuicServer
+    where
+        H: Fn(QuicConnectionEvent) + Send + Sync + 'static,
+    {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server.set_datagram_dispatch_mode(DispatchMode::Direct);
+        server
+            .set_certificate_chain(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN]);
+        server.on_connection(handler);
+        server.start(2);
+        server
+    }
+
+    async fn quinn_connect(port: u16) -> (quinn::Endpoint, quinn::Connection) {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap())));
+        let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], port)), "localhost").unwrap();
+        let connection = tokio::time::timeout(Duration::from_secs(5), connecting).await.expect("handshake timed out").expect("handshake failed");
+        (endpoint, connection)
+    }
+
+    fn quinn_server() -> quinn::Endpoint {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(certificate_chain(), PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap()));
+        quinn::Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap()
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn server_streams_with_quinn_client() {
+        const PORT: u16 = 4441;
+        let (received_tx, received_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, move |event: QuicConnectionEvent| {
+            let received_tx = Mutex::new(received_tx.clone());
+            event.connection.on_stream(move |stream: &mut QuicStream| {
+                let id = stream.id();
+                let data = Arc::new(Mutex::new(Vec::new()));
+                let sink = data.clone();
+                stream.on_data(move |chunk| sink.lock().unwrap().extend_from_slice(chunk));
+                let done = Mutex::new(received_tx.lock().unwrap().clone());
+                stream.on_close(move || done.lock().unwrap().send((id, std::mem::take(&mut *data.lock().unwrap()))).unwrap());
+                if !voidio::net::is_unidirectional(id) {
+                    stream.write(b"welcome").unwrap();
+                    stream.finish().unwrap();
+                }
+            });
+            /* a stream the server opens as soon as the handshake is done */
+            let mut push = event.connection.open_unistream().unwrap();
+            push.write(b"server push").unwrap();
+            push.finish().unwrap();
+        });
+        let (_endpoint, connection) = quinn_connect(PORT).await;
+
+        let mut push = connection.accept_uni().await.unwrap();
+        assert_eq!(push.read_to_end(64).await.unwrap(), b"server push");
+
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"hello voidio").await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(recv.read_to_end(64).await.unwrap(), b"welcome");
+        let (id, data) = received_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((id, data.as_slice()), (0, &b"hello voidio"[..]));
+
+        /* three times the stream window: the server has to extend it with MAX_STREAM_DATA */
+        let payload = pattern(3 * DEFAULT_MAX_STREAM_DATA as usize);
+        let mut send = connection.open_uni().await.unwrap();
+        send.write_all(&payload).await.unwrap();
+        send.finish().unwrap();
+        let (id, data) = received_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(id, 2);
+        assert!(data == payload, "{} of {} bytes", data.len(), payload.len());
+
+        /* more streams than the initial limit, one after the other: closed streams give their credit back */
+        for _ in 0..DEFAULT_MAX_STREAMS + 20 {
+            let (mut send, mut recv) = connection.open_bi().await.unwrap();
+            send.write_all(b"again").await.unwrap();
+            send.finish().unwrap();
+            assert_eq!(tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(64)).await.unwrap().unwrap(), b"welcome");
+            assert_eq!(received_rx.recv_timeout(Duration::from_secs(5)).unwrap().1, b"again");
+        }
+        connection.close(0u32.into(), b"done");
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reset_and_stop_sending() {
+        const PORT: u16 = 4442;
+        let (closed_tx, closed_rx) = mpsc::channel();
+        let mut server = voidio_server(PORT, move |event: QuicConnectionEvent| {
+            let closed_tx = Mutex::new(closed_tx.clone());
+            event.connection.on_stream(move |stream: &mut QuicStream| {
+                let done = Mutex::new(closed_tx.lock().unwrap().clone());
+                let id = stream.id();
+                stream.on_close(move || done.lock().unwrap().send(id).unwrap());
+                match id >> 2 {
+                    0 => stream.stop_sending(7).unwrap(), // we do not want what the client sends
+                    _ => {
+                        stream.write(b"partial").unwrap();
+                        stream.reset(9).unwrap();
+                    }
+                }
+            });
+        });
+        let (_endpoint, connection) = quinn_connect(PORT).await;
+
+        /* STOP_SENDING makes the sender stop, and the reset the client answers with closes the stream */
+        let (mut send, _recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"unwanted").await.unwrap();
+        let stopped = tokio::time::timeout(Duration::from_secs(5), send.stopped()).await.unwrap().unwrap();
+        assert_eq!(stopped, Some(7u32.into()));
+        send.reset(7u32.into()).unwrap();
+        assert_eq!(closed_rx.recv_timeout(Duration::from_secs(5)).unwrap(), 0);
+
+        /* RESET_STREAM reaches the reader with its error code */
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"hi").await.unwrap();
+        let error = recv.read_to_end(64).await.unwrap_err();
+        assert!(matches!(error, quinn::ReadToEndError::Read(quinn::ReadError::Reset(code)) if code == 9u32.into()), "{:?}", error);
+
+        /* the client resetting its side of a stream closes it on the server */
+        send.reset(3u32.into()).unwrap();
+        assert_eq!(closed_rx.recv_timeout(Duration::from_secs(5)).unwrap(), 4);
+        connection.close(0u32.into(), b"done");
+        server.stop();
+    }
+
+    /*
+    A UDP relay between a client and `server` that holds back every third datagram until the next one
+    has passed and drops every 25th once the handshake is through, in both directions.
+    */
+    fn reordering_relay(server: SocketAddr, stop: Arc<AtomicBool>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+        let address = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut client: Option<SocketAddr> = None;
+            let mut held: [Option<Vec<u8>>; 2] = [None, None];
+            let mut count = [0usize; 2];
+            let mut buf = vec![0u8; 65535];
+            while !stop.load(Ordering::Relaxed) {
+                let Ok((len, from)) = socket.recv_from(&mut buf) else {
+                    /* nothing else came: let held datagrams go */
+                    for (direction, held) in held.iter_mut().enumerate() {
+                        let to = if direction == 0 { Some(server) } else { client };
+                        if let (Some(datagram), Some(to)) = (held.take(), to) {
+                            socket.send_to(&datagram, to).unwrap();
+                        }
+                    }
+                    continue;
+                };
+                let direction = if from == server { 1 } else { client = Some(from); 0 };
+                let Some(to) = (if direction == 0 { Some(server) } else { client }) else { continue; };
+                count[direction] += 1;
+                let n = count[direction];
+                if n > 10 && n % 25 == 0 { continue; }
+                if n > 10 && n % 3 == 0 && held[direction].is_none() {
+                    held[direction] = Some(buf[..len].to_vec());
+                    continue;
+                }
+                socket.send_to(&buf[..len], to).unwrap();
+                if let Some(datagram) = held[direction].take() {
+                    socket.send_to(&datagram, to).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn client_stream_over_a_reordering_lossy_path() {
+        let endpoint = quinn_server();
+        let stop = Arc::new(AtomicBool::new(false));
+        let relay = reordering_relay(endpoint.local_addr().unwrap(), stop.clone());
+
+        /* an echo server */
+        let server = tokio::spawn(async move {
+            let connection = endpoint.accept().await.unwrap().await.unwrap();
+            let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+            let data = recv.read_to_end(16 << 20).await.unwrap();
+            send.write_all(&data).await.unwrap();
+            send.finish().unwrap();
+            let _ = tokio::time::timeout(Duration::from_secs(10), connection.closed()).await;
+        });
+
+        let payload = pattern(400_000);
+        let expected = payload.clone();
+        let echoed = tokio::task::spawn_blocking(move || {
+            let mut client = QuicClient::new(&format!("localhost:{}", relay.port()));
+            client.set_address(relay).set_alpn_protocols(&[ALPN]);
+            client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+            client.connect().unwrap();
+
+            let echoed = Arc::new(Mutex::new(Vec::new()));
+            let closed = Arc::new(AtomicBool::new(false));
+            let mut stream = client.open_bistream().unwrap();
+            assert_eq!(stream.id(), 0);
+            stream.write(&payload).unwrap();
+            stream.finish().unwrap();
+            let sink = echoed.clone();
+            stream.on_data(move |chunk| sink.lock().unwrap().extend_from_slice(chunk));
+            let done = closed.clone();
+            stream.on_close(move || done.store(true, Ordering::Relaxed));
+
+            let deadline = Instant::now() + Duration::from_secs(30);
+            while !closed.load(Ordering::Relaxed) && Instant::now() < deadline {
+                client.poll(Duration::from_millis(50)).unwrap();
+            }
+            let conn: &mut QuicConnection = client.connection().unwrap();
+            assert!(conn.stream(0).is_none() || conn.stream(0).unwrap().is_finished());
+            let echoed = std::mem::take(&mut *echoed.lock().unwrap());
+            echoed
+        })
+        .await
+        .unwrap();
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(echoed.len(), expected.len());
+        assert!(echoed == expected, "the echo differs");
+        server.abort();
+    }
+}
@@ -0,0 +1,190 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddrV4, SocketAddrV6};
+    use std::time::Duration;
+
+    use proptest::prelude::*;
+    use voidio::net::connection::ConnectionId;
+    use voidio::net::{encode_varint_into, PreferredAddress, TransportParameters, VersionInformation, QUIC_VERSION_1, QUIC_VERSION_2};
+
+    fn param(out: &mut Vec<u8>, id: u64, value: &[u8]) {
+        encode_varint_into(id, out);
+        encode_varint_into(value.len() as u64, out);
+        out.extend_from_slice(value);
+    }
+
+    fn varint(v: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_varint_into(v, &mut out);
+        out
+    }
+
+    /* The least a valid set of client parameters carries. */
+    fn client_minimum() -> Vec<u8> {
+        let mut out = Vec::new();
+        param(&mut out, 0x0f, &[1, 2, 3, 4]);
+        out
+    }
+
+    fn full() -> TransportParameters {
+        TransportParameters {
+            original_destination_connection_id: Some(ConnectionId::from_slice(&[9; 8])),
+            max_idle_timeout: Duration::from_millis(30_000),
+            stateless_reset_token: Some([0x5a; 16]),
+            max_udp_payload_size: 1452,
+            initial_max_data: 1 << 24,
+            initial_max_stream_data_bidi_local: 1 << 20,
+            initial_max_stream_data_bidi_remote: 1 << 19,
+            initial_max_stream_data_uni: 1 << 18,
+            initial_max_streams_bidi: 100,
+            initial_max_streams_uni: 3,
+            ack_delay_exponent: 10,
+            max_ack_delay: Duration::from_millis(40),
+            disable_active_migration: true,
+            preferred_address: Some(PreferredAddress {
+                ipv4: Some("192.0.2.1:4433".parse::<SocketAddrV4>().unwrap()),
+                ipv6: Some("[2001:db8::1]:4433".parse::<SocketAddrV6>().unwrap()),
+                connection_id: ConnectionId::from_slice(&[7; 12]),
+                stateless_reset_token: [0xa5; 16],
+            }),
+            active_connection_id_limit: 8,
+            initial_source_connection_id: Some(ConnectionId::from_slice(&[1; 20])),
+            retry_source_connection_id: Some(ConnectionId::from_slice(&[2; 4])),
+            max_datagram_frame_size: Some(65535),
+            grease_quic_bit: true,
+            version_information: Some(VersionInformation { chosen_version: QUIC_VERSION_2, available_versions: vec![QUIC_VERSION_2, QUIC_VERSION_1] }),
+        }
+    }
+
+    #[test]
+    fn every_parameter_round_trips() {
+        let params = full();
+        assert_eq!(TransportParameters::decode(&params.encode(), true).unwrap(), params);
+
+        /* a preferred address may leave one family out */
+        let mut params = full();
+        params.preferred_address.as_mut().unwrap().ipv6 = None;
+        assert_eq!(TransportParameters::decode(&params.encode(), true).unwrap(), params);
+    }
+
+    #[test]
+    fn absent_parameters_take_their_defaults() {
+        let params = TransportParameters::decode(&client_minimum(), false).unwrap();
+        assert_eq!(params.max_udp_payload_size, 65527);
+        assert_eq!(params.ack_delay_exponent, 3);
+        assert_eq!(params.max_ack_delay, Duration::from_millis(25));
+        assert_eq!(params.active_connection_id_limit, 2);
+        assert_eq!((params.initial_max_data, params.initial_max_streams_bidi), (0, 0));
+        assert_eq!(params.max_datagram_frame_size, None);
+        assert!(!params.disable_active_migration);
+
+        /* defaults are left out of the encoding, one reserved parameter is always there */
+        let defaults = TransportParameters { initial_source_connection_id: Some(ConnectionId::from_slice(&[1])), ..Default::default() };
+        let encoded = defaults.encode();
+        assert!(encoded.starts_with(&[0x0f, 1, 1]), "{:02x?}", encoded);
+        let rest = &encoded[3..];
+        let len = 1 << (rest[0] >> 6);
+        let id = rest[1..len].iter().fold((rest[0] & 0x3f) as u64, |id, &b| (id << 8) | b as u64);
+        assert_eq!(id % 31, 27, "{:02x?}", rest);
+        assert_eq!(TransportParameters::decode(&encoded, false).unwrap(), defaults);
+    }
+
+    #[test]
+    fn unknown_and_reserved_parameters_are_ignored() {
+        let mut buf = client_minimum();
+        param(&mut buf, 31 * 5 + 27, b"grease");
+        param(&mut buf, 0x7f00, &[1, 2, 3]);
+        param(&mut buf, 0x04, &varint(4096));
+        let params = TransportParameters::decode(&buf, false).unwrap();
+        assert_eq!(params.initial_max_data, 4096);
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        for id in [0x04, 0x0c, 0x20, 31 * 9 + 27] {
+            let mut buf = client_minimum();
+            let value = if id == 0x0c { vec![] } else { varint(1) };
+            param(&mut buf, id, &value);
+            param(&mut buf, id, &value);
+            let error = TransportParameters::decode(&buf, false).unwrap_err();
+            assert!(error.to_string().starts_with("TRANSPORT_PARAMETER_ERROR"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn server_only_parameters_from_a_client_are_rejected() {
+        let values: [(u64, Vec<u8>); 4] = [(0x00, vec![1; 8]), (0x02, vec![0; 16]), (0x0d, full().encode()), (0x10, vec![2; 4])];
+        for (id, value) in values {
+            let mut buf = client_minimum();
+            param(&mut buf, id, &value);
+            assert!(TransportParameters::decode(&buf, false).is_err(), "{:#x} accepted from a client", id);
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_are_rejected() {
+        let bad: Vec<(u64, Vec<u8>)> = vec![
+            (0x03, varint(1199)),             // max_udp_payload_size
+            (0x0a, varint(21)),               // ack_delay_exponent
+            (0x0b, varint(1 << 14)),          // max_ack_delay
+            (0x0e, varint(1)),                // active_connection_id_limit
+            (0x08, varint((1 << 60) + 1)),    // initial_max_streams_bidi
+            (0x09, varint((1 << 60) + 1)),    // initial_max_streams_uni
+            (0x04, vec![0x40]),               // truncated varint
+            (0x04, [varint(1), vec![0]].concat()), // trailing bytes
+            (0x0c, vec![1]),                  // disable_active_migration with a value
+            (0x0f, vec![0; 21]),              // a CID longer than 20 bytes
+            (0x11, vec![]),                   // version_information without a chosen version
+            (0x11, vec![0, 0, 0, 1, 0]),      // version_information not made of 4-byte versions
+            (0x11, vec![0; 4]),               // version_information choosing Version Negotiation
+            (0x11, vec![0, 0, 0, 1, 0, 0, 0, 0]), // version_information offering Version Negotiation
+        ];
+        for (id, value) in bad {
+            let mut buf = Vec::new();
+            param(&mut buf, 0x00, &[1; 8]);
+            param(&mut buf, 0x0f, &[1; 4]);
+            param(&mut buf, id, &value);
+            assert!(TransportParameters::decode(&buf, true).is_err(), "{:#x} = {:02x?} accepted", id, value);
+        }
+
+        /* a stateless reset token is exactly 16 bytes and a preferred address needs a CID */
+        let mut params = full();
+        params.preferred_address.as_mut().unwrap().connection_id = ConnectionId::from_slice(&[]);
+        assert!(TransportParameters::decode(&params.encode(), true).is_err());
+        let mut buf = Vec::new();
+        param(&mut buf, 0x00, &[1; 8]);
+        param(&mut buf, 0x0f, &[1; 4]);
+        param(&mut buf, 0x02, &[0; 15]);
+        assert!(TransportParameters::decode(&buf, true).is_err());
+    }
+
+    #[test]
+    fn required_connection_ids() {
+        /* every endpoint sends initial_source_connection_id, a server also original_destination_connection_id */
+        let mut buf = Vec::new();
+        param(&mut buf, 0x04, &varint(1));
+        assert!(TransportParameters::decode(&buf, false).is_err());
+        assert!(TransportParameters::decode(&client_minimum(), true).is_err());
+        assert!(TransportParameters::decode(&[0x0f, 0x40], false).is_err()); // length past the end
+    }
+
+    proptest! {
+        #[test]
+        fn limits_round_trip(values in prop::array::uniform6(0..1u64 << 60), idle in 0..1u64 << 40, exponent in 0..=20u8, delay in 0..1u64 << 14) {
+            let params = TransportParameters {
+                max_idle_timeout: Duration::from_millis(idle),
+                initial_max_data: values[0],
+                initial_max_stream_data_bidi_local: values[1],
+                initial_max_stream_data_bidi_remote: values[2],
+                initial_max_stream_data_uni: values[3],
+                initial_max_streams_bidi: values[4],
+                initial_max_streams_uni: values[5],
+                ack_delay_exponent: exponent,
+                max_ack_delay: Duration::from_millis(delay),
+                initial_source_connection_id: Some(ConnectionId::from_slice(&values[0].to_be_bytes())),
+                ..Default::default()
+            };
+            prop_assert_eq!(TransportParameters::decode(&params.encode(), false).unwrap(), params);
+        }
+    }
+}
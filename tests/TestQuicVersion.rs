@@ -0,0 +1,207 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use rustls::quic::KeyChange;
+    use rustls::Side;
+    use voidio::net::connection::ConnectionId;
+    use voidio::net::{
+        EncryptionLevel, PacketProtector, QuicClient, QuicConnectionEvent, QuicFrame, QuicFrames, QuicPacket, QuicPacketHeader, QuicServer, TransportParameters,
+        VersionInformation, QUIC_VERSION_1, QUIC_VERSION_2,
+    };
+
+    const ALPN: &[u8] = b"voidio-test";
+
+    fn voidio_server(port: u16, versions: &[u32]) -> QuicServer {
+        let mut server = QuicServer::new(SocketAddr::from(([127, 0, 0, 1], port)));
+        server
+            .set_certificate_chain(
+                CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap(),
+                PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap(),
+            )
+            .unwrap()
+            .set_alpn_protocols(&[ALPN])
+            .set_versions(versions)
+            .unwrap();
+        server.on_connection(|_: QuicConnectionEvent| {});
+        server.start(1);
+        server
+    }
+
+    fn voidio_client(port: u16, versions: &[u32]) -> QuicClient {
+        let mut client = QuicClient::new(&format!("localhost:{}", port));
+        client.set_address(SocketAddr::from(([127, 0, 0, 1], port))).set_alpn_protocols(&[ALPN]).set_versions(versions).unwrap();
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        client
+    }
+
+    fn raw_socket(port: u16) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(SocketAddr::from(([127, 0, 0, 1], port))).unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        socket
+    }
+
+    /* A long header packet of `version`, padded to `len` bytes; the part after the CIDs is opaque to version negotiation. */
+    fn long_packet(version: u32, dcid: &[u8], scid: &[u8], len: usize) -> Vec<u8> {
+        let mut packet = vec![0xc0];
+        packet.extend_from_slice(&version.to_be_bytes());
+        packet.push(dcid.len() as u8);
+        packet.extend_from_slice(dcid);
+        packet.push(scid.len() as u8);
+        packet.extend_from_slice(scid);
+        packet.resize(len, 0);
+        packet
+    }
+
+    #[test]
+    fn server_answers_unknown_versions_with_version_negotiation() {
+        const PORT: u16 = 4471;
+        let mut server = voidio_server(PORT, &[QUIC_VERSION_2, QUIC_VERSION_1]);
+        let socket = raw_socket(PORT);
+        let (dcid, scid) = ([0x11; 8], [0x22; 5]);
+
+        socket.send(&long_packet(0x1a2a3a4a, &dcid, &scid, 1200)).unwrap();
+        let mut buf = [0u8; 1500];
+        let len = socket.recv(&mut buf).unwrap();
+        let Some(QuicPacket { header: QuicPacketHeader::VersionNegotiation(vn), .. }) = QuicPacket::parse(&buf[..len], 0) else { panic!("not a Version Negotiation packet") };
+        assert_eq!((vn.dcid, vn.scid), (&scid[..], &dcid[..]));
+        let versions: Vec<u32> = vn.supported.chunks_exact(4).map(|v| u32::from_be_bytes(v.try_into().unwrap())).collect();
+        assert_eq!(&versions[..2], &[QUIC_VERSION_2, QUIC_VERSION_1]);
+        assert_eq!(versions.len(), 3);
+        assert_eq!(versions[2] & 0x0f0f0f0f, 0x0a0a0a0a, "{:#x} is not a reserved version", versions[2]);
+
+        /* a datagram too small to start a connection gets nothing back */
+        socket.send(&long_packet(0x1a2a3a4a, &dcid, &scid, 1199)).unwrap();
+        assert!(socket.recv(&mut buf).is_err());
+        server.stop();
+    }
+
+    #[test]
+    fn client_and_server_speak_v2() {
+        const PORT: u16 = 4472;
+        let mut server = voidio_server(PORT, &[QUIC_VERSION_1, QUIC_VERSION_2]);
+        let mut client = voidio_client(PORT, &[QUIC_VERSION_2]);
+        client.connect().unwrap();
+        let conn = client.connection().unwrap();
+        assert_eq!(conn.version(), QUIC_VERSION_2);
+        let info = conn.peer_transport_parameters().unwrap().version_information.clone().unwrap();
+        assert_eq!(info.chosen_version, QUIC_VERSION_2);
+        assert_eq!(info.available_versions, [QUIC_VERSION_1, QUIC_VERSION_2]);
+        server.stop();
+    }
+
+    #[test]
+    fn client_falls_back_after_version_negotiation() {
+        const PORT: u16 = 4473;
+        let mut server = voidio_server(PORT, &[QUIC_VERSION_1]);
+        let mut client = voidio_client(PORT, &[QUIC_VERSION_2, QUIC_VERSION_1]);
+        client.connect().unwrap();
+        assert_eq!(client.connection().unwrap().version(), QUIC_VERSION_1);
+
+        /* without a version in common the connection fails instead of waiting for the timeout */
+        let mut client = voidio_client(PORT, &[QUIC_VERSION_2]);
+        let error = client.connect().unwrap_err();
+        assert!(error.contains("no QUIC version in common"), "{}", error);
+        server.stop();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn quinn_client_in_an_unsupported_version_gets_a_version_mismatch() {
+        const PORT: u16 = 4474;
+        let mut server = voidio_server(PORT, &[QUIC_VERSION_1, QUIC_VERSION_2]);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let mut config = quinn::ClientConfig::new(Arc::new(quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap()));
+        config.version(0xff00_001d); // draft-29
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(config);
+        let connecting = endpoint.connect(SocketAddr::from(([127, 0, 0, 1], PORT)), "localhost").unwrap();
+        let error = tokio::time::timeout(Duration::from_secs(5), connecting).await.unwrap().unwrap_err();
+        assert_eq!(error, quinn::ConnectionError::VersionMismatch);
+        server.stop();
+    }
+
+    /* Decrypts the packet at the start of `datagram` with `keys`, returning the CRYPTO data it carries at offset 0 and its length. */
+    fn open_crypto(keys: &mut PacketProtector, datagram: &[u8]) -> (Vec<u8>, usize) {
+        let parsed = QuicPacket::parse(datagram, 0).unwrap();
+        let (pn_offset, len) = (parsed.header.protected().unwrap().pn_offset, parsed.len());
+        let mut packet = datagram[..len].to_vec();
+        let (_, payload) = keys.open(&mut packet, pn_offset, None).unwrap();
+        let crypto = QuicFrames::new(payload).find_map(|frame| match frame.unwrap() {
+            QuicFrame::Crypto { offset: 0, data } => Some(data.to_vec()),
+            _ => None,
+        });
+        (crypto.unwrap(), len)
+    }
+
+    #[test]
+    fn server_moves_a_compatible_client_to_its_preferred_version() {
+        const PORT: u16 = 4475;
+        let mut server = voidio_server(PORT, &[QUIC_VERSION_2, QUIC_VERSION_1]);
+        let socket = raw_socket(PORT);
+        let (dcid, scid) = ([0x33; 8], [0x44; 8]);
+
+        /* a client that sends its first flight in v1, offers v2 and would follow the server to it */
+        let params = TransportParameters {
+            initial_source_connection_id: Some(ConnectionId::from_slice(&scid)),
+            version_information: Some(VersionInformation { chosen_version: QUIC_VERSION_1, available_versions: vec![QUIC_VERSION_1, QUIC_VERSION_2] }),
+            ..Default::default()
+        };
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
+        let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut tls = rustls::quic::ClientConnection::new(Arc::new(crypto), rustls::quic::Version::V2, name, params.encode()).unwrap();
+        let mut hello = Vec::new();
+        tls.write_hs(&mut hello);
+
+        let mut packet = vec![0xc3];
+        packet.extend_from_slice(&QUIC_VERSION_1.to_be_bytes());
+        packet.push(dcid.len() as u8);
+        packet.extend_from_slice(&dcid);
+        packet.push(scid.len() as u8);
+        packet.extend_from_slice(&scid);
+        packet.extend_from_slice(&[0, 0x40, 0]); // no token, Length filled in when sealed
+        let pn_offset = packet.len();
+        packet.extend_from_slice(&[0; 4]);
+        QuicFrame::Crypto { offset: 0, data: &hello }.encode(&mut packet).unwrap();
+        packet.resize(1200 - 16, 0);
+        PacketProtector::initial_for_version(QUIC_VERSION_1, &dcid, Side::Client).seal(&mut packet, 0, pn_offset, 0).unwrap();
+        socket.send(&packet).unwrap();
+
+        /* the server answers in v2, with v2 Initial keys derived from the same DCID */
+        let mut buf = [0u8; 1500];
+        let len = socket.recv(&mut buf).unwrap();
+        let Some(QuicPacket { header: QuicPacketHeader::Initial(initial), .. }) = QuicPacket::parse(&buf[..len], 0) else { panic!("not an Initial") };
+        assert_eq!(initial.version, QUIC_VERSION_2);
+        let (server_hello, initial_len) = open_crypto(&mut PacketProtector::initial_for_version(QUIC_VERSION_2, &dcid, Side::Client), &buf[..len]);
+        tls.read_hs(&server_hello).unwrap();
+        let Some(KeyChange::Handshake { keys }) = tls.write_hs(&mut Vec::new()) else { panic!("no Handshake keys") };
+
+        /* and its transport parameters confirm the choice */
+        let (extensions, _) = open_crypto(&mut PacketProtector::new(EncryptionLevel::Handshake, keys), &buf[initial_len..len]);
+        tls.read_hs(&extensions).unwrap();
+        let server_params = TransportParameters::decode(tls.quic_transport_parameters().unwrap(), true).unwrap();
+        let info = server_params.version_information.unwrap();
+        assert_eq!(info.chosen_version, QUIC_VERSION_2);
+        assert_eq!(info.available_versions, [QUIC_VERSION_2, QUIC_VERSION_1]);
+        server.stop();
+    }
+}
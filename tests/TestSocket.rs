@@ -211,4 +211,75 @@ mod tests {
 
         server.close().unwrap();
     }
+
+    #[test]
+    fn socket_udp_recv_return_value_ipv4() {
+        let server_addr = SocketAddrV4::new("127.0.0.1".parse().unwrap(), 44009);
+
+        let server = Socket::new(AfInet, SockDgram, IpProtoUdp).unwrap();
+        server.bind(server_addr).unwrap();
+        server.set_socket_option(SoRecvTimeout, std::time::Duration::from_millis(200)).unwrap();
+
+        // A one-byte datagram is data, not an error; only a negative return from recv(2) is.
+        let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"!", server_addr).unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(server.recv(&mut buf, 0).unwrap(), 1);
+        assert_eq!(buf[0], b'!');
+
+        // Nothing left: the timeout must surface as an error instead of a bogus length.
+        assert!(server.recv(&mut buf, 0).is_err());
+
+        server.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_addr_v4_is_stored_in_network_order() {
+        let addr: SocketAddrV4 = "127.1.2.3:4433".parse().unwrap();
+        let raw = *voidio::net::SocketAddrSrcV4::from_socket_addr(&addr).raw();
+        assert_eq!(raw.sin_addr.s_addr.to_ne_bytes(), [127, 1, 2, 3]);
+        assert_eq!(raw.sin_port.to_ne_bytes(), 4433u16.to_be_bytes());
+        assert_eq!(raw.to_socket_addr_v4(), addr);
+    }
+
+    #[test]
+    fn socket_udp_recv_from_reports_the_sender_ipv4() {
+        let server_addr = SocketAddrV4::new("127.0.0.1".parse().unwrap(), 44010);
+        let client_addr = SocketAddrV4::new("127.0.0.1".parse().unwrap(), 44011);
+
+        let server = Socket::new(AfInet, SockDgram, IpProtoUdp).unwrap();
+        server.bind(server_addr).unwrap();
+        let client = Socket::new(AfInet, SockDgram, IpProtoUdp).unwrap();
+        client.bind(client_addr).unwrap();
+        client.send_to(b"ping", &server_addr.into(), 0).unwrap();
+
+        let mut buf = [0u8; 8];
+        let mut len = 0;
+        let src = server.recv_from_v4(&mut buf, &mut len, 0).unwrap();
+        assert_eq!(src.to_socket_addr_v4(), client_addr);
+
+        client.close().unwrap();
+        server.close().unwrap();
+    }
+
+    #[test]
+    fn socket_udp_recv_from_reports_the_sender_ipv6() {
+        let server_addr = SocketAddrV6::new("::1".parse().unwrap(), 44012, 0, 0);
+        let client_addr = SocketAddrV6::new("::1".parse().unwrap(), 44013, 0, 0);
+
+        let server = Socket::new(AfInet6, SockDgram, IpProtoUdp).unwrap();
+        server.bind(server_addr).unwrap();
+        let client = Socket::new(AfInet6, SockDgram, IpProtoUdp).unwrap();
+        client.bind(client_addr).unwrap();
+        client.send_to(b"ping", &server_addr.into(), 0).unwrap();
+
+        let mut buf = [0u8; 8];
+        let mut len = 0;
+        let src = server.recv_from_v6(&mut buf, &mut len, 0).unwrap();
+        assert_eq!(src.to_socket_addr_v6(), client_addr);
+
+        client.close().unwrap();
+        server.close().unwrap();
+    }
 }
@@ -0,0 +1,101 @@
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use std::{net::UdpSocket, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+    use voidio::net::{AfInet, AfInet6, FilterExpr, IpProtoUdp, SoRecvTimeout, SockDgram, Socket, SocketFilter, UdpServer};
+
+    fn receive_all(sock: &Socket) -> Vec<Vec<u8>> {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 2048];
+        while let Ok(n) = sock.recv(&mut buf, 0) {
+            received.push(buf[..n].to_vec());
+        }
+        received
+    }
+
+    #[test]
+    fn socket_filter_parse() {
+        let parsed: FilterExpr = "dst port 4433 and (len 20-1350 or not payload 0xc0ff) and src net 10.0.0.0/8".parse().unwrap();
+        let built = FilterExpr::dst_port(4433)
+            .and(FilterExpr::payload_len(20, 1350).or(!FilterExpr::payload_prefix(&[0xc0, 0xff])))
+            .and(FilterExpr::src_net("10.0.0.0".parse().unwrap(), 8));
+        assert_eq!(parsed, built);
+
+        assert!(SocketFilter::parse("dst port 70000").is_err());
+        assert!(SocketFilter::parse("dst port 5000-4000").is_err());
+        assert!(SocketFilter::parse("len 100-20").is_err());
+        assert!(SocketFilter::parse("dst port 4000-4000").is_ok());
+        assert!(SocketFilter::new(FilterExpr::dst_ports(5000, 4000)).is_err());
+        assert!(SocketFilter::new(FilterExpr::src_ports(2, 1)).is_err());
+        assert!(SocketFilter::new(!FilterExpr::payload_len(100, 20)).is_err());
+        assert!(SocketFilter::parse("payload c0f").is_err());
+        assert!(SocketFilter::parse("src net 10.0.0.0/33").is_err());
+        assert!(SocketFilter::parse("len 1-2 and").is_err());
+        assert!(SocketFilter::parse("(dst port 1").is_err());
+    }
+
+    #[test]
+    fn socket_filter_drops_in_kernel() {
+        let sock = Socket::new(AfInet, SockDgram, IpProtoUdp).unwrap();
+        sock.set_socket_option(SoRecvTimeout, Duration::from_millis(200)).unwrap();
+        let filter = SocketFilter::parse("len 4-16 and payload c0ff and not src net 10.0.0.0/8 and src net 127.0.0.0/8").unwrap();
+        sock.attach_filter(&filter).unwrap();
+        sock.bind("127.0.0.1:46001").unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for payload in [&b"\xc0\xffok"[..], b"\xc0\xfe-bad-prefix", b"\xc0", b"\xc0\xff-much-too-long-payload", b"\xc0\xff"] {
+            sender.send_to(payload, "127.0.0.1:46001").unwrap();
+        }
+        assert_eq!(receive_all(&sock), vec![b"\xc0\xffok".to_vec()]);
+
+        sock.detach_filter().unwrap();
+        sender.send_to(b"anything", "127.0.0.1:46001").unwrap();
+        assert_eq!(receive_all(&sock), vec![b"anything".to_vec()]);
+        sock.close().unwrap();
+    }
+
+    #[test]
+    fn socket_filter_source_ports_and_ipv6() {
+        let sock = Socket::new(AfInet6, SockDgram, IpProtoUdp).unwrap();
+        sock.set_socket_option(SoRecvTimeout, Duration::from_millis(200)).unwrap();
+        sock.attach_filter(&SocketFilter::parse("src net ::1/128 and src port 46010-46011").unwrap()).unwrap();
+        sock.bind("[::1]:46002").unwrap();
+
+        let allowed = UdpSocket::bind("[::1]:46011").unwrap();
+        let other = UdpSocket::bind("[::1]:46012").unwrap();
+        other.send_to(b"other", "[::1]:46002").unwrap();
+        allowed.send_to(b"allowed", "[::1]:46002").unwrap();
+        assert_eq!(receive_all(&sock), vec![b"allowed".to_vec()]);
+        sock.close().unwrap();
+    }
+
+    #[test]
+    fn socket_filter_on_udp_server() {
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let rejected = Arc::new(AtomicUsize::new(0));
+        let mut server = UdpServer::new("127.0.0.1:46003".parse().unwrap());
+        server.filter(SocketFilter::parse("payload 564f4944").unwrap()); // "VOID"
+        server.thread({
+            let (accepted, rejected) = (accepted.clone(), rejected.clone());
+            move |mut ctx| {
+                let (accepted, rejected) = (accepted.clone(), rejected.clone());
+                ctx.on_datagram(move |_, data| {
+                    let counter = if data.starts_with(b"VOID") { &accepted } else { &rejected };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                });
+                ctx.run().unwrap();
+            }
+        });
+        server.start(1).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for i in 0..20 {
+            let payload: &[u8] = if i % 2 == 0 { b"VOID datagram" } else { b"junk datagram" };
+            sender.send_to(payload, "127.0.0.1:46003").unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        server.stop();
+        assert_eq!(accepted.load(Ordering::Relaxed), 10);
+        assert_eq!(rejected.load(Ordering::Relaxed), 0);
+    }
+}
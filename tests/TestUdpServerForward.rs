@@ -0,0 +1,41 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc};
+    use std::time::{Duration, Instant};
+    use voidio::net::{UdpServer, INBOX_CAPACITY};
+
+    #[test]
+    fn forwarding_to_a_full_inbox_drops_the_datagram() {
+        let mut server = UdpServer::new("127.0.0.1:47001".parse().unwrap());
+        let started = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        server.thread({
+            let done = done.clone();
+            move |ctx| {
+                ctx.make_ready();
+                /* no worker drains its inbox; the first one fills both */
+                if started.fetch_add(1, Ordering::Relaxed) == 0 {
+                    let forwarder = ctx.forwarder();
+                    let source = "127.0.0.1:47002".parse().unwrap();
+                    for worker in 0..2 {
+                        for _ in 0..INBOX_CAPACITY + 10 {
+                            assert!(forwarder.forward(worker, source, b"datagram"));
+                        }
+                    }
+                    assert!(!forwarder.forward(2, source, b"datagram"));
+                }
+                while !done.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+        });
+        server.start(2).expect("Failed to start server");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.forward_drops() < 20 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(server.forward_drops(), 20);
+        done.store(true, Ordering::Relaxed);
+        server.stop();
+    }
+}
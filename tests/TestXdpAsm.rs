@@ -0,0 +1,99 @@
+#[cfg(test)]
+mod tests {
+    use voidio::net::xdp::{xdp_udp_port_guard, AluOp, BpfAsm, JmpOp, XdpAction, XdpVm, R0, R1};
+    use voidio::net::BPF_ANY;
+
+    fn udp_packet(src: [u8; 4], dst_port: u16) -> Vec<u8> {
+        let mut p = vec![0u8; 14 + 20 + 8 + 4];
+        p[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        p[14] = 0x45;
+        p[14 + 9] = 17;
+        p[26..30].copy_from_slice(&src);
+        p[34..36].copy_from_slice(&5000u16.to_be_bytes());
+        p[36..38].copy_from_slice(&dst_port.to_be_bytes());
+        p
+    }
+
+    #[test]
+    fn asm_udp_port_guard_in_vm() {
+        let mut vm = XdpVm::from_object(xdp_udp_port_guard(4433, 16).unwrap()).unwrap();
+        vm.map_mut("allowed_sources").unwrap().update(&[10, 0, 0, 1], &[1], BPF_ANY).unwrap();
+
+        assert_eq!(vm.run(&udp_packet([10, 0, 0, 1], 4433)).unwrap().action, XdpAction::Pass);
+        assert_eq!(vm.run(&udp_packet([10, 0, 0, 2], 4433)).unwrap().action, XdpAction::Drop);
+        assert_eq!(vm.run(&udp_packet([10, 0, 0, 2], 53)).unwrap().action, XdpAction::Pass);
+        assert_eq!(vm.run(&udp_packet([10, 0, 0, 2], 4433)[..30]).unwrap().action, XdpAction::Pass);
+    }
+
+    #[test]
+    fn asm_resolves_forward_and_backward_labels() {
+        // r0 = 1 << 5 computed by a loop, then a forward jump skips an XDP_ABORTED return.
+        let mut asm = BpfAsm::new();
+        let top = asm.label();
+        let done = asm.label();
+        asm.mov64(R0, 1)
+            .mov64(R1, 5)
+            .bind(top)
+            .alu64(AluOp::Lsh, R0, 1)
+            .alu64(AluOp::Sub, R1, 1)
+            .jmp(JmpOp::Ne, R1, 0, top)
+            .jmp(JmpOp::Eq, R0, 32, done)
+            .ret(0)
+            .bind(done)
+            .ret(1);
+        let object = asm.build().unwrap();
+        assert_eq!(object.code.len(), asm.len() * 8);
+        assert_eq!(XdpVm::from_object(object).unwrap().run(&[0u8; 64]).unwrap().action, XdpAction::Drop);
+    }
+
+    #[test]
+    fn asm_unbound_label_fails() {
+        let mut asm = BpfAsm::new();
+        let nowhere = asm.label();
+        asm.ja(nowhere).ret(2);
+        assert!(asm.build().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn asm_programs_pass_the_kernel_verifier() {
+        use voidio::net::xdp::load_bpf_object;
+        use voidio::net::{BPF_PROG_TYPE_SOCKET_FILTER, BPF_PROG_TYPE_XDP};
+
+        let guard = xdp_udp_port_guard(4433, 16).unwrap();
+        let loaded = match load_bpf_object(&guard, BPF_PROG_TYPE_XDP, None) {
+            Ok(loaded) => loaded,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                println!("[XdpAsm Test] Skipped, cannot load BPF programs: {e}");
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+        let allowed = loaded.map("allowed_sources").unwrap();
+        allowed.update(&[10, 0, 0, 1], &[1], BPF_ANY).unwrap();
+
+        // Accept-everything socket filter: returns the number of bytes to keep.
+        let mut asm = BpfAsm::new();
+        asm.ret(0xffff);
+        load_bpf_object(&asm.build().unwrap(), BPF_PROG_TYPE_SOCKET_FILTER, None).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn asm_load_rejects_bad_map_relocations() {
+        use voidio::net::xdp::{load_bpf_object, BpfObject};
+        use voidio::net::BPF_PROG_TYPE_XDP;
+
+        // The fields are public, so a relocation can point anywhere; nothing may be written out of bounds.
+        let mut asm = BpfAsm::new();
+        asm.ld_imm64(R0, 0).exit();
+        let object = asm.build().unwrap();
+        let relocated = |map_relocations: Vec<(usize, usize)>| BpfObject { code: object.code.clone(), maps: Vec::new(), map_relocations };
+        for bad in [relocated(vec![(0, 0)]), relocated(vec![(2, 0)]), relocated(vec![(usize::MAX, 0)])] {
+            let err = load_bpf_object(&bad, BPF_PROG_TYPE_XDP, None).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+        let truncated = BpfObject { code: object.code[..12].to_vec(), maps: Vec::new(), map_relocations: Vec::new() };
+        assert_eq!(load_bpf_object(&truncated, BPF_PROG_TYPE_XDP, None).err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
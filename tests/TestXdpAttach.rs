@@ -0,0 +1,44 @@
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use voidio::net::xdp::{interface_index, load_xdp, XdpAttachment, XdpMode};
+
+    static XDP_OBJ: &[u8] = include_bytes!("../src/net/udp/xdp/build/xdp.o");
+
+    #[test]
+    fn xdp_mode_fallback_chain() {
+        assert_eq!(XdpMode::Offload.fallback(), Some(XdpMode::Native));
+        assert_eq!(XdpMode::Native.fallback(), Some(XdpMode::Generic));
+        assert_eq!(XdpMode::Generic.fallback(), None);
+    }
+
+    #[test]
+    fn xdp_attach_detach_loopback() {
+        // Needs CAP_BPF + CAP_NET_ADMIN; nothing to test on machines without them.
+        let prog = match load_xdp(XDP_OBJ, ".maps") {
+            Ok(prog) => prog,
+            Err(e) => { println!("[XdpAttach Test] Skipped, cannot load BPF programs here: {e}"); return; }
+        };
+        let ifindex = interface_index("lo").unwrap();
+        let attachment = XdpAttachment::attach(prog, ifindex, XdpMode::Native).unwrap();
+        assert_eq!(attachment.ifindex(), ifindex);
+        println!("[XdpAttach Test] Attached to lo in {:?} mode", attachment.mode());
+
+        // A second link on the same interface must be refused while the first one is alive.
+        assert!(XdpAttachment::attach_exact(prog, ifindex, attachment.mode().unwrap()).is_err());
+        attachment.detach().unwrap();
+
+        // Once detached, the interface is free again; programs can be swapped in place and dropping the guard detaches implicitly.
+        let mut attachment = XdpAttachment::attach(prog, ifindex, XdpMode::Generic).unwrap();
+        let replacement = load_xdp(XDP_OBJ, ".maps").unwrap();
+        attachment.replace(replacement).unwrap();
+        assert_eq!(attachment.program(), replacement);
+        drop(attachment);
+        XdpAttachment::attach(prog, ifindex, XdpMode::Generic).unwrap().detach().unwrap();
+    }
+
+    #[test]
+    fn xdp_interface_index_unknown() {
+        assert!(interface_index("voidio-nonexistent0").is_err());
+    }
+}
@@ -0,0 +1,91 @@
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use voidio::net::xdp::{interface_index, load_bpf_object, load_xdp_object, unpin, BpfAsm, BpfMapFd, XdpAttachment, XdpFd, XdpMode, BPF_FS_ROOT};
+    use voidio::net::{BpfMapDef, BPF_ANY, BPF_MAP_TYPE_HASH, BPF_PROG_TYPE_XDP};
+
+    static XDP_OBJ: &[u8] = include_bytes!("../src/net/udp/xdp/build/xdp.o");
+
+    fn pin_dir() -> Option<String> {
+        let dir = format!("{}/voidio-test-{}", BPF_FS_ROOT, std::process::id());
+        // Needs a mounted bpffs (mount -t bpf bpf /sys/fs/bpf) and CAP_BPF.
+        match std::fs::create_dir(&dir) {
+            Ok(()) => Some(dir),
+            Err(e) => { println!("[XdpPin Test] Skipped, bpffs unavailable: {e}"); None }
+        }
+    }
+
+    #[test]
+    fn xdp_pinned_maps_survive_reload() {
+        let Some(dir) = pin_dir() else { return };
+
+        let first = load_xdp_object(XDP_OBJ, ".maps", Some(&dir)).unwrap();
+        let port_filter = first.map("port_filter").unwrap();
+        assert!(first.map("xsks_map").is_some());
+        port_filter.update(&4433u16.to_be_bytes(), &[1], BPF_ANY).unwrap();
+
+        // A second load must pick up the map pinned by the first one, contents included.
+        let second = load_xdp_object(XDP_OBJ, ".maps", Some(&dir)).unwrap();
+        let mut value = [0u8; 1];
+        assert!(second.map("port_filter").unwrap().lookup(&4433u16.to_be_bytes(), &mut value).unwrap());
+        assert_eq!(value, [1]);
+
+        let reopened = BpfMapFd::open_pinned(&format!("{dir}/port_filter")).unwrap();
+        assert!(reopened.delete(&4433u16.to_be_bytes()).unwrap());
+        assert!(!port_filter.lookup(&4433u16.to_be_bytes(), &mut value).unwrap());
+
+        unpin(&format!("{dir}/port_filter")).unwrap();
+        unpin(&format!("{dir}/xsks_map")).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn xdp_pinned_map_with_another_layout_is_refused() {
+        let Some(dir) = pin_dir() else { return };
+
+        // An older build pinned "port_filter" with other key and value sizes.
+        let mut asm = BpfAsm::new();
+        asm.map("port_filter", BpfMapDef { type_: BPF_MAP_TYPE_HASH, key_size: 4, value_size: 8, max_entries: 4, map_flags: 0 });
+        asm.ret(2);
+        load_bpf_object(&asm.build().unwrap(), BPF_PROG_TYPE_XDP, Some(&dir)).unwrap();
+
+        let err = load_xdp_object(XDP_OBJ, ".maps", Some(&dir)).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        unpin(&format!("{dir}/port_filter")).unwrap();
+        let _ = unpin(&format!("{dir}/xsks_map"));
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn xdp_pinned_program_and_link() {
+        let Some(dir) = pin_dir() else { return };
+        let obj = load_xdp_object(XDP_OBJ, ".maps", None).unwrap();
+
+        let prog_path = format!("{dir}/prog");
+        obj.prog.pin(&prog_path).unwrap();
+        let prog = XdpFd::open_pinned(&prog_path).unwrap();
+
+        let ifindex = interface_index("lo").unwrap();
+        let link_path = format!("{dir}/link");
+        let mut attachment = XdpAttachment::attach(prog, ifindex, XdpMode::Generic).unwrap();
+        attachment.pin(&link_path).unwrap();
+        drop(attachment);
+
+        // The pinned link outlived its guard, so the interface is still taken.
+        assert!(XdpAttachment::attach_exact(prog, ifindex, XdpMode::Generic).is_err());
+        let attachment = XdpAttachment::open_pinned(&link_path).unwrap();
+        assert_eq!(attachment.ifindex(), ifindex);
+        assert!(attachment.is_pinned());
+        let reopened = attachment.program().as_raw_fd();
+        assert!(std::fs::read_link(format!("/proc/self/fd/{reopened}")).is_ok());
+        attachment.detach().unwrap();
+        // The program fd `open_pinned` opened went with the attachment.
+        assert!(std::fs::read_link(format!("/proc/self/fd/{reopened}")).is_err());
+        XdpAttachment::attach(prog, ifindex, XdpMode::Generic).unwrap().detach().unwrap();
+
+        unpin(&link_path).unwrap();
+        unpin(&prog_path).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
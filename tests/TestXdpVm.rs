@@ -0,0 +1,168 @@
+#[cfg(test)]
+mod tests {
+    use voidio::net::xdp::{XdpAction, XdpMapSpec, XdpRedirect, XdpRxMeta, XdpVm, BPF_FUNC_KTIME_GET_NS, BPF_FUNC_XDP_ADJUST_HEAD};
+    use voidio::net::{BpfMapDef, BPF_ANY, BPF_MAP_TYPE_ARRAY};
+
+    static XDP_OBJ: &[u8] = include_bytes!("../src/net/udp/xdp/build/xdp.o");
+
+    fn udp_packet(dst_port: u16, protocol: u8) -> Vec<u8> {
+        let mut p = vec![0u8; 14 + 20 + 8 + 4];
+        p[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        p[14] = 0x45;
+        p[14 + 9] = protocol;
+        p[34..36].copy_from_slice(&5000u16.to_be_bytes());
+        p[36..38].copy_from_slice(&dst_port.to_be_bytes());
+        p
+    }
+
+    fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> [u8; 8] {
+        let mut raw = [0u8; 8];
+        raw[0] = code;
+        raw[1] = (src << 4) | dst;
+        raw[2..4].copy_from_slice(&off.to_le_bytes());
+        raw[4..8].copy_from_slice(&imm.to_le_bytes());
+        raw
+    }
+
+    fn program(insns: &[[u8; 8]]) -> Vec<u8> {
+        insns.concat()
+    }
+
+    #[test]
+    fn xdp_vm_runs_udp_filter() {
+        let mut vm = XdpVm::from_elf(XDP_OBJ, ".maps").unwrap();
+        let meta = XdpRxMeta { ingress_ifindex: 1, rx_queue_index: 3 };
+
+        // Port not in the filter: passed to the stack.
+        assert_eq!(vm.run_with(&udp_packet(4433, 17), meta).unwrap().action, XdpAction::Pass);
+
+        // Port accepted but no AF_XDP socket on the queue yet.
+        vm.map_mut("port_filter").unwrap().update(&4433u16.to_be_bytes(), &[1], BPF_ANY).unwrap();
+        assert_eq!(vm.run_with(&udp_packet(4433, 17), meta).unwrap().action, XdpAction::Pass);
+
+        vm.map_mut("xsks_map").unwrap().update(&3u32.to_le_bytes(), &7u32.to_le_bytes(), BPF_ANY).unwrap();
+        let result = vm.run_with(&udp_packet(4433, 17), meta).unwrap();
+        assert_eq!(result.action, XdpAction::Redirect);
+        assert_eq!(result.redirect, Some(XdpRedirect::Map { map: "xsks_map".to_string(), key: 3 }));
+        assert_eq!(result.packet, udp_packet(4433, 17));
+
+        // TCP to the same port, truncated frames and other queues are left alone.
+        assert_eq!(vm.run_with(&udp_packet(4433, 6), meta).unwrap().action, XdpAction::Pass);
+        assert_eq!(vm.run_with(&udp_packet(4433, 17)[..20], meta).unwrap().action, XdpAction::Pass);
+        let other_queue = XdpRxMeta { rx_queue_index: 0, ..meta };
+        assert_eq!(vm.run_with(&udp_packet(4433, 17), other_queue).unwrap().action, XdpAction::Pass);
+    }
+
+    #[test]
+    fn xdp_vm_alu_and_jumps() {
+        // r0 = 0; for (r1 = 10; r1 != 0; r1--) r0 += r1; return r0 == 55 ? XDP_DROP : XDP_ABORTED
+        let code = program(&[
+            insn(0xb7, 0, 0, 0, 0),    // r0 = 0
+            insn(0xb7, 1, 0, 0, 10),   // r1 = 10
+            insn(0x0f, 0, 1, 0, 0),    // r0 += r1
+            insn(0x17, 1, 0, 0, 1),    // r1 -= 1
+            insn(0x55, 1, 0, -3, 0),   // if r1 != 0 goto -3
+            insn(0x15, 0, 0, 2, 55),   // if r0 == 55 goto +2
+            insn(0xb7, 0, 0, 0, 0),    // r0 = XDP_ABORTED
+            insn(0x95, 0, 0, 0, 0),    // exit
+            insn(0xb7, 0, 0, 0, 1),    // r0 = XDP_DROP
+            insn(0x95, 0, 0, 0, 0),    // exit
+        ]);
+        let mut vm = XdpVm::new(&code, Vec::new()).unwrap();
+        assert_eq!(vm.run(&[0u8; 64]).unwrap().action, XdpAction::Drop);
+    }
+
+    #[test]
+    fn xdp_vm_adjust_head_and_ktime() {
+        // Strips the 14-byte Ethernet header, then returns XDP_TX if ktime reports 1000.
+        let code = program(&[
+            insn(0xb7, 2, 0, 0, 14),                        // r2 = 14
+            insn(0x85, 0, 0, 0, BPF_FUNC_XDP_ADJUST_HEAD),  // r0 = xdp_adjust_head(ctx, 14)
+            insn(0x55, 0, 0, 3, 0),                         // if r0 != 0 goto abort
+            insn(0x85, 0, 0, 0, BPF_FUNC_KTIME_GET_NS),     // r0 = ktime
+            insn(0x15, 0, 0, 2, 1000),                      // if r0 == 1000 goto tx
+            insn(0xb7, 0, 0, 0, 0),                         // abort: r0 = XDP_ABORTED
+            insn(0x95, 0, 0, 0, 0),
+            insn(0xb7, 0, 0, 0, 3),                         // tx: r0 = XDP_TX
+            insn(0x95, 0, 0, 0, 0),
+        ]);
+        let mut vm = XdpVm::new(&code, Vec::new()).unwrap();
+        vm.set_clock(|| 1000);
+        let packet = udp_packet(53, 17);
+        let result = vm.run(&packet).unwrap();
+        assert_eq!(result.action, XdpAction::Tx);
+        assert_eq!(result.packet, &packet[14..]);
+    }
+
+    #[test]
+    fn xdp_vm_array_map_counter() {
+        // Bumps slot 0 of an array map with an atomic add and passes the packet.
+        let code = program(&[
+            insn(0x62, 10, 0, -4, 0),   // *(u32 *)(r10 - 4) = 0
+            insn(0xbf, 2, 10, 0, 0),    // r2 = r10
+            insn(0x07, 2, 0, 0, -4),    // r2 += -4
+            insn(0x18, 1, 1, 0, 0),     // r1 = map[0]
+            insn(0x00, 0, 0, 0, 0),
+            insn(0x85, 0, 0, 0, 1),     // r0 = map_lookup_elem(r1, r2)
+            insn(0x15, 0, 0, 2, 0),     // if r0 == 0 goto +2
+            insn(0xb7, 1, 0, 0, 1),     // r1 = 1
+            insn(0xdb, 0, 1, 0, 0x00),  // lock *(u64 *)(r0 + 0) += r1
+            insn(0xb7, 0, 0, 0, 2),     // r0 = XDP_PASS
+            insn(0x95, 0, 0, 0, 0),
+        ]);
+        let def = BpfMapDef { type_: BPF_MAP_TYPE_ARRAY, key_size: 4, value_size: 8, max_entries: 1, map_flags: 0 };
+        let mut vm = XdpVm::new(&code, vec![XdpMapSpec { name: "counter".to_string(), def }]).unwrap();
+        for _ in 0..3 {
+            assert_eq!(vm.run(&[0u8; 64]).unwrap().action, XdpAction::Pass);
+        }
+        assert_eq!(vm.map("counter").unwrap().lookup(&0u32.to_le_bytes()).unwrap(), &3u64.to_le_bytes());
+    }
+
+    #[test]
+    fn xdp_vm_refuses_huge_array_maps() {
+        let exit = program(&[insn(0xb7, 0, 0, 0, 2), insn(0x95, 0, 0, 0, 0)]);
+        let def = BpfMapDef { type_: BPF_MAP_TYPE_ARRAY, key_size: 4, value_size: 8, max_entries: u32::MAX, map_flags: 0 };
+        assert!(XdpVm::new(&exit, vec![XdpMapSpec { name: "huge".to_string(), def }]).is_err());
+        let def = BpfMapDef { max_entries: 1024, ..def };
+        assert!(XdpVm::new(&exit, vec![XdpMapSpec { name: "small".to_string(), def }]).is_ok());
+    }
+
+    #[test]
+    fn xdp_vm_rejects_out_of_bounds_access() {
+        // r2 = ctx->data; r0 = *(u8 *)(r2 + 100) on a 64-byte packet
+        let code = program(&[
+            insn(0x61, 2, 1, 0, 0),
+            insn(0x71, 0, 2, 100, 0),
+            insn(0x95, 0, 0, 0, 0),
+        ]);
+        let mut vm = XdpVm::new(&code, Vec::new()).unwrap();
+        assert!(vm.run(&[0u8; 64]).is_err());
+
+        // Infinite loop is stopped by the instruction limit.
+        let mut vm = XdpVm::new(&program(&[insn(0x05, 0, 0, -1, 0)]), Vec::new()).unwrap();
+        assert!(vm.run(&[0u8; 64]).is_err());
+    }
+
+    #[test]
+    fn xdp_vm_rejects_registers_above_r10() {
+        // mov r15, 1; exit
+        let code = [0xb7, 0x0f, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert!(XdpVm::new(&code, Vec::new()).is_err());
+        // r0 = *(u8 *)(r11 + 0); exit
+        assert!(XdpVm::new(&program(&[insn(0x71, 0, 11, 0, 0), insn(0x95, 0, 0, 0, 0)]), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn xdp_vm_rejects_writes_to_the_frame_pointer() {
+        let exit = insn(0x95, 0, 0, 0, 0);
+        // mov r10, 0
+        assert!(XdpVm::new(&program(&[insn(0xb7, 10, 0, 0, 0), exit]), Vec::new()).is_err());
+        // r10 = *(u64 *)(r1 + 0)
+        assert!(XdpVm::new(&program(&[insn(0x79, 10, 1, 0, 0), exit]), Vec::new()).is_err());
+        // lock r10 = xchg(r2 + 0, r10)
+        assert!(XdpVm::new(&program(&[insn(0xdb, 2, 10, 0, 0xe1), exit]), Vec::new()).is_err());
+        // Reading r10 and storing through it stay allowed: *(u64 *)(r10 - 8) = 1; r0 = r10
+        let code = program(&[insn(0x7a, 10, 0, -8, 1), insn(0xbf, 0, 10, 0, 0), insn(0xb7, 0, 0, 0, 2), exit]);
+        assert_eq!(XdpVm::new(&code, Vec::new()).unwrap().run(&[0u8; 64]).unwrap().action, XdpAction::Pass);
+    }
+}
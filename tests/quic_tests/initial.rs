@@ -1,15 +1,32 @@
 #[cfg(test)]
 mod tests {
     use std::net::SocketAddr;
-    use voidio::net::QuicClient;
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use voidio::net::{QuicClient, QuicServer};
+
+    const ALPN: &[u8] = b"voidio-test";
 
     #[test]
     fn quic_initial () {
         let address: SocketAddr = "127.0.0.1:5003".parse().unwrap();
-        let mut client = QuicClient::new("127.0.0.1:5003");
+        let mut server = QuicServer::new(address);
+        let chain = CertificateDer::pem_file_iter("tests/certs/localhost.pem").unwrap().collect::<Result<_, _>>().unwrap();
+        server
+            .set_certificate_chain(chain, PrivateKeyDer::from_pem_file("tests/certs/localhost.key").unwrap())
+            .unwrap()
+            .set_alpn_protocols(&[ALPN]);
+        server.on_connection(|_| {});
+        server.start(1);
+
+        let mut client = QuicClient::new("localhost:5003");
+        client.set_address(address).set_alpn_protocols(&[ALPN]);
+        client.add_root_certificate(CertificateDer::from_pem_file("tests/certs/ca.pem").unwrap()).unwrap();
         match client.connect() {
             Ok(_) => println!("Connected to QUIC server at {}", address),
             Err(e) => panic!("Failed to connect to QUIC server: {}", e),
         }
+        server.stop();
     }
 }